@@ -0,0 +1,18 @@
+//! Feeds arbitrary bytes to the frame decoder, as a BMC receives them from a misbehaving host and
+//! the other way around. Decoding must fail cleanly, without panicking.
+
+#![no_main]
+
+use bmc_proto::frame::{self, FrameDecoder};
+use bmc_proto::{Request, Response};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = FrameDecoder::new();
+    for &byte in data {
+        if let Some(Ok(message)) = decoder.push(byte) {
+            let _ = frame::decode::<Request>(message);
+            let _ = frame::decode::<Response>(message);
+        }
+    }
+});
@@ -0,0 +1,23 @@
+//! Feeds arbitrary bytes to the image header decoder, as the bootloader finds them in a slot and
+//! the host in a file to inspect. Decoding must fail cleanly, and decoded headers must encode back
+//! into the same header.
+
+#![no_main]
+
+use bmc_proto::image::{GitCommit, ImageHeader, Version};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = ImageHeader::decode(data) {
+        assert_eq!(ImageHeader::decode(&header.encode()), Ok(header));
+    }
+
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Some(version) = Version::parse(s) {
+            assert_eq!(Version::parse(&version.to_string()), Some(version));
+        }
+        if let Some(commit) = GitCommit::parse(s) {
+            assert_eq!(GitCommit::parse(&commit.to_string()), Some(commit));
+        }
+    }
+});
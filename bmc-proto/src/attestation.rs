@@ -0,0 +1,216 @@
+//! Attestation reports, in which a BMC vouches for the firmware it runs and how it booted.
+//!
+//! Each BMC signs its reports with an Ed25519 key of its own, derived from a secret provisioned
+//! with `racklet-bmc attest provision` and the unique ID of its MCU. The host enrolls the public
+//! key once and from then on checks reports offline. A report answers a nonce chosen by the host,
+//! so an old report can't be replayed.
+//!
+//! The boot log digest sums up the [`Measurement`]s of the parts the BMC boots from, each extended
+//! into it in order as with a TPM: the digest starts out as zero, and each measurement replaces it
+//! with the SHA-256 of [`extend_input`].
+//!
+//! | Offset | Size | Field                                                          |
+//! |--------|------|----------------------------------------------------------------|
+//! | 0      | 4    | [`MAGIC`]                                                      |
+//! | 4      | 2    | [`REPORT_VERSION`]                                             |
+//! | 8      | 16   | Unique ID of the MCU, zero padded                              |
+//! | 24     | 32   | Nonce of the host                                              |
+//! | 56     | 32   | SHA-256 of the running firmware, zero if it has no image header |
+//! | 88     | 6    | Firmware version: major, minor and patch, 2 each               |
+//! | 96     | 4    | Security version of the running firmware                       |
+//! | 100    | 4    | Minimum security version the BMC is provisioned with           |
+//! | 104    | 32   | Boot log digest                                                |
+//! | 136    | 64   | Ed25519 signature of bytes 0 to 135                            |
+//!
+//! Multi-byte fields are little endian, the bytes between the fields are zero.
+
+use serde::{Deserialize, Serialize};
+
+use crate::image::{DecodeError, Version};
+
+/// "RBMA" in little endian
+pub const MAGIC: u32 = 0x414D_4252;
+pub const REPORT_VERSION: u16 = 1;
+
+/// The encoded length of a report
+pub const REPORT_LEN: usize = 200;
+/// The bytes covered by the signature
+pub const SIGNED_LEN: usize = 136;
+pub const NONCE_LEN: usize = 32;
+
+/// What a measurement is of
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasurementKind {
+    /// The flash of the bootloader
+    Bootloader,
+    /// The image header of the running firmware, which holds its hash and is signed
+    Firmware,
+    /// The minimum security version and the key store, without the attestation secret
+    SecurityPage,
+}
+
+impl MeasurementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MeasurementKind::Bootloader => "bootloader",
+            MeasurementKind::Firmware => "firmware",
+            MeasurementKind::SecurityPage => "security-page",
+        }
+    }
+}
+
+/// An entry of the boot log, see [`crate::RequestBody::GetMeasurement`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+    pub kind: MeasurementKind,
+    /// SHA-256 of what was measured
+    pub digest: [u8; 32],
+}
+
+/// What is hashed to extend the boot log `digest` by `measurement`
+pub fn extend_input(digest: &[u8; 32], measurement: &Measurement) -> [u8; 64] {
+    let mut input = [0; 64];
+    input[..32].copy_from_slice(digest);
+    input[32..].copy_from_slice(&measurement.digest);
+    input
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub unique_id: [u8; 16],
+    pub nonce: [u8; NONCE_LEN],
+    pub firmware_sha256: [u8; 32],
+    pub version: Version,
+    pub security_version: u32,
+    pub min_security_version: u32,
+    pub boot_log_digest: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl Report {
+    pub fn encode(&self) -> [u8; REPORT_LEN] {
+        let mut data = [0; REPORT_LEN];
+        data[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        data[4..6].copy_from_slice(&REPORT_VERSION.to_le_bytes());
+        data[8..24].copy_from_slice(&self.unique_id);
+        data[24..56].copy_from_slice(&self.nonce);
+        data[56..88].copy_from_slice(&self.firmware_sha256);
+        data[88..90].copy_from_slice(&self.version.major.to_le_bytes());
+        data[90..92].copy_from_slice(&self.version.minor.to_le_bytes());
+        data[92..94].copy_from_slice(&self.version.patch.to_le_bytes());
+        data[96..100].copy_from_slice(&self.security_version.to_le_bytes());
+        data[100..104].copy_from_slice(&self.min_security_version.to_le_bytes());
+        data[104..136].copy_from_slice(&self.boot_log_digest);
+        data[SIGNED_LEN..].copy_from_slice(&self.signature);
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let data = data.get(..REPORT_LEN).ok_or(DecodeError::Truncated)?;
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        if word(0) != MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+        let half = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        if half(4) != REPORT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(half(4)));
+        }
+
+        Ok(Self {
+            unique_id: data[8..24].try_into().unwrap(),
+            nonce: data[24..56].try_into().unwrap(),
+            firmware_sha256: data[56..88].try_into().unwrap(),
+            version: Version {
+                major: half(88),
+                minor: half(90),
+                patch: half(92),
+            },
+            security_version: word(96),
+            min_security_version: word(100),
+            boot_log_digest: data[104..136].try_into().unwrap(),
+            signature: data[SIGNED_LEN..].try_into().unwrap(),
+        })
+    }
+
+    /// The part of the encoded report the signature is made over
+    pub fn signed_bytes(&self) -> [u8; SIGNED_LEN] {
+        let mut signed = [0; SIGNED_LEN];
+        signed.copy_from_slice(&self.encode()[..SIGNED_LEN]);
+        signed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        Report {
+            unique_id: [1; 16],
+            nonce: [2; NONCE_LEN],
+            firmware_sha256: [3; 32],
+            version: Version {
+                major: 0x0405,
+                minor: 0x0607,
+                patch: 0x0809,
+            },
+            security_version: 0x0A0B_0C0D,
+            min_security_version: 0x0E0F_1011,
+            boot_log_digest: [4; 32],
+            signature: [5; 64],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let report = report();
+        let data = report.encode();
+        assert_eq!(Report::decode(&data), Ok(report));
+        assert_eq!(report.signed_bytes(), data[..SIGNED_LEN]);
+    }
+
+    /// The offsets of the table in the module documentation
+    #[test]
+    fn layout() {
+        let data = report().encode();
+        assert_eq!(data[0..8], [0x52, 0x42, 0x4D, 0x41, 1, 0, 0, 0]);
+        assert_eq!(data[8..24], [1; 16]);
+        assert_eq!(data[24..56], [2; 32]);
+        assert_eq!(data[56..88], [3; 32]);
+        assert_eq!(data[88..96], [0x05, 0x04, 0x07, 0x06, 0x09, 0x08, 0, 0]);
+        assert_eq!(
+            data[96..104],
+            [0x0D, 0x0C, 0x0B, 0x0A, 0x11, 0x10, 0x0F, 0x0E]
+        );
+        assert_eq!(data[104..136], [4; 32]);
+        assert_eq!(data[136..], [5; 64]);
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        let mut data = report().encode();
+        assert_eq!(
+            Report::decode(&data[..REPORT_LEN - 1]),
+            Err(DecodeError::Truncated)
+        );
+        data[4] = 2;
+        assert_eq!(
+            Report::decode(&data),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+        // An image header
+        data[0..4].copy_from_slice(&crate::image::MAGIC.to_le_bytes());
+        assert_eq!(Report::decode(&data), Err(DecodeError::InvalidMagic));
+    }
+
+    #[test]
+    fn extend_input_is_the_digest_then_the_measurement() {
+        let measurement = Measurement {
+            kind: MeasurementKind::Firmware,
+            digest: [7; 32],
+        };
+        let input = extend_input(&[6; 32], &measurement);
+        assert_eq!(input[..32], [6; 32]);
+        assert_eq!(input[32..], [7; 32]);
+    }
+}
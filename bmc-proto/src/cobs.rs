@@ -0,0 +1,65 @@
+//! Consistent Overhead Byte Stuffing, which removes all zero bytes from a frame so that a zero
+//! byte can delimit frames. Every run of up to 254 non-zero bytes is prefixed with a code byte
+//! giving its length plus one, a code below 0xFF also stands for a zero following the run.
+
+/// Longest encoding of `len` bytes
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encodes `data` into `out`, which must hold at least [`max_encoded_len`] bytes, and returns the
+/// length of the encoding
+pub fn encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut code_index = 0;
+    let mut write = 1;
+    let mut code = 1;
+
+    for &byte in data {
+        if byte != 0 {
+            out[write] = byte;
+            write += 1;
+            code += 1;
+        }
+
+        if byte == 0 || code == 0xFF {
+            out[code_index] = code;
+            code_index = write;
+            write += 1;
+            code = 1;
+        }
+    }
+
+    out[code_index] = code;
+    write
+}
+
+/// Decodes `data` in place and returns the decoded length, or `None` if `data` isn't valid COBS
+pub fn decode_in_place(data: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+
+    while read < data.len() {
+        let code = data[read] as usize;
+        if code == 0 || read + code > data.len() {
+            return None;
+        }
+        read += 1;
+
+        // The decoding is never longer than what has been read, so the writes stay behind
+        for _ in 1..code {
+            if data[read] == 0 {
+                return None;
+            }
+            data[write] = data[read];
+            read += 1;
+            write += 1;
+        }
+
+        if code != 0xFF && read < data.len() {
+            data[write] = 0;
+            write += 1;
+        }
+    }
+
+    Some(write)
+}
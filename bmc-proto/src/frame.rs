@@ -0,0 +1,115 @@
+//! Framing of messages on the serial port.
+//!
+//! A frame is the postcard encoding of a message followed by its CRC-32 (little endian), COBS
+//! encoded and terminated by a zero byte. Senders may also start frames with a zero byte, which
+//! ends any garbage the receiver has collected, e.g. shell output from before the mode switch.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use serde::{Deserialize, Serialize};
+
+use crate::cobs;
+
+/// Longest encoding of a message
+pub const MAX_MESSAGE_LEN: usize = 240;
+
+const CRC_LEN: usize = 4;
+
+/// Longest frame including the delimiter
+pub const MAX_FRAME_LEN: usize = cobs::max_encoded_len(MAX_MESSAGE_LEN + CRC_LEN) + 1;
+
+/// The CRC-32 of Ethernet and zlib
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The message encodes to more than [`MAX_MESSAGE_LEN`] bytes
+    TooLong,
+    /// The frame isn't valid COBS or is too short to hold a CRC
+    Malformed,
+    /// The CRC doesn't match, the frame was corrupted
+    Crc,
+    /// The message doesn't decode into the expected type
+    Decode,
+}
+
+/// Encodes `message` into a frame in `buf` and returns the frame, including the delimiter
+pub fn encode<'b, T: Serialize>(
+    message: &T,
+    buf: &'b mut [u8; MAX_FRAME_LEN],
+) -> Result<&'b [u8], Error> {
+    let mut payload = [0; MAX_MESSAGE_LEN + CRC_LEN];
+    let len = postcard::to_slice(message, &mut payload[..MAX_MESSAGE_LEN])
+        .map_err(|_| Error::TooLong)?
+        .len();
+    let crc = CRC.checksum(&payload[..len]);
+    payload[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+    let frame_len = cobs::encode(&payload[..len + CRC_LEN], buf);
+    buf[frame_len] = 0;
+    Ok(&buf[..frame_len + 1])
+}
+
+/// Decodes a message returned by [`FrameDecoder::push`]
+pub fn decode<'a, T: Deserialize<'a>>(message: &'a [u8]) -> Result<T, Error> {
+    postcard::from_bytes(message).map_err(|_| Error::Decode)
+}
+
+/// Collects received bytes into frames
+pub struct FrameDecoder {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    /// The frame being received has outgrown the buffer and is dropped
+    overflow: bool,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Adds a received byte. At the end of a frame returns the message, checked against its CRC.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+        if byte != 0 {
+            if self.len < self.buf.len() {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Some(Err(Error::TooLong));
+        }
+        // Empty frames are only delimiters sent for synchronization
+        if len == 0 {
+            return None;
+        }
+
+        Some(self.check(len))
+    }
+
+    fn check(&mut self, len: usize) -> Result<&[u8], Error> {
+        let len = cobs::decode_in_place(&mut self.buf[..len]).ok_or(Error::Malformed)?;
+        let message_len = len.checked_sub(CRC_LEN).ok_or(Error::Malformed)?;
+
+        let (message, crc) = self.buf[..len].split_at(message_len);
+        if CRC.checksum(message).to_le_bytes() != crc {
+            return Err(Error::Crc);
+        }
+        Ok(message)
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
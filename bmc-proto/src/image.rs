@@ -0,0 +1,276 @@
+//! The header of signed firmware images, which the bootloader checks before starting an image.
+//!
+//! An image is the firmware as linked for its slot, preceded by [`HEADER_SIZE`] bytes holding the
+//! header. The header is prepended by `racklet-bmc sign` after the build. It carries the SHA-256
+//! of the firmware, metadata about the build and an Ed25519 signature over its first
+//! [`SIGNED_LEN`] bytes, which include the rest. The rest of the header region is erased flash.
+//!
+//! | Offset | Size | Field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 4    | [`MAGIC`]                                          |
+//! | 4      | 2    | [`HEADER_VERSION`]                                 |
+//! | 8      | 4    | Firmware size in bytes                             |
+//! | 12     | 4    | Load address, where the vector table is            |
+//! | 16     | 32   | SHA-256 of the firmware                            |
+//! | 48     | 4    | Security version, see below                        |
+//! | 52     | 6    | Firmware version: major, minor and patch, 2 each   |
+//! | 64     | 8    | Build time in seconds since the Unix epoch         |
+//! | 72     | 20   | Git commit the firmware was built from, or zero    |
+//! | 192    | 64   | Ed25519 signature of bytes 0 to 191                |
+//!
+//! Multi-byte fields are little endian, the bytes between the fields are zero.
+//!
+//! The security version is raised by releases fixing vulnerabilities. A BMC provisioned with a
+//! minimum security version rejects images below it, so older firmware can't be installed even
+//! though its signature is valid.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// "RBMI" in little endian
+pub const MAGIC: u32 = 0x494D_4252;
+pub const HEADER_VERSION: u16 = 1;
+
+/// The flash taken by the header in front of the firmware, which keeps the vector table behind
+/// it aligned as the Cortex-M4 requires
+pub const HEADER_SIZE: u32 = 0x400;
+/// The encoded length of the header
+pub const HEADER_LEN: usize = 256;
+/// The bytes covered by the signature
+pub const SIGNED_LEN: usize = 192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    /// No image header, e.g. erased flash, or no attestation report
+    InvalidMagic,
+    UnsupportedVersion(u16),
+}
+
+/// A semantic version without pre-release or build parts
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl Version {
+    /// Parses `major.minor.patch`
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('.').map(|part| part.parse().ok());
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Version {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The SHA-1 of a git commit, shown in hex. A precision shortens it, e.g. `{:.12}`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GitCommit(pub [u8; 20]);
+
+impl GitCommit {
+    /// Parses the 40 hex digits of a commit
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.len() != 40 {
+            return None;
+        }
+        let mut commit = [0; 20];
+        for (i, digit) in s.chars().enumerate() {
+            commit[i / 2] |= (digit.to_digit(16)? as u8) << if i % 2 == 0 { 4 } else { 0 };
+        }
+        Some(Self(commit))
+    }
+
+    /// Whether the firmware was built from a known commit
+    pub fn is_known(&self) -> bool {
+        self.0 != [0; 20]
+    }
+}
+
+impl fmt::Display for GitCommit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = f.precision().unwrap_or(40).min(40);
+        for i in 0..digits {
+            let byte = self.0[i / 2];
+            let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xF };
+            write!(f, "{:x}", nibble)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    /// Of the firmware behind the header
+    pub image_size: u32,
+    /// The address the firmware is linked for, [`HEADER_SIZE`] past the header
+    pub load_address: u32,
+    pub sha256: [u8; 32],
+    /// Compared against the minimum the BMC is provisioned with
+    pub security_version: u32,
+    pub version: Version,
+    /// Seconds since the Unix epoch
+    pub build_time: i64,
+    pub git_commit: GitCommit,
+    pub signature: [u8; 64],
+}
+
+impl ImageHeader {
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut data = [0; HEADER_LEN];
+        data[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        data[4..6].copy_from_slice(&HEADER_VERSION.to_le_bytes());
+        data[8..12].copy_from_slice(&self.image_size.to_le_bytes());
+        data[12..16].copy_from_slice(&self.load_address.to_le_bytes());
+        data[16..48].copy_from_slice(&self.sha256);
+        data[48..52].copy_from_slice(&self.security_version.to_le_bytes());
+        data[52..54].copy_from_slice(&self.version.major.to_le_bytes());
+        data[54..56].copy_from_slice(&self.version.minor.to_le_bytes());
+        data[56..58].copy_from_slice(&self.version.patch.to_le_bytes());
+        data[64..72].copy_from_slice(&self.build_time.to_le_bytes());
+        data[72..92].copy_from_slice(&self.git_commit.0);
+        data[SIGNED_LEN..].copy_from_slice(&self.signature);
+        data
+    }
+
+    /// Decodes the header at the start of `data`
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let data = data.get(..HEADER_LEN).ok_or(DecodeError::Truncated)?;
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        if word(0) != MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+        let half = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        if half(4) != HEADER_VERSION {
+            return Err(DecodeError::UnsupportedVersion(half(4)));
+        }
+
+        let mut header = Self {
+            image_size: word(8),
+            load_address: word(12),
+            sha256: [0; 32],
+            security_version: word(48),
+            version: Version {
+                major: half(52),
+                minor: half(54),
+                patch: half(56),
+            },
+            build_time: i64::from_le_bytes(data[64..72].try_into().unwrap()),
+            git_commit: GitCommit(data[72..92].try_into().unwrap()),
+            signature: [0; 64],
+        };
+        header.sha256.copy_from_slice(&data[16..48]);
+        header.signature.copy_from_slice(&data[SIGNED_LEN..]);
+        Ok(header)
+    }
+
+    /// The part of the encoded header the signature is made over
+    pub fn signed_bytes(&self) -> [u8; SIGNED_LEN] {
+        let mut signed = [0; SIGNED_LEN];
+        signed.copy_from_slice(&self.encode()[..SIGNED_LEN]);
+        signed
+    }
+}
+
+/// What the BMC reports about the image it runs, see [`crate::RequestBody::GetImage`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub version: Version,
+    pub git_commit: GitCommit,
+    /// Seconds since the Unix epoch
+    pub build_time: i64,
+    pub security_version: u32,
+    pub image_size: u32,
+    pub load_address: u32,
+    pub sha256: [u8; 32],
+}
+
+impl From<&ImageHeader> for ImageInfo {
+    fn from(header: &ImageHeader) -> Self {
+        Self {
+            version: header.version,
+            git_commit: header.git_commit,
+            build_time: header.build_time,
+            security_version: header.security_version,
+            image_size: header.image_size,
+            load_address: header.load_address,
+            sha256: header.sha256,
+        }
+    }
+}
+
+/// What became of the last update the BMC received since it started, see
+/// [`crate::RequestBody::GetUpdate`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateStatus {
+    /// Received and being checked
+    Installing,
+    /// The image checked out and starts on trial at the next reset, which the BMC does right away
+    Scheduled,
+    Rejected(Rejection),
+}
+
+/// Why the BMC didn't install an update
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// No image header, e.g. a UF2 file of unsigned firmware
+    NoImage,
+    InvalidHeader,
+    /// The image is linked for another address, i.e. the other slot
+    WrongAddress(u32),
+    /// The image doesn't fit the slot
+    TooLarge,
+    HashMismatch,
+    /// The image isn't signed by a trusted firmware key
+    InvalidSignature,
+    /// The security version of the image is below the minimum of the BMC
+    Rollback {
+        version: u32,
+        minimum: u32,
+    },
+    /// The security page of the BMC is corrupt, so it trusts no image
+    SecurityPage,
+    /// Saving the slot to try failed
+    Flash,
+    /// The hash peripheral of the BMC timed out
+    Crypto,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::NoImage => f.write_str("no signed image"),
+            Rejection::InvalidHeader => f.write_str("invalid image header"),
+            Rejection::WrongAddress(address) => write!(f, "image linked for {:#010x}", address),
+            Rejection::TooLarge => f.write_str("image larger than the slot"),
+            Rejection::HashMismatch => f.write_str("image hash mismatch"),
+            Rejection::InvalidSignature => f.write_str("image not signed by a trusted key"),
+            Rejection::Rollback { version, minimum } => write!(
+                f,
+                "image security version {} is below the minimum {}",
+                version, minimum
+            ),
+            Rejection::SecurityPage => f.write_str("corrupt security page"),
+            Rejection::Flash => f.write_str("saving the slot to try failed"),
+            Rejection::Crypto => f.write_str("hashing the image timed out"),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! The keys a BMC trusts, which `racklet-bmc keys` provisions over the protocol.
+//!
+//! Each key has a role: firmware images are only started when signed by a firmware key, boot
+//! files for the host by a boot files key. Keys are added while the key store is unlocked. Once
+//! it is locked, which can't be undone, every change has to be signed by a trusted firmware key,
+//! so a fleet rotates its keys with the keys it already has rather than with a debug probe.
+//! Revoked keys are never trusted again.
+
+use serde::{Deserialize, Serialize};
+
+/// Most keys a BMC keeps, revoked ones included
+pub const MAX_KEYS: usize = 8;
+
+/// The length of [`change_message`]
+pub const CHANGE_MESSAGE_LEN: usize = 46;
+
+/// Starts the signed messages, so signatures over other data can't be passed off as them
+const CHANGE_CONTEXT: &[u8; 8] = b"RBMC-KEY";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeyRole {
+    /// Signs firmware images
+    Firmware,
+    /// Signs the files the host boots from
+    BootFiles,
+}
+
+impl KeyRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyRole::Firmware => "firmware",
+            KeyRole::BootFiles => "boot-files",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "firmware" => Some(KeyRole::Firmware),
+            "boot-files" => Some(KeyRole::BootFiles),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEntry {
+    /// Ed25519 public key
+    pub key: [u8; 32],
+    pub role: KeyRole,
+    pub revoked: bool,
+    /// Compiled into the firmware rather than provisioned
+    pub built_in: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyChange {
+    Add(KeyRole),
+    Revoke,
+}
+
+/// The message a trusted firmware key signs to authorize `change` of `key` in a locked key store,
+/// which has had `generation` changes before. The count keeps the signature from being replayed.
+pub fn change_message(
+    change: KeyChange,
+    key: &[u8; 32],
+    generation: u32,
+) -> [u8; CHANGE_MESSAGE_LEN] {
+    let mut message = [0; CHANGE_MESSAGE_LEN];
+    message[..8].copy_from_slice(CHANGE_CONTEXT);
+    (message[8], message[9]) = match change {
+        KeyChange::Add(role) => (0, role as u8),
+        KeyChange::Revoke => (1, 0),
+    };
+    message[10..42].copy_from_slice(key);
+    message[42..].copy_from_slice(&generation.to_le_bytes());
+    message
+}
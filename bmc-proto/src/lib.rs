@@ -0,0 +1,242 @@
+#![no_std]
+
+//! The binary protocol between the BMC firmware and `racklet-bmc`, shared by both.
+//!
+//! The protocol runs on the console serial port after the `rpc` shell command. The host sends
+//! [`Request`]s and the BMC answers each with a [`Response`] carrying the same sequence number,
+//! so responses to earlier, timed out requests can be told apart. Messages are postcard encoded
+//! and sent in frames, see [`frame`].
+//!
+//! A session starts with [`RequestBody::Hello`] to agree on the [`VERSION`] and ends with
+//! [`RequestBody::Exit`], which returns the console to the shell.
+
+pub mod attestation;
+pub mod cobs;
+pub mod frame;
+pub mod image;
+pub mod keys;
+pub mod sdr;
+pub mod time;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the messages. Variants are only ever added at the end, so this changes only when
+/// the meaning or encoding of existing messages does.
+pub const VERSION: u16 = 1;
+
+/// Longest chunk of log output in a [`ResponseBody::Log`]
+pub const MAX_LOG_CHUNK: usize = 192;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub seq: u16,
+    #[serde(borrow)]
+    pub body: RequestBody<'a>,
+}
+
+impl Request<'_> {
+    /// Reads the sequence number of an encoded request that doesn't decode as a whole, so it can
+    /// be answered with [`Error::InvalidRequest`]
+    pub fn seq_of(message: &[u8]) -> Option<u16> {
+        postcard::take_from_bytes(message).ok().map(|(seq, _)| seq)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RequestBody<'a> {
+    /// Answered with [`ResponseBody::Hello`] if the BMC speaks `version`
+    Hello {
+        version: u16,
+    },
+    /// Returns the console to the shell after answering
+    Exit,
+    /// Answered with [`ResponseBody::Power`]
+    GetPower,
+    SetPower(bool),
+    /// Holds the host in reset while `true`
+    SetReset(bool),
+    /// Shows a color on the status LED, or the BMC status again with `None`
+    SetLed(Option<[u8; 3]>),
+    /// Answered with [`ResponseBody::Config`]
+    GetConfig(&'a str),
+    SetConfig {
+        key: &'a str,
+        value: &'a str,
+    },
+    UnsetConfig(&'a str),
+    /// Answered with [`ResponseBody::ConfigEntry`] holding the setting at this index, for listing
+    /// the settings one at a time
+    ConfigEntry(u8),
+    /// Answered with [`ResponseBody::Log`] holding up to [`MAX_LOG_CHUNK`] bytes of the retained
+    /// log output from `offset` on
+    ReadLog {
+        offset: u32,
+    },
+    ClearLog,
+    /// Answered with [`ResponseBody::Identity`]
+    GetIdentity,
+    /// Answered with [`ResponseBody::Sensor`] holding the sensor at this index, for listing the
+    /// sensors one at a time
+    GetSensor(u8),
+    /// Answered with [`ResponseBody::Sdr`] holding the Sensor Data Record with this record ID.
+    /// The record IDs count up from 0, one per sensor in the order of [`RequestBody::GetSensor`].
+    GetSdr(u16),
+    /// Answered with [`ResponseBody::Time`]
+    GetTime,
+    /// Sets the clock of the BMC to seconds since the Unix epoch, see [`time`]
+    SetTime(i64),
+    /// Answered with [`ResponseBody::Image`]
+    GetImage,
+    /// Answered with [`ResponseBody::KeyStore`]
+    GetKeyStore,
+    /// Answered with [`ResponseBody::Key`] holding the key at this index, for listing the keys one
+    /// at a time
+    GetKey(u8),
+    /// Trusts an Ed25519 public key for `role`. A locked key store needs the signature of a
+    /// trusted firmware key over [`keys::change_message`].
+    AddKey {
+        key: [u8; 32],
+        role: keys::KeyRole,
+        signature: Option<&'a [u8]>,
+    },
+    /// Stops trusting a key for good, authorized like [`RequestBody::AddKey`]
+    RevokeKey {
+        key: [u8; 32],
+        signature: Option<&'a [u8]>,
+    },
+    /// Locks the key store, which can't be undone
+    LockKeys,
+    /// Answered with [`ResponseBody::Attestation`] holding a report for this nonce
+    Attest {
+        nonce: [u8; attestation::NONCE_LEN],
+    },
+    /// Answered with [`ResponseBody::Measurement`] holding the boot log entry at this index
+    GetMeasurement(u8),
+    /// Answered with [`ResponseBody::AttestationKey`]
+    GetAttestationKey,
+    /// Provisions the secret the attestation key is derived from, which is only possible while
+    /// the key store is unlocked. The BMC never gives it out again.
+    SetAttestationSecret([u8; 32]),
+    /// Answered with [`ResponseBody::Update`]
+    GetUpdate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Response<'a> {
+    /// The sequence number of the request
+    pub seq: u16,
+    #[serde(borrow)]
+    pub body: ResponseBody<'a>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ResponseBody<'a> {
+    Hello {
+        version: u16,
+        firmware: &'a str,
+    },
+    /// The request succeeded and has nothing to return
+    Ok,
+    Power(bool),
+    Config(&'a str),
+    /// A key and its value, `None` past the last setting
+    ConfigEntry(Option<(&'a str, &'a str)>),
+    /// Empty at the end of the log
+    Log(&'a [u8]),
+    Error(Error),
+    /// The identity the BMC derives from the unique ID of its MCU
+    Identity {
+        serial_number: &'a str,
+        node_name: &'a str,
+        mac_address: [u8; 6],
+    },
+    /// `None` past the last sensor
+    Sensor(Option<SensorReading<'a>>),
+    /// A record encoded as in [`sdr`], `None` past the last record
+    Sdr(Option<&'a [u8]>),
+    /// Seconds since the Unix epoch, `None` while the clock of the BMC isn't set
+    Time(Option<i64>),
+    /// The header of the running firmware image, `None` if it wasn't started from one
+    Image(Option<image::ImageInfo>),
+    KeyStore {
+        locked: bool,
+        /// The changes made to the keys, which a signature authorizing the next one covers
+        generation: u32,
+    },
+    /// `None` past the last key
+    Key(Option<keys::KeyEntry>),
+    /// A report encoded as in [`attestation`]
+    Attestation(&'a [u8]),
+    /// `None` past the last entry of the boot log
+    Measurement(Option<attestation::Measurement>),
+    /// The Ed25519 public key the BMC signs its attestation reports with
+    AttestationKey([u8; 32]),
+    Update {
+        /// The lowest security version of images the BMC installs
+        min_security_version: u32,
+        /// `None` until the BMC receives an update
+        last: Option<image::UpdateStatus>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorReading<'a> {
+    pub name: &'a str,
+    pub unit: Unit,
+    /// In thousandths of the unit, `None` while the sensor is unavailable
+    pub value: Option<i32>,
+    pub status: SensorStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Celsius,
+    Volts,
+    Amps,
+    Rpm,
+}
+
+/// Which thresholds a reading has crossed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorStatus {
+    Ok,
+    LowerNonCritical,
+    UpperNonCritical,
+    LowerCritical,
+    UpperCritical,
+    /// The sensor has not been read yet or didn't answer
+    Unavailable,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The request didn't decode, e.g. because it is from a newer version
+    InvalidRequest,
+    /// The BMC doesn't speak the version of the [`RequestBody::Hello`]
+    UnsupportedVersion,
+    /// The setting isn't set, or no attestation secret is provisioned
+    NotFound,
+    /// The key is empty, too long or contains `=` or control characters
+    InvalidKey,
+    /// The value is too long or contains control characters
+    InvalidValue,
+    /// All settings, or all entries of the key store, are in use
+    Full,
+    /// Saving to flash failed
+    Flash,
+    /// The firmware was built without what the request needs, e.g. the `ram-log` feature
+    Unsupported,
+    /// The value is outside of what the BMC can handle, e.g. a time its clock can't keep
+    OutOfRange,
+    /// The key store is locked and the change isn't signed by a trusted firmware key
+    Unauthorized,
+    /// The key is revoked, revoking it would leave no firmware key to trust, or locking would keep
+    /// the development key trusted
+    KeyRefused,
+    /// The security page is neither erased nor valid, so the BMC trusts no image and changes
+    /// nothing
+    Corrupt,
+    /// The hash peripheral of the BMC timed out
+    Crypto,
+}
@@ -0,0 +1,675 @@
+//! Sensor Data Records describing the sensors of the BMC, encoded as the Full Sensor Records of
+//! the IPMI v2.0 specification (section 43.1), so IPMI tools can read them as they are.
+//!
+//! A record tells how to convert the raw 8-bit readings of a sensor: the value is
+//! `(M * raw + B * 10^B_exp) * 10^R_exp` in the base unit, with the raw reading unsigned or two's
+//! complement. The thresholds are raw readings too. The BMC serves its records with
+//! [`RequestBody::GetSdr`](crate::RequestBody::GetSdr), one per sensor.
+
+/// The SDR version of IPMI v2.0
+pub const SDR_VERSION: u8 = 0x51;
+/// The record type of a Full Sensor Record
+pub const FULL_SENSOR_RECORD: u8 = 0x01;
+/// The IPMB slave address of the BMC, which owns the sensors
+pub const BMC_OWNER_ID: u8 = 0x20;
+
+/// Longest sensor name a record carries
+pub const MAX_NAME_LEN: usize = 16;
+/// The fixed part of a Full Sensor Record before the name
+const FIXED_LEN: usize = 48;
+/// The record header: record ID, SDR version, record type and length
+const HEADER_LEN: usize = 5;
+pub const MAX_RECORD_LEN: usize = FIXED_LEN + MAX_NAME_LEN;
+
+/// Sensor type codes (table 42-3)
+pub mod sensor_type {
+    pub const TEMPERATURE: u8 = 0x01;
+    pub const VOLTAGE: u8 = 0x02;
+    pub const CURRENT: u8 = 0x03;
+    pub const FAN: u8 = 0x04;
+}
+
+/// Sensor unit type codes (table 43-15)
+pub mod unit {
+    pub const DEGREES_C: u8 = 1;
+    pub const VOLTS: u8 = 4;
+    pub const AMPS: u8 = 5;
+    pub const RPM: u8 = 18;
+}
+
+/// Entity ID codes (table 43-13)
+pub mod entity {
+    pub const SYSTEM_BOARD: u8 = 0x07;
+    pub const POWER_SUPPLY: u8 = 0x0A;
+    pub const FAN: u8 = 0x1D;
+    pub const AIR_INLET: u8 = 0x37;
+}
+
+/// The event/reading type code of threshold based sensors
+const THRESHOLD_READING: u8 = 0x01;
+/// Scanning enabled and initialized with the sensor type
+const INITIALIZATION: u8 = 0x45;
+/// Auto re-arm, thresholds readable as in the readable threshold mask, no events
+const CAPABILITIES: u8 = 0x47;
+/// The analog data format of two's complement readings in the sensor units 1 byte
+const TWOS_COMPLEMENT: u8 = 0x80;
+/// 8-bit ASCII in the ID string type/length byte
+const ASCII_NAME: u8 = 0xC0;
+
+/// The linearization of a sensor, see the [module documentation](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Linearization {
+    /// 10-bit two's complement
+    pub m: i16,
+    /// 10-bit two's complement
+    pub b: i16,
+    /// 4-bit two's complement
+    pub b_exponent: i8,
+    /// 4-bit two's complement
+    pub r_exponent: i8,
+}
+
+impl Linearization {
+    /// The value of a raw reading in thousandths of the unit, rounded towards zero
+    pub fn to_milli(&self, raw: i32) -> i64 {
+        let exponent = self.r_exponent as i32 + 3;
+        scale(self.m as i64 * raw as i64, exponent)
+            + scale(self.b as i64, self.b_exponent as i32 + exponent)
+    }
+
+    /// The raw reading closest to a value in thousandths of the unit, not limited to 8 bits
+    pub fn to_raw(&self, milli: i64) -> i64 {
+        let exponent = self.r_exponent as i32 + 3;
+        let offset = milli - scale(self.b as i64, self.b_exponent as i32 + exponent);
+        // milli / (M * 10^exponent), rounded to the nearest
+        let (numerator, denominator) = if exponent >= 0 {
+            (offset, self.m as i64 * 10i64.pow(exponent as u32))
+        } else {
+            (offset * 10i64.pow(exponent.unsigned_abs()), self.m as i64)
+        };
+        if denominator == 0 {
+            return 0;
+        }
+        let half = denominator.abs() / 2;
+        let rounded = if (numerator < 0) == (denominator < 0) {
+            numerator.abs() + half
+        } else {
+            -(numerator.abs() + half)
+        };
+        rounded / denominator.abs()
+    }
+}
+
+/// `value * 10^exponent`, rounded towards zero
+fn scale(value: i64, exponent: i32) -> i64 {
+    if exponent >= 0 {
+        value * 10i64.pow(exponent as u32)
+    } else {
+        value / 10i64.pow(exponent.unsigned_abs())
+    }
+}
+
+/// The thresholds of a sensor as raw readings, `None` where it has none
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RawThresholds {
+    pub lower_non_recoverable: Option<u8>,
+    pub lower_critical: Option<u8>,
+    pub lower_non_critical: Option<u8>,
+    pub upper_non_critical: Option<u8>,
+    pub upper_critical: Option<u8>,
+    pub upper_non_recoverable: Option<u8>,
+}
+
+impl RawThresholds {
+    /// In the order of the readable threshold mask
+    fn in_mask_order(&self) -> [Option<u8>; 6] {
+        [
+            self.lower_non_critical,
+            self.lower_critical,
+            self.lower_non_recoverable,
+            self.upper_non_critical,
+            self.upper_critical,
+            self.upper_non_recoverable,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Shorter than its length says or than a Full Sensor Record
+    Truncated,
+    /// Not an IPMI v2.0 record
+    UnsupportedVersion(u8),
+    /// Another record type than a Full Sensor Record
+    UnsupportedType(u8),
+    /// The name is not ASCII
+    InvalidName,
+}
+
+/// A Full Sensor Record of a threshold based sensor owned by the BMC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FullSensorRecord<'a> {
+    pub record_id: u16,
+    pub sensor_number: u8,
+    pub entity_id: u8,
+    pub entity_instance: u8,
+    /// See [`sensor_type`]
+    pub sensor_type: u8,
+    /// See [`unit`]
+    pub base_unit: u8,
+    /// Whether raw readings are two's complement
+    pub signed: bool,
+    pub linearization: Linearization,
+    pub thresholds: RawThresholds,
+    /// Up to [`MAX_NAME_LEN`] ASCII characters, longer names are cut off
+    pub name: &'a str,
+}
+
+impl<'a> FullSensorRecord<'a> {
+    /// Encodes the record into `buf`, returning the encoding
+    pub fn encode<'b>(&self, buf: &'b mut [u8; MAX_RECORD_LEN]) -> &'b [u8] {
+        let name = &self.name.as_bytes()[..self.name.len().min(MAX_NAME_LEN)];
+        let len = FIXED_LEN + name.len();
+        let r = &mut buf[..len];
+        r.fill(0);
+
+        r[0..2].copy_from_slice(&self.record_id.to_le_bytes());
+        r[2] = SDR_VERSION;
+        r[3] = FULL_SENSOR_RECORD;
+        r[4] = (len - HEADER_LEN) as u8;
+        r[5] = BMC_OWNER_ID;
+        r[7] = self.sensor_number;
+        r[8] = self.entity_id;
+        r[9] = self.entity_instance & 0x7F;
+        r[10] = INITIALIZATION;
+        r[11] = CAPABILITIES;
+        r[12] = self.sensor_type;
+        r[13] = THRESHOLD_READING;
+
+        // The threshold reading masks: which thresholds the reading is compared with, in bits
+        // 12-14 of the assertion and deassertion event masks
+        let mask = self.readable_mask();
+        r[15] = (mask & 0x07) << 4;
+        r[17] = ((mask >> 3) & 0x07) << 4;
+        r[18] = mask;
+
+        r[20] = if self.signed { TWOS_COMPLEMENT } else { 0 };
+        r[21] = self.base_unit;
+
+        let Linearization {
+            m,
+            b,
+            b_exponent,
+            r_exponent,
+        } = self.linearization;
+        r[24] = m as u8;
+        r[25] = ((m >> 2) as u8) & 0xC0;
+        r[26] = b as u8;
+        r[27] = ((b >> 2) as u8) & 0xC0;
+        r[29] = ((r_exponent as u8) << 4) | (b_exponent as u8 & 0x0F);
+
+        // The sensor maximum and minimum reading
+        let (max, min) = if self.signed {
+            (0x7F, 0x80)
+        } else {
+            (0xFF, 0x00)
+        };
+        r[34] = max;
+        r[35] = min;
+
+        let t = &self.thresholds;
+        let thresholds = [
+            t.upper_non_recoverable,
+            t.upper_critical,
+            t.upper_non_critical,
+            t.lower_non_recoverable,
+            t.lower_critical,
+            t.lower_non_critical,
+        ];
+        for (byte, threshold) in r[36..42].iter_mut().zip(thresholds) {
+            *byte = threshold.unwrap_or(0);
+        }
+
+        r[47] = ASCII_NAME | name.len() as u8;
+        r[FIXED_LEN..].copy_from_slice(name);
+        r
+    }
+
+    /// Decodes the record at the start of `data`, returning it with its encoded length
+    pub fn decode(data: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if data.len() < HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        if data[2] != SDR_VERSION {
+            return Err(DecodeError::UnsupportedVersion(data[2]));
+        }
+        if data[3] != FULL_SENSOR_RECORD {
+            return Err(DecodeError::UnsupportedType(data[3]));
+        }
+        let len = HEADER_LEN + data[4] as usize;
+        if len < FIXED_LEN || data.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+        let r = &data[..len];
+
+        let name_len = ((r[47] & 0x1F) as usize).min(len - FIXED_LEN);
+        let name = core::str::from_utf8(&r[FIXED_LEN..FIXED_LEN + name_len])
+            .ok()
+            .filter(|name| name.is_ascii())
+            .ok_or(DecodeError::InvalidName)?;
+
+        let ten_bit = |low: u8, high: u8| (((high as i16 & 0xC0) << 8) >> 6) | low as i16;
+        let four_bit = |nibble: u8| ((nibble << 4) as i8) >> 4;
+        let linearization = Linearization {
+            m: ten_bit(r[24], r[25]),
+            b: ten_bit(r[26], r[27]),
+            b_exponent: four_bit(r[29] & 0x0F),
+            r_exponent: four_bit(r[29] >> 4),
+        };
+
+        let readable = |bit: u8, byte: usize| (r[18] & (1 << bit) != 0).then_some(r[byte]);
+        let thresholds = RawThresholds {
+            lower_non_critical: readable(0, 41),
+            lower_critical: readable(1, 40),
+            lower_non_recoverable: readable(2, 39),
+            upper_non_critical: readable(3, 38),
+            upper_critical: readable(4, 37),
+            upper_non_recoverable: readable(5, 36),
+        };
+
+        let record = Self {
+            record_id: u16::from_le_bytes([r[0], r[1]]),
+            sensor_number: r[7],
+            entity_id: r[8],
+            entity_instance: r[9] & 0x7F,
+            sensor_type: r[12],
+            base_unit: r[21],
+            signed: r[20] & 0xC0 == TWOS_COMPLEMENT,
+            linearization,
+            thresholds,
+            name,
+        };
+        Ok((record, len))
+    }
+
+    /// The value of a raw reading in thousandths of the unit
+    pub fn to_milli(&self, raw: u8) -> i64 {
+        let raw = if self.signed {
+            raw as i8 as i32
+        } else {
+            raw as i32
+        };
+        self.linearization.to_milli(raw)
+    }
+
+    /// The raw reading closest to a value in thousandths of the unit, limited to what the sensor
+    /// can read
+    pub fn to_raw(&self, milli: i64) -> u8 {
+        let raw = self.linearization.to_raw(milli);
+        if self.signed {
+            raw.clamp(i8::MIN as i64, i8::MAX as i64) as i8 as u8
+        } else {
+            raw.clamp(0, u8::MAX as i64) as u8
+        }
+    }
+
+    /// The readable threshold mask, bit 0 to 5 for lower non-critical, lower critical, lower
+    /// non-recoverable, upper non-critical, upper critical and upper non-recoverable
+    fn readable_mask(&self) -> u8 {
+        self.thresholds
+            .in_mask_order()
+            .iter()
+            .enumerate()
+            .filter(|(_, threshold)| threshold.is_some())
+            .fold(0, |mask, (bit, _)| mask | 1 << bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temperature sensor read in whole degrees, laid out as in table 43-1 of the specification
+    const INLET_TEMP: [u8; 58] = [
+        0x01, 0x00, 0x51, 0x01, 0x35, 0x20, 0x00, 0x00, 0x37, 0x01, 0x45, 0x47, 0x01, 0x01, 0x00,
+        0x00, 0x00, 0x30, 0x18, 0x00, 0x80, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x7F, 0x80, 0x00, 0x55, 0x46, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0xCA, b'I', b'n', b'l', b'e', b't', b' ', b'T', b'e', b'm', b'p',
+    ];
+
+    fn inlet_temp() -> FullSensorRecord<'static> {
+        FullSensorRecord {
+            record_id: 1,
+            sensor_number: 0,
+            entity_id: entity::AIR_INLET,
+            entity_instance: 1,
+            sensor_type: sensor_type::TEMPERATURE,
+            base_unit: unit::DEGREES_C,
+            signed: true,
+            linearization: Linearization {
+                m: 1,
+                b: 0,
+                b_exponent: 0,
+                r_exponent: 0,
+            },
+            thresholds: RawThresholds {
+                upper_non_critical: Some(70),
+                upper_critical: Some(85),
+                ..RawThresholds::default()
+            },
+            name: "Inlet Temp",
+        }
+    }
+
+    /// A fan read in steps of 30 RPM with lower thresholds
+    const FAN_1: [u8; 53] = [
+        0x05, 0x00, 0x51, 0x01, 0x30, 0x20, 0x00, 0x04, 0x1D, 0x01, 0x45, 0x47, 0x04, 0x01, 0x00,
+        0x30, 0x00, 0x00, 0x03, 0x00, 0x00, 0x12, 0x00, 0x00, 0x1E, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x0F, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0xC5, b'F', b'a', b'n', b' ', b'1',
+    ];
+
+    fn fan_1() -> FullSensorRecord<'static> {
+        FullSensorRecord {
+            record_id: 5,
+            sensor_number: 4,
+            entity_id: entity::FAN,
+            entity_instance: 1,
+            sensor_type: sensor_type::FAN,
+            base_unit: unit::RPM,
+            signed: false,
+            linearization: Linearization {
+                m: 30,
+                b: 0,
+                b_exponent: 0,
+                r_exponent: 0,
+            },
+            thresholds: RawThresholds {
+                lower_critical: Some(10),
+                lower_non_critical: Some(15),
+                ..RawThresholds::default()
+            },
+            name: "Fan 1",
+        }
+    }
+
+    #[test]
+    fn encodes_fixtures() {
+        let mut buf = [0; MAX_RECORD_LEN];
+        assert_eq!(inlet_temp().encode(&mut buf), INLET_TEMP);
+        assert_eq!(fan_1().encode(&mut buf), FAN_1);
+    }
+
+    #[test]
+    fn decodes_fixtures() {
+        assert_eq!(
+            FullSensorRecord::decode(&INLET_TEMP),
+            Ok((inlet_temp(), INLET_TEMP.len()))
+        );
+        assert_eq!(FullSensorRecord::decode(&FAN_1), Ok((fan_1(), FAN_1.len())));
+    }
+
+    /// Records one after the other, as `ipmitool sdr dump` saves them
+    #[test]
+    fn decodes_an_sdr_dump() {
+        let mut data = [0; INLET_TEMP.len() + FAN_1.len()];
+        data[..INLET_TEMP.len()].copy_from_slice(&INLET_TEMP);
+        data[INLET_TEMP.len()..].copy_from_slice(&FAN_1);
+
+        let (record, len) = FullSensorRecord::decode(&data).unwrap();
+        assert_eq!(record, inlet_temp());
+        let (record, _) = FullSensorRecord::decode(&data[len..]).unwrap();
+        assert_eq!(record, fan_1());
+    }
+
+    #[test]
+    fn round_trips_linearization_extremes() {
+        let mut buf = [0; MAX_RECORD_LEN];
+        for (m, b) in [(-512, 511), (511, -512), (-1, -1), (500, -2)] {
+            for exponent in [-8, -3, 0, 7] {
+                let record = FullSensorRecord {
+                    linearization: Linearization {
+                        m,
+                        b,
+                        b_exponent: exponent,
+                        r_exponent: -exponent - 1,
+                    },
+                    ..fan_1()
+                };
+                let encoded = record.encode(&mut buf);
+                assert_eq!(
+                    FullSensorRecord::decode(encoded),
+                    Ok((record, encoded.len()))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_thresholds() {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let all = RawThresholds {
+            lower_non_recoverable: Some(1),
+            lower_critical: Some(2),
+            lower_non_critical: Some(3),
+            upper_non_critical: Some(4),
+            upper_critical: Some(5),
+            upper_non_recoverable: Some(6),
+        };
+        for thresholds in [RawThresholds::default(), all] {
+            let record = FullSensorRecord {
+                thresholds,
+                ..inlet_temp()
+            };
+            let encoded = record.encode(&mut buf);
+            assert_eq!(
+                FullSensorRecord::decode(encoded),
+                Ok((record, encoded.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn cuts_off_long_names() {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let record = FullSensorRecord {
+            name: "Power Supply Current",
+            ..fan_1()
+        };
+        let encoded = record.encode(&mut buf);
+        assert_eq!(encoded.len(), MAX_RECORD_LEN);
+        let (decoded, _) = FullSensorRecord::decode(encoded).unwrap();
+        assert_eq!(decoded.name, "Power Supply Cur");
+    }
+
+    #[test]
+    fn rejects_other_records() {
+        let mut data = INLET_TEMP;
+        assert_eq!(
+            FullSensorRecord::decode(&data[..4]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            FullSensorRecord::decode(&data[..57]),
+            Err(DecodeError::Truncated)
+        );
+
+        data[2] = 0x02;
+        assert_eq!(
+            FullSensorRecord::decode(&data),
+            Err(DecodeError::UnsupportedVersion(0x02))
+        );
+        data[2] = SDR_VERSION;
+        // A Compact Sensor Record
+        data[3] = 0x02;
+        assert_eq!(
+            FullSensorRecord::decode(&data),
+            Err(DecodeError::UnsupportedType(0x02))
+        );
+        data[3] = FULL_SENSOR_RECORD;
+        data[4] = 0x20;
+        assert_eq!(FullSensorRecord::decode(&data), Err(DecodeError::Truncated));
+        data[4] = 0x35;
+        data[50] = 0xB0;
+        assert_eq!(
+            FullSensorRecord::decode(&data),
+            Err(DecodeError::InvalidName)
+        );
+    }
+
+    #[test]
+    fn converts_readings() {
+        let temp = inlet_temp();
+        assert_eq!(temp.to_milli(25), 25_000);
+        assert_eq!(temp.to_milli(0xF6), -10_000);
+        assert_eq!(temp.to_raw(-10_000), 0xF6);
+        assert_eq!(temp.to_raw(200_000), 0x7F);
+        assert_eq!(temp.to_raw(-200_000), 0x80);
+
+        let fan = fan_1();
+        assert_eq!(fan.to_milli(0xFF), 7_650_000);
+        assert_eq!(fan.to_raw(1_500_000), 50);
+        // Rounded to the nearest step
+        assert_eq!(fan.to_raw(1_514_000), 50);
+        assert_eq!(fan.to_raw(1_516_000), 51);
+        assert_eq!(fan.to_raw(-1), 0);
+
+        // 20 mV steps from 1 V
+        let volts = Linearization {
+            m: 20,
+            b: 1,
+            b_exponent: 3,
+            r_exponent: -3,
+        };
+        assert_eq!(volts.to_milli(100), 3_000);
+        assert_eq!(volts.to_raw(3_000), 100);
+        assert_eq!(volts.to_raw(3_009), 100);
+        assert_eq!(volts.to_raw(3_011), 101);
+    }
+
+    /// How ipmitool reads a Full Sensor Record, ported from `struct sdr_record_full_sensor` and the
+    /// `__TO_M`, `__TO_B`, `__TO_B_EXP` and `__TO_R_EXP` macros of its `ipmi_sdr.h`, which read the
+    /// record as little endian words and byte swap them. Unlike the fixtures above this doesn't
+    /// follow our reading of table 43-1.
+    mod ipmitool {
+        extern crate std;
+
+        use std::string::String;
+
+        pub struct Full {
+            pub sensor_num: u8,
+            pub entity_id: u8,
+            pub entity_instance: u8,
+            pub sensor_type: u8,
+            /// `cmn.unit.analog`, 2 for two's complement
+            pub analog: u8,
+            pub unit_type_base: u8,
+            pub m: i32,
+            pub b: i32,
+            pub b_exp: i32,
+            pub r_exp: i32,
+            /// `mask.type.threshold.read`, UNR to LNC from bit 5 down
+            pub readable: u8,
+            /// `threshold.upper.non_recover` to `threshold.lower.non_critical`
+            pub thresholds: [u8; 6],
+            pub id_string: String,
+        }
+
+        /// `tos32`, sign extending a `bits` wide field
+        fn tos32(value: u32, bits: u32) -> i32 {
+            if value & 1 << (bits - 1) != 0 {
+                -((!value & ((1 << bits) - 1)) as i32 + 1)
+            } else {
+                value as i32
+            }
+        }
+
+        pub fn parse(record: &[u8]) -> Full {
+            let mtol = u16::from_le_bytes([record[24], record[25]]).swap_bytes() as u32;
+            let bacc =
+                u32::from_le_bytes([record[26], record[27], record[28], record[29]]).swap_bytes();
+            let id_len = (record[47] & 0x1f) as usize;
+            Full {
+                sensor_num: record[7],
+                entity_id: record[8],
+                entity_instance: record[9] & 0x7f,
+                sensor_type: record[12],
+                analog: record[20] >> 6,
+                unit_type_base: record[21],
+                m: tos32(((mtol & 0xff00) >> 8) | ((mtol & 0xc0) << 2), 10),
+                b: tos32(
+                    ((bacc & 0xff00_0000) >> 24) | ((bacc & 0xc0_0000) >> 14),
+                    10,
+                ),
+                b_exp: tos32(bacc & 0xf, 4),
+                r_exp: tos32((bacc & 0xf0) >> 4, 4),
+                readable: record[18] & 0x3f,
+                thresholds: record[36..42].try_into().unwrap(),
+                id_string: String::from_utf8_lossy(&record[48..48 + id_len]).into_owned(),
+            }
+        }
+
+        /// `sdr_convert_sensor_reading`, for unsigned and two's complement readings
+        pub fn convert(full: &Full, value: u8) -> f64 {
+            let value = if full.analog == 2 {
+                value as i8 as f64
+            } else {
+                value as f64
+            };
+            (full.m as f64 * value + full.b as f64 * 10f64.powi(full.b_exp))
+                * 10f64.powi(full.r_exp)
+        }
+    }
+
+    /// The records as ipmitool shows them, e.g. with `ipmitool sdr get`
+    #[test]
+    fn ipmitool_reads_the_records() {
+        let mut buf = [0; MAX_RECORD_LEN];
+
+        let temp = ipmitool::parse(inlet_temp().encode(&mut buf));
+        assert_eq!(temp.id_string, "Inlet Temp");
+        assert_eq!(temp.sensor_num, 0);
+        assert_eq!(
+            (temp.entity_id, temp.entity_instance),
+            (entity::AIR_INLET, 1)
+        );
+        assert_eq!(temp.sensor_type, sensor_type::TEMPERATURE);
+        assert_eq!(temp.unit_type_base, unit::DEGREES_C);
+        assert_eq!(ipmitool::convert(&temp, 25), 25.0);
+        assert_eq!(ipmitool::convert(&temp, 0xF6), -10.0);
+        // Upper non-critical and critical
+        assert_eq!(temp.readable, 0b01_1000);
+        assert_eq!(temp.thresholds[1..3], [85, 70]);
+
+        let fan = ipmitool::parse(fan_1().encode(&mut buf));
+        assert_eq!(fan.id_string, "Fan 1");
+        assert_eq!(fan.unit_type_base, unit::RPM);
+        assert_eq!(ipmitool::convert(&fan, 50), 1500.0);
+        // Lower critical and non-critical
+        assert_eq!(fan.readable, 0b00_0011);
+        assert_eq!(fan.thresholds[4..6], [10, 15]);
+
+        // Every bit of M, B and the exponents
+        for (m, b, b_exponent, r_exponent) in
+            [(-512, 511, -8, 7), (511, -512, 7, -8), (-3, 2, 1, -2)]
+        {
+            let linearization = Linearization {
+                m,
+                b,
+                b_exponent,
+                r_exponent,
+            };
+            let full = ipmitool::parse(
+                FullSensorRecord {
+                    linearization,
+                    ..fan_1()
+                }
+                .encode(&mut buf),
+            );
+            assert_eq!(
+                (full.m, full.b, full.b_exp, full.r_exp),
+                (m as i32, b as i32, b_exponent as i32, r_exponent as i32)
+            );
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Wall-clock time as the BMC and the host exchange it: seconds since the Unix epoch in UTC, shown
+//! and entered as ISO 8601 dates like `2026-10-19T12:34:56Z`.
+//!
+//! The calendar is the proleptic Gregorian one, converted with the algorithms of Howard Hinnant's
+//! "chrono-Compatible Low-Level Date Algorithms".
+
+use core::fmt;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+/// Days from 0000-03-01 to 1970-01-01
+const EPOCH_DAYS: i64 = 719_468;
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// A UTC date and time to the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i32,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_unix(seconds: i64) -> Self {
+        let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+        let time = seconds.rem_euclid(SECONDS_PER_DAY);
+        Self {
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+
+    /// Parses `YYYY-MM-DDTHH:MM:SS` with an optional `Z`, `None` if it isn't a valid date and time
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.strip_suffix('Z').unwrap_or(s);
+        let (date, time) = s.split_once('T')?;
+
+        let mut date = date.splitn(3, '-');
+        let year = number(date.next()?, 4)?;
+        let month = number(date.next()?, 2)? as u8;
+        let day = number(date.next()?, 2)? as u8;
+
+        let mut time = time.splitn(3, ':');
+        let hour = number(time.next()?, 2)? as u8;
+        let minute = number(time.next()?, 2)? as u8;
+        let second = number(time.next()?, 2)? as u8;
+
+        let valid = (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        valid.then_some(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for DateTime {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{=i32:04}-{=u8:02}-{=u8:02}T{=u8:02}:{=u8:02}:{=u8:02}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+/// A number of exactly `digits` decimal digits
+fn number(s: &str, digits: usize) -> Option<i32> {
+    if s.len() != digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+pub fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The days since 1970-01-01 of a date
+pub fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    // The year starts in March, so the leap day is the last day of the year
+    let year = year as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * DAYS_PER_ERA + day_of_era - EPOCH_DAYS
+}
+
+/// The date of a number of days since 1970-01-01, as year, month and day
+pub fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let days = days + EPOCH_DAYS;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days - era * DAYS_PER_ERA;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as i32, month as u8, day as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::format;
+
+    use super::*;
+
+    #[test]
+    fn days_of_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1970, 1, 2), 1);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(1900, 1, 1), -25_567);
+        assert_eq!(days_from_civil(0, 3, 1), -EPOCH_DAYS);
+        assert_eq!(days_from_civil(2000, 1, 1), 10_957);
+        assert_eq!(days_from_civil(2026, 10, 19), 20_745);
+    }
+
+    #[test]
+    fn dates_of_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(-25_567), (1900, 1, 1));
+        assert_eq!(civil_from_days(-EPOCH_DAYS - 1), (0, 2, 29));
+        assert_eq!(civil_from_days(-EPOCH_DAYS - 366), (-1, 3, 1));
+        assert_eq!(civil_from_days(20_745), (2026, 10, 19));
+    }
+
+    #[test]
+    fn leap_days() {
+        // 2000 is divisible by 400, so a leap year
+        assert!(is_leap_year(2000));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        // 2100 is divisible by 100 but not 400, so not
+        assert!(!is_leap_year(2100));
+        assert_eq!(days_in_month(2100, 2), 28);
+        let february_28 = days_from_civil(2100, 2, 28);
+        assert_eq!(civil_from_days(february_28 + 1), (2100, 3, 1));
+
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(2026));
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(
+            days_from_civil(2024, 3, 1) - days_from_civil(2024, 2, 28),
+            2
+        );
+        assert_eq!(
+            days_from_civil(2025, 1, 1) - days_from_civil(2024, 1, 1),
+            366
+        );
+        assert_eq!(
+            days_from_civil(2101, 1, 1) - days_from_civil(2100, 1, 1),
+            365
+        );
+    }
+
+    #[test]
+    fn days_round_trip() {
+        let mut last = civil_from_days(-800_001);
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+
+            // Each day follows the one before
+            let (last_year, last_month, last_day) = last;
+            if day == 1 {
+                assert_eq!(last_day, days_in_month(last_year, last_month));
+                if month == 1 {
+                    assert_eq!((last_year, last_month), (year - 1, 12));
+                } else {
+                    assert_eq!((last_year, last_month), (year, month - 1));
+                }
+            } else {
+                assert_eq!(last, (year, month, day - 1));
+            }
+            last = (year, month, day);
+        }
+    }
+
+    #[test]
+    fn unix_times() {
+        let time = DateTime::from_unix(1_000_000_000);
+        assert_eq!(
+            time,
+            DateTime {
+                year: 2001,
+                month: 9,
+                day: 9,
+                hour: 1,
+                minute: 46,
+                second: 40,
+            }
+        );
+        assert_eq!(time.to_unix(), 1_000_000_000);
+
+        let time = DateTime::from_unix(-1);
+        assert_eq!((time.year, time.month, time.day), (1969, 12, 31));
+        assert_eq!((time.hour, time.minute, time.second), (23, 59, 59));
+        assert_eq!(time.to_unix(), -1);
+
+        assert_eq!(DateTime::from_unix(0).to_unix(), 0);
+        assert_eq!(DateTime::from_unix(2_966_371_199).year, 2063);
+    }
+
+    #[test]
+    fn parses_dates() {
+        let time = DateTime::parse("2026-10-19T12:34:56Z").unwrap();
+        assert_eq!(
+            time,
+            DateTime {
+                year: 2026,
+                month: 10,
+                day: 19,
+                hour: 12,
+                minute: 34,
+                second: 56,
+            }
+        );
+        assert_eq!(DateTime::parse("2026-10-19T12:34:56"), Some(time));
+        assert_eq!(DateTime::parse(&format!("{}", time)), Some(time));
+        assert!(DateTime::parse("2024-02-29T00:00:00Z").is_some());
+        assert!(DateTime::parse("1970-01-01T00:00:00Z").is_some());
+        assert!(DateTime::parse("2026-12-31T23:59:59Z").is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for s in [
+            "",
+            "2026-10-19",
+            "2026-10-19 12:34:56",
+            "2026-02-29T00:00:00Z",
+            "2100-02-29T00:00:00Z",
+            "2026-04-31T00:00:00Z",
+            "2026-00-01T00:00:00Z",
+            "2026-13-01T00:00:00Z",
+            "2026-10-00T00:00:00Z",
+            "2026-10-19T24:00:00Z",
+            "2026-10-19T12:60:00Z",
+            "2026-10-19T12:34:60Z",
+            "2026-1-19T12:34:56Z",
+            "26-10-19T12:34:56Z",
+            "+026-10-19T12:34:56Z",
+            "2026-10-19T12:34",
+            "2026-10-19T12:34:56:00",
+            "2026-10-19T12:34:56ZZ",
+            "2026-10-19T12:34:5x",
+        ] {
+            assert_eq!(DateTime::parse(s), None, "{}", s);
+        }
+    }
+}
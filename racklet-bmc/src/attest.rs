@@ -0,0 +1,221 @@
+//! Attestation reports of the BMC, see [`bmc_proto::attestation`].
+//!
+//! `provision` gives the BMC its attestation secret and prints the public key to enroll, which
+//! `verify` checks saved reports against without the BMC.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bmc_proto::attestation::{self, Report};
+use sha2::{Digest, Sha256};
+
+use crate::rpc::{BmcError, Client};
+use crate::sign;
+
+/// Provisions a random attestation secret, which only the BMC keeps, and prints the public key
+pub fn provision(client: &mut Client) -> Result<()> {
+    client
+        .set_attestation_secret(random()?)
+        .context("provisioning the attestation secret")?;
+    println!("{}", sign::hex(&attestation_key(client)?));
+    Ok(())
+}
+
+pub fn key(client: &mut Client) -> Result<()> {
+    println!("{}", sign::hex(&attestation_key(client)?));
+    Ok(())
+}
+
+/// Prints a report of the BMC answering `nonce`, a random one if not given, and its boot log.
+/// The report is checked against the key the BMC gives, `verify` checks it against an enrolled
+/// one.
+pub fn report(client: &mut Client, nonce: Option<[u8; 32]>, output: Option<&Path>) -> Result<()> {
+    let nonce = match nonce {
+        Some(nonce) => nonce,
+        None => random()?,
+    };
+    let key = attestation_key(client)?;
+    let data = client.attest(nonce)?;
+    let report =
+        Report::decode(&data).map_err(|e| anyhow!("the BMC sent an invalid report: {:?}", e))?;
+    print(&report);
+    check(&data, &report, &key, Some(&nonce))?;
+
+    let mut digest = [0; 32];
+    for measurement in client.measurements()? {
+        println!(
+            "  {:16} {}",
+            measurement.kind.as_str(),
+            sign::hex(&measurement.digest)
+        );
+        digest = Sha256::digest(attestation::extend_input(&digest, &measurement)).into();
+    }
+    ensure!(
+        digest == report.boot_log_digest,
+        "the boot log doesn't match the digest in the report"
+    );
+
+    if let Some(output) = output {
+        fs::write(output, &data).with_context(|| format!("writing {}", output.display()))?;
+    }
+    Ok(())
+}
+
+/// Prints a saved report and checks it was signed with `key` in answer to `nonce`
+pub fn verify(path: &Path, key: &[u8; 32], nonce: Option<&[u8; 32]>) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let report = Report::decode(&data)
+        .map_err(|e| anyhow!("{} is not an attestation report: {:?}", path.display(), e))?;
+    print(&report);
+    check(&data, &report, key, nonce)
+}
+
+fn print(report: &Report) {
+    let unique_id: String = report
+        .unique_id
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    println!("unique ID         {}", unique_id);
+    println!(
+        "firmware          {}, security version {}, minimum {}",
+        report.version, report.security_version, report.min_security_version
+    );
+    println!("firmware SHA-256  {}", sign::hex(&report.firmware_sha256));
+    println!("nonce             {}", sign::hex(&report.nonce));
+    println!("boot log digest   {}", sign::hex(&report.boot_log_digest));
+}
+
+/// Checks the signature over the bytes as received, `data`, so that the bytes between the fields
+/// are covered as well as the decoded `report`
+fn check(data: &[u8], report: &Report, key: &[u8; 32], nonce: Option<&[u8; 32]>) -> Result<()> {
+    let key = salty::PublicKey::try_from(key).map_err(|_| anyhow!("invalid attestation key"))?;
+    let signature = salty::Signature::from(&report.signature);
+    ensure!(
+        key.verify(&data[..attestation::SIGNED_LEN], &signature)
+            .is_ok(),
+        "the report isn't signed with the attestation key"
+    );
+    if let Some(nonce) = nonce {
+        ensure!(report.nonce == *nonce, "the report answers another nonce");
+    }
+    println!("signature OK");
+    Ok(())
+}
+
+fn attestation_key(client: &mut Client) -> Result<[u8; 32]> {
+    match client.attestation_key() {
+        Err(e) if e.downcast_ref() == Some(&BmcError(bmc_proto::Error::NotFound)) => {
+            bail!("the BMC has no attestation secret yet, see `racklet-bmc attest provision`")
+        }
+        result => result,
+    }
+}
+
+fn random() -> Result<[u8; 32]> {
+    let mut bytes = [0; 32];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .context("reading /dev/urandom")?;
+    Ok(bytes)
+}
+
+/// Parses 64 hex digits, e.g. a nonce or an attestation key, optionally prefixed with `0x`
+pub fn parse_hex32(s: &str) -> Result<[u8; 32]> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    ensure!(
+        s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()),
+        "expected 64 hex digits"
+    );
+    let mut bytes = [0; 32];
+    for (byte, digits) in bytes.iter_mut().zip(s.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits)?, 16)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use bmc_proto::image::Version;
+
+    use super::*;
+    use crate::sign::tests::{dev_key, temp_dir};
+
+    const NONCE: [u8; 32] = [0x5A; 32];
+
+    /// A report answering [`NONCE`], signed with the development firmware key
+    fn signed_report() -> (Vec<u8>, [u8; 32]) {
+        let keypair = sign::read_key(&dev_key()).unwrap();
+        let mut report = Report {
+            unique_id: [1; 16],
+            nonce: NONCE,
+            firmware_sha256: [2; 32],
+            version: Version {
+                major: 0,
+                minor: 3,
+                patch: 1,
+            },
+            security_version: 2,
+            min_security_version: 1,
+            boot_log_digest: [3; 32],
+            signature: [0; 64],
+        };
+        report.signature = keypair.sign(&report.signed_bytes()).to_bytes();
+        (report.encode().to_vec(), keypair.public.to_bytes())
+    }
+
+    #[test]
+    fn verifies_a_signed_report() {
+        let (data, key) = signed_report();
+        let path = temp_dir("verify").join("report.bin");
+        fs::write(&path, &data).unwrap();
+        verify(&path, &key, Some(&NONCE)).unwrap();
+        verify(&path, &key, None).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_changed_report() {
+        let (data, key) = signed_report();
+        // Every byte but the magic and format version, which fail to decode instead, including
+        // the zeros between the fields
+        for i in 6..data.len() {
+            let mut data = data.clone();
+            data[i] ^= 0x01;
+            let report = Report::decode(&data).unwrap();
+            assert!(check(&data, &report, &key, None).is_err(), "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn refuses_other_keys_and_nonces() {
+        let (data, key) = signed_report();
+        let report = Report::decode(&data).unwrap();
+        let other = salty::Keypair::from(&[9; 32]).public.to_bytes();
+        assert!(check(&data, &report, &other, Some(&NONCE)).is_err());
+        assert!(check(&data, &report, &key, Some(&[0xA5; 32])).is_err());
+
+        let path = temp_dir("verify-other").join("report.bin");
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(verify(&path, &key, None).is_err());
+    }
+
+    #[test]
+    fn parses_hex() {
+        let digits = "00112233445566778899aabbccddeeffFFEEDDCCBBAA99887766554433221100";
+        let bytes = parse_hex32(digits).unwrap();
+        assert_eq!(bytes[..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(bytes[28..], [0x33, 0x22, 0x11, 0x00]);
+        assert_eq!(parse_hex32(&format!("0x{}", digits)).unwrap(), bytes);
+        assert_eq!(sign::hex(&bytes), digits.to_lowercase());
+
+        // Too short, too long, e.g. a larger number, and not hex at all
+        assert!(parse_hex32(&digits[..62]).is_err());
+        assert!(parse_hex32(&format!("{}00", digits)).is_err());
+        assert!(parse_hex32(&format!("0x0x{}", &digits[..62])).is_err());
+        assert!(parse_hex32(&format!("+f{}", &digits[2..])).is_err());
+        assert!(parse_hex32(&format!("{}é", &digits[..62])).is_err());
+        assert!(parse_hex32("").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Attaching the terminal to the BMC console.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+/// Ctrl-], like in telnet
+const ESCAPE: u8 = 0x1d;
+
+/// Passes the terminal through to the serial port at `path` until Ctrl-] is pressed
+pub fn attach(path: &str) -> Result<()> {
+    let mut port = serialport::new(path, 115_200)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .with_context(|| format!("opening {}", path))?;
+    let mut reader = port.try_clone()?;
+
+    eprintln!("Connected to {}, press Ctrl-] to exit", path);
+    let raw_mode = RawMode::enable()?;
+    let saved = raw_mode.saved;
+
+    thread::spawn(move || {
+        let mut stdout = io::stdout();
+        let mut buf = [0; 256];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(len) => {
+                    stdout.write_all(&buf[..len]).ok();
+                    stdout.flush().ok();
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => {
+                    // The main thread is blocked reading stdin, so exit from here
+                    restore(&saved);
+                    eprintln!("\r\nDisconnected: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    });
+
+    let mut stdin = io::stdin();
+    let mut buf = [0; 256];
+    loop {
+        let len = stdin.read(&mut buf)?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        let input = &buf[..len];
+        match input.iter().position(|&b| b == ESCAPE) {
+            Some(end) => {
+                port.write_all(&input[..end])?;
+                eprint!("\r\n");
+                return Ok(());
+            }
+            None => port.write_all(input)?,
+        }
+    }
+}
+
+/// Passes keypresses from the terminal through unchanged while alive
+struct RawMode {
+    saved: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        unsafe {
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error()).context("stdin is not a terminal");
+            }
+
+            let saved = termios;
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(Self { saved })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore(&self.saved);
+    }
+}
+
+fn restore(termios: &libc::termios) {
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+    }
+}
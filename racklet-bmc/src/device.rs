@@ -0,0 +1,71 @@
+//! Finding BMCs among the serial ports of the host.
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPortType;
+
+/// USB IDs of the BMC firmware looked for by default: the ID in `usb.toml` of the firmware, which
+/// is that of the dapboot bootloader, and the shared pid.codes test ID of earlier builds
+pub const USB_IDS: &[(u16, u16)] = &[(0x1209, 0xdb42), (0x16c0, 0x27dd)];
+
+/// The serial port of a BMC connected over USB
+pub struct Device {
+    pub port: String,
+    pub serial_number: Option<String>,
+    pub product: Option<String>,
+}
+
+/// Lists the serial ports with one of `usb_ids`
+pub fn discover(usb_ids: &[(u16, u16)]) -> Result<Vec<Device>> {
+    let devices = serialport::available_ports()?
+        .into_iter()
+        .filter_map(|port| match port.port_type {
+            SerialPortType::UsbPort(usb) if usb_ids.contains(&(usb.vid, usb.pid)) => Some(Device {
+                port: port.port_name,
+                serial_number: usb.serial_number,
+                product: usb.product,
+            }),
+            _ => None,
+        })
+        .collect();
+    Ok(devices)
+}
+
+/// Returns `port` if given, otherwise the port of the only BMC connected, or the only one with
+/// `serial_number`
+pub fn select(
+    port: Option<String>,
+    serial_number: Option<&str>,
+    usb_ids: &[(u16, u16)],
+) -> Result<String> {
+    if let Some(port) = port {
+        return Ok(port);
+    }
+
+    let mut devices: Vec<_> = discover(usb_ids)?
+        .into_iter()
+        .filter(|device| {
+            serial_number.is_none() || device.serial_number.as_deref() == serial_number
+        })
+        .collect();
+
+    match (devices.len(), serial_number) {
+        (1, _) => Ok(devices.remove(0).port),
+        (0, Some(serial_number)) => bail!("no BMC with serial number {} found", serial_number),
+        (0, None) => bail!("no BMC found, give its serial port with --port"),
+        _ => bail!(
+            "{} BMCs found, pick one with --serial or --port",
+            devices.len()
+        ),
+    }
+}
+
+/// Parses a USB ID given as `<vid>:<pid>` in hex, as printed by `lsusb`
+pub fn parse_usb_id(id: &str) -> Result<(u16, u16)> {
+    let (vid, pid) = id
+        .split_once(':')
+        .context("expected <vid>:<pid>, e.g. 1209:db42")?;
+    Ok((
+        u16::from_str_radix(vid, 16).context("invalid vendor ID")?,
+        u16::from_str_radix(pid, 16).context("invalid product ID")?,
+    ))
+}
@@ -0,0 +1,77 @@
+//! The keys the BMC trusts to sign images, see [`bmc_proto::keys`].
+//!
+//! Once the key store is locked, changes are signed with a trusted firmware key given with
+//! `--authorize`.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use bmc_proto::keys::{self, KeyChange, KeyRole};
+
+use crate::rpc::{BmcError, Client};
+use crate::sign;
+
+pub fn list(client: &mut Client) -> Result<()> {
+    for key in client.keys()? {
+        print!("{:10}  {}", key.role.as_str(), sign::hex(&key.key));
+        if key.built_in {
+            print!("  built-in");
+        }
+        if key.revoked {
+            print!("  revoked");
+        }
+        println!();
+    }
+    let (locked, generation) = client.key_store()?;
+    let state = if locked { "locked" } else { "unlocked" };
+    println!("{}, {} changes", state, generation);
+    Ok(())
+}
+
+/// Trusts the public key in `key` for `role`
+pub fn add(client: &mut Client, key: &Path, role: KeyRole, authorize: Option<&Path>) -> Result<()> {
+    let key = sign::read_public_key(key)?.to_bytes();
+    let signature = authorization(client, KeyChange::Add(role), &key, authorize)?;
+    client.add_key(key, role, signature)
+}
+
+/// Stops trusting the public key in `key`
+pub fn revoke(client: &mut Client, key: &Path, authorize: Option<&Path>) -> Result<()> {
+    let key = sign::read_public_key(key)?.to_bytes();
+    let signature = authorization(client, KeyChange::Revoke, &key, authorize)?;
+    client.revoke_key(key, signature)
+}
+
+/// Locks the key store, which the BMC refuses while it trusts the development key
+pub fn lock(client: &mut Client) -> Result<()> {
+    match client.lock_keys() {
+        Err(e) if e.downcast_ref() == Some(&BmcError(bmc_proto::Error::KeyRefused)) => bail!(
+            "the BMC still trusts the development key, revoke \
+             rtic-testing/keys/dev-firmware.pub.pem first"
+        ),
+        result => result,
+    }
+}
+
+/// Signs `change` with the private key in `authorize` if the key store is locked
+fn authorization(
+    client: &mut Client,
+    change: KeyChange,
+    key: &[u8; 32],
+    authorize: Option<&Path>,
+) -> Result<Option<[u8; 64]>> {
+    let (locked, generation) = client.key_store().context("reading the key store")?;
+    if !locked {
+        return Ok(None);
+    }
+    let Some(authorize) = authorize else {
+        bail!("the key store is locked, sign the change with a trusted firmware key using --authorize");
+    };
+    let keypair = sign::read_key(authorize)?;
+    let message = keys::change_message(change, key, generation);
+    Ok(Some(keypair.sign(&message).to_bytes()))
+}
+
+pub fn parse_role(role: &str) -> Result<KeyRole> {
+    KeyRole::parse(role).context("roles are firmware and boot-files")
+}
@@ -0,0 +1,68 @@
+//! Decoding of the defmt log frames the firmware emits when built with the `defmt` feature.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use defmt_decoder::{DecodeError, Table};
+
+pub enum Source {
+    Port(String),
+    File(PathBuf),
+    Stdin,
+}
+
+/// Decodes frames from `source` and prints them until the source is exhausted. Serial ports are
+/// read until interrupted.
+pub fn decode(elf: &Path, source: Source) -> Result<()> {
+    let elf_data = fs::read(elf).with_context(|| format!("reading {}", elf.display()))?;
+    let table = Table::parse(&elf_data)?
+        .ok_or_else(|| anyhow!("{} contains no defmt data", elf.display()))?;
+    let locations = table.get_locations(&elf_data)?;
+
+    let mut reader: Box<dyn Read> = match source {
+        Source::Port(port) => Box::new(
+            serialport::new(&port, 115_200)
+                .timeout(Duration::from_secs(1))
+                .open()
+                .with_context(|| format!("opening {}", port))?,
+        ),
+        Source::File(file) => {
+            Box::new(File::open(&file).with_context(|| format!("opening {}", file.display()))?)
+        }
+        Source::Stdin => Box::new(io::stdin()),
+    };
+
+    let mut decoder = table.new_stream_decoder();
+    let mut buf = [0; 1024];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        decoder.received(&buf[..len]);
+        loop {
+            match decoder.decode() {
+                Ok(frame) => {
+                    println!("{}", frame.display(true));
+                    if let Some(location) = locations.get(&frame.index()) {
+                        println!(
+                            "└─ {} @ {}:{}",
+                            location.module,
+                            location.file.display(),
+                            location.line
+                        );
+                    }
+                }
+                Err(DecodeError::UnexpectedEof) => break,
+                // The buffers on the BMC drop old data, so partial frames are expected
+                Err(DecodeError::Malformed) => eprintln!("(skipped malformed frame)"),
+            }
+        }
+    }
+}
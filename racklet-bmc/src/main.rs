@@ -0,0 +1,470 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use bmc_proto::image::{GitCommit, Version};
+use bmc_proto::keys::KeyRole;
+use bmc_proto::time::DateTime;
+use clap::{Args, Parser, Subcommand};
+
+use crate::rpc::{BmcError, Client};
+
+mod attest;
+mod console;
+mod device;
+mod keys;
+mod log;
+mod rpc;
+mod sdr;
+mod sign;
+mod update;
+
+/// Host companion tool for the Racklet BMC
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    target: Target,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Which BMC to talk to. Without either option the only BMC connected is used.
+#[derive(Args)]
+struct Target {
+    /// Serial port of the BMC console, e.g. /dev/ttyACM0 or the PTY of the simulated BMC
+    #[arg(long, conflicts_with = "serial")]
+    port: Option<String>,
+    /// USB serial number of the BMC
+    #[arg(long)]
+    serial: Option<String>,
+    /// USB ID (<vid>:<pid> in hex) of BMC firmware built or configured with its own IDs, can be
+    /// given more than once
+    #[arg(long = "usb-id", value_name = "VID:PID", value_parser = device::parse_usb_id)]
+    usb_ids: Vec<(u16, u16)>,
+    /// Don't set the clock of the BMC to the time of the host
+    #[arg(long)]
+    no_time_sync: bool,
+}
+
+impl Target {
+    /// The USB IDs BMCs are looked for with
+    fn usb_ids(&self) -> &[(u16, u16)] {
+        if self.usb_ids.is_empty() {
+            device::USB_IDS
+        } else {
+            &self.usb_ids
+        }
+    }
+
+    fn port(&self) -> Result<String> {
+        device::select(self.port.clone(), self.serial.as_deref(), self.usb_ids())
+    }
+
+    /// Connects to the BMC and syncs its clock, which has nothing else to keep it right. BMCs
+    /// without an RTC are left alone.
+    fn client(self) -> Result<Client> {
+        let (mut client, _) = Client::open(&self.port()?)?;
+        if !self.no_time_sync {
+            match client.sync_time() {
+                Err(e) if is_unsupported(&e) => {}
+                result => result.context("syncing the time")?,
+            }
+        }
+        Ok(client)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the BMCs connected over USB
+    List,
+    /// Print the firmware version of the BMC and the image it runs
+    Version,
+    /// Print the serial number, node name and MAC address of the BMC
+    Id,
+    /// Print the sensor readings of the BMC
+    Sensors,
+    /// Work with the clock of the BMC
+    #[command(subcommand)]
+    Time(TimeCommand),
+    /// Print the Sensor Data Records of the BMC
+    Sdr {
+        /// Save the records to this file instead, in the format of `ipmitool sdr dump` for
+        /// `ipmitool -S`
+        #[arg(long, value_name = "FILE")]
+        dump: Option<PathBuf>,
+    },
+    /// Control the status LED
+    #[command(subcommand)]
+    Led(LedCommand),
+    /// Control the power of the host
+    #[command(subcommand)]
+    Power(PowerCommand),
+    /// Work with the BMC log output
+    #[command(subcommand)]
+    Log(LogCommand),
+    /// Work with the settings stored on the BMC
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Program a UF2 firmware image through the GhostFat drive of the BMC and wait for the BMC to
+    /// install it
+    Update {
+        /// UF2 file to program
+        image: PathBuf,
+        /// Mount point of the GhostFat drive, found from the mounted drives if not given
+        #[arg(long)]
+        drive: Option<PathBuf>,
+    },
+    /// Sign a firmware ELF for the bootloader, as a UF2 file for `update` or a binary
+    Sign {
+        /// Firmware ELF file, linked for the slot it goes into
+        elf: PathBuf,
+        /// Ed25519 private key in PKCS#8 PEM format
+        #[arg(long)]
+        key: PathBuf,
+        /// Output file, a UF2 file if it ends in .uf2 and a binary to flash at the image address
+        /// otherwise
+        #[arg(short, long)]
+        output: PathBuf,
+        /// UF2 family ID in hex
+        #[arg(long, value_parser = sign::parse_family_id, default_value = "55114460")]
+        family: u32,
+        /// Version of the firmware, e.g. 1.2.3
+        #[arg(long, value_parser = sign::parse_version)]
+        firmware_version: Version,
+        /// Security version of the image, BMCs provisioned with a higher minimum reject it
+        #[arg(long, default_value_t = 0)]
+        security_version: u32,
+        /// Git commit the firmware was built from, the one checked out where the ELF is if not
+        /// given
+        #[arg(long, value_parser = sign::parse_git_commit)]
+        git_commit: Option<GitCommit>,
+    },
+    /// Print the header of a signed image and check its hash and signature
+    Inspect {
+        /// Image as a UF2 file or a binary starting with the header
+        image: PathBuf,
+        /// Ed25519 public key in PEM format to check the signature with
+        #[arg(long)]
+        key: Option<PathBuf>,
+    },
+    /// Work with the keys the BMC trusts to sign images
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Work with the attestation reports of the BMC
+    #[command(subcommand)]
+    Attest(AttestCommand),
+    /// Attach the terminal to the BMC console, Ctrl-] exits
+    Console,
+}
+
+#[derive(Subcommand)]
+enum AttestCommand {
+    /// Give the BMC a random attestation secret and print its attestation key, which is only
+    /// possible while the key store is unlocked
+    Provision,
+    /// Print the public key the BMC signs its reports with
+    Key,
+    /// Print a report of the BMC and its boot log
+    Report {
+        /// 64 hex digits, optionally prefixed with 0x, for the report to answer, random if not
+        /// given
+        #[arg(long, value_parser = attest::parse_hex32)]
+        nonce: Option<[u8; 32]>,
+        /// Save the report to this file for `attest verify`
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check a saved report offline
+    Verify {
+        /// Report saved by `attest report`
+        report: PathBuf,
+        /// Attestation key of the BMC, as printed by `attest provision`
+        #[arg(long, value_parser = attest::parse_hex32)]
+        device_key: [u8; 32],
+        /// Nonce the report has to answer
+        #[arg(long, value_parser = attest::parse_hex32)]
+        nonce: Option<[u8; 32]>,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Print the keys and whether the key store is locked
+    List,
+    /// Trust a key for signing images
+    Add {
+        /// Ed25519 public key in PEM format
+        key: PathBuf,
+        /// What the key signs: firmware or boot-files
+        #[arg(long, value_parser = keys::parse_role)]
+        role: KeyRole,
+        /// Trusted firmware private key signing the change once the key store is locked
+        #[arg(long, value_name = "KEY")]
+        authorize: Option<PathBuf>,
+    },
+    /// Stop trusting a key for good
+    Revoke {
+        /// Ed25519 public key in PEM format
+        key: PathBuf,
+        /// Trusted firmware private key signing the change once the key store is locked
+        #[arg(long, value_name = "KEY")]
+        authorize: Option<PathBuf>,
+    },
+    /// Lock the key store, after which changes have to be signed. This can't be undone, and
+    /// needs the development key revoked first.
+    Lock,
+}
+
+#[derive(Subcommand)]
+enum LedCommand {
+    /// Show a color instead of the BMC status
+    Set { r: u8, g: u8, b: u8 },
+    /// Show the BMC status again
+    Auto,
+}
+
+#[derive(Subcommand)]
+enum TimeCommand {
+    /// Print the time of the BMC and how far it is off the time of the host
+    Show,
+    /// Set the clock of the BMC to the time of the host, which the other commands do as well
+    Sync,
+}
+
+#[derive(Subcommand)]
+enum PowerCommand {
+    /// Print whether the host is powered on
+    Status,
+    On,
+    Off,
+    /// Pulse the reset line of the host
+    Reset {
+        /// How long to hold the host in reset, in milliseconds
+        #[arg(long, default_value_t = 100)]
+        duration: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum LogCommand {
+    /// Print the log output retained on the BMC, which needs the `ram-log` feature
+    Show,
+    /// Discard the log output retained on the BMC
+    Clear,
+    /// Decode defmt log frames using the string table of the firmware ELF
+    Decode {
+        /// Firmware ELF file the BMC is running
+        #[arg(long)]
+        elf: PathBuf,
+        /// Serial port to read the frames from, e.g. /dev/ttyACM0
+        #[arg(long, conflicts_with = "file")]
+        port: Option<String>,
+        /// File to read the frames from, e.g. LOG.BIN on the GhostFat drive of firmware built
+        /// with `ram-log`. Reads from stdin if neither this nor --port is given.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print all settings
+    List,
+    /// Print a setting
+    Get { key: String },
+    /// Change a setting
+    Set { key: String, value: String },
+    /// Remove a setting
+    Unset { key: String },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let target = cli.target;
+
+    match cli.command {
+        Command::List => {
+            for device in device::discover(target.usb_ids())? {
+                println!(
+                    "{}\t{}\t{}",
+                    device.port,
+                    device.serial_number.as_deref().unwrap_or("-"),
+                    device.product.as_deref().unwrap_or("-")
+                );
+            }
+            Ok(())
+        }
+        Command::Version => {
+            let (mut client, firmware) = Client::open(&target.port()?)?;
+            println!("{}", firmware);
+            match client.image() {
+                Ok(Some(image)) => {
+                    print!("image {}", image.version);
+                    if image.git_commit.is_known() {
+                        print!(", git {:.12}", image.git_commit);
+                    }
+                    println!(
+                        ", built {}, security version {}",
+                        DateTime::from_unix(image.build_time),
+                        image.security_version
+                    );
+                }
+                Ok(None) => println!("not started from a signed image"),
+                // Boards without the bootloader
+                Err(e) if is_unsupported(&e) => {}
+                Err(e) => return Err(e),
+            }
+            Ok(())
+        }
+        Command::Id => {
+            let identity = target.client()?.identity()?;
+            println!("serial number  {}", identity.serial_number);
+            println!("node name      {}", identity.node_name);
+            println!("MAC address    {}", identity.mac_address);
+            Ok(())
+        }
+        Command::Sensors => {
+            for sensor in target.client()?.sensors()? {
+                let (unit, status) = (sensor.unit_symbol(), sensor.status_name());
+                match sensor.value {
+                    Some(value) => println!(
+                        "{:16}  {:>9.3} {}  {}",
+                        sensor.name,
+                        value as f64 / 1000.0,
+                        unit,
+                        status
+                    ),
+                    None => println!("{:16}  {:>9} {}  {}", sensor.name, "-", unit, status),
+                }
+            }
+            Ok(())
+        }
+        Command::Time(TimeCommand::Show) => {
+            // Without syncing first, which would hide the offset
+            let (mut client, _) = Client::open(&target.port()?)?;
+            match client.time()? {
+                Some(time) => {
+                    let host = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+                    println!(
+                        "{} ({:+.1} s from the host)",
+                        DateTime::from_unix(time),
+                        time as f64 - host
+                    );
+                }
+                None => println!("not set"),
+            }
+            Ok(())
+        }
+        Command::Time(TimeCommand::Sync) => {
+            let mut client = Client::open(&target.port()?)?.0;
+            client.sync_time()
+        }
+        Command::Sdr { dump } => {
+            let records = target.client()?.sdr()?;
+            match dump {
+                Some(path) => sdr::dump(&records, &path),
+                None => sdr::print(&records),
+            }
+        }
+        Command::Led(LedCommand::Set { r, g, b }) => target.client()?.set_led(Some([r, g, b])),
+        Command::Led(LedCommand::Auto) => target.client()?.set_led(None),
+        Command::Power(PowerCommand::Status) => {
+            let on = target.client()?.power()?;
+            println!("{}", if on { "on" } else { "off" });
+            Ok(())
+        }
+        Command::Power(PowerCommand::On) => target.client()?.set_power(true),
+        Command::Power(PowerCommand::Off) => target.client()?.set_power(false),
+        Command::Power(PowerCommand::Reset { duration }) => {
+            let mut client = target.client()?;
+            client.set_reset(true)?;
+            thread::sleep(Duration::from_millis(duration));
+            client.set_reset(false)
+        }
+        Command::Log(LogCommand::Show) => {
+            io::stdout().write_all(&target.client()?.log()?)?;
+            Ok(())
+        }
+        Command::Log(LogCommand::Clear) => target.client()?.clear_log(),
+        Command::Log(LogCommand::Decode { elf, port, file }) => {
+            let source = match (port, file) {
+                (Some(port), _) => log::Source::Port(port),
+                (_, Some(file)) => log::Source::File(file),
+                _ => log::Source::Stdin,
+            };
+            log::decode(&elf, source)
+        }
+        Command::Config(ConfigCommand::List) => {
+            for (key, value) in target.client()?.config_entries()? {
+                println!("{}={}", key, value);
+            }
+            Ok(())
+        }
+        Command::Config(ConfigCommand::Get { key }) => match target.client()?.config(&key)? {
+            Some(value) => {
+                println!("{}", value);
+                Ok(())
+            }
+            None => bail!("{} is not set", key),
+        },
+        Command::Config(ConfigCommand::Set { key, value }) => target
+            .client()?
+            .set_config(&key, &value)
+            .with_context(|| format!("setting {}", key)),
+        Command::Config(ConfigCommand::Unset { key }) => target
+            .client()?
+            .unset_config(&key)
+            .with_context(|| format!("unsetting {}", key)),
+        Command::Update { image, drive } => {
+            // Looks the port up again after the BMC resets into the update
+            let connect = || Ok(Client::open(&target.port()?)?.0);
+            update::update(&image, drive, connect)
+        }
+        Command::Sign {
+            elf,
+            key,
+            output,
+            family,
+            firmware_version,
+            security_version,
+            git_commit,
+        } => {
+            let metadata = sign::Metadata {
+                version: firmware_version,
+                security_version,
+                git_commit,
+            };
+            sign::sign(&elf, &key, &output, family, metadata)
+        }
+        Command::Inspect { image, key } => sign::inspect(&image, key.as_deref()),
+        Command::Keys(KeysCommand::List) => keys::list(&mut target.client()?),
+        Command::Keys(KeysCommand::Add {
+            key,
+            role,
+            authorize,
+        }) => keys::add(&mut target.client()?, &key, role, authorize.as_deref()),
+        Command::Keys(KeysCommand::Revoke { key, authorize }) => {
+            keys::revoke(&mut target.client()?, &key, authorize.as_deref())
+        }
+        Command::Keys(KeysCommand::Lock) => keys::lock(&mut target.client()?),
+        Command::Attest(AttestCommand::Provision) => attest::provision(&mut target.client()?),
+        Command::Attest(AttestCommand::Key) => attest::key(&mut target.client()?),
+        Command::Attest(AttestCommand::Report { nonce, output }) => {
+            attest::report(&mut target.client()?, nonce, output.as_deref())
+        }
+        Command::Attest(AttestCommand::Verify {
+            report,
+            device_key,
+            nonce,
+        }) => attest::verify(&report, &device_key, nonce.as_ref()),
+        Command::Console => console::attach(&target.port()?),
+    }
+}
+
+fn is_unsupported(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<BmcError>() == Some(&BmcError(bmc_proto::Error::Unsupported))
+}
@@ -0,0 +1,443 @@
+//! Requests to the BMC using the binary protocol of [`bmc_proto`] on its console.
+
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bmc_proto::attestation::{Measurement, NONCE_LEN};
+use bmc_proto::frame::{self, FrameDecoder, MAX_FRAME_LEN};
+use bmc_proto::image::{ImageInfo, UpdateStatus};
+use bmc_proto::keys::{KeyEntry, KeyRole};
+use bmc_proto::{Error, Request, RequestBody, Response, ResponseBody, SensorStatus, Unit, VERSION};
+use serialport::{ClearBuffer, SerialPort};
+
+/// How long to wait for a response before sending the request again
+const TIMEOUT: Duration = Duration::from_secs(1);
+const ATTEMPTS: usize = 3;
+
+pub struct Client {
+    port: Box<dyn SerialPort>,
+    decoder: FrameDecoder,
+    seq: u16,
+}
+
+impl Client {
+    /// Opens the serial port, switches the console from the shell to the protocol and checks the
+    /// BMC speaks its version. Returns the client and the firmware version.
+    pub fn open(path: &str) -> Result<(Self, String)> {
+        let mut port = serialport::new(path, 115_200)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .with_context(|| format!("opening {}", path))?;
+
+        // The zero byte ends a frame left over from an interrupted session, the shell ignores it.
+        // Ctrl-C then clears the shell line for the `rpc` command. Whatever the console was in,
+        // the BMC is now waiting for a frame.
+        port.clear(ClearBuffer::Input)?;
+        port.write_all(b"\0\x03rpc\r")?;
+
+        let mut client = Self {
+            port,
+            decoder: FrameDecoder::new(),
+            seq: 0,
+        };
+        let firmware =
+            client.request(RequestBody::Hello { version: VERSION }, |body| match body {
+                ResponseBody::Hello { firmware, .. } => Some(firmware.to_owned()),
+                _ => None,
+            })?;
+        Ok((client, firmware))
+    }
+
+    pub fn power(&mut self) -> Result<bool> {
+        self.request(RequestBody::GetPower, |body| match body {
+            ResponseBody::Power(on) => Some(on),
+            _ => None,
+        })
+    }
+
+    pub fn set_power(&mut self, on: bool) -> Result<()> {
+        self.request_ok(RequestBody::SetPower(on))
+    }
+
+    pub fn set_reset(&mut self, asserted: bool) -> Result<()> {
+        self.request_ok(RequestBody::SetReset(asserted))
+    }
+
+    /// Shows `color` on the status LED, or the BMC status with `None`
+    pub fn set_led(&mut self, color: Option<[u8; 3]>) -> Result<()> {
+        self.request_ok(RequestBody::SetLed(color))
+    }
+
+    /// Returns the value of a setting, `None` if it isn't set
+    pub fn config(&mut self, key: &str) -> Result<Option<String>> {
+        let result = self.request(RequestBody::GetConfig(key), |body| match body {
+            ResponseBody::Config(value) => Some(value.to_owned()),
+            _ => None,
+        });
+        match result {
+            Err(e) if e.downcast_ref() == Some(&BmcError(Error::NotFound)) => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    pub fn set_config(&mut self, key: &str, value: &str) -> Result<()> {
+        self.request_ok(RequestBody::SetConfig { key, value })
+    }
+
+    pub fn unset_config(&mut self, key: &str) -> Result<()> {
+        self.request_ok(RequestBody::UnsetConfig(key))
+    }
+
+    /// Returns all settings as key-value pairs
+    pub fn config_entries(&mut self) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        for index in 0..=u8::MAX {
+            let entry = self.request(RequestBody::ConfigEntry(index), |body| match body {
+                ResponseBody::ConfigEntry(entry) => {
+                    Some(entry.map(|(key, value)| (key.to_owned(), value.to_owned())))
+                }
+                _ => None,
+            })?;
+            match entry {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the log output retained on the BMC
+    pub fn log(&mut self) -> Result<Vec<u8>> {
+        let mut log = Vec::new();
+        loop {
+            let offset = log.len() as u32;
+            let chunk = self.request(RequestBody::ReadLog { offset }, |body| match body {
+                ResponseBody::Log(chunk) => Some(chunk.to_vec()),
+                _ => None,
+            })?;
+            if chunk.is_empty() {
+                return Ok(log);
+            }
+            log.extend_from_slice(&chunk);
+        }
+    }
+
+    pub fn clear_log(&mut self) -> Result<()> {
+        self.request_ok(RequestBody::ClearLog)
+    }
+
+    pub fn identity(&mut self) -> Result<Identity> {
+        self.request(RequestBody::GetIdentity, |body| match body {
+            ResponseBody::Identity {
+                serial_number,
+                node_name,
+                mac_address,
+            } => Some(Identity {
+                serial_number: serial_number.to_owned(),
+                node_name: node_name.to_owned(),
+                mac_address: mac_address.map(|byte| format!("{:02x}", byte)).join(":"),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Returns the sensors of the BMC in the order of its sensor table
+    pub fn sensors(&mut self) -> Result<Vec<Sensor>> {
+        let mut sensors = Vec::new();
+        for index in 0..=u8::MAX {
+            let sensor = self.request(RequestBody::GetSensor(index), |body| match body {
+                ResponseBody::Sensor(sensor) => Some(sensor.map(|sensor| Sensor {
+                    name: sensor.name.to_owned(),
+                    unit: sensor.unit,
+                    value: sensor.value,
+                    status: sensor.status,
+                })),
+                _ => None,
+            })?;
+            match sensor {
+                Some(sensor) => sensors.push(sensor),
+                None => break,
+            }
+        }
+        Ok(sensors)
+    }
+
+    /// Returns the time of the BMC in seconds since the Unix epoch, `None` if it isn't set
+    pub fn time(&mut self) -> Result<Option<i64>> {
+        self.request(RequestBody::GetTime, |body| match body {
+            ResponseBody::Time(time) => Some(time),
+            _ => None,
+        })
+    }
+
+    /// Returns the header of the image the BMC runs, `None` if it wasn't started from one
+    pub fn image(&mut self) -> Result<Option<ImageInfo>> {
+        self.request(RequestBody::GetImage, |body| match body {
+            ResponseBody::Image(image) => Some(image),
+            _ => None,
+        })
+    }
+
+    /// Returns the minimum security version of images the BMC installs and what became of the
+    /// last update it received since it started
+    pub fn update_status(&mut self) -> Result<(u32, Option<UpdateStatus>)> {
+        self.request(RequestBody::GetUpdate, |body| match body {
+            ResponseBody::Update {
+                min_security_version,
+                last,
+            } => Some((min_security_version, last)),
+            _ => None,
+        })
+    }
+
+    /// Returns whether the key store of the BMC is locked and how many changes it has had
+    pub fn key_store(&mut self) -> Result<(bool, u32)> {
+        self.request(RequestBody::GetKeyStore, |body| match body {
+            ResponseBody::KeyStore { locked, generation } => Some((locked, generation)),
+            _ => None,
+        })
+    }
+
+    /// Returns the keys the BMC knows, the built-in one first
+    pub fn keys(&mut self) -> Result<Vec<KeyEntry>> {
+        let mut keys = Vec::new();
+        for index in 0..=u8::MAX {
+            let key = self.request(RequestBody::GetKey(index), |body| match body {
+                ResponseBody::Key(key) => Some(key),
+                _ => None,
+            })?;
+            match key {
+                Some(key) => keys.push(key),
+                None => break,
+            }
+        }
+        Ok(keys)
+    }
+
+    pub fn add_key(
+        &mut self,
+        key: [u8; 32],
+        role: KeyRole,
+        signature: Option<[u8; 64]>,
+    ) -> Result<()> {
+        self.request_ok(RequestBody::AddKey {
+            key,
+            role,
+            signature: signature.as_ref().map(|signature| &signature[..]),
+        })
+    }
+
+    pub fn revoke_key(&mut self, key: [u8; 32], signature: Option<[u8; 64]>) -> Result<()> {
+        self.request_ok(RequestBody::RevokeKey {
+            key,
+            signature: signature.as_ref().map(|signature| &signature[..]),
+        })
+    }
+
+    pub fn lock_keys(&mut self) -> Result<()> {
+        self.request_ok(RequestBody::LockKeys)
+    }
+
+    /// Returns an attestation report of the BMC answering `nonce`, as encoded
+    pub fn attest(&mut self, nonce: [u8; NONCE_LEN]) -> Result<Vec<u8>> {
+        self.request(RequestBody::Attest { nonce }, |body| match body {
+            ResponseBody::Attestation(report) => Some(report.to_vec()),
+            _ => None,
+        })
+    }
+
+    /// Returns the boot log of the BMC
+    pub fn measurements(&mut self) -> Result<Vec<Measurement>> {
+        let mut measurements = Vec::new();
+        for index in 0..=u8::MAX {
+            let measurement =
+                self.request(RequestBody::GetMeasurement(index), |body| match body {
+                    ResponseBody::Measurement(measurement) => Some(measurement),
+                    _ => None,
+                })?;
+            match measurement {
+                Some(measurement) => measurements.push(measurement),
+                None => break,
+            }
+        }
+        Ok(measurements)
+    }
+
+    pub fn attestation_key(&mut self) -> Result<[u8; 32]> {
+        self.request(RequestBody::GetAttestationKey, |body| match body {
+            ResponseBody::AttestationKey(key) => Some(key),
+            _ => None,
+        })
+    }
+
+    pub fn set_attestation_secret(&mut self, secret: [u8; 32]) -> Result<()> {
+        self.request_ok(RequestBody::SetAttestationSecret(secret))
+    }
+
+    /// Sets the clock of the BMC to the time of the host, rounded to the second
+    pub fn sync_time(&mut self) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("the host clock is before 1970")?;
+        let seconds = (now + Duration::from_millis(500)).as_secs() as i64;
+        self.request_ok(RequestBody::SetTime(seconds))
+    }
+
+    /// Returns the Sensor Data Records of the BMC as encoded, in the order of their record IDs
+    pub fn sdr(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut records = Vec::new();
+        for record_id in 0..=u16::MAX {
+            let record = self.request(RequestBody::GetSdr(record_id), |body| match body {
+                ResponseBody::Sdr(record) => Some(record.map(<[u8]>::to_vec)),
+                _ => None,
+            })?;
+            match record {
+                Some(record) => records.push(record),
+                None => break,
+            }
+        }
+        Ok(records)
+    }
+
+    fn request_ok(&mut self, body: RequestBody) -> Result<()> {
+        self.request(body, |body| match body {
+            ResponseBody::Ok => Some(()),
+            _ => None,
+        })
+    }
+
+    /// Sends a request until the BMC responds and returns what `parse` makes of the response.
+    /// `parse` returns `None` for responses that don't fit the request, error responses are
+    /// returned as [`BmcError`].
+    fn request<T>(
+        &mut self,
+        body: RequestBody,
+        parse: impl FnOnce(ResponseBody) -> Option<T>,
+    ) -> Result<T> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(&Request { seq, body }, &mut buf)
+            .map_err(|e| anyhow!("encoding the request failed: {:?}", e))?;
+
+        for _ in 0..ATTEMPTS {
+            // Starting with a delimiter drops anything the BMC has collected before the frame
+            self.port.write_all(&[0])?;
+            self.port.write_all(frame)?;
+
+            if let Some(message) = self.receive(seq)? {
+                // Only messages which decode are returned
+                return match frame::decode::<Response>(&message).unwrap().body {
+                    ResponseBody::Error(e) => Err(BmcError(e).into()),
+                    body => parse(body).ok_or_else(|| anyhow!("unexpected response from the BMC")),
+                };
+            }
+        }
+
+        bail!("the BMC did not respond")
+    }
+
+    /// Waits for the response to the request `seq` and returns its encoding, `None` on timeout
+    fn receive(&mut self, seq: u16) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut byte = [0];
+        while Instant::now() < deadline {
+            match self.port.read(&mut byte) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            // Responses to earlier attempts and shell output before the switch are skipped
+            if let Some(Ok(message)) = self.decoder.push(byte[0]) {
+                if frame::decode::<Response>(message).is_ok_and(|response| response.seq == seq) {
+                    return Ok(Some(message.to_vec()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Returns the console to the shell for the next user
+        self.request_ok(RequestBody::Exit).ok();
+    }
+}
+
+/// A sensor of the BMC with its latest reading
+pub struct Sensor {
+    pub name: String,
+    pub unit: Unit,
+    /// In thousandths of the unit, `None` while the sensor is unavailable
+    pub value: Option<i32>,
+    pub status: SensorStatus,
+}
+
+impl Sensor {
+    pub fn unit_symbol(&self) -> &'static str {
+        match self.unit {
+            Unit::Celsius => "C",
+            Unit::Volts => "V",
+            Unit::Amps => "A",
+            Unit::Rpm => "RPM",
+        }
+    }
+
+    pub fn status_name(&self) -> &'static str {
+        match self.status {
+            SensorStatus::Ok => "ok",
+            SensorStatus::LowerNonCritical => "low",
+            SensorStatus::UpperNonCritical => "high",
+            SensorStatus::LowerCritical => "critical low",
+            SensorStatus::UpperCritical => "critical high",
+            SensorStatus::Unavailable => "unavailable",
+        }
+    }
+}
+
+/// The identity the BMC derives from the unique ID of its MCU
+pub struct Identity {
+    /// Also the USB serial number for `--serial`
+    pub serial_number: String,
+    pub node_name: String,
+    /// In the usual colon separated hex notation
+    pub mac_address: String,
+}
+
+/// An error response from the BMC
+#[derive(Debug, PartialEq, Eq)]
+pub struct BmcError(pub Error);
+
+impl fmt::Display for BmcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.0 {
+            Error::InvalidRequest => "the BMC didn't understand the request",
+            Error::UnsupportedVersion => "the BMC firmware speaks another protocol version",
+            Error::NotFound => "not set",
+            Error::InvalidKey => "keys are 1-16 printable characters without =",
+            Error::InvalidValue => "values are up to 32 printable characters",
+            Error::Full => "all settings or key slots are in use",
+            Error::Flash => "saving to flash failed",
+            Error::Unsupported => "the BMC firmware was built without support for this",
+            Error::OutOfRange => "the value is out of the range the BMC supports",
+            Error::Unauthorized => {
+                "the key store is locked, a trusted firmware key has to sign this"
+            }
+            Error::KeyRefused => {
+                "the key is revoked or the last trusted firmware key, or the development key is \
+                 still trusted"
+            }
+            Error::Corrupt => {
+                "the security page of the BMC is corrupt, reflash it with a debug probe"
+            }
+            Error::Crypto => "the hash peripheral of the BMC timed out",
+        })
+    }
+}
+
+impl std::error::Error for BmcError {}
@@ -0,0 +1,81 @@
+//! Printing and saving the Sensor Data Records of the BMC.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use bmc_proto::sdr::{entity, sensor_type, unit, FullSensorRecord};
+
+/// Prints a line per record with its sensor number, type, entity, linearization and thresholds
+pub fn print(records: &[Vec<u8>]) -> Result<()> {
+    for encoded in records {
+        let (record, _) = FullSensorRecord::decode(encoded)
+            .map_err(|e| anyhow!("the BMC sent an invalid record: {:?}", e))?;
+        let l = record.linearization;
+        print!(
+            "{:16}  {:3}  {:11}  {:>13}.{}  {:9}  M {} B {} Bexp {} Rexp {}",
+            record.name,
+            record.sensor_number,
+            type_name(record.sensor_type),
+            entity_name(record.entity_id),
+            record.entity_instance,
+            unit_name(record.base_unit),
+            l.m,
+            l.b,
+            l.b_exponent,
+            l.r_exponent
+        );
+
+        let t = record.thresholds;
+        let thresholds = [
+            ("lnr", t.lower_non_recoverable),
+            ("lc", t.lower_critical),
+            ("lnc", t.lower_non_critical),
+            ("unc", t.upper_non_critical),
+            ("uc", t.upper_critical),
+            ("unr", t.upper_non_recoverable),
+        ];
+        for (name, raw) in thresholds {
+            if let Some(raw) = raw {
+                print!("  {} {:.3}", name, record.to_milli(raw) as f64 / 1000.0);
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Saves the records one after the other as `ipmitool sdr dump` does, for `ipmitool -S`
+pub fn dump(records: &[Vec<u8>], path: &Path) -> Result<()> {
+    fs::write(path, records.concat()).with_context(|| format!("writing {}", path.display()))
+}
+
+fn type_name(code: u8) -> &'static str {
+    match code {
+        sensor_type::TEMPERATURE => "Temperature",
+        sensor_type::VOLTAGE => "Voltage",
+        sensor_type::CURRENT => "Current",
+        sensor_type::FAN => "Fan",
+        _ => "Other",
+    }
+}
+
+fn entity_name(id: u8) -> &'static str {
+    match id {
+        entity::SYSTEM_BOARD => "System Board",
+        entity::POWER_SUPPLY => "Power Supply",
+        entity::FAN => "Fan Device",
+        entity::AIR_INLET => "Air Inlet",
+        _ => "Other",
+    }
+}
+
+fn unit_name(code: u8) -> &'static str {
+    match code {
+        unit::DEGREES_C => "degrees C",
+        unit::VOLTS => "Volts",
+        unit::AMPS => "Amps",
+        unit::RPM => "RPM",
+        _ => "other",
+    }
+}
@@ -0,0 +1,171 @@
+//! Firmware updates through the GhostFat drive of the BMC, which programs UF2 files copied onto it.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context, Result};
+use bmc_proto::image::{ImageHeader, UpdateStatus};
+
+use crate::rpc::{BmcError, Client};
+use crate::{is_unsupported, sign};
+
+/// `INFO_UF2.TXT` on the drive names the model, which tells the BMC apart from other UF2 drives
+const INFO_FILE: &str = "INFO_UF2.TXT";
+const MODEL: &str = "Model: Racklet BMC";
+
+/// How long the BMC gets to check the update, reset and start it
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Copies the UF2 file at `image` onto the GhostFat drive mounted at `drive`, or the only BMC
+/// drive mounted if not given, and waits for the BMC to install it. `connect` opens the console
+/// of the BMC, which reports whether it does.
+///
+/// Images below the minimum security version of the BMC aren't copied at all. BMCs without the
+/// bootloader program the file as it is and report nothing.
+pub fn update(
+    image: &Path,
+    drive: Option<PathBuf>,
+    connect: impl Fn() -> Result<Client>,
+) -> Result<()> {
+    let data = fs::read(image).with_context(|| format!("reading {}", image.display()))?;
+    let (_, contents) =
+        sign::read_uf2(&data).with_context(|| format!("reading {}", image.display()))?;
+    let header = ImageHeader::decode(&contents).ok();
+
+    let drive = match drive {
+        Some(drive) => drive,
+        None => find_drive()?,
+    };
+    let name = image.file_name().context("the image has no file name")?;
+    let target = drive.join(name);
+
+    let mut client = connect()?;
+    let minimum = match client.update_status() {
+        Ok((minimum, _)) => Some(minimum),
+        Err(e) if is_unsupported(&e) => None,
+        Err(e) => return Err(e),
+    };
+    if let (Some(header), Some(minimum)) = (&header, minimum) {
+        ensure!(
+            header.security_version >= minimum,
+            "the image has security version {}, the BMC only installs {} and up",
+            header.security_version,
+            minimum
+        );
+    }
+
+    eprintln!("Copying {} to {}", image.display(), target.display());
+    fs::copy(image, &target).with_context(|| format!("copying to {}", target.display()))?;
+    // The BMC only sees the blocks once they have been written out
+    File::open(&target)?.sync_all()?;
+    if minimum.is_none() {
+        eprintln!("Update written");
+        return Ok(());
+    }
+    eprintln!("Update written, waiting for the BMC to install it");
+    wait(client, header.as_ref(), connect)
+}
+
+/// Waits for the BMC to check the update and, as it resets right after scheduling it, to start it
+fn wait(
+    mut client: Client,
+    header: Option<&ImageHeader>,
+    connect: impl Fn() -> Result<Client>,
+) -> Result<()> {
+    let deadline = Instant::now() + INSTALL_TIMEOUT;
+    loop {
+        match client.update_status() {
+            Ok((_, Some(UpdateStatus::Rejected(rejection)))) => {
+                bail!("the BMC rejected the update: {}", rejection)
+            }
+            // Seen when the BMC doesn't reset into it, e.g. the simulated one
+            Ok((_, Some(UpdateStatus::Scheduled))) => {
+                eprintln!("Update installed, the BMC starts it on trial at the next reset");
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) if e.downcast_ref::<BmcError>().is_some() => return Err(e),
+            // The session ended with the reset
+            Err(_) => break,
+        }
+        ensure!(
+            Instant::now() < deadline,
+            "the BMC didn't install the update in time"
+        );
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    drop(client);
+    eprintln!("The BMC reset, waiting for it to start the update");
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if let Ok(mut client) = connect() {
+            let running = client.image()?;
+            return match (running, header) {
+                (Some(running), Some(header)) if running.sha256 != header.sha256 => {
+                    bail!("the BMC runs another image, its bootloader didn't start the update")
+                }
+                _ => {
+                    eprintln!("Update running on trial");
+                    Ok(())
+                }
+            };
+        }
+        ensure!(
+            Instant::now() < deadline,
+            "the BMC didn't come back after resetting into the update"
+        );
+    }
+}
+
+/// Finds the mounted drive whose `INFO_UF2.TXT` names the BMC
+fn find_drive() -> Result<PathBuf> {
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    let mut drives: Vec<_> = mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(unescape_mount_path)
+        .filter(|path| is_bmc_drive(path).unwrap_or(false))
+        .collect();
+
+    match drives.len() {
+        1 => Ok(drives.remove(0)),
+        0 => bail!("no BMC drive is mounted, give its mount point with --drive"),
+        _ => bail!(
+            "{} BMC drives are mounted, pick one with --drive",
+            drives.len()
+        ),
+    }
+}
+
+fn is_bmc_drive(path: &Path) -> io::Result<bool> {
+    let info = fs::read_to_string(path.join(INFO_FILE))?;
+    Ok(info.lines().any(|line| line == MODEL))
+}
+
+/// `/proc/mounts` escapes spaces and other special characters as octal, e.g. `\040`
+fn unescape_mount_path(path: &str) -> PathBuf {
+    let mut unescaped = Vec::new();
+    let mut bytes = path.as_bytes();
+    while let Some((&b, rest)) = bytes.split_first() {
+        let octal = rest
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (b, octal) {
+            (b'\\', Some(c)) => {
+                unescaped.push(c);
+                bytes = &rest[3..];
+            }
+            _ => {
+                unescaped.push(b);
+                bytes = rest;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&unescaped).into_owned())
+}
@@ -0,0 +1,192 @@
+use std::{
+    convert::TryInto,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use base64::prelude::*;
+use serde::Deserialize;
+
+/// The USB identity compiled into the firmware unless `BMC_USB_CONFIG` names another file
+const DEFAULT_USB_CONFIG: &str = "usb.toml";
+
+/// The built-in firmware signing key, unless `BMC_FIRMWARE_KEY` names another file
+const DEFAULT_FIRMWARE_KEY: &str = "keys/dev-firmware.pub.pem";
+
+/// The DER encoding of an Ed25519 public key up to the key itself (RFC 8410)
+const ED25519_PUBLIC_KEY_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// The linker memory layouts of a board, in `memory/`
+struct Layouts {
+    feature: &'static str,
+    /// Of the firmware linked for slot A and B
+    firmware: [&'static str; 2],
+    bootloader: Option<&'static str>,
+    /// Of the examples and other binaries, which run without the bootloader
+    standalone: &'static str,
+}
+
+const BOARDS: &[Layouts] = &[
+    Layouts {
+        feature: "CARGO_FEATURE_BOARD_ITSYBITSY_M4",
+        firmware: ["itsybitsy_m4_slot_a.x", "itsybitsy_m4_slot_b.x"],
+        bootloader: Some("itsybitsy_m4_bootloader.x"),
+        standalone: "itsybitsy_m4.x",
+    },
+    // The Blue Pill has no room for a bootloader
+    Layouts {
+        feature: "CARGO_FEATURE_BOARD_BLUEPILL",
+        firmware: ["bluepill.x", "bluepill.x"],
+        bootloader: None,
+        standalone: "bluepill.x",
+    },
+];
+
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    if let Some(layouts) = BOARDS
+        .iter()
+        .find(|layouts| env::var_os(layouts.feature).is_some())
+    {
+        // cortex-m-rt includes memory.x from the linker search path, where the board crates put
+        // their own layouts too. The one searched first includes the layout of the binary being
+        // linked, which is found in the directory added for it.
+        fs::write(out.join("memory.x"), "INCLUDE layout.x\n").unwrap();
+        println!("cargo:rustc-link-search={}", out.display());
+
+        let slot = env::var_os("CARGO_FEATURE_SLOT_B").is_some() as usize;
+        let firmware = link_layout(&out, "firmware", layouts.firmware[slot]);
+        println!(
+            "cargo:rustc-link-arg-bin=rtic-testing=-L{}",
+            firmware.display()
+        );
+        if let Some(file) = layouts.bootloader {
+            let bootloader = link_layout(&out, "bootloader", file);
+            println!(
+                "cargo:rustc-link-arg-bin=bootloader=-L{}",
+                bootloader.display()
+            );
+        }
+        let standalone = link_layout(&out, "standalone", layouts.standalone);
+        println!(
+            "cargo:rustc-link-arg-bin=usb_led=-L{}",
+            standalone.display()
+        );
+        println!("cargo:rustc-link-arg-examples=-L{}", standalone.display());
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+
+    write_usb_identity(&out);
+    write_firmware_key(&out);
+
+    // The defmt string table is placed by its own linker script
+    if env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        println!("cargo:rustc-link-arg=-Tdefmt.x");
+    }
+}
+
+/// Puts the layout in `memory/<file>` into a directory of its own as layout.x
+fn link_layout(out: &Path, name: &str, file: &str) -> PathBuf {
+    let dir = out.join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let memory = PathBuf::from("memory").join(file);
+    fs::copy(&memory, dir.join("layout.x")).unwrap();
+    println!("cargo:rerun-if-changed={}", memory.display());
+    dir
+}
+
+/// The format of `usb.toml`, see the comments there
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsbConfig {
+    vid: u16,
+    pid: u16,
+    manufacturer: String,
+    product: String,
+    scsi: ScsiConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScsiConfig {
+    vendor: String,
+    product: String,
+    revision: String,
+}
+
+/// Turns the USB configuration into constants for `src/usb_identity.rs`
+fn write_usb_identity(out: &Path) {
+    println!("cargo:rerun-if-env-changed=BMC_USB_CONFIG");
+    let path = env::var("BMC_USB_CONFIG").unwrap_or_else(|_| DEFAULT_USB_CONFIG.to_owned());
+    println!("cargo:rerun-if-changed={}", path);
+
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("reading the USB configuration {} failed: {}", path, e));
+    let config: UsbConfig = toml::from_str(&contents)
+        .unwrap_or_else(|e| panic!("invalid USB configuration {}: {}", path, e));
+
+    // The same limits as for the overrides in the config store
+    let strings = [
+        ("MANUFACTURER", "manufacturer", &config.manufacturer, 32),
+        ("PRODUCT", "product", &config.product, 32),
+        ("SCSI_VENDOR", "scsi.vendor", &config.scsi.vendor, 8),
+        ("SCSI_PRODUCT", "scsi.product", &config.scsi.product, 16),
+        ("SCSI_REVISION", "scsi.revision", &config.scsi.revision, 4),
+    ];
+
+    let mut code = format!(
+        "pub const VID: u16 = {:#06x};\npub const PID: u16 = {:#06x};\n",
+        config.vid, config.pid
+    );
+    for (name, key, value, max_len) in strings {
+        if value.len() > max_len || !value.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+            panic!(
+                "{} in {} must be up to {} printable ASCII characters",
+                key, path, max_len
+            );
+        }
+        code += &format!("pub const {}: &str = {:?};\n", name, value);
+    }
+    fs::write(out.join("usb_identity.rs"), code).unwrap();
+}
+
+/// Turns the PEM encoded Ed25519 public key into a constant for `src/keys.rs`, together with the
+/// development key, which the key store can't be locked trusting
+fn write_firmware_key(out: &Path) {
+    println!("cargo:rerun-if-env-changed=BMC_FIRMWARE_KEY");
+    let path = env::var("BMC_FIRMWARE_KEY").unwrap_or_else(|_| {
+        println!(
+            "cargo:warning=BMC_FIRMWARE_KEY is not set, the firmware trusts the development key \
+             {}, whose private key is public",
+            DEFAULT_FIRMWARE_KEY
+        );
+        DEFAULT_FIRMWARE_KEY.to_owned()
+    });
+    println!("cargo:rerun-if-changed={}", DEFAULT_FIRMWARE_KEY);
+    println!("cargo:rerun-if-changed={}", path);
+
+    let code = format!(
+        "pub const FIRMWARE_KEY: [u8; 32] = {:?};\npub const DEV_FIRMWARE_KEY: [u8; 32] = {:?};\n",
+        read_public_key(&path),
+        read_public_key(DEFAULT_FIRMWARE_KEY)
+    );
+    fs::write(out.join("firmware_key.rs"), code).unwrap();
+}
+
+/// The Ed25519 public key in the PEM file at `path`
+fn read_public_key(path: &str) -> [u8; 32] {
+    let pem = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("reading the firmware key {} failed: {}", path, e));
+    let base64: String = pem
+        .lines()
+        .skip_while(|line| *line != "-----BEGIN PUBLIC KEY-----")
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .collect();
+    let der = BASE64_STANDARD.decode(base64).unwrap_or_default();
+    der.strip_prefix(&ED25519_PUBLIC_KEY_PREFIX[..])
+        .and_then(|key| key.try_into().ok())
+        .unwrap_or_else(|| panic!("{} is not a PEM encoded Ed25519 public key", path))
+}
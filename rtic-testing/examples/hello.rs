@@ -3,22 +3,21 @@
 
 use atsamd_hal::common::timer::SpinTimer;
 use cortex_m::peripheral::Peripherals as CMP;
-use cortex_m::peripheral::SYST;
 use itsybitsy_m4::clock::GenericClockController;
 use itsybitsy_m4::delay::Delay;
 use itsybitsy_m4::pac::Peripherals;
 use itsybitsy_m4::prelude::*;
 use itsybitsy_m4::{dotstar_bitbang, entry};
 use panic_probe as _;
-use rtt_target::{rprintln, rtt_init_print};
+use rtic_testing::logging::{self, info, LevelFilter};
 use smart_leds::SmartLedsWrite;
 use smart_leds::RGB8;
 
 #[entry]
 fn main() -> ! {
-    rtt_init_print!(); // Initialize RTT I/O for printing and backtraces
+    logging::init(LevelFilter::Info, &[]); // Initialize RTT I/O (by default) for printing
 
-    let mut cmp = CMP::take().unwrap();
+    let cmp = CMP::take().unwrap();
     let mut peripherals = Peripherals::take().unwrap();
     let mut clocks = GenericClockController::with_internal_32kosc(
         peripherals.GCLK,
@@ -41,11 +40,11 @@ fn main() -> ! {
     let off: [RGB8; 1] = [RGB8 { r: 0, g: 0, b: 0 }];
     let on: [RGB8; 1] = [RGB8 { r: 1, g: 1, b: 1 }]; // Goes up to 255, but honestly this is bright enough
 
-    let a: Option<u8> = None;
+    let _a: Option<u8> = None;
 
     loop {
-        // a.unwrap(); // Uncomment to test stack backtrace output
-        rprintln!("Hello, world!");
+        // _a.unwrap(); // Uncomment to test stack backtrace output
+        info!("Hello, world!");
         rgb.write(on.iter().cloned()).unwrap();
         delay.delay_ms(500u32);
         rgb.write(off.iter().cloned()).unwrap();
@@ -2,43 +2,36 @@
 #![no_main]
 #![no_std]
 
-use atsamd_hal::target_device::Interrupt;
-use itsybitsy_m4::timer::TimerCounter3;
-use itsybitsy_m4::{
-    clock::GenericClockController,
-    dotstar_bitbang,
-    gpio::v2::PA22,
-    gpio::{Input, Output, Pa27, Pb2, Pb3, Pin, PullUp, PushPull},
-    prelude::*, // Required for for example timer.start()
-    timer::{SpinTimer, TimerCounter, TimerCounter2},
-};
 use panic_halt as _;
-use smart_leds::{SmartLedsWrite, RGB8};
-// use itsybitsy_m4::pac::Interrupt;
-
-// I don't see a way to avoid writing this out since the Resources struct in an rtic app cannot
-// be monomorphized (no generics) and we don't have an allocator to use Box<dyn SmartLedsWrite>.
-// type DotStar = apa102_spi::Apa102<
-//     bitbang_hal::spi::SPI<Pa27<Input<PullUp>>, Pb3<Output<PushPull>>, Pb2<Output<PushPull>>, SpinTimer>>;
-type DotStar = apa102_spi::Apa102<
-    bitbang_hal::spi::SPI<
-        Pa27<Input<PullUp>>,
-        Pb3<Output<PushPull>>,
-        Pb2<Output<PushPull>>,
-        TimerCounter3,
-    >,
->;
-
-#[rtic::app(device = itsybitsy_m4::pac, peripherals = true)]
-const APP: () = {
-    struct Resources {
-        timer: TimerCounter2,
-        // led: DotStar,
-        led: Pin<PA22, Output<PushPull>>,
+
+#[rtic::app(device = itsybitsy_m4::pac, peripherals = true, dispatchers = [EVSYS_0])]
+mod app {
+    use itsybitsy_m4::clock::{ClockGenId, GenericClockController};
+    use rtic_testing::board::itsybitsy_m4::StatusLed;
+    use rtic_testing::board::itsybitsy_m4::Tc0Monotonic;
+    use rtic_testing::board::Led;
+    use rtic_testing::logging::{self, debug, info, LevelFilter};
+    use rtic_testing::monotonic::{Duration, ExtU64};
+    use smart_leds::RGB8;
+
+    const BLINK_PERIOD: Duration = Duration::millis(500);
+
+    #[monotonic(binds = TC0, default = true)]
+    type Mono = Tc0Monotonic;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        led: StatusLed,
     }
 
     #[init]
-    fn init(c: init::Context) -> init::LateResources {
+    fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
+        logging::init(LevelFilter::Info, &[]);
+        logging::set_timestamp_source(|| monotonics::now().ticks());
+
         let mut device = c.device; // This mutability conversion is safe
                                    // let mut clocks = GenericClockController::with_external_32kosc(
         let mut clocks = GenericClockController::with_internal_32kosc(
@@ -48,67 +41,41 @@ const APP: () = {
             &mut device.OSCCTRL,
             &mut device.NVMCTRL,
         );
-        //
-        let gclk0 = clocks.gclk0();
-        let timer_clock = clocks.tc2_tc3(&gclk0).unwrap();
-        let mut timer = TimerCounter::tc2_(&timer_clock, device.TC2, &mut device.MCLK);
-        // // let mut timer2 = TimerCounter::tc3_(&timer_clock, device.TC3, &mut device.MCLK);
-        //
-        // timer.start(100.hz());
-
-        timer.start(2.hz());
-        timer.enable_interrupt();
 
-        // timer2.start(10.hz());
-        // timer2.enable_interrupt();
+        // GCLK5 runs at 2 MHz, which the monotonic divides down to its 1 MHz tick
+        let gclk5 = clocks.get_gclk(ClockGenId::GCLK5).unwrap();
+        let timer_clock = clocks.tc0_tc1(&gclk5).unwrap();
+        let mono = Tc0Monotonic::new(device.TC0, device.TC1, &timer_clock, &mut device.MCLK);
 
         let mut pins = itsybitsy_m4::Pins::new(device.PORT);
-        let mut red_led = pins.d13.into_open_drain_output(&mut pins.port);
-        // pins.GPIO13;
-        // let led = dotstar_bitbang(pins.dotstar, &mut pins.port, SpinTimer::new(12));
-        // let led = dotstar_bitbang(pins.dotstar, &mut pins.port, timer2);
-
         let dotstar = itsybitsy_m4::pins::Dotstar {
             ci: pins.dotstar_ci,
             di: pins.dotstar_di,
             nc: pins.dotstar_nc,
         };
 
-        let mut rgb = dotstar_bitbang(dotstar, &mut pins.port, SpinTimer::new(12));
-        let off: [RGB8; 1] = [RGB8 { r: 0, g: 0, b: 0 }];
-        rgb.write(off.iter().cloned()).unwrap();
+        let led = StatusLed::new(dotstar, &mut pins.port);
 
-        // rtic::pend(Interrupt::TC2);
-        // rtic::pend(Interrupt::TC3);
+        // The first blink happens after a second, the rest every BLINK_PERIOD
+        blink::spawn_after(1u64.secs()).unwrap();
 
-        init::LateResources {
-            timer,
-            led: red_led,
-        }
-    }
+        info!("Init done, blinking the DotStar");
 
-    #[task(binds = TC2, resources = [timer, led])]
-    fn tc2(c: tc2::Context) {
-        static mut EVEN: bool = true;
+        (Shared {}, Local { led }, init::Monotonics(mono))
+    }
 
-        if !c.resources.timer.wait().is_ok() {
-            return;
-        }
+    #[task(local = [led, even: bool = true])]
+    fn blink(c: blink::Context) {
+        let even = c.local.even;
 
-        // let color = [if *EVEN {
-        //     // RGB8 { r: 60, g: 60, b: 0 }
-        //     RGB8 { r: 255, g: 255, b: 255 }
-        // } else {
-        //     // RGB8 { r: 0, g: 60, b: 60 }
-        //     RGB8 { r: 0, g: 0, b: 0 }
-        // }];
-
-        // c.resources.led.write(color.iter().cloned()).unwrap();
-        if *EVEN {
-            c.resources.led.set_high().unwrap();
+        debug!("LED {}", if *even { "on" } else { "off" });
+        if *even {
+            c.local.led.set(RGB8 { r: 60, g: 60, b: 0 });
         } else {
-            c.resources.led.set_low().unwrap();
+            c.local.led.off();
         }
-        *EVEN = !*EVEN;
+        *even = !*even;
+
+        blink::spawn_after(BLINK_PERIOD).unwrap();
     }
-};
+}
@@ -2,53 +2,49 @@
 #![no_main]
 #![no_std]
 
-use atsamd_hal::common::usb::usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
-use atsamd_hal::common::usb::usb_device::UsbError;
-use atsamd_hal::common::usb::UsbBus;
-use atsamd_hal::target_device::Interrupt;
-use itsybitsy_m4::timer::TimerCounter3;
-use itsybitsy_m4::usb::usb_device::bus::UsbBusAllocator;
-use itsybitsy_m4::{
-    clock::GenericClockController,
-    dotstar_bitbang,
-    gpio::v2::PA22,
-    gpio::{Input, Output, Pa27, Pb2, Pb3, Pin, PullUp, PushPull},
-    prelude::*,
-    timer::{SpinTimer, TimerCounter, TimerCounter2},
-    uart,
-};
 use panic_halt as _;
-use smart_leds::{SmartLedsWrite, RGB8};
-use usbd_serial::{DefaultBufferStore, SerialPort, USB_CLASS_CDC};
-// use itsybitsy_m4::pac::Interrupt;
-
-// I don't see a way to avoid writing this out since the Resources struct in an rtic app cannot
-// be monomorphized (no generics) and we don't have an allocator to use Box<dyn SmartLedsWrite>.
-type DotStar = apa102_spi::Apa102<
-    bitbang_hal::spi::SPI<
-        Pa27<Input<PullUp>>,
-        Pb3<Output<PushPull>>,
-        Pb2<Output<PushPull>>,
-        TimerCounter3,
-    >,
->;
-
-#[rtic::app(device = itsybitsy_m4::pac, peripherals = true)]
-const APP: () = {
-    struct Resources {
-        timer: TimerCounter2,
-        // led: DotStar,
-        led: Pin<PA22, Output<PushPull>>,
-        // led: itsybitsy_m4::gpio,
-        usb_bus: &'static UsbBusAllocator<UsbBus>,
+
+#[rtic::app(device = itsybitsy_m4::pac, peripherals = true, dispatchers = [EVSYS_0])]
+mod app {
+    use atsamd_hal::common::usb::usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+    use atsamd_hal::common::usb::usb_device::UsbError;
+    use atsamd_hal::common::usb::UsbBus;
+    use itsybitsy_m4::clock::{ClockGenId, GenericClockController};
+    use itsybitsy_m4::usb::usb_device::bus::UsbBusAllocator;
+    use rtic_testing::board::itsybitsy_m4::Tc0Monotonic;
+    use rtic_testing::board::itsybitsy_m4::{ItsyBitsyM4, StatusLed};
+    use rtic_testing::board::{Board, Led};
+    use rtic_testing::identity::Identity;
+    use rtic_testing::logging::{self, info, LevelFilter};
+    use rtic_testing::monotonic::Duration;
+    use rtic_testing::usb_identity::defaults;
+    use smart_leds::RGB8;
+    use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+    const TICK_PERIOD: Duration = Duration::millis(500);
+
+    #[monotonic(binds = TC0, default = true)]
+    type Mono = Tc0Monotonic;
+
+    #[shared]
+    struct Shared {
         usb_serial: SerialPort<'static, UsbBus>,
         usb_serial2: SerialPort<'static, UsbBus>,
         usb_device: UsbDevice<'static, UsbBus>,
     }
 
-    #[init]
-    fn init(c: init::Context) -> init::LateResources {
-        static mut USB_ALLOCATOR: Option<UsbBusAllocator<UsbBus>> = None;
+    #[local]
+    struct Local {
+        led: StatusLed,
+    }
+
+    #[init(local = [
+        usb_allocator: Option<UsbBusAllocator<UsbBus>> = None,
+        identity: Option<Identity> = None,
+    ])]
+    fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
+        logging::init(LevelFilter::Info, &[]);
+        logging::set_timestamp_source(|| monotonics::now().ticks());
 
         let mut peripherals = c.device; // This mutability conversion is safe
         let mut clocks = GenericClockController::with_internal_32kosc(
@@ -59,27 +55,23 @@ const APP: () = {
             &mut peripherals.NVMCTRL,
         );
 
-        let gclk0 = clocks.gclk0();
-        let timer_clock = clocks.tc2_tc3(&gclk0).unwrap();
-        let mut timer = TimerCounter::tc2_(&timer_clock, peripherals.TC2, &mut peripherals.MCLK);
-
-        timer.start(2.hz());
-        timer.enable_interrupt(); // TODO: Is this necessary with rtic?
+        // GCLK5 runs at 2 MHz, which the monotonic divides down to its 1 MHz tick
+        let gclk5 = clocks.get_gclk(ClockGenId::GCLK5).unwrap();
+        let timer_clock = clocks.tc0_tc1(&gclk5).unwrap();
+        let mono = Tc0Monotonic::new(
+            peripherals.TC0,
+            peripherals.TC1,
+            &timer_clock,
+            &mut peripherals.MCLK,
+        );
 
         let mut pins = itsybitsy_m4::Pins::new(peripherals.PORT);
-        let mut red_led = pins.d13.into_open_drain_output(&mut pins.port);
-        // pins.GPIO13;
-        // let led = dotstar_bitbang(pins.dotstar, &mut pins.port, SpinTimer::new(12));
-        // let led = dotstar_bitbang(pins.dotstar, &mut pins.port, timer2);
-
         let dotstar = itsybitsy_m4::pins::Dotstar {
             ci: pins.dotstar_ci,
             di: pins.dotstar_di,
             nc: pins.dotstar_nc,
         };
 
-        // static mut USB_ALLOCATOR: Option<bus::UsbBusAllocator<UsbBusType>> = None;
-
         // let a = uart(
         //     pins.uart,
         //     &mut clocks,
@@ -101,7 +93,14 @@ const APP: () = {
             dp: pins.usb_dp,
         };
 
-        *USB_ALLOCATOR = Some(usb.usb_allocator(
+        // The USB device borrows the serial number for the lifetime of the program
+        let identity = &*c
+            .local
+            .identity
+            .insert(Identity::new(&ItsyBitsyM4::unique_id()));
+
+        // The allocator is borrowed by the USB classes for the lifetime of the program
+        let usb_allocator = c.local.usb_allocator.insert(usb.usb_allocator(
             peripherals.USB,
             &mut clocks,
             &mut peripherals.MCLK,
@@ -110,127 +109,119 @@ const APP: () = {
 
         // TODO: Allocating two SerialPorts technically compiles and runs, but Linux
         //  isn't happy about some device descriptors and refuses to work with it
-        let usb_allocator = USB_ALLOCATOR.as_ref().unwrap();
-        let mut usb_serial = SerialPort::new(usb_allocator);
-        let mut usb_serial2 = SerialPort::new(usb_allocator);
-
-        let mut usb_device = UsbDeviceBuilder::new(usb_allocator, UsbVidPid(0x16c0, 0x27dd))
-            .manufacturer("Fake Company")
-            .product("Suspicious Serial Port")
-            .serial_number("TEST")
-            .device_class(USB_CLASS_CDC)
-            .composite_with_iads()
-            .build();
-
-        let mut rgb = dotstar_bitbang(dotstar, &mut pins.port, SpinTimer::new(12));
-        let off: [RGB8; 1] = [RGB8 { r: 0, g: 0, b: 0 }];
-        rgb.write(off.iter().cloned()).unwrap();
-
-        // unsafe {
-        //     USB_SERIAL = Some(SerialPort::new(&bus_allocator));
-        //     USB_BUS = Some(
-        //         UsbDeviceBuilder::new(&bus_allocator, UsbVidPid(0x16c0, 0x27dd))
-        //             .manufacturer("Fake company")
-        //             .product("Serial port")
-        //             .serial_number("TEST")
-        //             .device_class(USB_CLASS_CDC)
-        //             .build(),
-        //     );
-        // }
-
-        init::LateResources {
-            timer,
-            led: red_led,
-            usb_bus: usb_allocator,
-            usb_serial,
-            usb_serial2,
-            usb_device,
-        }
+        let usb_serial = SerialPort::new(usb_allocator);
+        let usb_serial2 = SerialPort::new(usb_allocator);
+
+        let usb_device =
+            UsbDeviceBuilder::new(usb_allocator, UsbVidPid(defaults::VID, defaults::PID))
+                .manufacturer(defaults::MANUFACTURER)
+                .product(defaults::PRODUCT)
+                .serial_number(identity.serial_number())
+                .device_class(USB_CLASS_CDC)
+                .composite_with_iads()
+                .build();
+
+        let led = StatusLed::new(dotstar, &mut pins.port);
+
+        tick::spawn_after(TICK_PERIOD).unwrap();
+
+        (
+            Shared {
+                usb_serial,
+                usb_serial2,
+                usb_device,
+            },
+            Local { led },
+            init::Monotonics(mono),
+        )
     }
 
-    #[task(binds = USB_OTHER, resources = [usb_device, usb_serial, usb_serial2])]
-    fn usb_other(cx: usb_other::Context) {
-        usb_poll(
-            cx.resources.usb_device,
-            cx.resources.usb_serial,
-            cx.resources.usb_serial2,
-        );
+    #[task(binds = USB_OTHER, shared = [usb_device, usb_serial, usb_serial2])]
+    fn usb_other(c: usb_other::Context) {
+        (
+            c.shared.usb_device,
+            c.shared.usb_serial,
+            c.shared.usb_serial2,
+        )
+            .lock(usb_poll);
     }
 
-    #[task(binds = USB_TRCPT0, resources = [usb_device, usb_serial, usb_serial2])]
-    fn usb_trcpt0(cx: usb_trcpt0::Context) {
-        usb_poll(
-            cx.resources.usb_device,
-            cx.resources.usb_serial,
-            cx.resources.usb_serial2,
-        );
+    #[task(binds = USB_TRCPT0, shared = [usb_device, usb_serial, usb_serial2])]
+    fn usb_trcpt0(c: usb_trcpt0::Context) {
+        (
+            c.shared.usb_device,
+            c.shared.usb_serial,
+            c.shared.usb_serial2,
+        )
+            .lock(usb_poll);
     }
 
-    #[task(binds = USB_TRCPT1, resources = [usb_device, usb_serial, usb_serial2])]
-    fn usb_trcpt1(cx: usb_trcpt1::Context) {
-        usb_poll(
-            cx.resources.usb_device,
-            cx.resources.usb_serial,
-            cx.resources.usb_serial2,
-        );
+    #[task(binds = USB_TRCPT1, shared = [usb_device, usb_serial, usb_serial2])]
+    fn usb_trcpt1(c: usb_trcpt1::Context) {
+        (
+            c.shared.usb_device,
+            c.shared.usb_serial,
+            c.shared.usb_serial2,
+        )
+            .lock(usb_poll);
     }
 
-    #[task(binds = TC2, resources = [timer, led, usb_serial])]
-    fn tc2(c: tc2::Context) {
-        static mut EVEN: bool = true;
+    #[task(shared = [usb_serial], local = [led, even: bool = true])]
+    fn tick(mut c: tick::Context) {
+        let even = c.local.even;
 
-        if !c.resources.timer.wait().is_ok() {
-            return;
-        }
+        info!("test");
 
-        c.resources.usb_serial.write(b"test\r\n").ok();
-        // write!(c.resources.usb_serial, "test");
+        // With the usb-log feature the log output is sent to the first serial port
+        #[cfg(feature = "usb-log")]
+        c.shared
+            .usb_serial
+            .lock(|serial| logging::usb::drain(|b| serial.write(b).unwrap_or(0)));
 
-        // let color = [if *EVEN {
-        //     // RGB8 { r: 60, g: 60, b: 0 }
-        //     RGB8 { r: 255, g: 255, b: 255 }
-        // } else {
-        //     // RGB8 { r: 0, g: 60, b: 60 }
-        //     RGB8 { r: 0, g: 0, b: 0 }
-        // }];
+        // Without the feature, keep sending a marker to show the port is alive
+        #[cfg(not(feature = "usb-log"))]
+        c.shared
+            .usb_serial
+            .lock(|serial| serial.write(b"test\r\n").ok());
 
-        // c.resources.led.write(color.iter().cloned()).unwrap();
-        if *EVEN {
-            c.resources.led.set_high().unwrap();
+        c.local.led.set(if *even {
+            RGB8 { r: 60, g: 60, b: 0 }
         } else {
-            c.resources.led.set_low().unwrap();
-        }
-        *EVEN = !*EVEN;
+            RGB8 { r: 0, g: 60, b: 60 }
+        });
+        *even = !*even;
+
+        tick::spawn_after(TICK_PERIOD).unwrap();
     }
-};
-
-// Throw away incoming data
-fn usb_poll<B: usb_device::bus::UsbBus>(
-    usb_dev: &mut UsbDevice<'static, B>,
-    serial: &mut SerialPort<'static, B>,
-    serial2: &mut SerialPort<'static, B>,
-) {
-    let serial_data = usb_dev.poll(&mut [serial]);
-    let serial2_data = usb_dev.poll(&mut [serial2]);
-
-    // if !usb_dev.poll(&mut [serial]) {
-    //     return;
-    // }
-    if serial_data {
-        let mut buf = [0; 10];
-        match serial.read(&mut buf) {
-            Ok(_) => {}
-            Err(UsbError::WouldBlock) => {}
-            e => panic!("USB read error: {:?}", e),
+
+    // Throw away incoming data
+    fn usb_poll(
+        usb_dev: &mut UsbDevice<'static, UsbBus>,
+        serial: &mut SerialPort<'static, UsbBus>,
+        serial2: &mut SerialPort<'static, UsbBus>,
+    ) {
+        let serial_data = usb_dev.poll(&mut [serial]);
+        let serial2_data = usb_dev.poll(&mut [serial2]);
+
+        // if !usb_dev.poll(&mut [serial]) {
+        //     return;
+        // }
+        if serial_data {
+            let mut buf = [0; 10];
+            match serial.read(&mut buf) {
+                Ok(_) => {}
+                Err(UsbError::WouldBlock) => {}
+                e => panic!("USB read error: {:?}", e),
+            }
         }
-    }
 
-    if serial2_data {
-        let mut buf = [0; 10];
-        match serial2.read(&mut buf) {
-            Ok(_) => {}
-            Err(UsbError::WouldBlock) => {}
-            e => panic!("USB read error: {:?}", e),
+        if serial2_data {
+            let mut buf = [0; 10];
+            match serial2.read(&mut buf) {
+                Ok(_) => {}
+                Err(UsbError::WouldBlock) => {}
+                e => panic!("USB read error: {:?}", e),
+            }
         }
     }
 }
@@ -0,0 +1,7 @@
+MEMORY
+{
+  /* The last 1K page of the 64K parts holds the settings, see src/main.rs */
+  FLASH (rx) : ORIGIN = 0x08000000, LENGTH = 63K
+  RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 20K
+}
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
@@ -1,5 +1,6 @@
 MEMORY
 {
+  /* The examples and other binaries run without the bootloader, which they overwrite */
   FLASH (rx) : ORIGIN = 0x00000000, LENGTH = 512K
   RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 192K
 }
@@ -0,0 +1,7 @@
+MEMORY
+{
+  /* The bootloader, the firmware slots follow it, see src/board/itsybitsy_m4 */
+  FLASH (rx) : ORIGIN = 0x00000000, LENGTH = 64K
+  RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 192K
+}
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
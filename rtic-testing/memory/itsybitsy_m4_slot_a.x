@@ -0,0 +1,7 @@
+MEMORY
+{
+  /* Slot A behind the 1K image header, see src/board/itsybitsy_m4 */
+  FLASH (rx) : ORIGIN = 0x00010400, LENGTH = 207K
+  RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 192K
+}
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
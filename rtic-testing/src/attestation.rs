@@ -0,0 +1,138 @@
+//! Attestation reports signed with a key unique to the BMC, see [`bmc_proto::attestation`].
+//!
+//! The attestation key is derived from the secret in the security page and the unique ID of the
+//! MCU, so BMCs provisioned with the same secret still have keys of their own. The boot log is
+//! measured when a report is made: the bootloader in front of slot A, the image header of the
+//! running firmware and the security page. Neither updates nor `config` can write them, so they
+//! are what the BMC booted from.
+//!
+//! The key doesn't depend on the boot log, and the secret is in flash any firmware the BMC runs
+//! can read. So the firmware measures itself: firmware that is compromised, or validly signed but
+//! malicious, can derive the key and sign any boot log digest. A report proves which BMC made it,
+//! and what it runs only as far as the firmware signed by the trusted keys is trusted. Deriving the
+//! key in the bootloader over the boot log wouldn't change that while the secret stays readable.
+
+use bmc_proto::attestation::{self, Measurement, MeasurementKind, Report, NONCE_LEN, REPORT_LEN};
+use bmc_proto::image::HEADER_LEN;
+use salty::Keypair;
+
+use crate::boot::{self, Layout, Slot};
+use crate::crypto::{Crypto, Timeout};
+use crate::keys::{self, SecurityPage};
+
+/// Starts the input of the key derivation, so the seed can't be made for anything else
+const KEY_CONTEXT: &[u8; 11] = b"RBMC-ATTEST";
+
+/// The entries of the boot log in the order they are extended in
+const MEASURED: [MeasurementKind; 3] = [
+    MeasurementKind::Bootloader,
+    MeasurementKind::Firmware,
+    MeasurementKind::SecurityPage,
+];
+
+/// Why the BMC can't attest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No attestation secret is provisioned
+    NotProvisioned,
+    /// Neither copy of the security page is valid
+    SecurityPage,
+    /// The hash peripheral timed out
+    Crypto,
+}
+
+impl From<Timeout> for Error {
+    fn from(_: Timeout) -> Self {
+        Error::Crypto
+    }
+}
+
+/// The attestation key of the BMC with `unique_id`
+pub fn key(
+    security: &SecurityPage,
+    unique_id: &[u8],
+    crypto: &mut dyn Crypto,
+) -> Result<Keypair, Error> {
+    let secret = security.attestation_secret().ok_or(Error::NotProvisioned)?;
+    crypto.sha256_start();
+    crypto.sha256_update(KEY_CONTEXT);
+    crypto.sha256_update(secret);
+    crypto.sha256_update(unique_id);
+    Ok(Keypair::from(&crypto.sha256_finish()?))
+}
+
+/// The boot log entry at `index`, read through `read`, `None` past the last one
+pub fn measurement(
+    layout: &Layout,
+    index: usize,
+    crypto: &mut dyn Crypto,
+    read: impl FnMut(u32, &mut [u8]),
+) -> Result<Option<Measurement>, Timeout> {
+    match MEASURED.get(index) {
+        Some(&kind) => Ok(Some(measure(layout, kind, crypto, read)?)),
+        None => Ok(None),
+    }
+}
+
+fn measure(
+    layout: &Layout,
+    kind: MeasurementKind,
+    crypto: &mut dyn Crypto,
+    mut read: impl FnMut(u32, &mut [u8]),
+) -> Result<Measurement, Timeout> {
+    let digest = match kind {
+        MeasurementKind::Bootloader => boot::sha256(0..layout.slot_a, crypto, read)?,
+        MeasurementKind::Firmware => {
+            let start = layout.start(Slot::RUNNING);
+            boot::sha256(start..start + HEADER_LEN as u32, crypto, read)?
+        }
+        MeasurementKind::SecurityPage => {
+            // The copy that counts, or the first one of a corrupt page
+            let copy = SecurityPage::load(layout.security_pages, &mut read)
+                .map_or(0, |security| security.copy());
+            let start = layout.security_pages[copy];
+            boot::sha256(start..start + keys::MEASURED_LEN as u32, crypto, read)?
+        }
+    };
+    Ok(Measurement { kind, digest })
+}
+
+/// Signs a report answering `nonce`
+pub fn report(
+    layout: &Layout,
+    unique_id: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    crypto: &mut dyn Crypto,
+    mut read: impl FnMut(u32, &mut [u8]),
+) -> Result<[u8; REPORT_LEN], Error> {
+    let security =
+        SecurityPage::load(layout.security_pages, &mut read).ok_or(Error::SecurityPage)?;
+    let keypair = key(&security, unique_id, crypto)?;
+
+    let mut boot_log_digest = [0; 32];
+    for &kind in MEASURED.iter() {
+        let measurement = measure(layout, kind, crypto, &mut read)?;
+        let input = attestation::extend_input(&boot_log_digest, &measurement);
+        boot_log_digest = crypto.sha256(&input)?;
+    }
+
+    let mut report = Report {
+        unique_id: [0; 16],
+        nonce: *nonce,
+        firmware_sha256: [0; 32],
+        version: Default::default(),
+        security_version: 0,
+        min_security_version: security.min_security_version,
+        boot_log_digest,
+        signature: [0; 64],
+    };
+    let len = unique_id.len().min(report.unique_id.len());
+    report.unique_id[..len].copy_from_slice(&unique_id[..len]);
+    if let Some(header) = boot::running_image(layout, &mut read) {
+        report.firmware_sha256 = header.sha256;
+        report.version = header.version;
+        report.security_version = header.security_version;
+    }
+    report.signature = keypair.sign(&report.signed_bytes()).to_bytes();
+    Ok(report.encode())
+}
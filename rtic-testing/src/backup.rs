@@ -0,0 +1,285 @@
+//! State kept across resets in the backup memory of the MCU: the backup SRAM of the SAMD51 or the
+//! backup data registers of the STM32F103. The memory keeps its contents through resets but not
+//! through power cycles, so the state is guarded by a magic and a CRC and starts over when they
+//! don't match.
+//!
+//! The state takes [`STATE_LEN`] bytes, which the 20 bytes of backup registers of the STM32F103
+//! limit it to.
+
+use core::fmt;
+
+use crc::{Crc, NoTable, CRC_32_ISO_HDLC};
+
+use crate::logging::{info, warn};
+use crate::watchdog::Culprit;
+
+/// The encoded length of [`BackupState`]
+pub const STATE_LEN: usize = 20;
+
+/// Marks initialized state, "BMCs" in little endian
+const MAGIC: u32 = 0x7343_4D42;
+/// The table would cost 1 KiB of flash for checking 16 bytes
+const CRC: Crc<u32, NoTable> = Crc::<u32, NoTable>::new(&CRC_32_ISO_HDLC);
+/// The encoding of no watchdog culprit
+const NO_CULPRIT: u8 = 0xFF;
+
+/// The backup memory of the board, see [`crate::board::Board::backup`]
+pub trait BackupMemory {
+    fn read(&self) -> [u8; STATE_LEN];
+
+    fn write(&mut self, data: &[u8; STATE_LEN]);
+}
+
+/// What the next reset starts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BootMode {
+    #[default]
+    Application,
+    /// Stays in the bootloader, for updating the firmware
+    Bootloader,
+}
+
+/// Why the application stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crash {
+    /// A panic. Reading where it happened would keep the formatting of every panic message in the
+    /// firmware, which doesn't fit.
+    Panic,
+    /// A HardFault, at the address of the faulting instruction
+    HardFault { pc: u32 },
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Crash::Panic => f.write_str("panic"),
+            Crash::HardFault { pc } => write!(f, "HardFault at {:#010x}", pc),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Crash {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Crash::Panic => defmt::write!(f, "panic"),
+            Crash::HardFault { pc } => defmt::write!(f, "HardFault at {=u32:#010x}", pc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupState {
+    /// Requested before a reset, for the bootloader
+    pub boot_mode: BootMode,
+    pub last_crash: Option<Crash>,
+    /// The boots since the MCU was powered on, counting this one
+    pub boot_count: u32,
+    /// The task that stopped checking in with the watchdog before it reset the MCU, see
+    /// [`crate::watchdog::Task`]
+    pub watchdog_culprit: Option<u8>,
+    /// The boots of the firmware slot on trial, see [`crate::boot`]
+    pub trial_boots: u8,
+}
+
+impl BackupState {
+    /// The state in `memory`, `None` if it was lost when the MCU lost power
+    pub fn load<M: BackupMemory + ?Sized>(memory: &M) -> Option<Self> {
+        Self::decode(&memory.read())
+    }
+
+    pub fn store<M: BackupMemory + ?Sized>(&self, memory: &mut M) {
+        memory.write(&self.encode());
+    }
+
+    /// Counts a boot, starting over from lost state, and returns the state of this boot after
+    /// logging what it tells about the previous ones
+    pub fn boot<M: BackupMemory + ?Sized>(memory: &mut M) -> Self {
+        let mut state = Self::load(memory).unwrap_or_default();
+        state.boot_count = state.boot_count.wrapping_add(1);
+        state.store(memory);
+
+        info!("Boot {} since power-on", state.boot_count);
+        if let Some(crash) = state.last_crash {
+            warn!("The last crash was a {}", crash);
+        }
+        if let Some(task) = state.watchdog_culprit {
+            warn!(
+                "The watchdog reset the MCU waiting for task {}",
+                Culprit(task)
+            );
+        }
+        state
+    }
+
+    /// Records a crash, for the crash handlers
+    pub fn record_crash<M: BackupMemory + ?Sized>(memory: &mut M, crash: Crash) {
+        let mut state = Self::load(memory).unwrap_or_default();
+        state.last_crash = Some(crash);
+        state.store(memory);
+    }
+
+    /// Records the task the watchdog is about to reset the MCU waiting for, for its early warning
+    pub fn record_watchdog_culprit<M: BackupMemory + ?Sized>(memory: &mut M, task: u8) {
+        let mut state = Self::load(memory).unwrap_or_default();
+        state.watchdog_culprit = Some(task);
+        state.store(memory);
+    }
+
+    fn encode(&self) -> [u8; STATE_LEN] {
+        let (crash, address) = match self.last_crash {
+            None => (0, 0),
+            Some(Crash::Panic) => (1, 0),
+            Some(Crash::HardFault { pc }) => (2, pc),
+        };
+
+        let mut data = [0; STATE_LEN];
+        data[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        data[4] = self.boot_mode as u8;
+        data[5] = crash;
+        data[6] = self.watchdog_culprit.unwrap_or(NO_CULPRIT);
+        data[7] = self.trial_boots;
+        data[8..12].copy_from_slice(&address.to_le_bytes());
+        data[12..16].copy_from_slice(&self.boot_count.to_le_bytes());
+        let crc = CRC.checksum(&data[..16]);
+        data[16..20].copy_from_slice(&crc.to_le_bytes());
+        data
+    }
+
+    fn decode(data: &[u8; STATE_LEN]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        if word(0) != MAGIC || word(16) != CRC.checksum(&data[..16]) {
+            return None;
+        }
+
+        let boot_mode = match data[4] {
+            0 => BootMode::Application,
+            1 => BootMode::Bootloader,
+            _ => return None,
+        };
+        let last_crash = match data[5] {
+            0 => None,
+            1 => Some(Crash::Panic),
+            2 => Some(Crash::HardFault { pc: word(8) }),
+            _ => return None,
+        };
+        Some(Self {
+            boot_mode,
+            last_crash,
+            boot_count: word(12),
+            watchdog_culprit: Some(data[6]).filter(|&task| task != NO_CULPRIT),
+            trial_boots: data[7],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimBackup;
+
+    fn state() -> BackupState {
+        BackupState {
+            boot_mode: BootMode::Bootloader,
+            last_crash: Some(Crash::HardFault { pc: 0x0001_2345 }),
+            boot_count: 0x0102_0304,
+            watchdog_culprit: Some(1),
+            trial_boots: 2,
+        }
+    }
+
+    /// Encodes `state` with `change` made to its bytes and the CRC fixed up
+    fn encode_with(
+        state: &BackupState,
+        change: impl FnOnce(&mut [u8; STATE_LEN]),
+    ) -> [u8; STATE_LEN] {
+        let mut data = state.encode();
+        change(&mut data);
+        let crc = CRC.checksum(&data[..16]);
+        data[16..20].copy_from_slice(&crc.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let states = [
+            BackupState::default(),
+            state(),
+            BackupState {
+                last_crash: Some(Crash::Panic),
+                watchdog_culprit: None,
+                ..state()
+            },
+        ];
+        for state in states {
+            assert_eq!(BackupState::decode(&state.encode()), Some(state));
+        }
+    }
+
+    #[test]
+    fn corrupt_state_is_lost() {
+        let data = state().encode();
+        // As after power-on
+        assert_eq!(BackupState::decode(&[0; STATE_LEN]), None);
+        assert_eq!(BackupState::decode(&[0xFF; STATE_LEN]), None);
+        // Any bit flipped
+        for bit in 0..STATE_LEN * 8 {
+            let mut flipped = data;
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert_eq!(BackupState::decode(&flipped), None, "bit {}", bit);
+        }
+        // Unknown boot modes and crashes, even with a valid CRC
+        assert_eq!(
+            BackupState::decode(&encode_with(&state(), |data| data[4] = 2)),
+            None
+        );
+        assert_eq!(
+            BackupState::decode(&encode_with(&state(), |data| data[5] = 3)),
+            None
+        );
+        // Another magic
+        assert_eq!(
+            BackupState::decode(&encode_with(&state(), |data| data[0] ^= 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn boots_are_counted_from_power_on() {
+        let mut memory = SimBackup::default();
+        assert_eq!(BackupState::boot(&mut memory).boot_count, 1);
+        assert_eq!(BackupState::boot(&mut memory).boot_count, 2);
+
+        // Lost state starts over
+        memory.write(&[0xFF; STATE_LEN]);
+        assert_eq!(
+            BackupState::boot(&mut memory),
+            BackupState {
+                boot_count: 1,
+                ..BackupState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn records_keep_the_rest_of_the_state() {
+        let mut memory = SimBackup::default();
+        let state = BackupState {
+            last_crash: None,
+            watchdog_culprit: None,
+            ..state()
+        };
+        state.store(&mut memory);
+
+        BackupState::record_crash(&mut memory, Crash::Panic);
+        BackupState::record_watchdog_culprit(&mut memory, 0);
+        assert_eq!(
+            BackupState::load(&memory),
+            Some(BackupState {
+                last_crash: Some(Crash::Panic),
+                watchdog_culprit: Some(0),
+                ..state
+            })
+        );
+    }
+}
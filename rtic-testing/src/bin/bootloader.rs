@@ -0,0 +1,72 @@
+//! The bootloader of the ItsyBitsy M4, replacing the UF2 bootloader it ships with. It picks the
+//! firmware slot to start, checks the image in it and jumps to it, see `src/boot.rs`. Firmware on
+//! trial is started with the watchdog running, see `src/watchdog.rs`. Updates are received by the
+//! firmware itself, there is no update mode.
+
+#![no_std]
+#![no_main]
+
+use core::slice;
+
+use cortex_m::peripheral::SCB;
+use itsybitsy_m4::entry;
+use itsybitsy_m4::pac::Peripherals;
+use panic_probe as _;
+use rtic_testing::board::itsybitsy_m4::{ItsyBitsyM4, Nvm};
+use rtic_testing::board::Board;
+use rtic_testing::boot::{self, Slot};
+use rtic_testing::config::ConfigStore;
+use rtic_testing::flash::Flash;
+use rtic_testing::logging::{self, error, info, warn, LevelFilter};
+use rtic_testing::watchdog::Watchdog as _;
+
+#[entry]
+fn main() -> ! {
+    logging::init(LevelFilter::Info, &[]);
+
+    let device = Peripherals::take().unwrap();
+
+    let nvm = Nvm::new(device.NVMCTRL);
+    let config_address = nvm.end_address() - nvm.page_size();
+    let mut config = ConfigStore::new(nvm, config_address);
+
+    let layout = ItsyBitsyM4::SLOTS;
+    let mut crypto = ItsyBitsyM4::crypto();
+    let valid = |slot: Slot| match boot::verify(&layout, slot, &mut crypto, read_flash) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Slot {}: {}", slot, e);
+            false
+        }
+    };
+    let Some(slot) = boot::select(&mut config, &mut ItsyBitsyM4::backup(), valid) else {
+        error!("No valid firmware image, flash one with a debug probe");
+        loop {
+            cortex_m::asm::wfi();
+        }
+    };
+
+    // A trial that hangs is reset and counted like one that crashes
+    if boot::trial(&config) == Some(slot) {
+        ItsyBitsyM4::watchdog().start();
+    }
+
+    let address = layout.load_address(slot);
+    info!("Starting slot {} at {:#010x}", slot, address);
+    jump(address)
+}
+
+/// The images are read where the flash is mapped, the settings own the NVM controller
+fn read_flash(address: u32, data: &mut [u8]) {
+    let flash = unsafe { slice::from_raw_parts(address as *const u8, data.len()) };
+    data.copy_from_slice(flash);
+}
+
+/// Starts the firmware whose vector table is at `address`, with the stack pointer and the reset
+/// handler from it
+fn jump(address: u32) -> ! {
+    unsafe {
+        (*SCB::PTR).vtor.write(address);
+        cortex_m::asm::bootload(address as *const u32)
+    }
+}
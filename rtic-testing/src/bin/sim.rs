@@ -0,0 +1,250 @@
+//! Runs the BMC on the host with simulated hardware, see the "Simulation" section of the README.
+//!
+//! The console is on a pseudoterminal, whose path is printed on startup. With `--disk <image>`
+//! the GhostFat drive is written to a FAT image file, UF2 files copied onto the image (e.g. with
+//! `mcopy -i <image> app.uf2 ::`) are programmed into the simulated flash. With `--nbd <address>`
+//! the drive is served over NBD instead, for mounting it using the Linux kernel. With
+//! `--flash <file>` the slot updates go into is written to a file after every update, so tests can
+//! check what got programmed. Updates signed by the firmware key are put on trial like on the board,
+//! but the simulated BMC keeps running. With `--stall-fan <n>` fan n (from 1) stops, to see
+//! the fan failure handling.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bmc_proto::image::{Rejection, UpdateStatus};
+use rtic_testing::backup::BackupState;
+use rtic_testing::board::{GpioPower, Led, PowerControl};
+use rtic_testing::boot::{self, Slot};
+use rtic_testing::config::ConfigStore;
+use rtic_testing::console::Console;
+use rtic_testing::crypto::Software;
+use rtic_testing::fans::{FanControl, FanPwm};
+use rtic_testing::flash::{Flash as _, FlashWrapper};
+use rtic_testing::ghostfat::GhostFat;
+use rtic_testing::identity::Identity;
+use rtic_testing::logging::{error, info, warn, LevelFilter};
+use rtic_testing::rtc::{self, WallClock};
+use rtic_testing::sensors::{self, AnalogInput, SensorConfig, Sensors};
+use rtic_testing::shell::{Bmc, PROMPT};
+use rtic_testing::sim::{
+    self, nbd::NbdServer, FileDisk, Pty, RamFlash, SimAnalog, SimBackup, SimFans, SimI2c, SimLed,
+    SimPin, SimRtc, ThermalPlant,
+};
+use rtic_testing::status::{State, StatusIndicator, TICK_PERIOD};
+
+/// How often the console and the disk image are checked for input
+const POLL_PERIOD: Duration = Duration::from_millis(10);
+
+const SENSOR_TABLES: &[&[SensorConfig]] =
+    &[sensors::SENSORS, sim::ON_CHIP_SENSORS, sim::FAN_SENSORS];
+
+/// Set by GhostFat when an update is complete
+static UPDATED: AtomicBool = AtomicBool::new(false);
+
+const USAGE: &str = "usage: sim [--disk <image>] [--nbd <address>] [--flash <file>] \
+                     [--stall-fan <n>] [--verbose]";
+
+fn main() -> io::Result<()> {
+    let mut disk_path = None;
+    let mut nbd_address = None;
+    let mut flash_path = None;
+    let mut fans = SimFans::new();
+    let mut level = LevelFilter::Info;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--disk" => disk_path = Some(PathBuf::from(args.next().expect(USAGE))),
+            "--nbd" => nbd_address = Some(args.next().expect(USAGE)),
+            "--flash" => flash_path = Some(PathBuf::from(args.next().expect(USAGE))),
+            "--stall-fan" => match args.next().and_then(|fan| fan.parse::<usize>().ok()) {
+                Some(fan @ 1..=SimFans::COUNT) => fans.stall(fan - 1),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    std::process::exit(2);
+                }
+            },
+            "--verbose" => level = LevelFilter::Debug,
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    sim::init_logging(level);
+
+    let identity = Identity::new(&sim::UNIQUE_ID);
+    info!(
+        "Serial number {}, node name {}",
+        identity.serial_number(),
+        identity.node_name()
+    );
+    let mut backup = SimBackup::default();
+    let boot = BackupState::boot(&mut backup);
+
+    let flash = RamFlash::new(0, sim::SLOTS.security_pages[0], sim::PAGE_SIZE);
+    let flash_wrapper = FlashWrapper::new(flash, sim::APP_START, sim::APP_END);
+    let mut ghostfat = GhostFat::new(flash_wrapper, sim::UF2_FAMILY_ID, sim::UF2_INFO);
+    ghostfat.set_update_hook(|| {
+        boot::LAST_UPDATE.set(UpdateStatus::Installing);
+        UPDATED.store(true, Ordering::Relaxed);
+    });
+
+    // The settings and the security page, which updates can't write
+    let security_page = sim::SLOTS.security_pages[0];
+    let config_flash = RamFlash::new(
+        security_page,
+        sim::FLASH_SIZE - security_page,
+        sim::PAGE_SIZE,
+    );
+    let mut config = ConfigStore::new(config_flash, sim::CONFIG_ADDRESS);
+
+    let mut power = GpioPower::new(SimPin::new("power"), SimPin::new("reset"));
+    let mut led = SimLed::default();
+    let mut status = StatusIndicator::new();
+
+    // The sensors of the tables read 25 °C, 5.02 V and 0.5 A, and 3.3 V on-chip. The MCU
+    // temperature follows the thermal model as the fans cool it.
+    let mut i2c = SimI2c::default();
+    i2c.set_register(0x48, 0x00, 0x1900);
+    i2c.set_register(0x40, 0x01, 0x1388);
+    i2c.set_register(0x40, 0x02, 0x2738);
+    let mut analog = SimAnalog::default();
+    analog.set_millivolts(AnalogInput::IoSupply, 3_300);
+    let mut plant = ThermalPlant::new();
+    let mut sensors = Sensors::new(SENSOR_TABLES);
+    let mut fan_control = FanControl::load(&config, sensors::POLL_PERIOD);
+    let mut clock = WallClock::load(SimRtc::default(), &config);
+    let mut crypto = Software::new();
+
+    let mut disk = match &disk_path {
+        Some(path) => {
+            let disk = FileDisk::create(path, &ghostfat)?;
+            info!("GhostFat drive written to {}", path.display());
+            Some(disk)
+        }
+        None => None,
+    };
+
+    let mut nbd = match &nbd_address {
+        Some(address) => {
+            let server = NbdServer::bind(address.as_str())?;
+            info!("NBD server on {}", server.listener().local_addr()?);
+            Some(server)
+        }
+        None => None,
+    };
+
+    let mut pty = Pty::open()?;
+    info!("Console on {}", pty.path().display());
+    pty.write(PROMPT.as_bytes())?;
+
+    let mut console = Console::new();
+    let mut next_tick = Instant::now();
+    let mut next_poll = Instant::now();
+    let mut next_correction = Instant::now();
+    loop {
+        let mut input = [0; 64];
+        let len = pty.read(&mut input)?;
+        if len > 0 {
+            let mut bmc = Bmc {
+                identity: &identity,
+                boot: &boot,
+                power: &mut power,
+                status: &mut status,
+                config: &mut config,
+                sensors: &sensors,
+                clock: &mut clock,
+                slots: &sim::SLOTS,
+                crypto: &mut crypto,
+            };
+            console.input(&input[..len], &mut bmc);
+            while !console.output().is_empty() {
+                pty.write(console.output())?;
+                console.consume(console.output().len());
+            }
+        }
+
+        if let Some(disk) = &mut disk {
+            disk.sync(&mut ghostfat)?;
+        }
+
+        if let Some(nbd) = &mut nbd {
+            nbd.poll(&mut ghostfat);
+        }
+
+        if UPDATED.swap(false, Ordering::Relaxed) {
+            // As on the board, but there is no bootloader to reset into
+            let flash = ghostfat.flash();
+            let read = |address, data: &mut [u8]| {
+                if address >= security_page {
+                    config.flash_mut().read(address, data)
+                } else {
+                    flash.read(address, data)
+                }
+            };
+            match boot::verify(&sim::SLOTS, Slot::RUNNING.other(), &mut crypto, read) {
+                Ok(_) => match boot::schedule_trial(&mut config, &mut backup) {
+                    Ok(()) => boot::LAST_UPDATE.set(UpdateStatus::Scheduled),
+                    Err(_) => {
+                        warn!("Saving boot.trial failed");
+                        boot::LAST_UPDATE.set(UpdateStatus::Rejected(Rejection::Flash));
+                    }
+                },
+                Err(e) => {
+                    error!("Update rejected: {}", e);
+                    boot::LAST_UPDATE.set(UpdateStatus::Rejected(e.into()));
+                }
+            }
+
+            if let Some(path) = &flash_path {
+                let flash = ghostfat.flash();
+                let mut contents = vec![0; (flash.max_address() - flash.min_address()) as usize];
+                flash.read(flash.min_address(), &mut contents);
+                fs::write(path, contents)?;
+                info!("Flash written to {}", path.display());
+            }
+        }
+
+        if Instant::now() >= next_poll {
+            plant.step(sensors::POLL_PERIOD, fans.airflow());
+            analog.set_millicelsius(plant.millicelsius());
+
+            let pulses = fans.pulses(sensors::POLL_PERIOD);
+            for (i, sensor) in sensors::all(SENSOR_TABLES).enumerate() {
+                let value = sensor
+                    .device
+                    .read(&mut i2c, &mut analog, &pulses, sensor.channel)
+                    .ok();
+                sensors.update(i, value);
+            }
+
+            let duty = fan_control.update(sensors.temperature_headroom(), sensors.any_fan_failed());
+            fans.set_duty(duty);
+            next_poll += Duration::from_micros(sensors::POLL_PERIOD.to_micros());
+        }
+
+        if Instant::now() >= next_correction {
+            clock.correct();
+            next_correction += Duration::from_micros(rtc::CORRECTION_PERIOD.to_micros());
+        }
+
+        if Instant::now() >= next_tick {
+            status.set_state(if sensors.any_critical() {
+                State::Error
+            } else if power.is_powered() {
+                State::HostOn
+            } else {
+                State::Idle
+            });
+            led.set(status.tick());
+            next_tick += Duration::from_micros(TICK_PERIOD.to_micros());
+        }
+
+        thread::sleep(POLL_PERIOD);
+    }
+}
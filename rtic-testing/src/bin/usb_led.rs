@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+
+/// Makes the itsybitsy_m4 appear as a USB serial port. The color of the
+/// dotstar LED can be changed by sending bytes to the serial port.
+///
+/// Sending the characters R, G, B, W and O set the LED red, green, blue, white
+/// and off respectively. For example:
+/// $> sudo stty -F /dev/ttyACM0 115200 raw -echo
+/// $> sudo bash -c "echo 'R' > /dev/ttyACM0"
+/// $> sudo bash -c "echo 'G' > /dev/ttyACM0"
+/// $> sudo bash -c "echo 'O' > /dev/ttyACM0"
+use panic_halt as _;
+
+#[rtic::app(device = itsybitsy_m4::pac, peripherals = true, dispatchers = [EVSYS_0])]
+mod app {
+    use itsybitsy_m4::{clock::GenericClockController, usb::UsbBus};
+    use rtic_testing::board::itsybitsy_m4::{ItsyBitsyM4, StatusLed};
+    use rtic_testing::board::{Board, Led};
+    use rtic_testing::identity::Identity;
+    use rtic_testing::logging::{self, info, LevelFilter};
+    use rtic_testing::usb_identity::defaults;
+    use smart_leds::RGB8;
+    use usb_device::bus::UsbBusAllocator;
+    use usb_device::prelude::*;
+    use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+    #[shared]
+    struct Shared {
+        usb_dev: UsbDevice<'static, UsbBus>,
+        serial: SerialPort<'static, UsbBus>,
+    }
+
+    #[local]
+    struct Local {
+        led: StatusLed,
+    }
+
+    #[init(local = [
+        usb_allocator: Option<UsbBusAllocator<UsbBus>> = None,
+        identity: Option<Identity> = None,
+    ])]
+    fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
+        logging::init(LevelFilter::Info, &[]);
+
+        let mut peripherals = c.device;
+        let mut clocks = GenericClockController::with_internal_32kosc(
+            peripherals.GCLK,
+            &mut peripherals.MCLK,
+            &mut peripherals.OSC32KCTRL,
+            &mut peripherals.OSCCTRL,
+            &mut peripherals.NVMCTRL,
+        );
+        let mut pins = itsybitsy_m4::Pins::new(peripherals.PORT).split();
+
+        let led = StatusLed::new(pins.dotstar, &mut pins.port);
+
+        info!("~========== STARTING ==========~");
+        info!(
+            "Last reset cause: {:#x}",
+            peripherals.RSTC.rcause.read().bits()
+        );
+
+        // The USB device borrows the serial number for the lifetime of the program
+        let identity = &*c
+            .local
+            .identity
+            .insert(Identity::new(&ItsyBitsyM4::unique_id()));
+
+        // The allocator is borrowed by the USB classes for the lifetime of the program
+        let usb_allocator = c.local.usb_allocator.insert(pins.usb.usb_allocator(
+            peripherals.USB,
+            &mut clocks,
+            &mut peripherals.MCLK,
+            &mut pins.port,
+        ));
+
+        let serial = SerialPort::new(usb_allocator);
+        let usb_dev = UsbDeviceBuilder::new(usb_allocator, UsbVidPid(defaults::VID, defaults::PID))
+            .manufacturer(defaults::MANUFACTURER)
+            .product(defaults::PRODUCT)
+            .serial_number(identity.serial_number())
+            .device_class(USB_CLASS_CDC)
+            .build();
+
+        (
+            Shared { usb_dev, serial },
+            Local { led },
+            init::Monotonics(),
+        )
+    }
+
+    #[task(binds = USB_OTHER, priority = 2, shared = [usb_dev, serial])]
+    fn usb_other(c: usb_other::Context) {
+        (c.shared.usb_dev, c.shared.serial).lock(poll_usb);
+    }
+
+    #[task(binds = USB_TRCPT0, priority = 2, shared = [usb_dev, serial])]
+    fn usb_trcpt0(c: usb_trcpt0::Context) {
+        (c.shared.usb_dev, c.shared.serial).lock(poll_usb);
+    }
+
+    #[task(binds = USB_TRCPT1, priority = 2, shared = [usb_dev, serial])]
+    fn usb_trcpt1(c: usb_trcpt1::Context) {
+        (c.shared.usb_dev, c.shared.serial).lock(poll_usb);
+    }
+
+    /// Writing to the bitbanged LED is slow, so it is done outside the USB interrupts
+    #[task(priority = 1, capacity = 4, local = [led])]
+    fn set_color(c: set_color::Context, color: RGB8) {
+        c.local.led.set(color);
+    }
+
+    fn poll_usb(
+        usb_dev: &mut UsbDevice<'static, UsbBus>,
+        serial: &mut SerialPort<'static, UsbBus>,
+    ) {
+        usb_dev.poll(&mut [serial]);
+        let mut buf = [0u8; 64];
+
+        if let Ok(count) = serial.read(&mut buf) {
+            for c in buf[..count].iter() {
+                let color = match *c as char {
+                    'R' => RGB8 { r: 60, g: 0, b: 0 },
+                    'G' => RGB8 { r: 0, g: 60, b: 0 },
+                    'B' => RGB8 { r: 0, g: 0, b: 60 },
+                    'W' => RGB8 {
+                        r: 60,
+                        g: 60,
+                        b: 60,
+                    },
+                    'O' => StatusLed::OFF,
+                    _ => continue,
+                };
+
+                // Colors sent faster than the LED is updated are dropped
+                set_color::spawn(color).ok();
+            }
+        };
+    }
+}
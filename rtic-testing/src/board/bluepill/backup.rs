@@ -0,0 +1,32 @@
+//! The ten 16-bit backup data registers DR1 to DR10, which hold the
+//! [`BackupState`](crate::backup::BackupState). Writing them needs the backup domain write access
+//! enabled by `BluePill::init`.
+
+use stm32f1xx_hal::pac;
+
+use crate::backup::{BackupMemory, STATE_LEN};
+
+pub struct BackupRegisters;
+
+impl BackupRegisters {
+    fn registers(&self) -> &pac::bkp::RegisterBlock {
+        unsafe { &*pac::BKP::ptr() }
+    }
+}
+
+impl BackupMemory for BackupRegisters {
+    fn read(&self) -> [u8; STATE_LEN] {
+        let mut data = [0; STATE_LEN];
+        for (bytes, register) in data.chunks_exact_mut(2).zip(self.registers().dr.iter()) {
+            bytes.copy_from_slice(&register.read().d().bits().to_le_bytes());
+        }
+        data
+    }
+
+    fn write(&mut self, data: &[u8; STATE_LEN]) {
+        for (bytes, register) in data.chunks_exact(2).zip(self.registers().dr.iter()) {
+            let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+            register.write(|w| w.d().bits(value));
+        }
+    }
+}
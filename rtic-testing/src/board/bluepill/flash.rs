@@ -0,0 +1,86 @@
+//! Programming of the internal flash.
+
+use core::ptr::read_volatile;
+
+use stm32f1xx_hal::flash::{self, FlashSize, SectorSize, FLASH_START};
+
+use crate::flash::{Error, Flash};
+
+/// Flash size in KiB, programmed into the device signature by ST
+const FLASH_SIZE_REGISTER: *const u16 = 0x1FFF_F7E0 as *const u16;
+
+/// The STM32F1 flash, erased in pages of 1 KiB on low and medium density devices and 2 KiB on
+/// larger ones.
+pub struct InternalFlash {
+    parts: flash::Parts,
+    size: FlashSize,
+    sector_size: SectorSize,
+}
+
+impl InternalFlash {
+    /// Takes the flash once the clocks have been frozen with its `acr`
+    pub fn new(parts: flash::Parts) -> Self {
+        let kib = unsafe { read_volatile(FLASH_SIZE_REGISTER) };
+
+        // The reference manual ties the page size to the device density, which only roughly
+        // follows the flash size. The "128 KiB" parts are medium density.
+        let sector_size = if kib > 128 {
+            SectorSize::Sz2K
+        } else {
+            SectorSize::Sz1K
+        };
+        let size = match kib {
+            0..=16 => FlashSize::Sz16K,
+            17..=32 => FlashSize::Sz32K,
+            33..=64 => FlashSize::Sz64K,
+            65..=128 => FlashSize::Sz128K,
+            129..=256 => FlashSize::Sz256K,
+            257..=384 => FlashSize::Sz384K,
+            385..=512 => FlashSize::Sz512K,
+            513..=768 => FlashSize::Sz768K,
+            _ => FlashSize::Sz1M,
+        };
+
+        Self {
+            parts,
+            size,
+            sector_size,
+        }
+    }
+
+    /// Size of the flash in bytes
+    pub fn size(&self) -> u32 {
+        self.size as u32 * 1024
+    }
+}
+
+impl Flash for InternalFlash {
+    fn page_size(&self) -> u32 {
+        self.sector_size as u32 * 1024
+    }
+
+    fn end_address(&self) -> u32 {
+        FLASH_START + self.size()
+    }
+
+    fn read(&self, address: u32, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = unsafe { read_volatile((address as usize + i) as *const u8) };
+        }
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        if address < FLASH_START
+            || !address.is_multiple_of(self.page_size())
+            || data.len() != self.page_size() as usize
+        {
+            return Err(Error::InvalidAddress);
+        }
+
+        // The writer unlocks the flash for each operation and verifies what it did
+        let offset = address - FLASH_START;
+        let mut writer = self.parts.writer(self.sector_size, self.size);
+        writer.page_erase(offset).map_err(|_| Error::Erase)?;
+        writer.write(offset, data).map_err(|_| Error::Write)
+    }
+}
@@ -0,0 +1,209 @@
+//! The "Blue Pill" STM32F103C8 development board.
+//!
+//! The host power enable is on PB12 and the host reset (active low) on PB13. The UART is USART1 on
+//! PA9 (TX) and PA10 (RX) at [`UART_BAUD_RATE`], the sensor I2C bus is I2C1 on PB6 (SCL) and
+//! PB7 (SDA) at [`I2C_FREQUENCY`]. The board needs an 8 MHz crystal for USB. The on-chip sensors
+//! aren't read and fans and the RTC aren't supported, the firmware barely fits the flash as it is.
+//! The state kept across resets is in the backup data registers, see [`BackupRegisters`].
+
+mod backup;
+mod flash;
+mod monotonic;
+
+use cortex_m::asm::delay;
+use embedded_hal::digital::v2::OutputPin;
+use smart_leds::RGB8;
+use stm32f1xx_hal::{
+    gpio::{
+        gpioa::{PA10, PA9},
+        gpiob::{PB12, PB13, PB6, PB7},
+        gpioc::PC13,
+        Alternate, Floating, Input, OpenDrain, Output, PushPull,
+    },
+    i2c::{self, BlockingI2c},
+    prelude::*,
+    serial::{self, Serial},
+    timer::Timer,
+    usb::{Peripheral, UsbBus, UsbBusType},
+};
+use usb_device::bus::UsbBusAllocator;
+
+use super::{Board, GpioPower, Led, Parts};
+use crate::fans::NoFans;
+use crate::identity::UniqueId;
+use crate::rtc::NoRtc;
+use crate::sensors::{analog::NoAnalog, SensorConfig};
+
+pub use self::backup::BackupRegisters;
+pub use self::flash::InternalFlash;
+pub use self::monotonic::Tim2Monotonic;
+pub use stm32f1xx_hal::pac;
+
+pub const UART_BAUD_RATE: u32 = 115_200;
+/// Standard mode, which all of the sensor parts support
+pub const I2C_FREQUENCY: u32 = 100_000;
+
+const UNIQUE_ID_ADDRESS: u32 = 0x1FFF_F7E8;
+
+pub struct BluePill;
+
+impl Board for BluePill {
+    type Device = pac::Peripherals;
+    type Mono = Tim2Monotonic;
+    type UsbBus = UsbBusType;
+    type Flash = InternalFlash;
+    type StatusLed = StatusLed;
+    type Uart = Serial<pac::USART1, (PA9<Alternate<PushPull>>, PA10<Input<Floating>>)>;
+    type I2c = BlockingI2c<pac::I2C1, (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>)>;
+    type Analog = NoAnalog;
+    type FanPwm = NoFans;
+    type Tachometers = NoFans;
+    type Rtc = NoRtc;
+    type Backup = BackupRegisters;
+    type Power = GpioPower<PB12<Output<PushPull>>, PB13<Output<PushPull>>>;
+
+    // The first 64 KiB are reserved for the BMC firmware itself
+    const APP_START: u32 = 0x0801_0000;
+    const APP_END: u32 = u32::MAX;
+    const UF2_FAMILY_ID: u32 = 0x5EE2_1072;
+    const UF2_INFO: &'static str = concat!(
+        "UF2 Bootloader ",
+        env!("CARGO_PKG_VERSION"),
+        "\r\nModel: Racklet BMC\r\nBoard-ID: STM32F103C8-BluePill\r\n"
+    );
+    const ON_CHIP_SENSORS: &'static [SensorConfig] = &[];
+    const FAN_SENSORS: &'static [SensorConfig] = &[];
+
+    fn init(
+        mut device: pac::Peripherals,
+        usb_allocator: &'static mut Option<UsbBusAllocator<UsbBusType>>,
+    ) -> Parts<Self> {
+        let mut flash = device.FLASH.constrain();
+        let mut rcc = device.RCC.constrain();
+        let mut afio = device.AFIO.constrain(&mut rcc.apb2);
+        // Enables the clocks and write access of the backup domain, which stay on for
+        // [`BackupRegisters`] after the HAL handle is dropped
+        rcc.bkp
+            .constrain(device.BKP, &mut rcc.apb1, &mut device.PWR);
+
+        let clocks = rcc
+            .cfgr
+            .use_hse(8.mhz())
+            .sysclk(48.mhz())
+            .pclk1(24.mhz())
+            .freeze(&mut flash.acr);
+        assert!(clocks.usbclk_valid());
+
+        let tim2 = Timer::tim2(device.TIM2, &clocks, &mut rcc.apb1).release();
+        let mono = Tim2Monotonic::new(tim2, clocks.pclk1_tim());
+
+        let mut gpioa = device.GPIOA.split(&mut rcc.apb2);
+        let mut gpiob = device.GPIOB.split(&mut rcc.apb2);
+        let mut gpioc = device.GPIOC.split(&mut rcc.apb2);
+
+        let status_led = StatusLed::new(gpioc.pc13.into_open_drain_output(&mut gpioc.crh));
+
+        let tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+        let uart = Serial::usart1(
+            device.USART1,
+            (tx, gpioa.pa10),
+            &mut afio.mapr,
+            serial::Config::default().baudrate(UART_BAUD_RATE.bps()),
+            clocks,
+            &mut rcc.apb2,
+        );
+
+        // The timeouts keep a stuck bus from hanging the application
+        let i2c = BlockingI2c::i2c1(
+            device.I2C1,
+            (
+                gpiob.pb6.into_alternate_open_drain(&mut gpiob.crl),
+                gpiob.pb7.into_alternate_open_drain(&mut gpiob.crl),
+            ),
+            &mut afio.mapr,
+            i2c::Mode::standard(I2C_FREQUENCY.hz()),
+            clocks,
+            &mut rcc.apb1,
+            1000,
+            10,
+            1000,
+            1000,
+        );
+
+        let power = GpioPower::new(
+            gpiob.pb12.into_push_pull_output(&mut gpiob.crh),
+            gpiob.pb13.into_push_pull_output(&mut gpiob.crh),
+        );
+
+        // The board has a pull-up resistor on D+. Pulling D+ down signals a reset to the host,
+        // without it the host doesn't notice the device was reflashed.
+        let mut usb_dp = gpioa.pa12.into_push_pull_output(&mut gpioa.crh);
+        usb_dp.set_low().unwrap();
+        delay(clocks.sysclk().0 / 100);
+
+        let usb = Peripheral {
+            usb: device.USB,
+            pin_dm: gpioa.pa11,
+            pin_dp: usb_dp.into_floating_input(&mut gpioa.crh),
+        };
+        let usb_allocator = usb_allocator.insert(UsbBus::new(usb));
+
+        Parts {
+            mono,
+            usb_allocator,
+            flash: InternalFlash::new(flash),
+            status_led,
+            uart,
+            i2c,
+            analog: NoAnalog,
+            fan_pwm: NoFans,
+            tachometers: NoFans,
+            rtc: NoRtc,
+            power,
+            sysclk: clocks.sysclk().0,
+        }
+    }
+
+    fn unique_id() -> UniqueId {
+        // The 96-bit unique device ID register, RM0008 section 30.2
+        let id = unsafe { core::slice::from_raw_parts(UNIQUE_ID_ADDRESS as *const u8, 12) };
+        UniqueId::from_slice(id).unwrap()
+    }
+
+    fn backup() -> BackupRegisters {
+        BackupRegisters
+    }
+}
+
+/// The green LED on PC13, lit when the pin is pulled low
+pub struct StatusLed {
+    pin: PC13<Output<OpenDrain>>,
+    color: RGB8,
+}
+
+impl StatusLed {
+    /// Turns the LED off
+    pub fn new(pin: PC13<Output<OpenDrain>>) -> Self {
+        let mut led = Self {
+            pin,
+            color: RGB8::default(),
+        };
+        led.off();
+        led
+    }
+}
+
+impl Led for StatusLed {
+    fn set(&mut self, color: RGB8) {
+        if color == RGB8::default() {
+            self.pin.set_high().unwrap();
+        } else {
+            self.pin.set_low().unwrap();
+        }
+        self.color = color;
+    }
+
+    fn color(&self) -> RGB8 {
+        self.color
+    }
+}
@@ -0,0 +1,96 @@
+//! RTIC monotonic for scheduling software tasks using `spawn_after` and `spawn_at`.
+
+use rtic_monotonic::Monotonic;
+use stm32f1xx_hal::{pac::TIM2, time::Hertz};
+
+use crate::monotonic::{Duration, Instant, TICK_HZ};
+
+/// All TIM2 status flags, which are cleared by writing zero
+const SR_FLAGS: u32 = 0x1E5F;
+
+/// The 16-bit TIM2, extended to 64 bits in software by counting overflows. The counter wraps every
+/// 65 ms, so the overflow interrupt has to be served within that time.
+pub struct Tim2Monotonic {
+    tim2: TIM2,
+    overflows: u64,
+}
+
+impl Tim2Monotonic {
+    /// Sets up the timer, which must have its clock enabled, e.g. by
+    /// `Timer::tim2(..).release()`. `clock` is the timer input clock and must be a multiple of
+    /// [`TICK_HZ`]. The counter is started by RTIC once `init` returns.
+    pub fn new(tim2: TIM2, clock: Hertz) -> Self {
+        assert!(
+            clock.0.is_multiple_of(TICK_HZ) && clock.0 / TICK_HZ <= 1 << 16,
+            "unsupported monotonic clock frequency {}",
+            clock.0
+        );
+
+        tim2.cr1.modify(|_, w| w.cen().clear_bit());
+        tim2.psc
+            .write(|w| w.psc().bits((clock.0 / TICK_HZ - 1) as u16));
+        tim2.arr.write(|w| w.arr().bits(u16::MAX));
+
+        // The prescaler is only loaded on an update event, with URS set generating one doesn't
+        // raise the overflow flag
+        tim2.cr1.modify(|_, w| w.urs().set_bit());
+        tim2.egr.write(|w| w.ug().set_bit());
+
+        Self { tim2, overflows: 0 }
+    }
+
+    fn clear_flags(&self, mask: u32) {
+        // Writing ones leaves the flags untouched, so this can't race with the hardware
+        self.tim2.sr.write(|w| unsafe { w.bits(SR_FLAGS & !mask) });
+    }
+}
+
+impl Monotonic for Tim2Monotonic {
+    // The overflow interrupt needs to stay enabled to extend the counter
+    const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+    type Instant = Instant;
+    type Duration = Duration;
+
+    fn now(&mut self) -> Instant {
+        let count = self.tim2.cnt.read().cnt().bits();
+
+        // A pending overflow has happened before reading the count if the count is small
+        let pending = self.tim2.sr.read().uif().bit_is_set() && count < u16::MAX / 2;
+        let overflows = self.overflows + pending as u64;
+
+        Instant::from_ticks(overflows << 16 | count as u64)
+    }
+
+    fn set_compare(&mut self, instant: Instant) {
+        // Instants further than a wrap away trigger early, RTIC then just sets the compare again
+        self.tim2
+            .ccr1
+            .write(|w| w.ccr().bits(instant.ticks() as u16));
+    }
+
+    fn clear_compare_flag(&mut self) {
+        self.clear_flags(1 << 1);
+    }
+
+    fn zero() -> Instant {
+        Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {
+        self.tim2.cnt.write(|w| w.cnt().bits(0));
+        self.clear_flags(SR_FLAGS);
+        self.tim2
+            .dier
+            .write(|w| w.uie().set_bit().cc1ie().set_bit());
+        self.tim2.cr1.modify(|_, w| w.cen().set_bit());
+        self.overflows = 0;
+    }
+
+    fn on_interrupt(&mut self) {
+        if self.tim2.sr.read().uif().bit_is_set() {
+            self.clear_flags(1 << 0);
+            self.overflows += 1;
+        }
+    }
+}
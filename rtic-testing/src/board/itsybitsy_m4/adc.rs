@@ -0,0 +1,134 @@
+//! The on-chip sensors read through ADC0: the temperature sensor of the MCU, its I/O supply and
+//! the voltages on A2 and A3.
+
+use embedded_hal::adc::{Channel, OneShot};
+use itsybitsy_m4::{
+    adc::Adc,
+    clock::GenericClockController,
+    gpio::{Pb8, Pb9, PfB},
+    pac::{
+        adc0::{avgctrl::SAMPLENUM_A, refctrl::REFSEL_A},
+        gclk::pchctrl::GEN_A,
+        ADC0, MCLK, SUPC,
+    },
+};
+
+use crate::sensors::analog::{self, Analog, AnalogInput, TemperatureCalibration};
+
+/// An internal input of ADC0 by its MUXPOS value
+struct Internal<const MUXPOS: u8>;
+
+impl<const MUXPOS: u8> Channel<ADC0> for Internal<MUXPOS> {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        MUXPOS
+    }
+}
+
+/// VDDIO divided by 4
+type ScaledIoVcc = Internal<0x1A>;
+/// The temperature sensor proportional to absolute temperature
+type Ptat = Internal<0x1C>;
+/// The temperature sensor complementary to absolute temperature
+type Ctat = Internal<0x1D>;
+
+/// The temperature log row in the NVM software calibration area
+const TEMPERATURE_LOG_ADDRESS: u32 = 0x0080_0100;
+
+const RESOLUTION_BITS: u32 = 12;
+/// The internal reference, set to the 1.0 V the temperature sensor is calibrated with
+const INTREF_MILLIVOLTS: i32 = 1000;
+/// The analog supply, which the pins are measured against
+const VDDANA_MILLIVOLTS: i32 = 3300;
+
+pub struct OnChipAnalog {
+    adc: Adc0,
+    a2: Pb8<PfB>,
+    a3: Pb9<PfB>,
+    calibration: TemperatureCalibration,
+}
+
+impl OnChipAnalog {
+    /// Sets up ADC0 clocked from GCLK11
+    pub fn new(
+        adc: ADC0,
+        a2: Pb8<PfB>,
+        a3: Pb9<PfB>,
+        supc: &SUPC,
+        mclk: &mut MCLK,
+        clocks: &mut GenericClockController,
+    ) -> Self {
+        // The temperature sensor is off unless enabled, on demand it is powered by the ADC only
+        // while converting
+        supc.vref
+            .modify(|_, w| w.sel()._1v0().tsen().set_bit().ondemand().set_bit());
+
+        let mut adc = Adc::adc0(adc, mclk, clocks, GEN_A::GCLK11);
+        // Averaging steadies the readings, the result stays 12-bit
+        adc.samples(SAMPLENUM_A::_16);
+        adc.reference(REFSEL_A::INTVCC1);
+
+        // Programmed at the factory, readable like flash
+        let words = unsafe { (TEMPERATURE_LOG_ADDRESS as *const [u32; 4]).read_volatile() };
+
+        Self {
+            adc: Adc0 {
+                adc,
+                reference: REFSEL_A::INTVCC1,
+            },
+            a2,
+            a3,
+            calibration: TemperatureCalibration::from_words(words),
+        }
+    }
+}
+
+impl Analog for OnChipAnalog {
+    fn millivolts(&mut self, input: AnalogInput) -> Option<i32> {
+        let millivolts =
+            |result| analog::adc_millivolts(result, RESOLUTION_BITS, VDDANA_MILLIVOLTS);
+        match input {
+            AnalogInput::IoSupply => {
+                let result = self.adc.convert(&mut ScaledIoVcc {}, REFSEL_A::INTREF)?;
+                let scaled = analog::adc_millivolts(result, RESOLUTION_BITS, INTREF_MILLIVOLTS);
+                Some(analog::undivided_millivolts(scaled, 4))
+            }
+            AnalogInput::Pin(2) => Some(millivolts(
+                self.adc.convert(&mut self.a2, REFSEL_A::INTVCC1)?,
+            )),
+            AnalogInput::Pin(3) => Some(millivolts(
+                self.adc.convert(&mut self.a3, REFSEL_A::INTVCC1)?,
+            )),
+            AnalogInput::Pin(_) => None,
+        }
+    }
+
+    fn millicelsius(&mut self) -> Option<i32> {
+        let ptat = self.adc.convert(&mut Ptat {}, REFSEL_A::INTREF)?;
+        let ctat = self.adc.convert(&mut Ctat {}, REFSEL_A::INTREF)?;
+        self.calibration.millicelsius(ptat, ctat)
+    }
+}
+
+/// ADC0 with the reference it is set to
+struct Adc0 {
+    adc: Adc<ADC0>,
+    reference: REFSEL_A,
+}
+
+impl Adc0 {
+    fn convert<C: Channel<ADC0, ID = u8>>(
+        &mut self,
+        input: &mut C,
+        reference: REFSEL_A,
+    ) -> Option<u16> {
+        if reference != self.reference {
+            self.adc.reference(reference);
+            self.reference = reference;
+            // The first conversion after changing the reference is inaccurate
+            let _: u16 = self.adc.read(input).ok()?;
+        }
+        self.adc.read(input).ok()
+    }
+}
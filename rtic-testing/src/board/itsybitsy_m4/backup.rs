@@ -0,0 +1,26 @@
+//! The 8 KiB backup SRAM, of which the start holds the [`BackupState`](crate::backup::BackupState).
+//! It keeps its contents through resets and the backup sleep mode, its clock is on after reset.
+
+use core::ptr;
+
+use crate::backup::{BackupMemory, STATE_LEN};
+
+const BKUPRAM_ADDRESS: usize = 0x4700_0000;
+
+pub struct BackupRam;
+
+impl BackupMemory for BackupRam {
+    fn read(&self) -> [u8; STATE_LEN] {
+        let mut data = [0; STATE_LEN];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile((BKUPRAM_ADDRESS + i) as *const u8) };
+        }
+        data
+    }
+
+    fn write(&mut self, data: &[u8; STATE_LEN]) {
+        for (i, &byte) in data.iter().enumerate() {
+            unsafe { ptr::write_volatile((BKUPRAM_ADDRESS + i) as *mut u8, byte) };
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! SHA-256 on the Integrity Check Monitor (ICM) and AES on the AES peripheral, enabled with the
+//! `hw-crypto` feature.
+//!
+//! The ICM hashes regions of memory described to it in a list, starting from an initial hash value
+//! of our choice. The hash in progress is kept here and each run continues it over the blocks
+//! buffered since the last one, so the peripheral is only held while it runs. It doesn't pad the
+//! message, [`IcmAes::sha256_finish`] does. Each run and each AES block happens in a critical
+//! section, so handles can be taken from any priority. A run or a block that doesn't finish within
+//! [`MAX_POLLS`] fails.
+
+use core::convert::TryInto;
+use core::ptr::{self, addr_of, addr_of_mut};
+
+use cortex_m::{asm, interrupt};
+
+use super::pac;
+use crate::crypto::{self, AesKey, Crypto, Timeout, AES_BLOCK_LEN, SHA256_BLOCK_LEN as BLOCK_LEN};
+
+/// The blocks hashed in one run of the ICM
+const BUFFER_BLOCKS: usize = 4;
+const BUFFER_LEN: usize = BUFFER_BLOCKS * BLOCK_LEN;
+/// How often the end of a run or a block is polled for before giving up, far longer than either
+/// takes
+const MAX_POLLS: u32 = 100_000;
+
+/// The region configuration of the descriptor: the end of the list, SHA-256
+const RCFG_EOM: u32 = 1 << 2;
+const RCFG_ALGO_SHA256: u32 = 1 << 12;
+
+/// The initial hash value of SHA-256
+const SHA256_IV: [u8; 32] = [
+    0x6a, 0x09, 0xe6, 0x67, 0xbb, 0x67, 0xae, 0x85, 0x3c, 0x6e, 0xf3, 0x72, 0xa5, 0x4f, 0xf5, 0x3a,
+    0x51, 0x0e, 0x52, 0x7f, 0x9b, 0x05, 0x68, 0x8c, 0x1f, 0x83, 0xd9, 0xab, 0x5b, 0xe0, 0xcd, 0x19,
+];
+
+/// What the ICM reads and writes: the hash area has to be aligned to 128 bytes, the descriptor
+/// list to 64.
+#[repr(C, align(128))]
+struct IcmMemory {
+    /// The digest of region 0
+    hash: [u32; 8],
+    _reserved: [u32; 8],
+    /// The region descriptor: start address, configuration, size in blocks minus one, next
+    descriptor: [u32; 4],
+    buffer: [u8; BUFFER_LEN],
+}
+
+pub struct IcmAes {
+    memory: IcmMemory,
+    /// The hash so far, in the byte order of the digest
+    state: [u8; 32],
+    /// The bytes in `memory.buffer`
+    buffered: usize,
+    /// The bytes hashed since the start
+    length: u64,
+    /// Whether a run of the hash in progress timed out
+    timed_out: bool,
+}
+
+impl IcmAes {
+    /// Enables the clocks of the peripherals, which are off after reset
+    pub(super) fn new() -> Self {
+        interrupt::free(|_| {
+            let mclk = unsafe { &*pac::MCLK::ptr() };
+            mclk.ahbmask.modify(|_, w| w.icm_().set_bit());
+            mclk.apbcmask
+                .modify(|_, w| w.icm_().set_bit().aes_().set_bit());
+        });
+        Self {
+            memory: IcmMemory {
+                hash: [0; 8],
+                _reserved: [0; 8],
+                descriptor: [0; 4],
+                buffer: [0; BUFFER_LEN],
+            },
+            state: SHA256_IV,
+            buffered: 0,
+            length: 0,
+            timed_out: false,
+        }
+    }
+
+    /// Continues the hash over the whole blocks in the buffer, or fails the hash if the ICM doesn't
+    /// finish
+    fn hash_buffer(&mut self) {
+        let blocks = (self.buffered / BLOCK_LEN) as u32;
+        let state = &mut self.state;
+        let memory = &mut self.memory;
+        let finished = interrupt::free(|_| {
+            let icm = unsafe { &*pac::ICM::ptr() };
+            icm.ctrl.write(|w| w.swrst().set_bit());
+            icm.cfg.write(|w| w.uihash().set_bit().ualgo().sha256());
+            for (uihval, word) in icm.uihval.iter().zip(state.chunks(4)) {
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                uihval.write(|w| unsafe { w.val().bits(word) });
+            }
+
+            let descriptor = [
+                memory.buffer.as_ptr() as u32,
+                RCFG_EOM | RCFG_ALGO_SHA256,
+                blocks - 1,
+                0,
+            ];
+            unsafe { ptr::write_volatile(addr_of_mut!(memory.descriptor), descriptor) };
+            let descriptor = addr_of!(memory.descriptor) as u32;
+            icm.dscr
+                .write(|w| unsafe { w.dasa().bits(descriptor >> 6) });
+            let hash = addr_of!(memory.hash) as u32;
+            icm.hash.write(|w| unsafe { w.hasa().bits(hash >> 7) });
+
+            // The descriptor and the buffer have to be in the memory before the ICM reads them
+            asm::dsb();
+            icm.ctrl.write(|w| w.enable().set_bit());
+            let finished = (0..MAX_POLLS).any(|_| icm.isr.read().rhc().bits() & 1 != 0);
+            icm.ctrl.write(|w| w.disable().set_bit());
+            asm::dsb();
+            if !finished {
+                return false;
+            }
+
+            let hash = unsafe { ptr::read_volatile(addr_of!(memory.hash)) };
+            for (bytes, word) in state.chunks_mut(4).zip(hash) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+            true
+        });
+        self.timed_out |= !finished;
+        self.buffered = 0;
+    }
+
+    fn aes(
+        &mut self,
+        key: AesKey,
+        block: &mut [u8; AES_BLOCK_LEN],
+        encrypt: bool,
+    ) -> Result<(), Timeout> {
+        interrupt::free(|_| {
+            let aes = unsafe { &*pac::AES::ptr() };
+            aes.ctrla.write(|w| w.swrst().set_bit());
+            while aes.ctrla.read().swrst().bit_is_set() {}
+            aes.ctrla.write(|w| {
+                let w = w.aesmode().ecb().startmode().manual().cipher().bit(encrypt);
+                match key {
+                    AesKey::Aes128(_) => w.keysize()._128bit(),
+                    AesKey::Aes256(_) => w.keysize()._256bit(),
+                }
+            });
+            aes.ctrla.modify(|_, w| w.enable().set_bit());
+
+            let key: &[u8] = match key {
+                AesKey::Aes128(key) => key,
+                AesKey::Aes256(key) => key,
+            };
+            for (keyword, word) in aes.keyword.iter().zip(key.chunks(4)) {
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                keyword.write(|w| unsafe { w.bits(word) });
+            }
+            aes.databufptr.write(|w| unsafe { w.indataptr().bits(0) });
+            for word in block.chunks(4) {
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                aes.indata.write(|w| unsafe { w.bits(word) });
+            }
+
+            aes.ctrlb.write(|w| w.start().set_bit());
+            let finished = (0..MAX_POLLS).any(|_| aes.intflag.read().enccmp().bit_is_set());
+            if finished {
+                aes.databufptr.write(|w| unsafe { w.indataptr().bits(0) });
+                for word in block.chunks_mut(4) {
+                    word.copy_from_slice(&aes.indata.read().bits().to_le_bytes());
+                }
+            }
+            aes.ctrla.write(|w| w.enable().clear_bit());
+            if finished {
+                Ok(())
+            } else {
+                Err(Timeout)
+            }
+        })
+    }
+}
+
+impl Crypto for IcmAes {
+    fn sha256_start(&mut self) {
+        self.state = SHA256_IV;
+        self.buffered = 0;
+        self.length = 0;
+        self.timed_out = false;
+    }
+
+    fn sha256_update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let len = (BUFFER_LEN - self.buffered).min(data.len());
+            self.memory.buffer[self.buffered..self.buffered + len].copy_from_slice(&data[..len]);
+            self.buffered += len;
+            data = &data[len..];
+            if self.buffered == BUFFER_LEN {
+                self.hash_buffer();
+            }
+        }
+    }
+
+    fn sha256_finish(&mut self) -> Result<[u8; 32], Timeout> {
+        let (padding, len) = crypto::sha256_padding(self.length);
+        self.sha256_update(&padding[..len]);
+        if self.buffered > 0 {
+            self.hash_buffer();
+        }
+
+        let result = if self.timed_out {
+            Err(Timeout)
+        } else {
+            Ok(self.state)
+        };
+        self.sha256_start();
+        result
+    }
+
+    fn aes_encrypt(&mut self, key: AesKey, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), Timeout> {
+        self.aes(key, block, true)
+    }
+
+    fn aes_decrypt(&mut self, key: AesKey, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), Timeout> {
+        self.aes(key, block, false)
+    }
+}
@@ -0,0 +1,116 @@
+//! Two 4-pin fans, driven by TCC0 on D10 and D11 with their tachometers on D9 and D12. The
+//! tachometers are open collector and need pull-ups to 3.3 V, their falling edges are counted in
+//! the EIC interrupts.
+
+use embedded_hal::Pwm;
+use itsybitsy_m4::{
+    clock::{GClock, GenericClockController},
+    eic::{
+        self,
+        pin::{EicPin, ExtInt3, ExtInt7, Sense},
+    },
+    gpio::{Pa19, Pa20, Pa21, Pa23, PfA, PfG, PinMode, Port},
+    pac::{EIC, MCLK, TCC0},
+    prelude::*,
+    pwm::{Channel, TCC0Pinout, Tcc0Pwm},
+};
+
+use crate::fans::{FanPwm, Pulses, Tachometers, MAX_FANS};
+
+/// The PWM frequency 4-pin fans expect
+pub const PWM_FREQUENCY: u32 = 25_000;
+
+/// D10 is TCC0/WO[0] on CC0, D11 is TCC0/WO[1] on CC1
+pub struct FanOutputs {
+    tcc: Tcc0Pwm,
+    _fan2: Pa21<PfG>,
+}
+
+impl FanOutputs {
+    /// Starts the fans at full speed
+    pub fn new(
+        tcc: TCC0,
+        fan1: Pa20<PfG>,
+        fan2: Pa21<PfG>,
+        gclk: &GClock,
+        clocks: &mut GenericClockController,
+        mclk: &mut MCLK,
+    ) -> Self {
+        let clock = clocks.tcc0_tcc1(gclk).unwrap();
+        let tcc = Tcc0Pwm::new(
+            &clock,
+            PWM_FREQUENCY.hz(),
+            tcc,
+            TCC0Pinout::Pa20(fan1),
+            mclk,
+        );
+        let mut outputs = Self { tcc, _fan2: fan2 };
+        outputs.set_duty(100);
+        outputs
+    }
+}
+
+impl FanPwm for FanOutputs {
+    fn set_duty(&mut self, percent: u8) {
+        let duty = self.tcc.get_max_duty() * percent.min(100) as u32 / 100;
+        self.tcc.set_duty(Channel::_0, duty);
+        self.tcc.set_duty(Channel::_1, duty);
+    }
+}
+
+pub struct FanTachometers {
+    fan1: ExtInt3<Pa19<PfA>>,
+    fan2: ExtInt7<Pa23<PfA>>,
+    pulses: [u32; 2],
+}
+
+impl FanTachometers {
+    /// Sets up the EIC, which runs from the ultra low power 32 kHz clock
+    pub fn new<M1: PinMode, M2: PinMode>(
+        eic: EIC,
+        fan1: Pa19<M1>,
+        fan2: Pa23<M2>,
+        gclk: &GClock,
+        clocks: &mut GenericClockController,
+        mclk: &mut MCLK,
+        port: &mut Port,
+    ) -> Self {
+        let clock = clocks.eic(gclk).unwrap();
+        let mut eic = eic::init_with_ulp32k(mclk, clock, eic);
+
+        let mut fan1: ExtInt3<_> = fan1.into_ei(port);
+        fan1.sense(&mut eic, Sense::FALL);
+        fan1.enable_interrupt(&mut eic);
+        let mut fan2: ExtInt7<_> = fan2.into_ei(port);
+        fan2.sense(&mut eic, Sense::FALL);
+        fan2.enable_interrupt(&mut eic);
+        eic.finalize();
+
+        Self {
+            fan1,
+            fan2,
+            pulses: [0; 2],
+        }
+    }
+}
+
+impl Tachometers for FanTachometers {
+    fn on_interrupt(&mut self) {
+        if self.fan1.is_interrupt() {
+            self.fan1.clear_interrupt();
+            self.pulses[0] += 1;
+        }
+        if self.fan2.is_interrupt() {
+            self.fan2.clear_interrupt();
+            self.pulses[1] += 1;
+        }
+    }
+
+    fn take_pulses(&mut self) -> Pulses {
+        let mut pulses = [None; MAX_FANS];
+        for (taken, counted) in pulses.iter_mut().zip(self.pulses.iter_mut()) {
+            *taken = Some(core::mem::take(counted));
+        }
+        pulses
+    }
+}
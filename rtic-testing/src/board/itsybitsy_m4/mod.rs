@@ -0,0 +1,347 @@
+//! The Adafruit ItsyBitsy M4 Express with the ATSAMD51G19A.
+//!
+//! The host power enable is on D5, which drives a rail-to-rail 5 V high level, and the host reset
+//! (active low) on D7. The UART on D0/D1 runs at [`UART_BAUD_RATE`], the sensor I2C bus on
+//! SDA/SCL at [`I2C_FREQUENCY`].
+//!
+//! Besides its temperature and I/O supply, the MCU measures the USB supply on A2 through a 1:2
+//! divider and the 12 V supply of the host on A3 through a 1:6 divider, see [`ON_CHIP_SENSORS`].
+//!
+//! Two fans are driven on D10 and D11 and their speeds measured on D9 and D12, see [`fans`].
+//!
+//! The RTC keeps the time in clock mode, see [`ClockRtc`]. The state kept across resets is in the
+//! backup SRAM, see [`BackupRam`]. The firmware runs from one of the A/B slots of the bootloader,
+//! see [`Board::SLOTS`], guarded by the [`Wdt`] while on trial. With the `hw-crypto` feature,
+//! SHA-256 and AES are computed on the ICM and AES peripherals, see [`IcmAes`].
+//!
+//! [`ON_CHIP_SENSORS`]: Board::ON_CHIP_SENSORS
+
+mod adc;
+mod backup;
+#[cfg(feature = "hw-crypto")]
+mod crypto;
+pub mod fans;
+mod monotonic;
+mod nvm;
+mod rtc;
+mod watchdog;
+
+use itsybitsy_m4::{
+    clock::{ClockGenId, GenericClockController},
+    dotstar_bitbang,
+    gpio::{
+        Input, Output, Pa12, Pa13, Pa15, Pa16, Pa17, Pa18, Pa27, Pb2, Pb3, PfC, PfD, Port, PullUp,
+        PushPull,
+    },
+    pins::{self, Dotstar},
+    prelude::*,
+    sercom::{I2CMaster2, Sercom2Pad0, Sercom2Pad1, Sercom3Pad0, Sercom3Pad1, UART3},
+    timer::SpinTimer,
+    usb::UsbBus,
+};
+use smart_leds::{SmartLedsWrite, RGB8};
+use usb_device::bus::UsbBusAllocator;
+
+use super::{Board, GpioPower, Led, Parts};
+use crate::boot::{Layout, Slot};
+#[cfg(not(feature = "hw-crypto"))]
+use crate::crypto::Software;
+use crate::fans::fan_sensor;
+use crate::identity::UniqueId;
+use crate::sensors::{AnalogInput, Channel, Device, Entity, SensorConfig, Thresholds};
+
+pub use self::adc::OnChipAnalog;
+pub use self::backup::BackupRam;
+#[cfg(feature = "hw-crypto")]
+pub use self::crypto::IcmAes;
+pub use self::fans::{FanOutputs, FanTachometers};
+pub use self::monotonic::Tc0Monotonic;
+pub use self::nvm::Nvm;
+pub use self::rtc::ClockRtc;
+pub use self::watchdog::Wdt;
+pub use itsybitsy_m4::pac;
+
+pub const UART_BAUD_RATE: u32 = 115_200;
+/// Standard mode, which all of the sensor parts support
+pub const I2C_FREQUENCY: u32 = 100_000;
+
+/// Cycles to wait between clock edges when bitbanging the DotStar
+const DOTSTAR_SPIN_CYCLES: u32 = 12;
+
+type DotStar = apa102_spi::Apa102<
+    bitbang_hal::spi::SPI<
+        Pa27<Input<PullUp>>,
+        Pb3<Output<PushPull>>,
+        Pb2<Output<PushPull>>,
+        SpinTimer,
+    >,
+>;
+
+pub struct ItsyBitsyM4;
+
+impl Board for ItsyBitsyM4 {
+    type Device = pac::Peripherals;
+    type Mono = Tc0Monotonic;
+    type UsbBus = UsbBus;
+    type Flash = Nvm;
+    type StatusLed = StatusLed;
+    type Uart = UART3<Sercom3Pad1<Pa16<PfD>>, Sercom3Pad0<Pa17<PfD>>, (), ()>;
+    type I2c = I2CMaster2<Sercom2Pad0<Pa12<PfC>>, Sercom2Pad1<Pa13<PfC>>>;
+    type Analog = OnChipAnalog;
+    type FanPwm = FanOutputs;
+    type Tachometers = FanTachometers;
+    type Rtc = ClockRtc;
+    type Backup = BackupRam;
+    #[cfg(feature = "hw-crypto")]
+    type Crypto = IcmAes;
+    #[cfg(not(feature = "hw-crypto"))]
+    type Crypto = Software;
+    type Watchdog = Wdt;
+    type Power = GpioPower<Pa15<Output<PushPull>>, Pa18<Output<PushPull>>>;
+
+    // Updates go into the slot the firmware isn't running from
+    const APP_START: u32 = Self::SLOTS.start(Slot::RUNNING.other());
+    const APP_END: u32 = Self::SLOTS.end(Slot::RUNNING.other());
+    // The bootloader takes the first 64 KiB and the slots the rest of the flash up to the last
+    // 32 KiB. Of those the first page is spare, the copies of the security page take the next two
+    // and the settings the last. memory/itsybitsy_m4_*.x link the binaries into them.
+    const SLOTS: Layout = Layout {
+        slot_a: 0x0001_0000,
+        slot_b: 0x0004_4000,
+        slot_size: 0x0003_4000,
+        security_pages: [0x0007_A000, 0x0007_C000],
+    };
+    const UF2_FAMILY_ID: u32 = 0x5511_4460;
+    const UF2_INFO: &'static str = concat!(
+        "UF2 Bootloader ",
+        env!("CARGO_PKG_VERSION"),
+        "\r\nModel: Racklet BMC\r\nBoard-ID: SAMD51G19A-ItsyBitsy-M4\r\n"
+    );
+    const ON_CHIP_SENSORS: &'static [SensorConfig] = &[
+        SensorConfig {
+            name: "MCU Temp",
+            entity: Entity::SystemBoard,
+            device: Device::McuTemperature,
+            channel: Channel::Temperature,
+            thresholds: Thresholds {
+                upper_non_critical: Some(70_000),
+                upper_critical: Some(85_000),
+                ..Thresholds::NONE
+            },
+        },
+        SensorConfig {
+            name: "BMC 3.3V",
+            entity: Entity::SystemBoard,
+            device: Device::Adc {
+                input: AnalogInput::IoSupply,
+                divider_ratio: 1,
+            },
+            channel: Channel::Voltage,
+            thresholds: Thresholds {
+                lower_critical: Some(3_000),
+                lower_non_critical: Some(3_135),
+                upper_non_critical: Some(3_465),
+                upper_critical: Some(3_600),
+            },
+        },
+        SensorConfig {
+            name: "USB 5V",
+            entity: Entity::PowerSupply,
+            device: Device::Adc {
+                input: AnalogInput::Pin(2),
+                divider_ratio: 2,
+            },
+            channel: Channel::Voltage,
+            thresholds: Thresholds {
+                lower_critical: Some(4_400),
+                lower_non_critical: Some(4_750),
+                upper_non_critical: Some(5_250),
+                upper_critical: Some(5_500),
+            },
+        },
+        SensorConfig {
+            name: "Host 12V",
+            entity: Entity::PowerSupply,
+            device: Device::Adc {
+                input: AnalogInput::Pin(3),
+                divider_ratio: 6,
+            },
+            channel: Channel::Voltage,
+            thresholds: Thresholds {
+                lower_critical: Some(10_800),
+                lower_non_critical: Some(11_400),
+                upper_non_critical: Some(12_600),
+                upper_critical: Some(13_200),
+            },
+        },
+    ];
+    const FAN_SENSORS: &'static [SensorConfig] = &[fan_sensor("Fan 1", 0), fan_sensor("Fan 2", 1)];
+
+    fn init(
+        mut device: pac::Peripherals,
+        usb_allocator: &'static mut Option<UsbBusAllocator<UsbBus>>,
+    ) -> Parts<Self> {
+        let mut clocks = GenericClockController::with_internal_32kosc(
+            device.GCLK,
+            &mut device.MCLK,
+            &mut device.OSC32KCTRL,
+            &mut device.OSCCTRL,
+            &mut device.NVMCTRL,
+        );
+        let sysclk: itsybitsy_m4::time::Hertz = clocks.gclk0().into();
+
+        // GCLK5 runs at 2 MHz, which the monotonic divides down to its 1 MHz tick
+        let gclk5 = clocks.get_gclk(ClockGenId::GCLK5).unwrap();
+        let timer_clock = clocks.tc0_tc1(&gclk5).unwrap();
+        let mono = Tc0Monotonic::new(device.TC0, device.TC1, &timer_clock, &mut device.MCLK);
+
+        let mut pins = itsybitsy_m4::Pins::new(device.PORT);
+
+        let dotstar = Dotstar {
+            ci: pins.dotstar_ci,
+            di: pins.dotstar_di,
+            nc: pins.dotstar_nc,
+        };
+        let status_led = StatusLed::new(dotstar, &mut pins.port);
+
+        let uart = itsybitsy_m4::uart(
+            pins::UART {
+                rx: pins.d0,
+                tx: pins.d1,
+            },
+            &mut clocks,
+            UART_BAUD_RATE.hz(),
+            device.SERCOM3,
+            &mut device.MCLK,
+            &mut pins.port,
+        );
+
+        let i2c = itsybitsy_m4::i2c_master(
+            pins::I2C {
+                sda: pins.i2c_sda,
+                scl: pins.i2c_scl,
+            },
+            &mut clocks,
+            I2C_FREQUENCY.hz(),
+            device.SERCOM2,
+            &mut device.MCLK,
+            &mut pins.port,
+        );
+
+        let analog = OnChipAnalog::new(
+            device.ADC0,
+            pins.a2.into_function_b(&mut pins.port),
+            pins.a3.into_function_b(&mut pins.port),
+            &device.SUPC,
+            &mut device.MCLK,
+            &mut clocks,
+        );
+
+        let gclk0 = clocks.gclk0();
+        let fan_pwm = FanOutputs::new(
+            device.TCC0,
+            pins.d10.into_function_g(&mut pins.port),
+            pins.d11.into_function_g(&mut pins.port),
+            &gclk0,
+            &mut clocks,
+            &mut device.MCLK,
+        );
+        let tachometers = FanTachometers::new(
+            device.EIC,
+            pins.d9,
+            pins.d12,
+            &gclk5,
+            &mut clocks,
+            &mut device.MCLK,
+            &mut pins.port,
+        );
+
+        let power = GpioPower::new(
+            pins.d5.into_push_pull_output(&mut pins.port),
+            pins.d7.into_push_pull_output(&mut pins.port),
+        );
+
+        let usb = pins::USB {
+            dm: pins.usb_dm,
+            dp: pins.usb_dp,
+        };
+        let usb_allocator = usb_allocator.insert(usb.usb_allocator(
+            device.USB,
+            &mut clocks,
+            &mut device.MCLK,
+            &mut pins.port,
+        ));
+
+        Parts {
+            mono,
+            usb_allocator,
+            flash: Nvm::new(device.NVMCTRL),
+            status_led,
+            uart,
+            i2c,
+            analog,
+            fan_pwm,
+            tachometers,
+            rtc: ClockRtc::new(device.RTC, &mut device.MCLK),
+            power,
+            sysclk: sysclk.0,
+        }
+    }
+
+    fn unique_id() -> UniqueId {
+        UniqueId::from_slice(&itsybitsy_m4::serial_number()).unwrap()
+    }
+
+    fn backup() -> BackupRam {
+        BackupRam
+    }
+
+    #[cfg(feature = "hw-crypto")]
+    fn crypto() -> IcmAes {
+        IcmAes::new()
+    }
+
+    #[cfg(not(feature = "hw-crypto"))]
+    fn crypto() -> Software {
+        Software::new()
+    }
+
+    fn watchdog() -> Wdt {
+        Wdt
+    }
+}
+
+/// The DotStar (APA102) RGB LED on the board. The SPI is bitbanged using a busy-looping
+/// [`SpinTimer`], so no timer peripherals are taken from the application.
+pub struct StatusLed {
+    dotstar: DotStar,
+    color: RGB8,
+}
+
+impl StatusLed {
+    pub const OFF: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+
+    /// Sets up the LED pins and turns the LED off
+    pub fn new(pins: Dotstar, port: &mut Port) -> Self {
+        let dotstar = dotstar_bitbang(pins, port, SpinTimer::new(DOTSTAR_SPIN_CYCLES));
+        let mut led = Self {
+            dotstar,
+            color: Self::OFF,
+        };
+        led.set(Self::OFF);
+        led
+    }
+}
+
+impl Led for StatusLed {
+    /// Each component goes up to 255, but the LED is very bright already at low values.
+    fn set(&mut self, color: RGB8) {
+        // Bitbanging can't fail, the error type is only there to satisfy the SPI traits
+        self.dotstar.write([color].iter().cloned()).unwrap();
+        self.color = color;
+    }
+
+    fn color(&self) -> RGB8 {
+        self.color
+    }
+}
@@ -0,0 +1,112 @@
+//! RTIC monotonic for scheduling software tasks using `spawn_after` and `spawn_at`.
+
+use itsybitsy_m4::clock::Tc0Tc1Clock;
+use itsybitsy_m4::pac::{tc0::COUNT32, MCLK, TC0, TC1};
+use rtic_monotonic::Monotonic;
+
+use crate::monotonic::{Duration, Instant, TICK_HZ};
+
+/// TC0 and TC1 chained into a 32-bit counter, extended to 64 bits in software by counting
+/// overflows. The counter wraps every 71 minutes, the extended one never in practice.
+pub struct Tc0Monotonic {
+    tc0: TC0,
+    _tc1: TC1,
+    overflows: u32,
+}
+
+impl Tc0Monotonic {
+    /// Sets up the timer pair, `clock` must run at [`TICK_HZ`] times 1, 2, 4, 8, 16, 64, 256 or
+    /// 1024, e.g. the 2 MHz GCLK5 set up by `GenericClockController`. The counter is started by
+    /// RTIC once `init` returns.
+    pub fn new(tc0: TC0, tc1: TC1, clock: &Tc0Tc1Clock, mclk: &mut MCLK) -> Self {
+        // TC1 is the slave of TC0 in 32-bit mode, but both need their bus clocks
+        mclk.apbamask
+            .modify(|_, w| w.tc0_().set_bit().tc1_().set_bit());
+
+        let tc = tc0.count32();
+        tc.ctrla.write(|w| w.swrst().set_bit());
+        while tc.syncbusy.read().swrst().bit_is_set() {}
+
+        tc.ctrla.write(|w| {
+            w.mode().count32();
+            match clock.freq().0 / TICK_HZ {
+                1 => w.prescaler().div1(),
+                2 => w.prescaler().div2(),
+                4 => w.prescaler().div4(),
+                8 => w.prescaler().div8(),
+                16 => w.prescaler().div16(),
+                64 => w.prescaler().div64(),
+                256 => w.prescaler().div256(),
+                1024 => w.prescaler().div1024(),
+                _ => panic!("unsupported monotonic clock frequency {}", clock.freq().0),
+            }
+        });
+
+        Self {
+            tc0,
+            _tc1: tc1,
+            overflows: 0,
+        }
+    }
+
+    fn tc(&self) -> &COUNT32 {
+        self.tc0.count32()
+    }
+
+    fn count(&self) -> u32 {
+        let tc = self.tc();
+        tc.ctrlbset.write(|w| w.cmd().readsync());
+        while tc.syncbusy.read().ctrlb().bit_is_set() {}
+        tc.count.read().bits()
+    }
+}
+
+impl Monotonic for Tc0Monotonic {
+    // The overflow interrupt needs to stay enabled to extend the counter
+    const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+    type Instant = Instant;
+    type Duration = Duration;
+
+    fn now(&mut self) -> Instant {
+        let count = self.count();
+
+        // A pending overflow has happened before reading the count if the count is small
+        let pending = self.tc().intflag.read().ovf().bit_is_set() && count < u32::MAX / 2;
+        let overflows = self.overflows + pending as u32;
+
+        Instant::from_ticks((overflows as u64) << 32 | count as u64)
+    }
+
+    fn set_compare(&mut self, instant: Instant) {
+        // Instants further than a wrap away trigger early, RTIC then just sets the compare again
+        let tc = self.tc();
+        tc.cc[0].write(|w| unsafe { w.cc().bits(instant.ticks() as u32) });
+        while tc.syncbusy.read().cc0().bit_is_set() {}
+    }
+
+    fn clear_compare_flag(&mut self) {
+        self.tc().intflag.write(|w| w.mc0().set_bit());
+    }
+
+    fn zero() -> Instant {
+        Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {
+        let tc = self.tc();
+        tc.intflag.write(|w| w.ovf().set_bit().mc0().set_bit());
+        tc.intenset.write(|w| w.ovf().set_bit().mc0().set_bit());
+        tc.ctrla.modify(|_, w| w.enable().set_bit());
+        while tc.syncbusy.read().enable().bit_is_set() {}
+        self.overflows = 0;
+    }
+
+    fn on_interrupt(&mut self) {
+        let tc = self.tc();
+        if tc.intflag.read().ovf().bit_is_set() {
+            tc.intflag.write(|w| w.ovf().set_bit());
+            self.overflows += 1;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Programming of the internal flash.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use itsybitsy_m4::pac::NVMCTRL;
+
+use crate::flash::{Error, Flash};
+
+/// The SAMD51 NVM controller. It erases 8 KiB blocks and writes 512 byte pages.
+pub struct Nvm {
+    nvmctrl: NVMCTRL,
+}
+
+impl Nvm {
+    const BLOCK_SIZE: u32 = 8192;
+    const WRITE_PAGE_SIZE: u32 = 512;
+
+    pub fn new(nvmctrl: NVMCTRL) -> Self {
+        // Manual write mode, pages are only written by an explicit command
+        nvmctrl.ctrla.modify(|_, w| w.wmode().man());
+        Self { nvmctrl }
+    }
+
+    /// Size of the flash in bytes
+    pub fn size(&self) -> u32 {
+        self.nvmctrl.param.read().nvmp().bits() as u32 * Self::WRITE_PAGE_SIZE
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmctrl.status.read().ready().bit_is_clear() {}
+    }
+
+    fn clear_flags(&self) {
+        self.nvmctrl.intflag.write(|w| {
+            w.done()
+                .set_bit()
+                .addre()
+                .set_bit()
+                .proge()
+                .set_bit()
+                .locke()
+                .set_bit()
+                .nvme()
+                .set_bit()
+        });
+    }
+
+    /// Waits for the running command, returning whether it succeeded
+    fn command_succeeded(&self) -> bool {
+        self.wait_ready();
+        let flags = self.nvmctrl.intflag.read();
+        !(flags.addre().bit_is_set()
+            || flags.proge().bit_is_set()
+            || flags.locke().bit_is_set()
+            || flags.nvme().bit_is_set())
+    }
+}
+
+impl Flash for Nvm {
+    fn page_size(&self) -> u32 {
+        Self::BLOCK_SIZE
+    }
+
+    fn end_address(&self) -> u32 {
+        // The flash is mapped from address 0
+        self.size()
+    }
+
+    fn read(&self, address: u32, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = unsafe { read_volatile((address as usize + i) as *const u8) };
+        }
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        if !address.is_multiple_of(Self::BLOCK_SIZE) || data.len() != Self::BLOCK_SIZE as usize {
+            return Err(Error::InvalidAddress);
+        }
+
+        let nvm = &self.nvmctrl;
+
+        self.wait_ready();
+        self.clear_flags();
+        nvm.addr.write(|w| unsafe { w.addr().bits(address) });
+        nvm.ctrlb.write(|w| w.cmdex().key().cmd().eb());
+        if !self.command_succeeded() {
+            return Err(Error::Erase);
+        }
+
+        for (i, page) in data.chunks(Self::WRITE_PAGE_SIZE as usize).enumerate() {
+            let page_address = address + i as u32 * Self::WRITE_PAGE_SIZE;
+
+            self.wait_ready();
+            self.clear_flags();
+            nvm.ctrlb.write(|w| w.cmdex().key().cmd().pbc());
+            self.wait_ready();
+
+            // The page buffer only accepts 32-bit writes, which also latch the page address
+            for (j, word) in page.chunks(4).enumerate() {
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                unsafe { write_volatile((page_address as usize + j * 4) as *mut u32, word) };
+            }
+
+            nvm.addr.write(|w| unsafe { w.addr().bits(page_address) });
+            nvm.ctrlb.write(|w| w.cmdex().key().cmd().wp());
+            if !self.command_succeeded() {
+                return Err(Error::Write);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! The RTC in clock/calendar mode, counting seconds from the 1.024 kHz output of the ultra low
+//! power 32 kHz oscillator that the clock setup selects for it.
+//!
+//! The RTC is only reset at power-on, so it keeps the time across other resets. It is left stopped
+//! until it is set, a running RTC in clock mode has therefore been set before.
+
+use bmc_proto::time::DateTime;
+use itsybitsy_m4::pac::{MCLK, RTC};
+
+use crate::rtc::Rtc;
+
+/// The year the 6-bit year field counts from, a leap year as the RTC takes every fourth year to
+/// be one
+const BASE_YEAR: i32 = 2000;
+
+pub struct ClockRtc {
+    rtc: RTC,
+    running: bool,
+}
+
+impl ClockRtc {
+    pub fn new(rtc: RTC, mclk: &mut MCLK) -> Self {
+        mclk.apbamask.modify(|_, w| w.rtc_().set_bit());
+        let ctrla = rtc.mode2().ctrla.read();
+        let running = ctrla.enable().bit_is_set() && ctrla.mode().is_clock();
+        Self { rtc, running }
+    }
+
+    fn sync(&self) {
+        while self.rtc.mode2().syncbusy.read().bits() != 0 {}
+    }
+
+    /// Switches the RTC to clock mode at 1 Hz from its 1.024 kHz clock and starts it
+    fn start(&mut self) {
+        let mode2 = self.rtc.mode2();
+        mode2.ctrla.modify(|_, w| w.swrst().set_bit());
+        self.sync();
+        // The CLOCK register is synchronized for reading, so it can be read at any time
+        mode2
+            .ctrla
+            .write(|w| w.mode().clock().prescaler().div1024().clocksync().set_bit());
+        self.sync();
+        mode2.ctrla.modify(|_, w| w.enable().set_bit());
+        self.sync();
+        self.running = true;
+    }
+}
+
+impl Rtc for ClockRtc {
+    fn unix_time(&mut self) -> Option<i64> {
+        if !self.running {
+            return None;
+        }
+        let clock = self.rtc.mode2().clock.read();
+        let time = DateTime {
+            year: BASE_YEAR + clock.year().bits() as i32,
+            month: clock.month().bits(),
+            day: clock.day().bits(),
+            hour: clock.hour().bits(),
+            minute: clock.minute().bits(),
+            second: clock.second().bits(),
+        };
+        Some(time.to_unix())
+    }
+
+    fn set_unix_time(&mut self, seconds: i64) {
+        if !self.running {
+            self.start();
+        }
+        let time = DateTime::from_unix(seconds);
+        self.rtc.mode2().clock.write(|w| unsafe {
+            w.year()
+                .bits((time.year - BASE_YEAR) as u8)
+                .month()
+                .bits(time.month)
+                .day()
+                .bits(time.day)
+                .hour()
+                .bits(time.hour)
+                .minute()
+                .bits(time.minute)
+                .second()
+                .bits(time.second)
+        });
+        self.sync();
+    }
+}
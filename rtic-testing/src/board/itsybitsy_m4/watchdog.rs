@@ -0,0 +1,39 @@
+//! The WDT, counting the 1.024 kHz output of the ultra low power 32 kHz oscillator, which is always
+//! on. Its registers are only reset by a reset, so any handle can feed it.
+
+use itsybitsy_m4::pac;
+
+use crate::watchdog::Watchdog;
+
+/// Resets the MCU 8 s after it was last fed, with the early warning after 4 s
+pub struct Wdt;
+
+impl Wdt {
+    fn sync(wdt: &pac::wdt::RegisterBlock) {
+        while wdt.syncbusy.read().bits() != 0 {}
+    }
+}
+
+impl Watchdog for Wdt {
+    fn start(&mut self) {
+        let wdt = unsafe { &*pac::WDT::ptr() };
+        wdt.config.write(|w| w.per().cyc8192());
+        wdt.ewctrl.write(|w| w.ewoffset().cyc4096());
+        wdt.intenset.write(|w| w.ew().set_bit());
+        wdt.ctrla.write(|w| w.enable().set_bit());
+        Self::sync(wdt);
+    }
+
+    fn feed(&mut self) {
+        let wdt = unsafe { &*pac::WDT::ptr() };
+        if wdt.ctrla.read().enable().bit_is_set() {
+            wdt.clear.write(|w| w.clear().key());
+            Self::sync(wdt);
+        }
+    }
+
+    fn acknowledge_warning(&mut self) {
+        let wdt = unsafe { &*pac::WDT::ptr() };
+        wdt.intflag.write(|w| w.ew().set_bit());
+    }
+}
@@ -0,0 +1,211 @@
+//! Board support. The application is written against [`Board`], the implementation for the board
+//! being built for is selected with a `board-*` cargo feature and exported as [`CurrentBoard`]
+//! together with the peripheral access crate of its MCU as `pac`.
+
+use core::fmt::Debug;
+use embedded_hal::{blocking::i2c::WriteRead, digital::v2::OutputPin, serial};
+use rtic_monotonic::Monotonic;
+use smart_leds::RGB8;
+use usb_device::bus::{UsbBus, UsbBusAllocator};
+
+use crate::backup::BackupMemory;
+#[cfg(feature = "slots")]
+use crate::boot::Layout;
+#[cfg(feature = "slots")]
+use crate::crypto::Crypto;
+use crate::fans::{FanPwm, Tachometers};
+use crate::flash::Flash;
+use crate::identity::UniqueId;
+use crate::monotonic::{Duration, Instant};
+use crate::rtc::Rtc;
+use crate::sensors::{Analog, SensorConfig};
+#[cfg(feature = "slots")]
+use crate::watchdog::Watchdog;
+
+#[cfg(feature = "board-bluepill")]
+pub mod bluepill;
+#[cfg(feature = "board-itsybitsy-m4")]
+pub mod itsybitsy_m4;
+
+#[cfg(feature = "board-bluepill")]
+pub use self::bluepill::{pac, BluePill as CurrentBoard};
+#[cfg(feature = "board-itsybitsy-m4")]
+pub use self::itsybitsy_m4::{pac, ItsyBitsyM4 as CurrentBoard};
+
+#[cfg(not(any(feature = "board-bluepill", feature = "board-itsybitsy-m4", feature = "sim")))]
+compile_error!("no board selected, enable one of the `board-*` features");
+
+#[cfg(all(feature = "board-bluepill", feature = "board-itsybitsy-m4"))]
+compile_error!("more than one board selected, enable only one of the `board-*` features");
+
+#[cfg(all(feature = "hw-crypto", not(feature = "board-itsybitsy-m4")))]
+compile_error!("only the ItsyBitsy M4 has crypto peripherals, it alone supports `hw-crypto`");
+
+#[cfg(all(feature = "board-bluepill", feature = "slots"))]
+compile_error!("the Blue Pill has no bootloader, it doesn't support the `slots` features");
+
+pub trait Board: Sized {
+    /// The device peripherals handed to RTIC `init`
+    type Device;
+    type Mono: Monotonic<Instant = Instant, Duration = Duration>;
+    type UsbBus: UsbBus + 'static;
+    type Flash: Flash;
+    type StatusLed: Led;
+    /// The serial port exposed on the board pins
+    type Uart: serial::Read<u8> + serial::Write<u8>;
+    /// The I2C bus of the sensors, see [`crate::sensors`]
+    type I2c: WriteRead;
+    /// The ADC of the on-chip sensors
+    type Analog: Analog;
+    /// The fans, see [`crate::fans`]
+    type FanPwm: FanPwm;
+    type Tachometers: Tachometers;
+    /// The real-time clock, see [`crate::rtc`]
+    type Rtc: Rtc;
+    /// The memory kept across resets, see [`crate::backup`]
+    type Backup: BackupMemory;
+    /// The SHA-256 and AES implementation, see [`crate::crypto`]
+    #[cfg(feature = "slots")]
+    type Crypto: Crypto;
+    /// The watchdog guarding firmware on trial, see [`crate::watchdog`]
+    #[cfg(feature = "slots")]
+    type Watchdog: Watchdog;
+    type Power: PowerControl;
+
+    /// Start of the flash region the updates received are programmed into
+    const APP_START: u32;
+    /// End of that region, the settings in the last page of the flash end it where they come first
+    const APP_END: u32;
+    /// The firmware slots of the bootloader, see [`crate::boot`]
+    #[cfg(feature = "slots")]
+    const SLOTS: Layout;
+    /// UF2 family ID of the MCU
+    const UF2_FAMILY_ID: u32;
+    /// Contents of `INFO_UF2.TXT` on the GhostFat drive
+    const UF2_INFO: &'static str;
+    /// The sensors read through [`Board::Analog`], polled together with the I2C sensors
+    const ON_CHIP_SENSORS: &'static [SensorConfig];
+    /// The speeds of the fans, read from the pulses counted by [`Board::Tachometers`]
+    const FAN_SENSORS: &'static [SensorConfig];
+
+    /// Sets up the clocks and splits the peripherals into the parts used by the application. The
+    /// USB allocator is placed into `usb_allocator` so the USB classes can borrow it forever.
+    fn init(
+        device: Self::Device,
+        usb_allocator: &'static mut Option<UsbBusAllocator<Self::UsbBus>>,
+    ) -> Parts<Self>;
+
+    /// The unique ID programmed into the MCU at the factory, see [`crate::identity`]
+    fn unique_id() -> UniqueId;
+
+    /// The backup memory, usable once [`Board::init`] has run. Crash handlers take it too, so the
+    /// application only writes it at startup and before resets.
+    fn backup() -> Self::Backup;
+
+    /// A handle to the crypto implementation. The application takes one in init for each task
+    /// that hashes, the bootloader one for the boot.
+    #[cfg(feature = "slots")]
+    fn crypto() -> Self::Crypto;
+
+    /// A handle to the watchdog, which the bootloader starts and the application feeds
+    #[cfg(feature = "slots")]
+    fn watchdog() -> Self::Watchdog;
+}
+
+pub struct Parts<B: Board> {
+    pub mono: B::Mono,
+    pub usb_allocator: &'static UsbBusAllocator<B::UsbBus>,
+    pub flash: B::Flash,
+    pub status_led: B::StatusLed,
+    pub uart: B::Uart,
+    pub i2c: B::I2c,
+    pub analog: B::Analog,
+    pub fan_pwm: B::FanPwm,
+    pub tachometers: B::Tachometers,
+    pub rtc: B::Rtc,
+    pub power: B::Power,
+    /// Core clock frequency in Hz
+    pub sysclk: u32,
+}
+
+/// A status LED, single color LEDs are lit for any color except off.
+pub trait Led {
+    fn set(&mut self, color: RGB8);
+
+    /// The color last set
+    fn color(&self) -> RGB8;
+
+    fn off(&mut self) {
+        self.set(RGB8::default());
+    }
+
+    fn is_on(&self) -> bool {
+        self.color() != RGB8::default()
+    }
+}
+
+/// The power and reset lines of the host managed by the BMC
+pub trait PowerControl {
+    fn set_power(&mut self, on: bool);
+
+    fn is_powered(&self) -> bool;
+
+    /// Holds the host in reset while `asserted`
+    fn set_reset(&mut self, asserted: bool);
+}
+
+/// [`PowerControl`] through a power enable GPIO and an active low reset GPIO
+pub struct GpioPower<E, R> {
+    enable: E,
+    reset: R,
+    powered: bool,
+}
+
+impl<E, R> GpioPower<E, R>
+where
+    E: OutputPin,
+    R: OutputPin,
+    E::Error: Debug,
+    R::Error: Debug,
+{
+    /// The host starts powered off and out of reset
+    pub fn new(enable: E, reset: R) -> Self {
+        let mut power = Self {
+            enable,
+            reset,
+            powered: false,
+        };
+        power.set_power(false);
+        power.set_reset(false);
+        power
+    }
+}
+
+impl<E, R> PowerControl for GpioPower<E, R>
+where
+    E: OutputPin,
+    R: OutputPin,
+    E::Error: Debug,
+    R::Error: Debug,
+{
+    fn set_power(&mut self, on: bool) {
+        if on {
+            self.enable.set_high().unwrap();
+        } else {
+            self.enable.set_low().unwrap();
+        }
+        self.powered = on;
+    }
+
+    fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    fn set_reset(&mut self, asserted: bool) {
+        if asserted {
+            self.reset.set_low().unwrap();
+        } else {
+            self.reset.set_high().unwrap();
+        }
+    }
+}
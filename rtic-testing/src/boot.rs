@@ -0,0 +1,808 @@
+//! A/B firmware slots for the bootloader in `src/bin/bootloader.rs`.
+//!
+//! The flash holds the bootloader and two slots, each with a signed image: the image header of
+//! [`bmc_proto::image`] followed by the firmware linked for the slot. The bootloader starts the
+//! confirmed slot, or once the running firmware has received an update into the other slot, that
+//! slot on trial. The firmware on trial confirms itself after running for [`CONFIRM_DELAY`], until
+//! then every boot of it is counted in the backup memory. After [`MAX_TRIAL_BOOTS`] boots without
+//! confirming, e.g. as the firmware keeps crashing, the bootloader rolls back to the confirmed
+//! slot. The slots are kept in the settings:
+//!
+//! | Key          | Value                                          |
+//! |--------------|------------------------------------------------|
+//! | `boot.slot`  | The confirmed slot, `a` (default) or `b`       |
+//! | `boot.trial` | The slot on trial, removed once it is decided |
+//!
+//! The boots on trial are counted in the backup memory, which loses them on power cycles. An image
+//! is only started when its hash and its signature by a trusted firmware key check out, see
+//! [`crate::keys`].
+//!
+//! Images also have to have at least the minimum security version the BMC is provisioned with,
+//! which keeps older firmware with known vulnerabilities out although it is validly signed. The
+//! minimum is kept in the security page next to the keys, and it can only be raised.
+//!
+//! What became of the last update received is kept in [`LAST_UPDATE`] until the next reset, for
+//! the host to learn whether the BMC installs it.
+
+use core::cell::Cell;
+use core::fmt;
+use core::ops::Range;
+
+use bmc_proto::image::{self, ImageHeader, Rejection, UpdateStatus, HEADER_LEN, HEADER_SIZE};
+use bmc_proto::keys::KeyRole;
+use bmc_proto::time::DateTime;
+#[cfg(not(feature = "sim"))]
+use cortex_m::interrupt::{self, Mutex};
+
+use crate::backup::{BackupMemory, BackupState};
+use crate::config::{self, ConfigStore};
+use crate::crypto::{Crypto, Timeout};
+use crate::flash::{self, Flash};
+use crate::keys::SecurityPage;
+use crate::logging::{info, warn};
+use crate::monotonic::Duration;
+
+/// How many boots the firmware on trial gets to confirm itself in
+pub const MAX_TRIAL_BOOTS: u8 = 3;
+
+/// How long the firmware on trial has to run before it confirms itself
+pub const CONFIRM_DELAY: Duration = Duration::millis(30_000);
+
+/// How long after receiving an update the firmware resets into it, which lets the host finish
+/// writing to the drive
+pub const INSTALL_DELAY: Duration = Duration::millis(1_000);
+
+/// The bytes hashed at a time while verifying
+const CHUNK_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    /// The slot the firmware is linked for
+    #[cfg(not(feature = "slot-b"))]
+    pub const RUNNING: Slot = Slot::A;
+    #[cfg(feature = "slot-b")]
+    pub const RUNNING: Slot = Slot::B;
+
+    pub const fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "a" => Some(Slot::A),
+            "b" => Some(Slot::B),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Slot::A => "a",
+            Slot::B => "b",
+        }
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Slot::A => "A",
+            Slot::B => "B",
+        })
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Slot {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Slot::A => defmt::write!(f, "A"),
+            Slot::B => defmt::write!(f, "B"),
+        }
+    }
+}
+
+/// Where the slots are in the flash of the board
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub slot_a: u32,
+    pub slot_b: u32,
+    /// Of each slot, including the image header
+    pub slot_size: u32,
+    /// The flash pages holding the two copies of the security page, see [`crate::keys`]
+    pub security_pages: [u32; 2],
+}
+
+impl Layout {
+    pub const fn start(&self, slot: Slot) -> u32 {
+        match slot {
+            Slot::A => self.slot_a,
+            Slot::B => self.slot_b,
+        }
+    }
+
+    pub const fn end(&self, slot: Slot) -> u32 {
+        self.start(slot) + self.slot_size
+    }
+
+    /// Where the vector table of the firmware in `slot` is
+    pub const fn load_address(&self, slot: Slot) -> u32 {
+        self.start(slot) + HEADER_SIZE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No image header at the start of the slot
+    Header(image::DecodeError),
+    /// The image is linked for another address
+    WrongAddress(u32),
+    /// The image doesn't fit the slot
+    TooLarge,
+    HashMismatch,
+    InvalidSignature,
+    /// The security version of the image is below the minimum
+    Rollback {
+        version: u32,
+        minimum: u32,
+    },
+    /// Neither copy of the security page is valid, so no image is trusted
+    SecurityPage,
+    /// The hash peripheral timed out
+    Crypto,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Header(image::DecodeError::InvalidMagic) => f.write_str("no image"),
+            Error::Header(e) => write!(f, "invalid image header ({:?})", e),
+            Error::WrongAddress(address) => write!(f, "image linked for {:#010x}", address),
+            Error::TooLarge => f.write_str("image larger than the slot"),
+            Error::HashMismatch => f.write_str("image hash mismatch"),
+            Error::InvalidSignature => f.write_str("invalid image signature"),
+            Error::Rollback { version, minimum } => write!(
+                f,
+                "image security version {} is below the minimum {}",
+                version, minimum
+            ),
+            Error::SecurityPage => f.write_str("corrupt security page"),
+            Error::Crypto => f.write_str("hashing the image timed out"),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::Header(image::DecodeError::InvalidMagic) => defmt::write!(f, "no image"),
+            Error::Header(_) => defmt::write!(f, "invalid image header"),
+            Error::WrongAddress(address) => {
+                defmt::write!(f, "image linked for {=u32:#010x}", address)
+            }
+            Error::TooLarge => defmt::write!(f, "image larger than the slot"),
+            Error::HashMismatch => defmt::write!(f, "image hash mismatch"),
+            Error::InvalidSignature => defmt::write!(f, "invalid image signature"),
+            Error::Rollback { version, minimum } => defmt::write!(
+                f,
+                "image security version {=u32} is below the minimum {=u32}",
+                version,
+                minimum
+            ),
+            Error::SecurityPage => defmt::write!(f, "corrupt security page"),
+            Error::Crypto => defmt::write!(f, "hashing the image timed out"),
+        }
+    }
+}
+
+impl From<Error> for Rejection {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Header(image::DecodeError::InvalidMagic) => Rejection::NoImage,
+            Error::Header(_) => Rejection::InvalidHeader,
+            Error::WrongAddress(address) => Rejection::WrongAddress(address),
+            Error::TooLarge => Rejection::TooLarge,
+            Error::HashMismatch => Rejection::HashMismatch,
+            Error::InvalidSignature => Rejection::InvalidSignature,
+            Error::Rollback { version, minimum } => Rejection::Rollback { version, minimum },
+            Error::SecurityPage => Rejection::SecurityPage,
+            Error::Crypto => Rejection::Crypto,
+        }
+    }
+}
+
+// The sim runs on the host, which has threads to lock out instead of interrupts to mask
+#[cfg(feature = "sim")]
+type Mutex<T> = std::sync::Mutex<T>;
+
+/// What became of the last update received since the firmware started
+pub struct LastUpdate(Mutex<Cell<Option<UpdateStatus>>>);
+
+/// The last update of the application
+pub static LAST_UPDATE: LastUpdate = LastUpdate::new();
+
+impl LastUpdate {
+    pub const fn new() -> Self {
+        Self(Mutex::new(Cell::new(None)))
+    }
+
+    pub fn set(&self, status: UpdateStatus) {
+        self.with(|last| last.set(Some(status)));
+    }
+
+    /// `None` until an update is received
+    pub fn get(&self) -> Option<UpdateStatus> {
+        self.with(|last| last.get())
+    }
+
+    #[cfg(not(feature = "sim"))]
+    fn with<R>(&self, f: impl FnOnce(&Cell<Option<UpdateStatus>>) -> R) -> R {
+        interrupt::free(|cs| f(self.0.borrow(cs)))
+    }
+
+    #[cfg(feature = "sim")]
+    fn with<R>(&self, f: impl FnOnce(&Cell<Option<UpdateStatus>>) -> R) -> R {
+        f(&self.0.lock().unwrap())
+    }
+}
+
+impl Default for LastUpdate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks the image in `slot`, read through `read`, against its header, the trusted firmware keys
+/// and the minimum security version, hashing it with `crypto`
+pub fn verify(
+    layout: &Layout,
+    slot: Slot,
+    crypto: &mut dyn Crypto,
+    mut read: impl FnMut(u32, &mut [u8]),
+) -> Result<ImageHeader, Error> {
+    let security =
+        SecurityPage::load(layout.security_pages, &mut read).ok_or(Error::SecurityPage)?;
+    let start = layout.start(slot);
+    let mut header = [0; HEADER_LEN];
+    read(start, &mut header);
+    let header = ImageHeader::decode(&header).map_err(Error::Header)?;
+
+    if header.load_address != layout.load_address(slot) {
+        return Err(Error::WrongAddress(header.load_address));
+    }
+    if header.image_size > layout.slot_size - HEADER_SIZE {
+        return Err(Error::TooLarge);
+    }
+
+    let end = header.load_address + header.image_size;
+    let sha256 = sha256(header.load_address..end, crypto, &mut read).map_err(|_| Error::Crypto)?;
+    if sha256 != header.sha256 {
+        return Err(Error::HashMismatch);
+    }
+
+    if !security.verify(KeyRole::Firmware, &header.signed_bytes(), &header.signature) {
+        return Err(Error::InvalidSignature);
+    }
+
+    // Only trusted once the signature is
+    if header.security_version < security.min_security_version {
+        return Err(Error::Rollback {
+            version: header.security_version,
+            minimum: security.min_security_version,
+        });
+    }
+    Ok(header)
+}
+
+/// The SHA-256 of the flash at `addresses`, read through `read`
+pub fn sha256(
+    addresses: Range<u32>,
+    crypto: &mut dyn Crypto,
+    mut read: impl FnMut(u32, &mut [u8]),
+) -> Result<[u8; 32], Timeout> {
+    crypto.sha256_start();
+    let mut chunk = [0; CHUNK_LEN];
+    for address in addresses.clone().step_by(CHUNK_LEN) {
+        let chunk = &mut chunk[..CHUNK_LEN.min((addresses.end - address) as usize)];
+        read(address, chunk);
+        crypto.sha256_update(chunk);
+    }
+    crypto.sha256_finish()
+}
+
+/// Why the minimum security version wasn't raised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaiseError {
+    /// The minimum is already higher, it is never lowered
+    BelowMinimum(u32),
+    /// The running firmware has a lower security version and wouldn't start anymore
+    AboveRunning(u32),
+    /// Neither copy of the security page is valid
+    SecurityPage,
+    Flash(flash::Error),
+}
+
+impl fmt::Display for RaiseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RaiseError::BelowMinimum(minimum) => {
+                write!(f, "the minimum is {} and can't be lowered", minimum)
+            }
+            RaiseError::AboveRunning(version) => {
+                write!(f, "the running firmware has security version {}", version)
+            }
+            RaiseError::SecurityPage => f.write_str("the security page is corrupt"),
+            RaiseError::Flash(e) => write!(f, "saving failed: {:?}", e),
+        }
+    }
+}
+
+/// The minimum security version of images, 0 until one is provisioned and `None` if the security
+/// page is corrupt
+pub fn min_security_version(layout: &Layout, read: impl FnMut(u32, &mut [u8])) -> Option<u32> {
+    SecurityPage::load(layout.security_pages, read).map(|security| security.min_security_version)
+}
+
+/// The header of the running firmware, `None` if it wasn't started from an image
+pub fn running_image(layout: &Layout, mut read: impl FnMut(u32, &mut [u8])) -> Option<ImageHeader> {
+    let mut header = [0; HEADER_LEN];
+    read(layout.start(Slot::RUNNING), &mut header);
+    ImageHeader::decode(&header).ok()
+}
+
+/// Writes the lines of `INFO_UF2.TXT` describing the image in `header`
+pub fn write_uf2_info(out: &mut impl fmt::Write, header: &ImageHeader) -> fmt::Result {
+    write!(out, "Firmware-Version: {}\r\n", header.version)?;
+    if header.git_commit.is_known() {
+        write!(out, "Git-Commit: {}\r\n", header.git_commit)?;
+    }
+    write!(
+        out,
+        "Build-Date: {}\r\nSecurity-Version: {}\r\n",
+        DateTime::from_unix(header.build_time),
+        header.security_version
+    )
+}
+
+/// Raises the minimum security version to `version`, which the running firmware must have
+pub fn raise_min_security_version<F: Flash>(
+    flash: &mut F,
+    layout: &Layout,
+    version: u32,
+) -> Result<(), RaiseError> {
+    let mut security = SecurityPage::load(layout.security_pages, |address, data| {
+        flash.read(address, data)
+    })
+    .ok_or(RaiseError::SecurityPage)?;
+    let minimum = security.min_security_version;
+    if version < minimum {
+        return Err(RaiseError::BelowMinimum(minimum));
+    }
+    let running = running_image(layout, |address, data| flash.read(address, data))
+        .map(|header| header.security_version);
+    if let Some(running) = running.filter(|&running| version > running) {
+        return Err(RaiseError::AboveRunning(running));
+    }
+    if version == minimum {
+        return Ok(());
+    }
+
+    security.min_security_version = version;
+    security
+        .save(flash, layout.security_pages)
+        .map_err(RaiseError::Flash)?;
+    info!("Minimum security version raised to {}", version);
+    Ok(())
+}
+
+/// The slot last confirmed
+pub fn confirmed<F: Flash>(config: &ConfigStore<F>) -> Slot {
+    config
+        .get("boot.slot")
+        .and_then(Slot::parse)
+        .unwrap_or(Slot::A)
+}
+
+/// The slot on trial, if any
+pub fn trial<F: Flash>(config: &ConfigStore<F>) -> Option<Slot> {
+    config.get("boot.trial").and_then(Slot::parse)
+}
+
+/// Picks the slot to start for the bootloader, counting the boot of a slot on trial and rolling it
+/// back once it has run out of boots. `valid` tells whether a slot has a valid image. `None` if
+/// neither slot has one.
+pub fn select<F: Flash, M: BackupMemory + ?Sized>(
+    config: &mut ConfigStore<F>,
+    backup: &mut M,
+    mut valid: impl FnMut(Slot) -> bool,
+) -> Option<Slot> {
+    let confirmed = confirmed(config);
+    if let Some(slot) = trial(config) {
+        let mut state = BackupState::load(backup).unwrap_or_default();
+        if state.trial_boots >= MAX_TRIAL_BOOTS {
+            warn!(
+                "Slot {} didn't confirm itself in {} boots, rolling back to slot {}",
+                slot, MAX_TRIAL_BOOTS, confirmed
+            );
+            end_trial(config);
+        } else if !valid(slot) {
+            warn!("Slot {} on trial has no valid image", slot);
+            end_trial(config);
+        } else {
+            state.trial_boots += 1;
+            state.store(backup);
+            info!(
+                "Starting slot {} on trial, boot {} of {}",
+                slot, state.trial_boots, MAX_TRIAL_BOOTS
+            );
+            return Some(slot);
+        }
+    }
+
+    if valid(confirmed) {
+        Some(confirmed)
+    } else if valid(confirmed.other()) {
+        warn!(
+            "Slot {} has no valid image, starting slot {}",
+            confirmed,
+            confirmed.other()
+        );
+        Some(confirmed.other())
+    } else {
+        None
+    }
+}
+
+fn end_trial<F: Flash>(config: &mut ConfigStore<F>) {
+    if config.remove("boot.trial").is_err() {
+        warn!("Removing boot.trial failed");
+    }
+}
+
+/// Puts the update received into the other slot on trial from the next reset on
+pub fn schedule_trial<F: Flash, M: BackupMemory + ?Sized>(
+    config: &mut ConfigStore<F>,
+    backup: &mut M,
+) -> Result<(), config::Error> {
+    let slot = Slot::RUNNING.other();
+    config.set("boot.trial", slot.as_str())?;
+
+    let mut state = BackupState::load(backup).unwrap_or_default();
+    state.trial_boots = 0;
+    state.store(backup);
+    info!("Slot {} goes on trial at the next reset", slot);
+    Ok(())
+}
+
+/// Makes the running slot the confirmed one, ending its trial. A trial of the other slot that is
+/// still to start is left alone.
+pub fn confirm<F: Flash>(config: &mut ConfigStore<F>) -> Result<(), config::Error> {
+    if trial(config) == Some(Slot::RUNNING) {
+        config.remove("boot.trial")?;
+    }
+    if confirmed(config) != Slot::RUNNING {
+        config.set("boot.slot", Slot::RUNNING.as_str())?;
+        info!("Slot {} confirmed", Slot::RUNNING);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bmc_proto::image::{GitCommit, Version};
+
+    use super::*;
+    use crate::crypto::Software;
+    use crate::flash::FlashWrapper;
+    use crate::keys;
+    use crate::sim::{RamFlash, SimBackup, CONFIG_ADDRESS, FLASH_SIZE, PAGE_SIZE, SLOTS};
+
+    /// The seed of the firmware key the tests trust, in place of the built-in key
+    const SEED: [u8; 32] = [7; 32];
+
+    fn config() -> ConfigStore<RamFlash> {
+        let flash = RamFlash::new(CONFIG_ADDRESS, PAGE_SIZE, PAGE_SIZE);
+        ConfigStore::new(flash, CONFIG_ADDRESS)
+    }
+
+    /// Erased slots and a security page trusting the key of `SEED`
+    fn flash() -> FlashWrapper<RamFlash> {
+        let mut flash = RamFlash::new(0, FLASH_SIZE, PAGE_SIZE);
+        let public = salty::Keypair::from(&SEED).public.to_bytes();
+        keys::change(&mut flash, SLOTS.security_pages, |page| {
+            page.add(&public, KeyRole::Firmware, None)
+        })
+        .unwrap();
+        FlashWrapper::new(flash, 0, SLOTS.security_pages[0])
+    }
+
+    /// The header of `firmware` in `slot`, signed with the key of `seed`
+    fn header(slot: Slot, firmware: &[u8], seed: &[u8; 32]) -> ImageHeader {
+        let mut header = ImageHeader {
+            image_size: firmware.len() as u32,
+            load_address: SLOTS.load_address(slot),
+            sha256: Software::new().sha256(firmware).unwrap(),
+            security_version: 0,
+            version: Version {
+                major: 1,
+                minor: 2,
+                patch: 3,
+            },
+            build_time: 0,
+            git_commit: GitCommit([0; 20]),
+            signature: [0; 64],
+        };
+        header.signature = sign(&header, seed);
+        header
+    }
+
+    fn sign(header: &ImageHeader, seed: &[u8; 32]) -> [u8; 64] {
+        salty::Keypair::from(seed)
+            .sign(&header.signed_bytes())
+            .to_bytes()
+    }
+
+    fn firmware() -> Vec<u8> {
+        (0..3000).map(|i| i as u8).collect()
+    }
+
+    fn install(
+        flash: &mut FlashWrapper<RamFlash>,
+        slot: Slot,
+        header: &ImageHeader,
+        firmware: &[u8],
+    ) {
+        flash.write(SLOTS.start(slot), &header.encode()).unwrap();
+        flash.write(SLOTS.load_address(slot), firmware).unwrap();
+        flash.flush().unwrap();
+    }
+
+    fn verify_slot(flash: &FlashWrapper<RamFlash>, slot: Slot) -> Result<ImageHeader, Error> {
+        verify(&SLOTS, slot, &mut Software::new(), |address, data| {
+            flash.read(address, data)
+        })
+    }
+
+    fn select_slot(
+        config: &mut ConfigStore<RamFlash>,
+        backup: &mut SimBackup,
+        flash: &FlashWrapper<RamFlash>,
+    ) -> Option<Slot> {
+        select(config, backup, |slot| verify_slot(flash, slot).is_ok())
+    }
+
+    fn trial_boots(backup: &SimBackup) -> u8 {
+        BackupState::load(backup).unwrap().trial_boots
+    }
+
+    /// The firmware on trial runs `confirm` once it has run for `CONFIRM_DELAY`, from then on it
+    /// is started without counting boots
+    #[test]
+    fn confirmed_trial_is_kept() {
+        let mut config = config();
+        let mut backup = SimBackup::default();
+        config
+            .set("boot.slot", Slot::RUNNING.other().as_str())
+            .unwrap();
+        config.set("boot.trial", Slot::RUNNING.as_str()).unwrap();
+
+        assert_eq!(
+            select(&mut config, &mut backup, |_| true),
+            Some(Slot::RUNNING)
+        );
+        assert_eq!(trial_boots(&backup), 1);
+
+        confirm(&mut config).unwrap();
+        assert_eq!(confirmed(&config), Slot::RUNNING);
+        assert_eq!(trial(&config), None);
+        for _ in 0..MAX_TRIAL_BOOTS + 1 {
+            assert_eq!(
+                select(&mut config, &mut backup, |_| true),
+                Some(Slot::RUNNING)
+            );
+        }
+        assert_eq!(trial_boots(&backup), 1);
+    }
+
+    #[test]
+    fn verify_accepts_a_signed_image() {
+        let mut flash = flash();
+        let firmware = firmware();
+        let header = header(Slot::B, &firmware, &SEED);
+        install(&mut flash, Slot::B, &header, &firmware);
+        assert_eq!(verify_slot(&flash, Slot::B), Ok(header));
+    }
+
+    #[test]
+    fn verify_rejects_bad_signatures() {
+        let mut flash = flash();
+        let firmware = firmware();
+        // Signed by an untrusted key
+        let untrusted = header(Slot::B, &firmware, &[8; 32]);
+        install(&mut flash, Slot::B, &untrusted, &firmware);
+        assert_eq!(verify_slot(&flash, Slot::B), Err(Error::InvalidSignature));
+
+        // A signed field changed after signing
+        let mut changed = header(Slot::B, &firmware, &SEED);
+        changed.security_version = 1;
+        install(&mut flash, Slot::B, &changed, &firmware);
+        assert_eq!(verify_slot(&flash, Slot::B), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn verify_rejects_a_bad_hash() {
+        let mut flash = flash();
+        let mut firmware = firmware();
+        let header = header(Slot::B, &firmware, &SEED);
+        firmware[2999] ^= 1;
+        install(&mut flash, Slot::B, &header, &firmware);
+        assert_eq!(verify_slot(&flash, Slot::B), Err(Error::HashMismatch));
+    }
+
+    #[test]
+    fn verify_rejects_images_not_for_the_slot() {
+        let mut flash = flash();
+        let firmware = firmware();
+        assert_eq!(
+            verify_slot(&flash, Slot::B),
+            Err(Error::Header(image::DecodeError::InvalidMagic))
+        );
+
+        // Linked for slot A
+        let for_a = header(Slot::A, &firmware, &SEED);
+        install(&mut flash, Slot::B, &for_a, &firmware);
+        assert_eq!(
+            verify_slot(&flash, Slot::B),
+            Err(Error::WrongAddress(SLOTS.load_address(Slot::A)))
+        );
+
+        let mut too_large = header(Slot::B, &firmware, &SEED);
+        too_large.image_size = SLOTS.slot_size - HEADER_SIZE + 1;
+        too_large.signature = sign(&too_large, &SEED);
+        install(&mut flash, Slot::B, &too_large, &firmware);
+        assert_eq!(verify_slot(&flash, Slot::B), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn verify_rejects_a_corrupt_security_page() {
+        let mut flash = flash();
+        let firmware = firmware();
+        let header = header(Slot::B, &firmware, &SEED);
+        install(&mut flash, Slot::B, &header, &firmware);
+
+        let read = |address, data: &mut [u8]| {
+            flash.read(address, data);
+            // Both copies read as if programming them was cut short
+            if SLOTS
+                .security_pages
+                .contains(&(address - address % PAGE_SIZE))
+            {
+                data[0] ^= 1;
+            }
+        };
+        assert_eq!(
+            verify(&SLOTS, Slot::B, &mut Software::new(), read),
+            Err(Error::SecurityPage)
+        );
+    }
+
+    #[test]
+    fn trial_without_confirmation_is_rolled_back() {
+        let mut flash = flash();
+        let mut config = config();
+        let mut backup = SimBackup::default();
+        let firmware = firmware();
+        for slot in [Slot::A, Slot::B] {
+            install(&mut flash, slot, &header(slot, &firmware, &SEED), &firmware);
+        }
+        assert_eq!(
+            select_slot(&mut config, &mut backup, &flash),
+            Some(Slot::RUNNING)
+        );
+
+        schedule_trial(&mut config, &mut backup).unwrap();
+        let update = Slot::RUNNING.other();
+        assert_eq!(trial(&config), Some(update));
+        for boot in 1..=MAX_TRIAL_BOOTS {
+            assert_eq!(select_slot(&mut config, &mut backup, &flash), Some(update));
+            assert_eq!(trial_boots(&backup), boot);
+        }
+
+        // Out of boots without confirming, e.g. as it kept crashing
+        assert_eq!(
+            select_slot(&mut config, &mut backup, &flash),
+            Some(Slot::RUNNING)
+        );
+        assert_eq!(trial(&config), None);
+        assert_eq!(confirmed(&config), Slot::RUNNING);
+    }
+
+    #[test]
+    fn trial_of_an_invalid_image_is_ended() {
+        let mut flash = flash();
+        let mut config = config();
+        let mut backup = SimBackup::default();
+        let firmware = firmware();
+        let running = header(Slot::RUNNING, &firmware, &SEED);
+        install(&mut flash, Slot::RUNNING, &running, &firmware);
+
+        schedule_trial(&mut config, &mut backup).unwrap();
+        assert_eq!(
+            select_slot(&mut config, &mut backup, &flash),
+            Some(Slot::RUNNING)
+        );
+        assert_eq!(trial(&config), None);
+    }
+
+    #[test]
+    fn invalid_confirmed_slot_falls_back_to_the_other() {
+        let mut flash = flash();
+        let mut config = config();
+        let mut backup = SimBackup::default();
+        let firmware = firmware();
+        let other = Slot::RUNNING.other();
+        install(
+            &mut flash,
+            other,
+            &header(other, &firmware, &SEED),
+            &firmware,
+        );
+        assert_eq!(select_slot(&mut config, &mut backup, &flash), Some(other));
+        // Only started, it still has to confirm itself
+        assert_eq!(confirmed(&config), Slot::A);
+    }
+
+    #[test]
+    fn nothing_starts_without_a_valid_image() {
+        let mut flash = flash();
+        let mut config = config();
+        let mut backup = SimBackup::default();
+        assert_eq!(select_slot(&mut config, &mut backup, &flash), None);
+
+        // A bad hash in one slot and an untrusted key in the other, with a trial scheduled
+        let mut firmware = firmware();
+        install(
+            &mut flash,
+            Slot::B,
+            &header(Slot::B, &firmware, &[8; 32]),
+            &firmware,
+        );
+        let header = header(Slot::A, &firmware, &SEED);
+        firmware[0] ^= 1;
+        install(&mut flash, Slot::A, &header, &firmware);
+        schedule_trial(&mut config, &mut backup).unwrap();
+        assert_eq!(select_slot(&mut config, &mut backup, &flash), None);
+        assert_eq!(trial(&config), None);
+    }
+
+    #[test]
+    fn confirm_leaves_a_scheduled_trial_alone() {
+        let mut config = config();
+        let mut backup = SimBackup::default();
+        schedule_trial(&mut config, &mut backup).unwrap();
+        confirm(&mut config).unwrap();
+        assert_eq!(trial(&config), Some(Slot::RUNNING.other()));
+        assert_eq!(confirmed(&config), Slot::RUNNING);
+    }
+
+    #[test]
+    fn last_update_is_kept() {
+        let last = LastUpdate::new();
+        assert_eq!(last.get(), None);
+        last.set(UpdateStatus::Installing);
+        last.set(UpdateStatus::Rejected(Rejection::HashMismatch));
+        assert_eq!(
+            last.get(),
+            Some(UpdateStatus::Rejected(Rejection::HashMismatch))
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Persistent key-value settings stored in a flash page.
+//!
+//! The page holds `key=value` lines, the erased remainder of the page ends the list. Keeping the
+//! format as text makes the page readable in a flash dump. Every change is written back at once,
+//! so the page is erased for each `set` or `remove`.
+
+use core::str;
+
+use heapless::{LinearMap, String};
+
+use crate::flash::{self, Flash, MAX_PAGE_SIZE};
+use crate::logging::warn;
+
+pub const MAX_ENTRIES: usize = 16;
+pub const MAX_KEY_LEN: usize = 16;
+pub const MAX_VALUE_LEN: usize = 32;
+
+/// All entries at their longest plus separators fit in a 1 KiB page
+const _: () = assert!(MAX_ENTRIES * (MAX_KEY_LEN + MAX_VALUE_LEN + 2) <= 1024);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The key is empty, too long or contains `=` or control characters
+    InvalidKey,
+    /// The value is too long or contains control characters
+    InvalidValue,
+    /// All entries are in use
+    Full,
+    Flash(flash::Error),
+}
+
+impl From<flash::Error> for Error {
+    fn from(e: flash::Error) -> Self {
+        Error::Flash(e)
+    }
+}
+
+pub struct ConfigStore<F> {
+    flash: F,
+    address: u32,
+    entries: LinearMap<String<MAX_KEY_LEN>, String<MAX_VALUE_LEN>, MAX_ENTRIES>,
+}
+
+impl<F: Flash> ConfigStore<F> {
+    /// Loads the settings from the page at `address`, which must not be used for anything else
+    pub fn new(flash: F, address: u32) -> Self {
+        assert!(address.is_multiple_of(flash.page_size()));
+
+        let mut store = Self {
+            flash,
+            address,
+            entries: LinearMap::new(),
+        };
+        store.load();
+        store
+    }
+
+    fn load(&mut self) {
+        let mut page = [0xFF; MAX_PAGE_SIZE];
+        let page = &mut page[..self.flash.page_size() as usize];
+        self.flash.read(self.address, page);
+
+        let len = page.iter().position(|&b| b == 0xFF).unwrap_or(page.len());
+        let text = match str::from_utf8(&page[..len]) {
+            Ok(text) => text,
+            Err(_) => {
+                warn!("Config page at {:#x} is corrupt, ignoring it", self.address);
+                return;
+            }
+        };
+
+        for line in text.lines() {
+            let parsed = line
+                .split_once('=')
+                .filter(|(key, value)| valid_key(key) && valid_value(value));
+            match parsed {
+                Some((key, value)) => {
+                    if self.entries.insert(key.into(), value.into()).is_err() {
+                        warn!("Config page has too many entries, ignoring {}", key);
+                    }
+                }
+                // Quoted without escaping, the Debug formatting of strings costs 2K of flash
+                None => warn!("Ignoring config line \"{}\"", line),
+            }
+        }
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        let mut page = [0xFF; MAX_PAGE_SIZE];
+        let mut len = 0;
+        for (key, value) in self.entries.iter() {
+            for part in [key.as_bytes(), b"=", value.as_bytes(), b"\n"] {
+                page[len..len + part.len()].copy_from_slice(part);
+                len += part.len();
+            }
+        }
+
+        let page_size = self.flash.page_size() as usize;
+        self.flash.program_page(self.address, &page[..page_size])?;
+        Ok(())
+    }
+
+    /// The flash the settings are kept in, which also holds other pages of state, e.g. the
+    /// minimum security version of [`crate::boot`]
+    pub fn flash_mut(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.as_str() == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets `key` to `value` and writes the settings to flash
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        if !valid_key(key) {
+            return Err(Error::InvalidKey);
+        }
+        if !valid_value(value) {
+            return Err(Error::InvalidValue);
+        }
+
+        self.entries
+            .insert(key.into(), value.into())
+            .map_err(|_| Error::Full)?;
+        self.save()
+    }
+
+    /// Removes `key` and writes the settings to flash, returns whether the key was set
+    pub fn remove(&mut self, key: &str) -> Result<bool, Error> {
+        let Some(key) = self.entries.keys().find(|k| k.as_str() == key).cloned() else {
+            return Ok(false);
+        };
+
+        self.entries.remove(&key);
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && key.bytes().all(|b| b.is_ascii_graphic() && b != b'=')
+}
+
+fn valid_value(value: &str) -> bool {
+    value.len() <= MAX_VALUE_LEN && value.chars().all(|c| !c.is_control())
+}
@@ -0,0 +1,140 @@
+//! The console on a USB CDC-ACM serial port, e.g. `/dev/ttyACM0` on Linux. It runs the [`Shell`]
+//! for humans until the `rpc` command switches it to the binary protocol of [`crate::rpc`] for
+//! tools, which returns to the shell at the end of the session. With the `usb-log` feature the log
+//! is written to the console as well while it runs the shell.
+
+use core::fmt::{self, Write};
+
+use heapless::Deque;
+use usb_device::bus::UsbBus;
+use usbd_serial::SerialPort;
+
+use crate::flash::Flash;
+use crate::rpc::RpcServer;
+use crate::shell::{Bmc, Shell, PROMPT};
+
+/// Output waiting for the host to read it, further output is dropped when full. Holds all of the
+/// retained log with `ram-log`, so the `log` command output is complete.
+#[cfg(not(feature = "ram-log"))]
+pub const OUTPUT_BUFFER_SIZE: usize = 1024;
+#[cfg(feature = "ram-log")]
+pub const OUTPUT_BUFFER_SIZE: usize = crate::logging::ram::BUFFER_SIZE + 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Shell,
+    Rpc,
+}
+
+pub struct Console {
+    mode: Mode,
+    shell: Shell,
+    rpc: RpcServer,
+    output: Deque<u8, OUTPUT_BUFFER_SIZE>,
+}
+
+impl Console {
+    pub const fn new() -> Self {
+        Self {
+            mode: Mode::Shell,
+            shell: Shell::new(),
+            rpc: RpcServer::new(),
+            output: Deque::new(),
+        }
+    }
+
+    /// Handles bytes received from the host, the responses are queued for [`Console::output`]
+    pub fn input<F: Flash>(&mut self, mut data: &[u8], bmc: &mut Bmc<F>) {
+        let mut output = Output(&mut self.output);
+        while !data.is_empty() {
+            let switched = match self.mode {
+                // Output beyond the buffer is dropped in Output::write_str
+                Mode::Shell => self.shell.input(data, bmc, &mut output).unwrap_or_default(),
+                Mode::Rpc => self.rpc.input(data, bmc, &mut |frame| output.write(frame)),
+            };
+
+            match switched {
+                Some(handled) => data = &data[handled..],
+                None => break,
+            }
+
+            self.mode = match self.mode {
+                // The delimiter ends the shell output for the frame decoder of the host
+                Mode::Shell => {
+                    output.write(&[0]);
+                    Mode::Rpc
+                }
+                Mode::Rpc => {
+                    output.write(PROMPT.as_bytes());
+                    Mode::Shell
+                }
+            };
+        }
+    }
+
+    /// The oldest output not yet sent, remove it with [`Console::consume`] once sent
+    pub fn output(&self) -> &[u8] {
+        self.output.as_slices().0
+    }
+
+    pub fn consume(&mut self, count: usize) {
+        for _ in 0..count {
+            self.output.pop_front();
+        }
+    }
+
+    /// Runs the console on what the host has sent to `serial` and sends the output back as the
+    /// host reads it. Call after every poll of the USB device.
+    pub fn poll<B: UsbBus, F: Flash>(&mut self, serial: &mut SerialPort<B>, bmc: &mut Bmc<F>) {
+        // New input is only taken once the previous output is out, which throttles the host
+        if self.output.is_empty() {
+            let mut input = [0; 64];
+            if let Ok(len) = serial.read(&mut input) {
+                self.input(&input[..len], bmc);
+            }
+        }
+
+        // The log goes out between the shell output, it would break up the frames of the RPCs
+        #[cfg(feature = "usb-log")]
+        if self.mode == Mode::Shell {
+            let output = &mut self.output;
+            crate::logging::usb::drain(|data| {
+                let len = data.len().min(output.capacity() - output.len());
+                Output(output).write(&data[..len]);
+                len
+            });
+        }
+
+        while !self.output.is_empty() {
+            match serial.write(self.output()) {
+                Ok(written) => self.consume(written),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Output<'a>(&'a mut Deque<u8, OUTPUT_BUFFER_SIZE>);
+
+impl Output<'_> {
+    fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            if self.0.push_back(byte).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
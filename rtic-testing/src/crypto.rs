@@ -0,0 +1,267 @@
+//! The SHA-256 and AES the firmware computes, see [`Crypto`].
+//!
+//! [`Software`] computes them with the RustCrypto crates and works everywhere, including the sim.
+//! Boards with crypto peripherals implement the trait on them, e.g. the ItsyBitsy M4 with the
+//! `hw-crypto` feature, and hand their implementation out as [`Board::Crypto`]. Both compute the
+//! same results, the peripherals are just faster on the images the bootloader hashes on every boot.
+//!
+//! [`Board::Crypto`]: crate::board::Board::Crypto
+
+use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+use sha2::{Digest, Sha256};
+
+pub const SHA256_BLOCK_LEN: usize = 64;
+pub const AES_BLOCK_LEN: usize = 16;
+
+/// A key of the AES block cipher
+#[derive(Clone, Copy)]
+pub enum AesKey<'a> {
+    Aes128(&'a [u8; 16]),
+    Aes256(&'a [u8; 32]),
+}
+
+/// The crypto peripheral didn't finish in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+pub trait Crypto {
+    /// Starts a new SHA-256 hash, dropping the one in progress
+    fn sha256_start(&mut self);
+    fn sha256_update(&mut self, data: &[u8]);
+    /// Ends the hash started with [`Crypto::sha256_start`], failing if the hardware timed out on
+    /// any part of it
+    fn sha256_finish(&mut self) -> Result<[u8; 32], Timeout>;
+
+    /// Encrypts a single `block` in place
+    fn aes_encrypt(&mut self, key: AesKey, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), Timeout>;
+    /// Decrypts a single `block` in place
+    fn aes_decrypt(&mut self, key: AesKey, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), Timeout>;
+
+    /// The SHA-256 of `data`
+    fn sha256(&mut self, data: &[u8]) -> Result<[u8; 32], Timeout> {
+        self.sha256_start();
+        self.sha256_update(data);
+        self.sha256_finish()
+    }
+}
+
+/// The padding SHA-256 appends to a message of `len` bytes, for hardware that only hashes whole
+/// blocks: a one bit, zeros up to the last 8 bytes of a block and the length in bits. Returns the
+/// buffer and how many of its bytes the padding takes.
+pub fn sha256_padding(len: u64) -> ([u8; SHA256_BLOCK_LEN + 8], usize) {
+    let block_len = SHA256_BLOCK_LEN as u64;
+    let zeros = ((block_len - (len + 9) % block_len) % block_len) as usize;
+    let mut padding = [0; SHA256_BLOCK_LEN + 8];
+    padding[0] = 0x80;
+    padding[1 + zeros..9 + zeros].copy_from_slice(&(len * 8).to_be_bytes());
+    (padding, 9 + zeros)
+}
+
+#[derive(Default)]
+pub struct Software {
+    hasher: Sha256,
+}
+
+impl Software {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Crypto for Software {
+    fn sha256_start(&mut self) {
+        self.hasher = Sha256::new();
+    }
+
+    fn sha256_update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn sha256_finish(&mut self) -> Result<[u8; 32], Timeout> {
+        Ok(self.hasher.finalize_reset().into())
+    }
+
+    fn aes_encrypt(&mut self, key: AesKey, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), Timeout> {
+        let block = GenericArray::from_mut_slice(block);
+        match key {
+            AesKey::Aes128(key) => Aes128::new(key.into()).encrypt_block(block),
+            AesKey::Aes256(key) => Aes256::new(key.into()).encrypt_block(block),
+        }
+        Ok(())
+    }
+
+    fn aes_decrypt(&mut self, key: AesKey, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), Timeout> {
+        let block = GenericArray::from_mut_slice(block);
+        match key {
+            AesKey::Aes128(key) => Aes128::new(key.into()).decrypt_block(block),
+            AesKey::Aes256(key) => Aes256::new(key.into()).decrypt_block(block),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::digest::generic_array::GenericArray;
+
+    use super::*;
+
+    fn hex<const N: usize>(s: &str) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (byte, digits) in bytes.iter_mut().zip(s.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap();
+        }
+        bytes
+    }
+
+    /// The examples of FIPS 180-2, appendix B, and the empty message
+    #[test]
+    fn software_sha256_vectors() {
+        let vectors: [(&[u8], &str); 4] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopq\
+                  klmnopqrlmnopqrsmnopqrstnopqrstu",
+                "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+            ),
+        ];
+        let mut crypto = Software::new();
+        for (message, digest) in vectors.iter() {
+            assert_eq!(crypto.sha256(message), Ok(hex(digest)));
+        }
+    }
+
+    #[test]
+    fn software_sha256_in_parts() {
+        let mut crypto = Software::new();
+        crypto.sha256_start();
+        for _ in 0..1000 {
+            crypto.sha256_update(&[b'a'; 1000]);
+        }
+        let digest = "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0";
+        assert_eq!(crypto.sha256_finish(), Ok(hex(digest)));
+
+        // Finishing starts over
+        crypto.sha256_update(b"abc");
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(crypto.sha256_finish(), Ok(hex(digest)));
+    }
+
+    #[test]
+    fn padding_ends_the_last_block() {
+        for len in 0..300 {
+            let (padding, padding_len) = sha256_padding(len);
+            assert_eq!((len as usize + padding_len) % SHA256_BLOCK_LEN, 0);
+            assert!((9..=SHA256_BLOCK_LEN + 8).contains(&padding_len));
+            assert_eq!(padding[0], 0x80);
+            assert!(padding[1..padding_len - 8].iter().all(|&byte| byte == 0));
+            let bits = &padding[padding_len - 8..padding_len];
+            assert_eq!(bits, &(len * 8).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn padding_lengths() {
+        // The length still fits the block after 55 bytes, not after 56
+        assert_eq!(sha256_padding(0).1, 64);
+        assert_eq!(sha256_padding(55).1, 9);
+        assert_eq!(sha256_padding(56).1, 72);
+        assert_eq!(sha256_padding(64).1, 64);
+        // Lengths past 32 bits
+        let (padding, len) = sha256_padding((1 << 40) + 55);
+        assert_eq!(padding[..len], [0x80, 0, 0, 0x08, 0, 0, 0, 0x01, 0xb8]);
+    }
+
+    /// The padded message hashed block by block the way the ICM does, with the compression
+    /// function of the `sha2` crate
+    #[test]
+    fn padding_matches_sha256() {
+        let message = [0x5a; 119];
+        let (padding, padding_len) = sha256_padding(message.len() as u64);
+        let mut padded = message.to_vec();
+        padded.extend_from_slice(&padding[..padding_len]);
+
+        let mut state: [u32; 8] = [
+            0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+            0x5be0cd19,
+        ];
+        for block in padded.chunks(SHA256_BLOCK_LEN) {
+            sha2::compress256(&mut state, &[*GenericArray::from_slice(block)]);
+        }
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_mut(4).zip(state.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        assert_eq!(Software::new().sha256(&message), Ok(digest));
+    }
+
+    /// Encrypts each of `blocks`, plaintext and ciphertext, and decrypts it back
+    fn check_aes(key: AesKey, blocks: &[(&str, &str)]) {
+        let mut crypto = Software::new();
+        for (plaintext, ciphertext) in blocks {
+            let mut block = hex(plaintext);
+            crypto.aes_encrypt(key, &mut block).unwrap();
+            assert_eq!(block, hex(ciphertext));
+            crypto.aes_decrypt(key, &mut block).unwrap();
+            assert_eq!(block, hex(plaintext));
+        }
+    }
+
+    /// The examples of FIPS 197, appendix C
+    #[test]
+    fn software_aes_fips_197() {
+        let plaintext = "00112233445566778899aabbccddeeff";
+        let key = hex("000102030405060708090a0b0c0d0e0f");
+        check_aes(
+            AesKey::Aes128(&key),
+            &[(plaintext, "69c4e0d86a7b0430d8cdb78070b4c55a")],
+        );
+        let key = hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        check_aes(
+            AesKey::Aes256(&key),
+            &[(plaintext, "8ea2b7ca516745bfeafc49904b496089")],
+        );
+    }
+
+    /// The ECB examples of NIST SP 800-38A for AES-128 and AES-256, F.1.1, F.1.2, F.1.5 and F.1.6
+    #[test]
+    fn software_aes_sp_800_38a() {
+        let plaintexts = [
+            "6bc1bee22e409f96e93d7e117393172a",
+            "ae2d8a571e03ac9c9eb76fac45af8e51",
+            "30c81c46a35ce411e5fbc1191a0a52ef",
+            "f69f2445df4f9b17ad2b417be66c3710",
+        ];
+        let key = hex("2b7e151628aed2a6abf7158809cf4f3c");
+        let ciphertexts = [
+            "3ad77bb40d7a3660a89ecaf32466ef97",
+            "f5d3d58503b9699de785895a96fdbaaf",
+            "43b1cd7f598ece23881b00e3ed030688",
+            "7b0c785e27e8ad3f8223207104725dd4",
+        ];
+        let blocks: Vec<_> = plaintexts.iter().copied().zip(ciphertexts).collect();
+        check_aes(AesKey::Aes128(&key), &blocks);
+
+        let key = hex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4");
+        let ciphertexts = [
+            "f3eed1bdb5d2a03c064b5a7e3db181f8",
+            "591ccb10d410ed26dc5ba74a31362870",
+            "b6ed21b99ca6f4f9f153e7b1beafed1d",
+            "23304b7a39f9f3ff067d8d8f9e24ecc7",
+        ];
+        let blocks: Vec<_> = plaintexts.iter().copied().zip(ciphertexts).collect();
+        check_aes(AesKey::Aes256(&key), &blocks);
+    }
+}
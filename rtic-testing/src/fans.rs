@@ -0,0 +1,398 @@
+//! Fan control: the fans are driven together by one PWM duty cycle, set by a thermal policy from
+//! the temperature sensors after each poll.
+//!
+//! The policy is keyed on the temperature headroom, how far the hottest sensor is below its upper
+//! non-critical threshold, so sensors with different limits weigh the same. It either follows
+//! [`CURVE`] or runs a PID loop keeping the headroom at a target. The settings below select it
+//! and take effect at the next reset:
+//!
+//! | Key          | Value                                          |
+//! |--------------|------------------------------------------------|
+//! | `fan.policy` | `curve` or `pid`                               |
+//! | `fan.target` | Headroom the PID loop keeps in °C, default 10  |
+//!
+//! The speed of each fan is measured from its tachometer pulses and read as a sensor, a fan below
+//! its critical threshold has failed. The other fans then run at full speed, as they do while no
+//! temperature can be read. The failure is only logged, as the sensor going critical; the BMC
+//! keeps no system event log to record it in.
+
+use crate::config::ConfigStore;
+use crate::flash::Flash;
+use crate::logging::{debug, info, warn};
+use crate::monotonic::Duration;
+use crate::sensors::{Channel, Device, Entity, SensorConfig, Thresholds};
+
+/// Most fans a board can have
+pub const MAX_FANS: usize = 4;
+
+/// The tachometer pulses of each fan counted over a poll period, `None` past the fans of the board
+pub type Pulses = [Option<u32>; MAX_FANS];
+
+/// PC fans pulse their tachometer twice per revolution
+pub const PULSES_PER_REVOLUTION: u32 = 2;
+
+/// The duty cycle the fans never go below, where 4-pin fans still spin reliably
+pub const MIN_DUTY: u8 = 20;
+pub const MAX_DUTY: u8 = 100;
+
+/// The duty cycle by headroom in millidegrees Celsius, interpolated linearly in between
+pub const CURVE: &[CurvePoint] = &[
+    CurvePoint {
+        headroom: 20_000,
+        duty: 30,
+    },
+    CurvePoint {
+        headroom: 10_000,
+        duty: 50,
+    },
+    CurvePoint {
+        headroom: 5_000,
+        duty: 80,
+    },
+    CurvePoint {
+        headroom: 0,
+        duty: MAX_DUTY,
+    },
+];
+
+/// The gains of the PID loop in percent of duty cycle per °C of headroom missing, per °C second
+/// and per °C per second
+pub const PID_GAINS: PidGains = PidGains {
+    proportional: 8,
+    integral: 1,
+    derivative: 4,
+};
+
+/// The headroom the PID loop keeps by default, in millidegrees Celsius
+pub const DEFAULT_TARGET: i32 = 10_000;
+
+/// The sensor of a fan with thresholds for a fan running at least at [`MIN_DUTY`]
+pub const fn fan_sensor(name: &'static str, index: u8) -> SensorConfig {
+    SensorConfig {
+        name,
+        entity: Entity::Fan,
+        device: Device::Fan { index },
+        channel: Channel::Speed,
+        thresholds: Thresholds {
+            lower_critical: Some(300_000),
+            lower_non_critical: Some(450_000),
+            ..Thresholds::NONE
+        },
+    }
+}
+
+/// The PWM outputs of the fans
+pub trait FanPwm {
+    /// Sets the duty cycle of all fans in percent
+    fn set_duty(&mut self, percent: u8);
+}
+
+/// The tachometer inputs of the fans
+pub trait Tachometers {
+    /// Counts the pulses, called from the tachometer interrupts
+    fn on_interrupt(&mut self);
+
+    /// The pulses counted since the last call
+    fn take_pulses(&mut self) -> Pulses;
+}
+
+/// For boards without fans
+pub struct NoFans;
+
+impl FanPwm for NoFans {
+    fn set_duty(&mut self, _percent: u8) {}
+}
+
+impl Tachometers for NoFans {
+    fn on_interrupt(&mut self) {}
+
+    fn take_pulses(&mut self) -> Pulses {
+        [None; MAX_FANS]
+    }
+}
+
+/// The speed in thousandths of RPM from the pulses counted over `period`
+pub fn millirpm(pulses: u32, period: Duration) -> i32 {
+    // Thousandths of pulses per minute
+    let per_minute = pulses as u64 * 60_000_000 / period.to_millis().max(1);
+    (per_minute / PULSES_PER_REVOLUTION as u64) as i32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurvePoint {
+    /// In millidegrees Celsius
+    pub headroom: i32,
+    /// In percent
+    pub duty: u8,
+}
+
+/// The duty cycle for `headroom` on `curve`, whose points go from the most headroom to the least.
+/// Beyond the ends the duty cycle of the nearest point applies.
+pub fn curve_duty(curve: &[CurvePoint], headroom: i32) -> u8 {
+    let (first, last) = match (curve.first(), curve.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return MAX_DUTY,
+    };
+    if headroom >= first.headroom {
+        return first.duty;
+    }
+    for pair in curve.windows(2) {
+        let (high, low) = (pair[0], pair[1]);
+        if headroom >= low.headroom {
+            let span = high.headroom - low.headroom;
+            let rise = (low.duty as i32 - high.duty as i32) * (high.headroom - headroom);
+            return (high.duty as i32 + rise / span.max(1)) as u8;
+        }
+    }
+    last.duty
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PidGains {
+    pub proportional: i32,
+    pub integral: i32,
+    pub derivative: i32,
+}
+
+/// A PID loop on the headroom, running once per `period`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pid {
+    gains: PidGains,
+    /// In millidegrees Celsius
+    target: i32,
+    period: Duration,
+    /// The accumulated error in millidegree seconds
+    integral: i64,
+    last_error: Option<i32>,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains, target: i32, period: Duration) -> Self {
+        Self {
+            gains,
+            target,
+            period,
+            integral: 0,
+            last_error: None,
+        }
+    }
+
+    /// The duty cycle for the latest headroom
+    pub fn update(&mut self, headroom: i32) -> u8 {
+        // Positive while too hot
+        let error = self.target - headroom;
+        let millis = self.period.to_millis() as i64;
+
+        let proportional = self.gains.proportional as i64 * error as i64 / 1000;
+        let derivative = match self.last_error {
+            Some(last) => self.gains.derivative as i64 * (error - last) as i64 / millis.max(1),
+            None => 0,
+        };
+        self.last_error = Some(error);
+
+        // The integral stops growing once it alone drives the fans to a limit, so it doesn't wind
+        // up while the fans can't do more
+        let integral = self.integral + error as i64 * millis / 1000;
+        let limit = match self.gains.integral {
+            0 => 0,
+            gain => MAX_DUTY as i64 * 1000 / gain as i64,
+        };
+        self.integral = integral.clamp(0, limit);
+        let integral = self.gains.integral as i64 * self.integral / 1000;
+
+        (proportional + integral + derivative).clamp(MIN_DUTY as i64, MAX_DUTY as i64) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Curve(&'static [CurvePoint]),
+    Pid(Pid),
+}
+
+/// The thermal policy with the duty cycle it last set
+pub struct FanControl {
+    policy: Policy,
+    duty: u8,
+}
+
+impl FanControl {
+    /// The fans start at full speed until the first update
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            duty: MAX_DUTY,
+        }
+    }
+
+    /// The policy selected by the settings, updated once per `period`. Invalid settings are
+    /// logged and ignored.
+    pub fn load<F: Flash>(config: &ConfigStore<F>, period: Duration) -> Self {
+        let mut target = DEFAULT_TARGET;
+        if let Some(value) = config.get("fan.target") {
+            match value.parse::<i32>() {
+                Ok(degrees) if (0..=100).contains(&degrees) => target = degrees * 1000,
+                _ => warn!("Ignoring fan.target, {} is not 0 to 100 degrees", value),
+            }
+        }
+
+        let policy = match config.get("fan.policy") {
+            None | Some("curve") => Policy::Curve(CURVE),
+            Some("pid") => Policy::Pid(Pid::new(PID_GAINS, target, period)),
+            Some(value) => {
+                warn!("Ignoring fan.policy, {} is not curve or pid", value);
+                Policy::Curve(CURVE)
+            }
+        };
+        Self::new(policy)
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    pub fn duty(&self) -> u8 {
+        self.duty
+    }
+
+    /// The duty cycle for the latest headroom, `None` if no temperature could be read. Changes of
+    /// the duty cycle are logged.
+    pub fn update(&mut self, headroom: Option<i32>, fan_failed: bool) -> u8 {
+        let duty = match (headroom, fan_failed) {
+            (Some(headroom), false) => match &mut self.policy {
+                Policy::Curve(curve) => curve_duty(curve, headroom).max(MIN_DUTY),
+                Policy::Pid(pid) => pid.update(headroom),
+            },
+            _ => MAX_DUTY,
+        };
+
+        if duty == MAX_DUTY && self.duty != MAX_DUTY {
+            info!("Fans at full speed");
+        } else if duty != self.duty {
+            debug!("Fans at {}%", duty);
+        }
+        self.duty = duty;
+        duty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::millis(1000);
+
+    #[test]
+    fn speed_from_pulses() {
+        // 100 pulses a second are 50 revolutions, 3000 RPM
+        assert_eq!(millirpm(100, SECOND), 3_000_000);
+        assert_eq!(millirpm(50, Duration::millis(500)), 3_000_000);
+        assert_eq!(millirpm(1, SECOND), 30_000);
+        assert_eq!(millirpm(0, SECOND), 0);
+        // No division by zero for an empty period
+        assert_eq!(millirpm(1, Duration::millis(0)), 30_000_000);
+    }
+
+    #[test]
+    fn curve_interpolates_between_points() {
+        assert_eq!(curve_duty(CURVE, 20_000), 30);
+        assert_eq!(curve_duty(CURVE, 15_000), 40);
+        assert_eq!(curve_duty(CURVE, 10_000), 50);
+        assert_eq!(curve_duty(CURVE, 7_500), 65);
+        assert_eq!(curve_duty(CURVE, 5_000), 80);
+        assert_eq!(curve_duty(CURVE, 2_500), 90);
+        assert_eq!(curve_duty(CURVE, 0), MAX_DUTY);
+    }
+
+    #[test]
+    fn curve_holds_beyond_its_ends() {
+        assert_eq!(curve_duty(CURVE, 50_000), 30);
+        assert_eq!(curve_duty(CURVE, -5_000), MAX_DUTY);
+        assert_eq!(curve_duty(&[], 10_000), MAX_DUTY);
+        let point = CurvePoint {
+            headroom: 10_000,
+            duty: 40,
+        };
+        assert_eq!(curve_duty(&[point], 0), 40);
+        assert_eq!(curve_duty(&[point], 20_000), 40);
+    }
+
+    #[test]
+    fn pid_proportional() {
+        let gains = PidGains {
+            proportional: 8,
+            integral: 0,
+            derivative: 0,
+        };
+        let mut pid = Pid::new(gains, 10_000, SECOND);
+        assert_eq!(pid.update(5_000), 40);
+        assert_eq!(pid.update(0), 80);
+        assert_eq!(pid.update(-10_000), MAX_DUTY);
+        // Never below the minimum, even with headroom to spare
+        assert_eq!(pid.update(15_000), MIN_DUTY);
+    }
+
+    #[test]
+    fn pid_derivative() {
+        let gains = PidGains {
+            proportional: 0,
+            integral: 0,
+            derivative: 4,
+        };
+        let mut pid = Pid::new(gains, 10_000, SECOND);
+        // Nothing to compare the first headroom with
+        assert_eq!(pid.update(0), MIN_DUTY);
+        assert_eq!(pid.update(0), MIN_DUTY);
+        // Losing 10 °C of headroom in a second
+        assert_eq!(pid.update(-10_000), 40);
+    }
+
+    #[test]
+    fn pid_integral_does_not_wind_up() {
+        let gains = PidGains {
+            proportional: 0,
+            integral: 1,
+            derivative: 0,
+        };
+        let mut pid = Pid::new(gains, 10_000, SECOND);
+        for _ in 0..1000 {
+            assert!(pid.update(0) <= MAX_DUTY);
+        }
+        // Unwinds from full speed within seconds, not the 1000 it was too hot for
+        for _ in 0..5 {
+            pid.update(20_000);
+        }
+        assert_eq!(pid.update(10_000), 50);
+
+        // Nor does it wind up below zero while there is headroom to spare
+        for _ in 0..1000 {
+            pid.update(20_000);
+        }
+        assert_eq!(pid.update(20_000), MIN_DUTY);
+        for _ in 0..4 {
+            pid.update(0);
+        }
+        assert_eq!(pid.update(0), 50);
+    }
+
+    #[test]
+    fn control_runs_at_full_speed_without_temperature_or_with_a_failed_fan() {
+        let mut control = FanControl::new(Policy::Curve(CURVE));
+        assert_eq!(control.duty(), MAX_DUTY);
+        assert_eq!(control.update(Some(20_000), false), 30);
+        assert_eq!(control.update(None, false), MAX_DUTY);
+        assert_eq!(control.update(Some(20_000), false), 30);
+        assert_eq!(control.update(Some(20_000), true), MAX_DUTY);
+        assert_eq!(control.duty(), MAX_DUTY);
+    }
+
+    #[test]
+    fn control_keeps_the_curve_above_the_minimum() {
+        const SILENT: &[CurvePoint] = &[CurvePoint {
+            headroom: 0,
+            duty: 0,
+        }];
+        let mut control = FanControl::new(Policy::Curve(SILENT));
+        assert_eq!(control.update(Some(10_000), false), MIN_DUTY);
+    }
+}
@@ -0,0 +1,220 @@
+//! Flash programming, buffered so that writes of any size can be made to pages that are
+//! only erasable as a whole.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Address is outside the writable range or not page aligned
+    InvalidAddress,
+    /// The controller reported an error while erasing
+    Erase,
+    /// The controller reported an error while writing
+    Write,
+}
+
+/// Flash memory that is erased and written a page at a time. A page here is the erase granularity,
+/// which may be larger than what the hardware programs at once.
+pub trait Flash {
+    /// Size of an erasable page in bytes
+    fn page_size(&self) -> u32;
+
+    /// The address just past the end of the flash
+    fn end_address(&self) -> u32;
+
+    /// Reads `data.len()` bytes starting from `address`
+    fn read(&self, address: u32, data: &mut [u8]);
+
+    /// Erases the page at `address` and programs it with `data`, which is a whole page
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error>;
+}
+
+/// Largest page size [`FlashWrapper`] can buffer
+pub const MAX_PAGE_SIZE: usize = 8192;
+
+/// Collects writes to a page in RAM and only programs the flash when a write moves to another page
+/// or [`FlashWrapper::flush`] is called. Writes are restricted to `min_address..max_address`.
+pub struct FlashWrapper<F> {
+    flash: F,
+    page_size: u32,
+    page_buffer: [u8; MAX_PAGE_SIZE],
+    current_page: Option<u32>,
+    min_address: u32,
+    max_address: u32,
+}
+
+impl<F: Flash> FlashWrapper<F> {
+    pub fn new(flash: F, min_address: u32, max_address: u32) -> Self {
+        let page_size = flash.page_size();
+        assert!(page_size as usize <= MAX_PAGE_SIZE);
+        assert!(min_address.is_multiple_of(page_size) && max_address.is_multiple_of(page_size));
+        assert!(min_address <= max_address);
+
+        Self {
+            flash,
+            page_size,
+            page_buffer: [0; MAX_PAGE_SIZE],
+            current_page: None,
+            min_address,
+            max_address,
+        }
+    }
+
+    pub fn min_address(&self) -> u32 {
+        self.min_address
+    }
+
+    pub fn max_address(&self) -> u32 {
+        self.max_address
+    }
+
+    /// Writes `data` to `address`, the write may span multiple pages
+    pub fn write(&mut self, mut address: u32, mut data: &[u8]) -> Result<(), Error> {
+        // The address comes from the UF2 blocks the host writes, so it may be anything
+        let end = address.checked_add(data.len() as u32);
+        if address < self.min_address || end.is_none_or(|end| end > self.max_address) {
+            return Err(Error::InvalidAddress);
+        }
+
+        while !data.is_empty() {
+            let page = address - address % self.page_size;
+            if self.current_page != Some(page) {
+                self.flush()?;
+                self.flash
+                    .read(page, &mut self.page_buffer[..self.page_size as usize]);
+                self.current_page = Some(page);
+            }
+
+            let offset = (address - page) as usize;
+            let len = data.len().min(self.page_size as usize - offset);
+            self.page_buffer[offset..offset + len].copy_from_slice(&data[..len]);
+
+            address += len as u32;
+            data = &data[len..];
+        }
+
+        Ok(())
+    }
+
+    /// Programs the buffered page, if any
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(page) = self.current_page.take() {
+            self.flash
+                .program_page(page, &self.page_buffer[..self.page_size as usize])?;
+        }
+        Ok(())
+    }
+
+    /// Reads from flash, returning data still in the page buffer where it hasn't been flushed
+    pub fn read(&self, address: u32, data: &mut [u8]) {
+        self.flash.read(address, data);
+
+        if let Some(page) = self.current_page {
+            let start = address.max(page);
+            let end = (address + data.len() as u32).min(page + self.page_size);
+            if start < end {
+                let (from, to) = ((start - page) as usize, (end - page) as usize);
+                let into = (start - address) as usize;
+                data[into..into + to - from].copy_from_slice(&self.page_buffer[from..to]);
+            }
+        }
+    }
+}
+
+/// A [`Flash`] used by several owners, e.g. the GhostFat drive and the settings, which each get a
+/// copy. Accesses are made in a critical section.
+pub struct SharedFlash<F: 'static> {
+    flash: &'static Mutex<RefCell<F>>,
+}
+
+impl<F> SharedFlash<F> {
+    pub fn new(flash: &'static Mutex<RefCell<F>>) -> Self {
+        Self { flash }
+    }
+}
+
+impl<F> Clone for SharedFlash<F> {
+    fn clone(&self) -> Self {
+        Self { flash: self.flash }
+    }
+}
+
+impl<F: Flash> Flash for SharedFlash<F> {
+    fn page_size(&self) -> u32 {
+        interrupt::free(|cs| self.flash.borrow(cs).borrow().page_size())
+    }
+
+    fn end_address(&self) -> u32 {
+        interrupt::free(|cs| self.flash.borrow(cs).borrow().end_address())
+    }
+
+    fn read(&self, address: u32, data: &mut [u8]) {
+        interrupt::free(|cs| self.flash.borrow(cs).borrow().read(address, data))
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        interrupt::free(|cs| {
+            self.flash
+                .borrow(cs)
+                .borrow_mut()
+                .program_page(address, data)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{RamFlash, APP_END, APP_START, PAGE_SIZE};
+
+    fn wrapper() -> FlashWrapper<RamFlash> {
+        let flash = RamFlash::new(APP_START, APP_END - APP_START, PAGE_SIZE);
+        FlashWrapper::new(flash, APP_START, APP_END)
+    }
+
+    #[test]
+    fn writes_across_pages_are_buffered_until_flushed() {
+        let mut wrapper = wrapper();
+        let address = APP_START + PAGE_SIZE - 2;
+        wrapper.write(address, &[1, 2, 3, 4]).unwrap();
+
+        // The second page is still buffered, the first one programmed
+        let mut data = [0; 4];
+        wrapper.read(address, &mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+        wrapper.flash.read(address, &mut data);
+        assert_eq!(data, [1, 2, 0xFF, 0xFF]);
+
+        wrapper.flush().unwrap();
+        wrapper.flash.read(address, &mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn writes_outside_the_range_are_refused() {
+        let mut wrapper = wrapper();
+        assert_eq!(
+            wrapper.write(APP_START - 1, &[0; 4]),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(
+            wrapper.write(APP_END - 2, &[0; 4]),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(wrapper.write(APP_END - 4, &[0; 4]), Ok(()));
+    }
+
+    #[test]
+    fn writes_wrapping_around_the_address_space_are_refused() {
+        let mut wrapper = wrapper();
+        for address in [u32::MAX - 255, u32::MAX - 1, u32::MAX] {
+            assert_eq!(
+                wrapper.write(address, &[0; 256]),
+                Err(Error::InvalidAddress)
+            );
+        }
+        assert_eq!(wrapper.current_page, None);
+    }
+}
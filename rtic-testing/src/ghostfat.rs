@@ -0,0 +1,448 @@
+//! A virtual FAT16 drive for updating the application over USB mass storage, following the
+//! [UF2] "ghost FAT" design. Nothing is stored on the drive, every sector is generated when read.
+//! The files show information about the device and the current application as `CURRENT.UF2`.
+//! A log can be added as `LOG.BIN`, see [`GhostFat::set_log`].
+//! UF2 blocks written to the drive are programmed into flash, anything else is ignored.
+//!
+//! [UF2]: https://github.com/microsoft/uf2
+
+use uf2_block::Block;
+use usbd_scsi::{BlockDevice, BlockDeviceError};
+
+use crate::flash::{Flash, FlashWrapper};
+use crate::logging::{info, warn};
+
+const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+/// Payload bytes per block in `CURRENT.UF2`, matching what most UF2 tools produce
+const UF2_PAYLOAD_SIZE: u32 = 256;
+/// Most UF2 blocks a single update can consist of
+const MAX_UF2_BLOCKS: usize = 2048;
+
+const BLOCK_SIZE: u32 = 512;
+const NUM_FAT_BLOCKS: u32 = 8000;
+const RESERVED_SECTORS: u32 = 1;
+const ROOT_DIR_SECTORS: u32 = 4;
+const SECTORS_PER_FAT: u32 = (NUM_FAT_BLOCKS * 2).div_ceil(BLOCK_SIZE);
+const START_FAT0: u32 = RESERVED_SECTORS;
+const START_FAT1: u32 = START_FAT0 + SECTORS_PER_FAT;
+const START_ROOT_DIR: u32 = START_FAT1 + SECTORS_PER_FAT;
+const START_CLUSTERS: u32 = START_ROOT_DIR + ROOT_DIR_SECTORS;
+
+const VOLUME_LABEL: &[u8; 11] = b"RACKLET-BMC";
+
+const INDEX_HTM: &[u8] = b"<!doctype html>\n<html><body><script>\n\
+    location.replace(\"https://github.com/racklet/racklet\");\n\
+    </script></body></html>\n";
+
+enum Contents {
+    Static(&'static [u8]),
+    Info,
+    Log,
+    Uf2,
+}
+
+struct File {
+    name: &'static [u8; 11],
+    contents: Contents,
+}
+
+const FILES: [File; 4] = [
+    File {
+        name: b"INFO_UF2TXT",
+        contents: Contents::Info,
+    },
+    File {
+        name: b"INDEX   HTM",
+        contents: Contents::Static(INDEX_HTM),
+    },
+    File {
+        name: b"LOG     BIN",
+        contents: Contents::Log,
+    },
+    File {
+        name: b"CURRENT UF2",
+        contents: Contents::Uf2,
+    },
+];
+
+/// Copies the log from an offset into a buffer and returns the number of bytes copied, e.g.
+/// [`crate::logging::ram::read`]
+pub type LogReader = fn(usize, &mut [u8]) -> usize;
+
+pub struct GhostFat<F> {
+    flash: FlashWrapper<F>,
+    family_id: u32,
+    info: &'static str,
+    /// Bitmap of the blocks received of the update in progress
+    written: [u32; MAX_UF2_BLOCKS / 32],
+    num_written: u32,
+    num_blocks: u32,
+    update_complete: bool,
+    update_hook: Option<fn()>,
+    /// Reads `LOG.BIN` and its size in bytes
+    log: Option<(LogReader, u32)>,
+}
+
+impl<F: Flash> GhostFat<F> {
+    /// Creates a drive exposing the range of `flash`. Only UF2 blocks for `family_id` are accepted,
+    /// `info` is the contents of `INFO_UF2.TXT`. An empty range leaves out `CURRENT.UF2`.
+    pub fn new(flash: FlashWrapper<F>, family_id: u32, info: &'static str) -> Self {
+        Self {
+            flash,
+            family_id,
+            info,
+            written: [0; MAX_UF2_BLOCKS / 32],
+            num_written: 0,
+            num_blocks: 0,
+            update_complete: false,
+            update_hook: None,
+            log: None,
+        }
+    }
+
+    /// Calls `hook` whenever an update is complete, e.g. to install it
+    pub fn set_update_hook(&mut self, hook: fn()) {
+        self.update_hook = Some(hook);
+    }
+
+    /// Adds `LOG.BIN`, which reads `size` bytes of log through `read`. Past the end of the log the
+    /// file reads as zeros, which the `defmt` decoder skips. Each sector is read as the log is at
+    /// the time, so a log that grows while the host reads the file may have a gap in it.
+    pub fn set_log(&mut self, read: LogReader, size: u32) {
+        self.log = Some((read, size));
+    }
+
+    /// Whether all blocks of an update have been written to flash
+    pub fn update_complete(&self) -> bool {
+        self.update_complete
+    }
+
+    /// The flash the updates are written to
+    pub fn flash(&self) -> &FlashWrapper<F> {
+        &self.flash
+    }
+
+    /// The files on the drive, without `CURRENT.UF2` when there is no room for updates and without
+    /// `LOG.BIN` unless there is a log
+    fn files(&self) -> impl Iterator<Item = &'static File> + '_ {
+        FILES.iter().filter(move |file| match file.contents {
+            Contents::Log => self.log.is_some(),
+            Contents::Uf2 => self.uf2_blocks() > 0,
+            _ => true,
+        })
+    }
+
+    fn uf2_blocks(&self) -> u32 {
+        (self.flash.max_address() - self.flash.min_address()) / UF2_PAYLOAD_SIZE
+    }
+
+    fn file_size(&self, file: &File) -> u32 {
+        match file.contents {
+            Contents::Static(data) => data.len() as u32,
+            Contents::Info => self.info.len() as u32,
+            Contents::Log => self.log.map_or(0, |(_, size)| size),
+            Contents::Uf2 => self.uf2_blocks() * BLOCK_SIZE,
+        }
+    }
+
+    fn file_sectors(&self, file: &File) -> u32 {
+        self.file_size(file).div_ceil(BLOCK_SIZE)
+    }
+
+    fn read_boot_sector(&self, block: &mut [u8]) {
+        block[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        block[3..11].copy_from_slice(b"UF2 UF2 ");
+        block[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        block[13] = 1; // Sectors per cluster
+        block[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        block[16] = 2; // Number of FATs
+        block[17..19].copy_from_slice(&((ROOT_DIR_SECTORS * BLOCK_SIZE / 32) as u16).to_le_bytes());
+        block[19..21].copy_from_slice(&(NUM_FAT_BLOCKS as u16).to_le_bytes());
+        block[21] = 0xF8; // Fixed disk
+        block[22..24].copy_from_slice(&(SECTORS_PER_FAT as u16).to_le_bytes());
+        block[24..26].copy_from_slice(&1u16.to_le_bytes()); // Sectors per track
+        block[26..28].copy_from_slice(&1u16.to_le_bytes()); // Heads
+        block[36] = 0x80; // Drive number
+        block[38] = 0x29; // Extended boot signature
+        block[39..43].copy_from_slice(&0x00420042u32.to_le_bytes());
+        block[43..54].copy_from_slice(VOLUME_LABEL);
+        block[54..62].copy_from_slice(b"FAT16   ");
+        block[510] = 0x55;
+        block[511] = 0xAA;
+    }
+
+    fn read_fat(&self, sector: u32, block: &mut [u8]) {
+        for (i, entry) in block.chunks_mut(2).enumerate() {
+            let cluster = sector * BLOCK_SIZE / 2 + i as u32;
+            let value: u16 = match cluster {
+                0 => 0xFFF8,
+                1 => 0xFFFF,
+                _ => {
+                    // Every file is a single chain of consecutive clusters
+                    let mut start = 2;
+                    let mut value = 0;
+                    for file in self.files() {
+                        let end = start + self.file_sectors(file);
+                        if cluster < end {
+                            value = if cluster + 1 == end {
+                                0xFFFF
+                            } else {
+                                cluster as u16 + 1
+                            };
+                            break;
+                        }
+                        start = end;
+                    }
+                    value
+                }
+            };
+            entry.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn read_root_dir(&self, sector: u32, block: &mut [u8]) {
+        if sector != 0 {
+            return;
+        }
+
+        let (label, entries) = block.split_at_mut(32);
+        label[..11].copy_from_slice(VOLUME_LABEL);
+        label[11] = 0x08; // Volume label attribute
+
+        let mut cluster = 2;
+        for (file, entry) in self.files().zip(entries.chunks_mut(32)) {
+            entry[..11].copy_from_slice(file.name);
+            entry[11] = 0x01; // Read only
+            entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+            entry[28..32].copy_from_slice(&self.file_size(file).to_le_bytes());
+            cluster += self.file_sectors(file);
+        }
+    }
+
+    fn read_cluster(&self, mut sector: u32, block: &mut [u8]) -> Result<(), BlockDeviceError> {
+        for file in self.files() {
+            if sector < self.file_sectors(file) {
+                match file.contents {
+                    Contents::Static(data) => read_static(data, sector, block),
+                    Contents::Info => read_static(self.info.as_bytes(), sector, block),
+                    Contents::Log => self.read_log(sector, block),
+                    Contents::Uf2 => self.read_uf2_block(sector, block)?,
+                }
+                return Ok(());
+            }
+            sector -= self.file_sectors(file);
+        }
+
+        Ok(())
+    }
+
+    fn read_log(&self, sector: u32, block: &mut [u8]) {
+        if let Some((read, size)) = self.log {
+            let start = sector * BLOCK_SIZE;
+            let len = BLOCK_SIZE.min(size - start) as usize;
+            read(start as usize, &mut block[..len]);
+        }
+    }
+
+    fn read_uf2_block(&self, index: u32, block: &mut [u8]) -> Result<(), BlockDeviceError> {
+        let mut data = [0; UF2_PAYLOAD_SIZE as usize];
+        let address = self.flash.min_address() + index * UF2_PAYLOAD_SIZE;
+        self.flash.read(address, &mut data);
+
+        let mut uf2 = Block::new(address, &data).map_err(|_| BlockDeviceError::HardwareError)?;
+        uf2.flags = UF2_FLAG_FAMILY_ID_PRESENT;
+        uf2.file_size_or_family_id = self.family_id;
+        uf2.block_number = index;
+        uf2.number_of_blocks = self.uf2_blocks();
+
+        let packed = uf2.pack().map_err(|_| BlockDeviceError::HardwareError)?;
+        block.copy_from_slice(&packed);
+        Ok(())
+    }
+
+    fn write_uf2(&mut self, uf2: &Block) -> Result<(), BlockDeviceError> {
+        if uf2.flags & UF2_FLAG_NOT_MAIN_FLASH != 0
+            || (uf2.flags & UF2_FLAG_FAMILY_ID_PRESENT != 0
+                && uf2.file_size_or_family_id != self.family_id)
+        {
+            // Meant for another device or not meant to be flashed, tools may send these
+            return Ok(());
+        }
+
+        if uf2.number_of_blocks as usize > MAX_UF2_BLOCKS
+            || uf2.block_number >= uf2.number_of_blocks
+        {
+            warn!(
+                "Ignoring UF2 block {} of {}",
+                uf2.block_number, uf2.number_of_blocks
+            );
+            return Ok(());
+        }
+
+        let payload = uf2
+            .data
+            .get(..uf2.payload_size as usize)
+            .ok_or(BlockDeviceError::InvalidAddress)?;
+        self.flash
+            .write(uf2.target_address, payload)
+            .map_err(|_| BlockDeviceError::InvalidAddress)?;
+
+        if uf2.number_of_blocks != self.num_blocks || self.update_complete {
+            // A new update, start tracking the received blocks from scratch
+            self.written = [0; MAX_UF2_BLOCKS / 32];
+            self.num_written = 0;
+            self.num_blocks = uf2.number_of_blocks;
+            self.update_complete = false;
+        }
+
+        let (word, bit) = (uf2.block_number as usize / 32, uf2.block_number % 32);
+        if self.written[word] & 1 << bit == 0 {
+            self.written[word] |= 1 << bit;
+            self.num_written += 1;
+        }
+
+        if self.num_written == self.num_blocks {
+            self.flash
+                .flush()
+                .map_err(|_| BlockDeviceError::WriteError)?;
+            self.update_complete = true;
+            info!("Update complete, {} UF2 blocks written", self.num_blocks);
+            if let Some(hook) = self.update_hook {
+                hook();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_static(data: &[u8], sector: u32, block: &mut [u8]) {
+    let start = (sector * BLOCK_SIZE) as usize;
+    let end = data.len().min(start + BLOCK_SIZE as usize);
+    block[..end - start].copy_from_slice(&data[start..end]);
+}
+
+impl<F: Flash> BlockDevice for GhostFat<F> {
+    const BLOCK_BYTES: usize = BLOCK_SIZE as usize;
+
+    fn read_block(&self, lba: u32, block: &mut [u8]) -> Result<(), BlockDeviceError> {
+        block.fill(0);
+
+        match lba {
+            0 => self.read_boot_sector(block),
+            _ if lba < START_FAT1 => self.read_fat(lba - START_FAT0, block),
+            _ if lba < START_ROOT_DIR => self.read_fat(lba - START_FAT1, block),
+            _ if lba < START_CLUSTERS => self.read_root_dir(lba - START_ROOT_DIR, block),
+            _ if lba < NUM_FAT_BLOCKS => self.read_cluster(lba - START_CLUSTERS, block)?,
+            _ => return Err(BlockDeviceError::InvalidAddress),
+        }
+
+        Ok(())
+    }
+
+    fn write_block(&mut self, lba: u32, block: &[u8]) -> Result<(), BlockDeviceError> {
+        if lba >= NUM_FAT_BLOCKS {
+            return Err(BlockDeviceError::InvalidAddress);
+        }
+
+        // The host also writes its own FAT and directory updates, which are dropped
+        match Block::parse(block) {
+            Ok(uf2) => self.write_uf2(&uf2),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn max_lba(&self) -> u32 {
+        NUM_FAT_BLOCKS - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+    use crate::sim::{RamFlash, APP_END, APP_START, PAGE_SIZE, UF2_FAMILY_ID, UF2_INFO};
+
+    fn ghostfat(end: u32) -> GhostFat<RamFlash> {
+        let flash = RamFlash::new(APP_START, APP_END - APP_START, PAGE_SIZE);
+        GhostFat::new(
+            FlashWrapper::new(flash, APP_START, end),
+            UF2_FAMILY_ID,
+            UF2_INFO,
+        )
+    }
+
+    fn file_names(ghostfat: &GhostFat<RamFlash>) -> Vec<[u8; 11]> {
+        let mut block = [0; BLOCK_SIZE as usize];
+        ghostfat.read_block(START_ROOT_DIR, &mut block).unwrap();
+        block
+            .chunks(32)
+            .skip(1)
+            .take_while(|entry| entry[0] != 0)
+            .map(|entry| entry[..11].try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn current_uf2_covers_the_update_region() {
+        let ghostfat = ghostfat(APP_END);
+        assert_eq!(
+            file_names(&ghostfat),
+            [*b"INFO_UF2TXT", *b"INDEX   HTM", *b"CURRENT UF2"]
+        );
+        assert_eq!(
+            ghostfat.uf2_blocks(),
+            (APP_END - APP_START) / UF2_PAYLOAD_SIZE
+        );
+    }
+
+    #[test]
+    fn no_current_uf2_without_room_for_updates() {
+        let ghostfat = ghostfat(APP_START);
+        assert_eq!(file_names(&ghostfat), [*b"INFO_UF2TXT", *b"INDEX   HTM"]);
+
+        let mut block = [0; BLOCK_SIZE as usize];
+        for lba in 0..START_CLUSTERS + 4 {
+            ghostfat.read_block(lba, &mut block).unwrap();
+        }
+    }
+
+    #[test]
+    fn log_bin_reads_the_log() {
+        const LOG: &[u8] = b"0123456789";
+        fn read(offset: usize, out: &mut [u8]) -> usize {
+            let log = LOG.get(offset..).unwrap_or_default();
+            let len = log.len().min(out.len());
+            out[..len].copy_from_slice(&log[..len]);
+            len
+        }
+
+        let mut ghostfat = ghostfat(APP_END);
+        ghostfat.set_log(read, 2 * BLOCK_SIZE);
+        assert_eq!(
+            file_names(&ghostfat),
+            [
+                *b"INFO_UF2TXT",
+                *b"INDEX   HTM",
+                *b"LOG     BIN",
+                *b"CURRENT UF2"
+            ]
+        );
+
+        // INFO_UF2.TXT and INDEX.HTM take a cluster each
+        let mut block = [0xff; BLOCK_SIZE as usize];
+        ghostfat.read_block(START_CLUSTERS + 2, &mut block).unwrap();
+        assert_eq!(&block[..LOG.len()], LOG);
+        assert!(block[LOG.len()..].iter().all(|&byte| byte == 0));
+        ghostfat.read_block(START_CLUSTERS + 3, &mut block).unwrap();
+        assert!(block.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn flash_range_has_to_be_ordered() {
+        ghostfat(APP_START - PAGE_SIZE);
+    }
+}
@@ -0,0 +1,91 @@
+//! The identity of the BMC, derived from the unique ID programmed into its MCU at the factory, so
+//! it survives firmware updates and tells the BMCs in a rack apart.
+//!
+//! The USB serial number is the unique ID in hex. The short node name and the MAC address of the
+//! network interface are derived from a hash of it, so they spread evenly even though the IDs of
+//! MCUs from the same wafer differ in few bits. The node name ends in the last three bytes of the
+//! MAC address, e.g. `bmc-a1b2c3` goes with `02:5e:e4:a1:b2:c3`.
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+/// Longest unique ID of the supported MCUs, the SAMD51 has 128 bits and the STM32F1 96 bits
+pub const MAX_UNIQUE_ID_LEN: usize = 16;
+
+pub type UniqueId = Vec<u8, MAX_UNIQUE_ID_LEN>;
+
+const NODE_NAME_PREFIX: &str = "bmc-";
+
+pub struct Identity {
+    unique_id: UniqueId,
+    serial_number: String<{ 2 * MAX_UNIQUE_ID_LEN }>,
+    node_name: String<{ NODE_NAME_PREFIX.len() + 6 }>,
+    mac_address: MacAddress,
+}
+
+impl Identity {
+    pub fn new(unique_id: &[u8]) -> Self {
+        let mut serial_number = String::new();
+        for byte in unique_id.iter().take(MAX_UNIQUE_ID_LEN) {
+            write!(serial_number, "{:02X}", byte).unwrap();
+        }
+
+        // Locally administered, unicast
+        let hash = fnv1a(unique_id).to_be_bytes();
+        let mut mac_address = [0x02; 6];
+        mac_address[1..].copy_from_slice(&hash[3..]);
+
+        let mut node_name = String::new();
+        node_name.push_str(NODE_NAME_PREFIX).unwrap();
+        for byte in &mac_address[3..] {
+            write!(node_name, "{:02x}", byte).unwrap();
+        }
+
+        Self {
+            unique_id: unique_id.iter().take(MAX_UNIQUE_ID_LEN).copied().collect(),
+            serial_number,
+            node_name,
+            mac_address: MacAddress(mac_address),
+        }
+    }
+
+    pub fn unique_id(&self) -> &[u8] {
+        &self.unique_id
+    }
+
+    /// The unique ID in uppercase hex, for the USB device descriptor
+    pub fn serial_number(&self) -> &str {
+        &self.serial_number
+    }
+
+    /// Short name usable as a hostname, e.g. `bmc-a1b2c3`
+    pub fn node_name(&self) -> &str {
+        &self.node_name
+    }
+
+    pub fn mac_address(&self) -> MacAddress {
+        self.mac_address
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+/// The 64-bit FNV-1a hash, which is small and good enough for spreading the IDs
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -0,0 +1,496 @@
+//! The security page: the keys the BMC trusts, the minimum security version of images and the
+//! attestation secret, see [`bmc_proto::keys`], [`crate::boot`] and [`crate::attestation`].
+//!
+//! The firmware key compiled in from `keys/` by `build.rs` is trusted as well until it is revoked,
+//! so a BMC without provisioned keys starts images signed with it. The private half of the
+//! development key there is public, so the key store can't be locked while it is trusted.
+//!
+//! The page is kept in two copies outside of the slots and the settings, which neither updates nor
+//! `config` can write, see [`Layout::security_pages`]. Each copy holds:
+//!
+//! | Offset | Size   | Field                                                        |
+//! |--------|--------|--------------------------------------------------------------|
+//! | 0      | 4      | Minimum security version                                     |
+//! | 4      | 4      | Its complement                                               |
+//! | 8      | 4      | Erased while the key store is unlocked                       |
+//! | 12     | 4      | Complement of the number of changes to the keys              |
+//! | 16     | 40 × 8 | Keys: the key, its role, 0 if revoked, erased if unused      |
+//! | 336    | 32     | Attestation secret, erased until provisioned                 |
+//! | 368    | 4      | Sequence number, counting the saves                          |
+//! | 372    | 4      | CRC-32 of the bytes before                                   |
+//!
+//! Changes are programmed into the copy that wasn't loaded, with the next sequence number, so
+//! losing power while programming it leaves the other copy as it was. The valid copy with the
+//! higher sequence number counts. The attestation secret can only be set while the key store is
+//! unlocked. Without a valid copy the page is trusted for nothing unless both copies are erased:
+//! the bootloader starts no image and the key store refuses changes.
+//!
+//! [`Layout::security_pages`]: crate::boot::Layout::security_pages
+
+use core::convert::TryFrom;
+
+use bmc_proto::keys::{self, KeyChange, KeyEntry, KeyRole, MAX_KEYS};
+use crc::{Crc, NoTable, CRC_32_ISO_HDLC};
+use heapless::Vec;
+use salty::{PublicKey, Signature};
+
+use crate::flash::{self, Flash, MAX_PAGE_SIZE};
+
+include!(concat!(env!("OUT_DIR"), "/firmware_key.rs"));
+
+const KEYS_OFFSET: usize = 16;
+const ENTRY_LEN: usize = 40;
+const SECRET_OFFSET: usize = KEYS_OFFSET + MAX_KEYS * ENTRY_LEN;
+const SEQUENCE_OFFSET: usize = SECRET_OFFSET + 32;
+const CRC_OFFSET: usize = SEQUENCE_OFFSET + 4;
+const PAGE_LEN: usize = CRC_OFFSET + 4;
+
+const CRC: Crc<u32, NoTable> = Crc::<u32, NoTable>::new(&CRC_32_ISO_HDLC);
+
+/// The start of the page up to the attestation secret, which is measured for attestation
+pub const MEASURED_LEN: usize = SECRET_OFFSET;
+
+const ROLE_FIRMWARE: u8 = 0;
+const ROLE_BOOT_FILES: u8 = 1;
+const ERASED: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyError {
+    /// The key store is locked and the change isn't signed by a trusted firmware key
+    Unauthorized,
+    /// The key is revoked, revoking it would leave no firmware key to trust, or locking would keep
+    /// the development key trusted
+    Refused,
+    /// All entries are in use
+    Full,
+    /// Neither copy of the page is valid and they aren't both erased, see [`SecurityPage::load`]
+    Corrupt,
+    Flash(flash::Error),
+}
+
+/// A provisioned key
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    key: [u8; 32],
+    role: KeyRole,
+    revoked: bool,
+}
+
+pub struct SecurityPage {
+    pub min_security_version: u32,
+    locked: bool,
+    generation: u32,
+    entries: Vec<Entry, MAX_KEYS>,
+    attestation_secret: Option<[u8; 32]>,
+    /// The sequence number of the copy loaded, 0 for erased copies
+    sequence: u32,
+    /// Which copy was loaded, the next save programs the other
+    copy: usize,
+}
+
+impl SecurityPage {
+    /// Reads the copies of the page at `addresses` through `read`. `None` if neither copy is valid
+    /// and they aren't both erased, e.g. after the first save lost power, since trusting an erased
+    /// page could start firmware the BMC is meant to refuse.
+    pub fn load(addresses: [u32; 2], mut read: impl FnMut(u32, &mut [u8])) -> Option<Self> {
+        let mut loaded: Option<Self> = None;
+        let mut erased = 0;
+        for (copy, &address) in addresses.iter().enumerate() {
+            let mut data = [0; PAGE_LEN];
+            read(address, &mut data);
+            if data == [ERASED; PAGE_LEN] {
+                erased += 1;
+            } else if let Some(page) = Self::decode(&data, copy) {
+                if loaded
+                    .as_ref()
+                    .is_none_or(|loaded| page.sequence > loaded.sequence)
+                {
+                    loaded = Some(page);
+                }
+            }
+        }
+        match loaded {
+            Some(page) => Some(page),
+            None if erased == addresses.len() => Some(Self::erased()),
+            None => None,
+        }
+    }
+
+    /// The page as provisioned by erasing both copies. The first save programs the first copy.
+    fn erased() -> Self {
+        Self {
+            min_security_version: 0,
+            locked: false,
+            generation: 0,
+            entries: Vec::new(),
+            attestation_secret: None,
+            sequence: 0,
+            copy: 1,
+        }
+    }
+
+    /// The page in a copy, `None` unless its CRC and its fields check out
+    fn decode(data: &[u8; PAGE_LEN], copy: usize) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        if word(CRC_OFFSET) != CRC.checksum(&data[..CRC_OFFSET]) {
+            return None;
+        }
+
+        let mut entries = Vec::new();
+        for entry in data[KEYS_OFFSET..SECRET_OFFSET].chunks(ENTRY_LEN) {
+            let role = match entry[32] {
+                ROLE_FIRMWARE => KeyRole::Firmware,
+                ROLE_BOOT_FILES => KeyRole::BootFiles,
+                ERASED => continue,
+                _ => return None,
+            };
+            let mut key = [0; 32];
+            key.copy_from_slice(&entry[..32]);
+            let revoked = entry[33] == 0;
+            entries.push(Entry { key, role, revoked }).ok();
+        }
+        let mut secret = [0; 32];
+        secret.copy_from_slice(&data[SECRET_OFFSET..SEQUENCE_OFFSET]);
+
+        if word(4) != !word(0) {
+            return None;
+        }
+        Some(Self {
+            min_security_version: word(0),
+            locked: word(8) != u32::MAX,
+            generation: !word(12),
+            entries,
+            attestation_secret: Some(secret).filter(|secret| *secret != [ERASED; 32]),
+            sequence: word(SEQUENCE_OFFSET),
+            copy,
+        })
+    }
+
+    /// Programs the copy at `addresses` that wasn't loaded, which counts from then on
+    pub fn save<F: Flash>(
+        &mut self,
+        flash: &mut F,
+        addresses: [u32; 2],
+    ) -> Result<(), flash::Error> {
+        let mut page = [0xFF; MAX_PAGE_SIZE];
+        page[0..4].copy_from_slice(&self.min_security_version.to_le_bytes());
+        page[4..8].copy_from_slice(&(!self.min_security_version).to_le_bytes());
+        if self.locked {
+            page[8..12].copy_from_slice(&0u32.to_le_bytes());
+        }
+        page[12..16].copy_from_slice(&(!self.generation).to_le_bytes());
+        for (entry, data) in self
+            .entries
+            .iter()
+            .zip(page[KEYS_OFFSET..SECRET_OFFSET].chunks_mut(ENTRY_LEN))
+        {
+            data[..32].copy_from_slice(&entry.key);
+            data[32] = match entry.role {
+                KeyRole::Firmware => ROLE_FIRMWARE,
+                KeyRole::BootFiles => ROLE_BOOT_FILES,
+            };
+            data[33] = if entry.revoked { 0 } else { ERASED };
+        }
+        if let Some(secret) = &self.attestation_secret {
+            page[SECRET_OFFSET..SEQUENCE_OFFSET].copy_from_slice(secret);
+        }
+        let sequence = self.sequence + 1;
+        page[SEQUENCE_OFFSET..CRC_OFFSET].copy_from_slice(&sequence.to_le_bytes());
+        let crc = CRC.checksum(&page[..CRC_OFFSET]);
+        page[CRC_OFFSET..PAGE_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        let copy = 1 - self.copy;
+        let page_size = flash.page_size() as usize;
+        flash.program_page(addresses[copy], &page[..page_size])?;
+        self.sequence = sequence;
+        self.copy = copy;
+        Ok(())
+    }
+
+    /// Which of the copies counts, the second for an erased page
+    pub fn copy(&self) -> usize {
+        self.copy
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// The changes made to the keys so far
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The keys, the built-in one first
+    pub fn keys(&self) -> impl Iterator<Item = KeyEntry> + '_ {
+        let built_in = KeyEntry {
+            key: FIRMWARE_KEY,
+            role: KeyRole::Firmware,
+            revoked: self.is_revoked(&FIRMWARE_KEY),
+            built_in: true,
+        };
+        let provisioned = self
+            .entries
+            .iter()
+            .filter(|entry| entry.key != FIRMWARE_KEY)
+            .map(|entry| KeyEntry {
+                key: entry.key,
+                role: entry.role,
+                revoked: entry.revoked,
+                built_in: false,
+            });
+        core::iter::once(built_in).chain(provisioned)
+    }
+
+    fn is_revoked(&self, key: &[u8; 32]) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.revoked && entry.key == *key)
+    }
+
+    /// Whether a signature by `key` counts for `role`
+    pub fn is_trusted(&self, role: KeyRole, key: &[u8; 32]) -> bool {
+        self.keys()
+            .any(|entry| entry.role == role && !entry.revoked && entry.key == *key)
+    }
+
+    /// Whether `signature` over `message` is made by a key trusted for `role`
+    pub fn verify(&self, role: KeyRole, message: &[u8], signature: &[u8; 64]) -> bool {
+        let signature = Signature::from(signature);
+        self.keys()
+            .filter(|entry| entry.role == role && !entry.revoked)
+            .filter_map(|entry| PublicKey::try_from(&entry.key).ok())
+            .any(|key| key.verify(message, &signature).is_ok())
+    }
+
+    /// Trusts `key` for `role`, with `signature` authorizing it once the key store is locked
+    pub fn add(
+        &mut self,
+        key: &[u8; 32],
+        role: KeyRole,
+        signature: Option<&[u8; 64]>,
+    ) -> Result<(), KeyError> {
+        self.authorize(KeyChange::Add(role), key, signature)?;
+        if self.is_revoked(key) {
+            return Err(KeyError::Refused);
+        }
+        if !self.is_trusted(role, key) {
+            let entry = Entry {
+                key: *key,
+                role,
+                revoked: false,
+            };
+            self.entries.push(entry).map_err(|_| KeyError::Full)?;
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Stops trusting `key` for any role, with `signature` authorizing it once the key store is
+    /// locked. Keys that aren't known yet are kept as revoked, so they can't be added later.
+    pub fn revoke(&mut self, key: &[u8; 32], signature: Option<&[u8; 64]>) -> Result<(), KeyError> {
+        self.authorize(KeyChange::Revoke, key, signature)?;
+        let other_firmware_key = self
+            .keys()
+            .any(|entry| entry.role == KeyRole::Firmware && !entry.revoked && entry.key != *key);
+        if !other_firmware_key {
+            return Err(KeyError::Refused);
+        }
+
+        let mut known = false;
+        for entry in self.entries.iter_mut().filter(|entry| entry.key == *key) {
+            entry.revoked = true;
+            known = true;
+        }
+        if !known {
+            let entry = Entry {
+                key: *key,
+                role: KeyRole::Firmware,
+                revoked: true,
+            };
+            self.entries.push(entry).map_err(|_| KeyError::Full)?;
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// From now on changes need the signature of a trusted firmware key. Refused while the
+    /// development key is trusted, which anyone could sign changes with.
+    pub fn lock(&mut self) -> Result<(), KeyError> {
+        if self.is_trusted(KeyRole::Firmware, &DEV_FIRMWARE_KEY) {
+            return Err(KeyError::Refused);
+        }
+        self.locked = true;
+        Ok(())
+    }
+
+    /// The secret the attestation key is derived from, `None` until provisioned
+    pub fn attestation_secret(&self) -> Option<&[u8; 32]> {
+        self.attestation_secret.as_ref()
+    }
+
+    pub fn set_attestation_secret(&mut self, secret: &[u8; 32]) -> Result<(), KeyError> {
+        if self.locked {
+            return Err(KeyError::Unauthorized);
+        }
+        self.attestation_secret = Some(*secret);
+        Ok(())
+    }
+
+    fn authorize(
+        &self,
+        change: KeyChange,
+        key: &[u8; 32],
+        signature: Option<&[u8; 64]>,
+    ) -> Result<(), KeyError> {
+        if !self.locked {
+            return Ok(());
+        }
+        let message = keys::change_message(change, key, self.generation);
+        match signature {
+            Some(signature) if self.verify(KeyRole::Firmware, &message, signature) => Ok(()),
+            _ => Err(KeyError::Unauthorized),
+        }
+    }
+}
+
+/// Loads the security page at `addresses` of `flash`, applies `change` to it and saves it
+pub fn change<F: Flash>(
+    flash: &mut F,
+    addresses: [u32; 2],
+    change: impl FnOnce(&mut SecurityPage) -> Result<(), KeyError>,
+) -> Result<(), KeyError> {
+    let mut page = SecurityPage::load(addresses, |address, data| flash.read(address, data))
+        .ok_or(KeyError::Corrupt)?;
+    change(&mut page)?;
+    page.save(flash, addresses).map_err(KeyError::Flash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{RamFlash, PAGE_SIZE};
+
+    const ADDRESSES: [u32; 2] = [0, PAGE_SIZE];
+
+    fn flash() -> RamFlash {
+        RamFlash::new(0, 2 * PAGE_SIZE, PAGE_SIZE)
+    }
+
+    fn load(flash: &RamFlash) -> Option<SecurityPage> {
+        SecurityPage::load(ADDRESSES, |address, data| flash.read(address, data))
+    }
+
+    fn erased() -> SecurityPage {
+        load(&flash()).unwrap()
+    }
+
+    /// Flips a bit of the copy at `address`, as programming it interrupted might
+    fn corrupt(flash: &mut RamFlash, address: u32, offset: usize) {
+        let mut page = [0; PAGE_SIZE as usize];
+        flash.read(address, &mut page);
+        page[offset] ^= 1;
+        flash.program_page(address, &page).unwrap();
+    }
+
+    #[test]
+    fn erased_page_trusts_the_built_in_key_only() {
+        let page = erased();
+        assert_eq!(page.min_security_version, 0);
+        assert!(!page.locked());
+        assert_eq!(page.keys().count(), 1);
+        assert!(page.attestation_secret().is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut flash = flash();
+        change(&mut flash, ADDRESSES, |page| {
+            page.min_security_version = 7;
+            page.add(&[1; 32], KeyRole::BootFiles, None)?;
+            page.revoke(&[2; 32], None)?;
+            page.set_attestation_secret(&[3; 32])
+        })
+        .unwrap();
+
+        let loaded = load(&flash).unwrap();
+        assert_eq!(loaded.min_security_version, 7);
+        assert_eq!(loaded.generation(), 2);
+        assert!(loaded.is_trusted(KeyRole::BootFiles, &[1; 32]));
+        assert!(loaded.is_revoked(&[2; 32]));
+        assert_eq!(loaded.attestation_secret(), Some(&[3; 32]));
+    }
+
+    #[test]
+    fn saves_alternate_between_the_copies() {
+        let mut flash = flash();
+        for (version, copy) in [(1, 0), (2, 1), (3, 0)] {
+            change(&mut flash, ADDRESSES, |page| {
+                page.min_security_version = version;
+                Ok(())
+            })
+            .unwrap();
+            let loaded = load(&flash).unwrap();
+            assert_eq!(loaded.min_security_version, version);
+            assert_eq!(loaded.copy(), copy);
+        }
+    }
+
+    #[test]
+    fn interrupted_save_keeps_the_other_copy() {
+        let mut flash = flash();
+        for version in [1, 2] {
+            change(&mut flash, ADDRESSES, |page| {
+                page.min_security_version = version;
+                Ok(())
+            })
+            .unwrap();
+        }
+        corrupt(&mut flash, ADDRESSES[1], 0);
+        let loaded = load(&flash).unwrap();
+        assert_eq!(loaded.min_security_version, 1);
+        assert_eq!(loaded.copy(), 0);
+
+        // The next save programs the broken copy again
+        change(&mut flash, ADDRESSES, |page| {
+            page.min_security_version = 3;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(load(&flash).unwrap().min_security_version, 3);
+    }
+
+    #[test]
+    fn corrupt_page_is_refused() {
+        let mut flash = flash();
+        change(&mut flash, ADDRESSES, |page| {
+            page.min_security_version = 7;
+            Ok(())
+        })
+        .unwrap();
+        // The only copy broken, next to an erased one
+        corrupt(&mut flash, ADDRESSES[0], KEYS_OFFSET + 32);
+        assert!(load(&flash).is_none());
+        assert_eq!(
+            change(&mut flash, ADDRESSES, |_| Ok(())),
+            Err(KeyError::Corrupt)
+        );
+
+        corrupt(&mut flash, ADDRESSES[1], 0);
+        assert!(load(&flash).is_none());
+    }
+
+    #[test]
+    fn lock_refused_while_the_development_key_is_trusted() {
+        let mut page = erased();
+        assert_eq!(page.lock(), Err(KeyError::Refused));
+        assert!(!page.locked());
+
+        let fleet_key = [1; 32];
+        page.add(&fleet_key, KeyRole::Firmware, None).unwrap();
+        assert_eq!(page.lock(), Err(KeyError::Refused));
+        page.revoke(&DEV_FIRMWARE_KEY, None).unwrap();
+        assert_eq!(page.lock(), Ok(()));
+        assert!(page.locked());
+    }
+}
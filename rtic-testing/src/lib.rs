@@ -0,0 +1,31 @@
+#![cfg_attr(not(feature = "sim"), no_std)]
+
+//! Shared building blocks for the binaries and examples in this crate.
+
+#[cfg(feature = "slots")]
+pub mod attestation;
+pub mod backup;
+pub mod board;
+#[cfg(feature = "slots")]
+pub mod boot;
+pub mod config;
+pub mod console;
+#[cfg(feature = "slots")]
+pub mod crypto;
+pub mod fans;
+pub mod flash;
+pub mod ghostfat;
+pub mod identity;
+#[cfg(feature = "slots")]
+pub mod keys;
+pub mod logging;
+pub mod monotonic;
+pub mod rpc;
+pub mod rtc;
+pub mod sensors;
+pub mod shell;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod status;
+pub mod usb_identity;
+pub mod watchdog;
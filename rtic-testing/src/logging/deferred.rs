@@ -0,0 +1,99 @@
+//! Compact binary logging using [`defmt`], enabled with the `defmt` feature.
+//!
+//! Only an index to the format string and the raw arguments are sent, the formatting happens on
+//! the host using the string table in the ELF file. Each log call produces one rzCOBS-encoded
+//! frame terminated by a zero byte. The frames are written to
+//!
+//! - the RTT up channel named "defmt" when a debug probe is attached, `probe-run` decodes these
+//!   directly,
+//! - the `usb-log` buffer, drained into a CDC-ACM port by the application,
+//! - the `ram-log` buffer, retained for retrieval without a probe, e.g. as `LOG.BIN` on the
+//!   GhostFat drive of the firmware.
+//!
+//! Frames cut in half by the ring buffers discarding old data are dropped by the decoder, which
+//! resynchronizes at the next zero byte. Run `racklet-bmc log decode` on the host to decode the
+//! output of the latter two transports.
+
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt;
+use cortex_m::peripheral::DCB;
+use cortex_m::register::primask;
+use defmt::Encoder;
+use rtt_target::{rtt_init, UpChannel};
+
+// Without a timestamp source all timestamps are zero
+defmt::timestamp!("{=u64:us}", super::timestamp().unwrap_or(0));
+
+// The following are only accessed by the logger between acquire and release, i.e. with
+// interrupts disabled, or during initialization
+static mut RTT: Option<UpChannel> = None;
+static mut ENCODER: Encoder = Encoder::new();
+static mut RESTORE_INTERRUPTS: bool = false;
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+pub(super) fn init() {
+    // Frames are dropped if the probe doesn't keep up with the 1 KiB buffer
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024
+                mode: NoBlockSkip
+                name: "defmt"
+            }
+        }
+    };
+
+    interrupt::free(|_| unsafe { *addr_of_mut!(RTT) = Some(channels.up.0) });
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let active = primask::read().is_active();
+        interrupt::disable();
+
+        if TAKEN.swap(true, Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+
+        // SAFETY: Interrupts are disabled and the logger is not taken
+        unsafe {
+            *addr_of_mut!(RESTORE_INTERRUPTS) = active;
+            (*addr_of_mut!(ENCODER)).start_frame(write);
+        }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        (*addr_of_mut!(ENCODER)).end_frame(write);
+        TAKEN.store(false, Ordering::Relaxed);
+
+        if *addr_of_mut!(RESTORE_INTERRUPTS) {
+            interrupt::enable();
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*addr_of_mut!(ENCODER)).write(bytes, write);
+    }
+}
+
+/// Writes encoded frame data to all transports, called with interrupts disabled
+fn write(bytes: &[u8]) {
+    if DCB::is_debugger_attached() {
+        // SAFETY: Interrupts are disabled by the logger
+        if let Some(channel) = unsafe { (*addr_of_mut!(RTT)).as_mut() } {
+            channel.write(bytes);
+        }
+    }
+
+    #[cfg(feature = "usb-log")]
+    super::usb::push(bytes);
+
+    #[cfg(feature = "ram-log")]
+    super::ram::push(bytes);
+}
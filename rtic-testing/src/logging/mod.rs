@@ -0,0 +1,335 @@
+//! Logging facade shared by all binaries and examples.
+//!
+//! Records are emitted using the [`log`] macros re-exported from this module and written to every
+//! backend enabled through cargo features:
+//!
+//! - `rtt`: RTT up channel 0, read by `probe-run` and `cargo-embed` (enabled by default),
+//! - `itm`: ITM stimulus port 0, output over SWO,
+//! - `usb-log`: a buffer the application drains into a USB CDC-ACM port using [`usb::drain`], the
+//!   firmware into its console while in the shell,
+//! - `ram-log`: a RAM ring buffer retaining the latest output, readable using [`ram::snapshot`]
+//!   and as `LOG.BIN` on the GhostFat drive of the firmware.
+//!
+//! Without any backend enabled the macros still compile, but all records are discarded.
+//!
+//! With the `defmt` feature the macros are replaced by their [`defmt`] counterparts, which defer
+//! formatting to the host. See [`deferred`] for how the binary frames are transported. Levels are
+//! then filtered at compile time using the `DEFMT_LOG` environment variable instead of [`init`].
+
+#[cfg(feature = "defmt")]
+pub mod deferred;
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::{self, Mutex};
+
+pub use log::{Level, LevelFilter};
+
+#[cfg(not(feature = "defmt"))]
+pub use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "defmt")]
+pub use defmt::{debug, error, info, trace, warn};
+
+/// Overrides the log level of a module and all of its submodules. With `defmt` the overrides are
+/// given in `DEFMT_LOG` instead, e.g. `DEFMT_LOG=info,rtic_testing::logging=debug`.
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    pub module: &'static str,
+    pub level: LevelFilter,
+}
+
+#[cfg(not(feature = "defmt"))]
+impl Filter {
+    fn matches(&self, target: &str) -> bool {
+        match target.strip_prefix(self.module) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        }
+    }
+}
+
+/// Baud rate of the SWO output, divides all trace clock frequencies used on the supported boards
+#[cfg(feature = "itm")]
+pub const ITM_BAUD_RATE: u32 = 2_000_000;
+
+#[cfg(feature = "itm")]
+pub use itm_logger::update_tpiu_baudrate;
+
+/// Text output through the `log` crate
+#[cfg(not(feature = "defmt"))]
+mod text {
+    use core::cell::Cell;
+    use core::fmt::{self, Write};
+    use cortex_m::interrupt::{self, Mutex};
+    use log::{LevelFilter, Log, Metadata, Record};
+
+    use super::Filter;
+
+    struct Logger {
+        level: Mutex<Cell<LevelFilter>>,
+        filters: Mutex<Cell<&'static [Filter]>>,
+    }
+
+    static LOGGER: Logger = Logger {
+        level: Mutex::new(Cell::new(LevelFilter::Info)),
+        filters: Mutex::new(Cell::new(&[])),
+    };
+
+    pub(super) fn set_levels(level: LevelFilter, filters: &'static [Filter]) {
+        interrupt::free(|cs| {
+            LOGGER.level.borrow(cs).set(level);
+            LOGGER.filters.borrow(cs).set(filters);
+        });
+
+        // The most verbose level of any filter must pass the global check in the log macros
+        let max_level = filters.iter().map(|f| f.level).fold(level, core::cmp::max);
+        log::set_max_level(max_level);
+    }
+
+    pub(super) fn init() {
+        #[cfg(feature = "rtt")]
+        rtt_target::rtt_init_print!();
+
+        // This can only fail if another logger is installed, which logging::init prevents
+        log::set_logger(&LOGGER).ok();
+    }
+
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            let (level, filters) =
+                interrupt::free(|cs| (self.level.borrow(cs).get(), self.filters.borrow(cs).get()));
+
+            // The longest matching module path is the most specific filter
+            let level = filters
+                .iter()
+                .filter(|f| f.matches(metadata.target()))
+                .max_by_key(|f| f.module.len())
+                .map_or(level, |f| f.level);
+
+            metadata.level() <= level
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            // Write the whole record at once so concurrent records don't interleave
+            interrupt::free(|_| {
+                if let Some(us) = super::timestamp() {
+                    write!(Backends, "{}.{:06} ", us / 1_000_000, us % 1_000_000).ok();
+                }
+
+                write!(
+                    Backends,
+                    "{:<5} [{}] {}\r\n",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                )
+                .ok();
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Fans out formatted output to all enabled backends
+    struct Backends;
+
+    impl Write for Backends {
+        #[allow(unused_variables)]
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            #[cfg(feature = "rtt")]
+            rtt_target::rprint!(s);
+
+            #[cfg(feature = "itm")]
+            {
+                use cortex_m::peripheral::ITM;
+                // SAFETY: Only called from within a critical section, stimulus port 0 is ours
+                let itm = unsafe { &mut *ITM::PTR };
+                cortex_m::itm::write_str(&mut itm.stim[0], s);
+            }
+
+            #[cfg(feature = "usb-log")]
+            super::usb::push(s.as_bytes());
+
+            #[cfg(feature = "ram-log")]
+            super::ram::push(s.as_bytes());
+
+            Ok(())
+        }
+    }
+}
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static TIMESTAMP: Mutex<Cell<Option<TimestampSource>>> = Mutex::new(Cell::new(None));
+
+/// Returns the current time in microseconds
+pub type TimestampSource = fn() -> u64;
+
+/// Installs the logger with the given default `level`, overridden per module by `filters`.
+/// Calling this again only updates the levels, the backends are set up on the first call.
+#[cfg_attr(feature = "defmt", allow(unused_variables))]
+pub fn init(level: LevelFilter, filters: &'static [Filter]) {
+    #[cfg(not(feature = "defmt"))]
+    text::set_levels(level, filters);
+
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    #[cfg(not(feature = "defmt"))]
+    text::init();
+
+    #[cfg(feature = "defmt")]
+    deferred::init();
+}
+
+/// Sets the source of the record timestamps in microseconds, e.g. the RTIC monotonic. Records
+/// have no timestamps until this is called.
+pub fn set_timestamp_source(source: TimestampSource) {
+    interrupt::free(|cs| TIMESTAMP.borrow(cs).set(Some(source)));
+}
+
+fn timestamp() -> Option<u64> {
+    interrupt::free(|cs| TIMESTAMP.borrow(cs).get()).map(|source| source())
+}
+
+/// Fixed-size byte queue that discards the oldest data when full
+#[cfg(any(feature = "usb-log", feature = "ram-log"))]
+struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+#[cfg(any(feature = "usb-log", feature = "ram-log"))]
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        for &b in data {
+            self.buf[(self.start + self.len) % N] = b;
+            if self.len < N {
+                self.len += 1;
+            } else {
+                self.start = (self.start + 1) % N;
+            }
+        }
+    }
+
+    /// Returns the oldest contiguous run of stored bytes
+    #[cfg(feature = "usb-log")]
+    fn front(&self) -> &[u8] {
+        let end = (self.start + self.len).min(N);
+        &self.buf[self.start..end]
+    }
+
+    fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.start = (self.start + count) % N;
+        self.len -= count;
+    }
+
+    /// Copies as many of the newest bytes as fit into `out`, oldest first
+    #[cfg(feature = "ram-log")]
+    fn copy_to(&self, out: &mut [u8]) -> usize {
+        let count = self.len.min(out.len());
+        self.read(self.len - count, &mut out[..count])
+    }
+
+    /// Copies the stored bytes from `offset` on that fit into `out`, oldest first
+    #[cfg(feature = "ram-log")]
+    fn read(&self, offset: usize, out: &mut [u8]) -> usize {
+        let count = self.len.saturating_sub(offset).min(out.len());
+        for (i, b) in out[..count].iter_mut().enumerate() {
+            *b = self.buf[(self.start + offset + i) % N];
+        }
+        count
+    }
+}
+
+/// Log output buffered for a USB CDC-ACM port
+#[cfg(feature = "usb-log")]
+pub mod usb {
+    use super::RingBuffer;
+    use core::cell::RefCell;
+    use cortex_m::interrupt::{self, Mutex};
+
+    /// Size of the buffer, output is dropped if the host doesn't read the port
+    pub const BUFFER_SIZE: usize = 1024;
+
+    static BUFFER: Mutex<RefCell<RingBuffer<BUFFER_SIZE>>> =
+        Mutex::new(RefCell::new(RingBuffer::new()));
+
+    pub(super) fn push(data: &[u8]) {
+        interrupt::free(|cs| BUFFER.borrow(cs).borrow_mut().push(data));
+    }
+
+    /// Passes buffered output to `write` until it accepts less than it is given. `write` returns
+    /// the number of bytes it consumed, e.g. `|b| serial.write(b).unwrap_or(0)`.
+    pub fn drain<F: FnMut(&[u8]) -> usize>(mut write: F) {
+        interrupt::free(|cs| {
+            let mut buffer = BUFFER.borrow(cs).borrow_mut();
+            loop {
+                let chunk = buffer.front();
+                if chunk.is_empty() {
+                    break;
+                }
+
+                let len = chunk.len();
+                let written = write(chunk);
+                buffer.consume(written);
+                if written < len {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Log output retained in RAM
+#[cfg(feature = "ram-log")]
+pub mod ram {
+    use super::RingBuffer;
+    use core::cell::RefCell;
+    use cortex_m::interrupt::{self, Mutex};
+
+    /// Size of the buffer, only the most recent output is kept
+    pub const BUFFER_SIZE: usize = 4096;
+
+    static BUFFER: Mutex<RefCell<RingBuffer<BUFFER_SIZE>>> =
+        Mutex::new(RefCell::new(RingBuffer::new()));
+
+    pub(super) fn push(data: &[u8]) {
+        interrupt::free(|cs| BUFFER.borrow(cs).borrow_mut().push(data));
+    }
+
+    /// Copies the newest retained output that fits into `out` and returns the number of bytes copied
+    pub fn snapshot(out: &mut [u8]) -> usize {
+        interrupt::free(|cs| BUFFER.borrow(cs).borrow().copy_to(out))
+    }
+
+    /// Copies the retained output from `offset` on that fits into `out` and returns the number of
+    /// bytes copied, for reading it in parts
+    pub fn read(offset: usize, out: &mut [u8]) -> usize {
+        interrupt::free(|cs| BUFFER.borrow(cs).borrow().read(offset, out))
+    }
+
+    /// Discards all retained output
+    pub fn clear() {
+        interrupt::free(|cs| {
+            let mut buffer = BUFFER.borrow(cs).borrow_mut();
+            let len = buffer.len;
+            buffer.consume(len);
+        });
+    }
+}
@@ -1,229 +1,487 @@
 #![no_main]
 #![no_std]
 
-use core::{
-    convert::TryFrom,
-    mem,
-    ops::RangeInclusive,
-    panic::PanicInfo,
-    ptr::{read_volatile, write_volatile},
-    str::from_utf8_unchecked,
-    sync::atomic::{self, Ordering},
-};
-use cortex_m::{asm::*, interrupt};
-use itsybitsy_m4::{
-    prelude::*,
-    time::Hertz,
-    timer::TimerCounter2, // Replacement for CountDownTimer<TIM2>
-    usb::UsbBus,
-};
-use rtic::app;
-// use stm32f1xx_hal::{
-//     prelude::*,
-//     time::Hertz,
-//     usb::{
-//         Peripheral,
-//         UsbBus,
-//         UsbBusType,
-//     },
-//     timer::{
-//         CountDownTimer,
-//         Timer,
-//         Event,
-//     },
-//     pac::{
-//         FLASH,
-//         TIM2,
-//     },
-// };
-use usb_device::{
-    bus,
-    device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
-};
-//use usb_device::prelude::*;
-use itm_logger::*;
-use usbd_mass_storage::USB_CLASS_MSC;
-use usbd_scsi::{BlockDevice, BlockDeviceError, Scsi};
-use usbd_serial::{CdcAcmClass, SerialPort, USB_CLASS_CDC};
-
-// VID and PID are from dapboot bluepill bootloader
-const USB_VID: u16 = 0x1209;
-const USB_PID: u16 = 0xDB42;
-//const USB_CLASS_MISCELLANEOUS: u8 =  0xEF;
-
-const TICK_MS: u32 = 10;
-const TICK_HZ: Hertz = Hertz(1000 / TICK_MS);
-
-use atsamd_hal::common::timer::TimerCounter;
+use core::panic::PanicInfo;
+use cortex_m::{interrupt, peripheral::SCB};
 #[cfg(feature = "itm")]
 use cortex_m::{iprintln, peripheral::ITM};
-
-#[app(device = itsybitsy_m4::pac, peripherals = true)]
-const APP: () = {
-    struct Resources {
-        usb_dev: UsbDevice<'static, UsbBusType>,
-        scsi: Scsi<'static, UsbBusType, GhostFat<FlashWrapper>>,
-        tick_timer: TimerCounter2, // TODO: Replace with trait
-    }
-
-    #[init]
-    fn init(mut cx: init::Context) -> init::LateResources {
-        static mut USB_BUS: Option<bus::UsbBusAllocator<UsbBusType>> = None;
-
-        // If caches are enabled, write operations to flash cause the core to hang because it
-        // is very likely to attempt to load into the prefetch buffer while the write is happening
-        // This can be proved by counting busy loops on the SR.BSY flag. With caches enabled this will
-        // almost always get < 2 cycles. With caches disabled it's a much more relistic figure of
-        // 350 cycles for a write and 150k cycles for a page erase.
-        // However, since we're just busy looping while writing it doesn't really matter. Might be
-        // worth disabling them if there was any useful work to be done in this time but for now,
-        // leave them enabled.
-        //cx.core.SCB.disable_icache();
-        //cx.core.SCB.disable_dcache(&mut cx.core.CPUID);
-
-        #[cfg(feature = "itm")]
-        {
-            update_tpiu_baudrate(8_000_000, ITM_BAUD_RATE).expect("Failed to reset TPIU baudrate");
-            logger_init();
-        }
-
-        info!("ITM reset ok.");
-
-        let mut flash = cx.device.FLASH.constrain();
-        let mut rcc = cx.device.RCC.constrain();
-        let bkp = rcc
-            .bkp
-            .constrain(cx.device.BKP, &mut rcc.apb1, &mut cx.device.PWR);
-        let tim2 = cx.device.TIM2;
-
-        let clocks = rcc
-            .cfgr
-            .use_hse(8.mhz())
-            .sysclk(48.mhz())
-            .pclk1(24.mhz())
-            .freeze(&mut flash.acr);
-
-        let a = itsybitsy_m4::pac::Peripherals::take().unwrap();
-
-        #[cfg(feature = "itm")]
-        {
-            let sysclk: Hertz = clocks.sysclk().into();
-            update_tpiu_baudrate(sysclk.0, ITM_BAUD_RATE).expect("Failed to reset TPIU baudrate");
+use cortex_m_rt::{exception, ExceptionFrame};
+use rtic_testing::backup::{BackupState, Crash};
+use rtic_testing::board::{Board, CurrentBoard};
+
+/// The application is the same for every board, only the interrupt names differ between the MCUs.
+/// RTIC checks the interrupts of `#[cfg]`'d tasks too, so they are passed in instead. The same goes
+/// for the tasks of boards with firmware slots, which RTIC can't leave out by `#[cfg]` either.
+macro_rules! bmc_app {
+    (
+        dispatchers = [$($dispatcher:ident),*],
+        monotonic = $mono_interrupt:ident,
+        usb = [$($usb_interrupt:ident => $usb_task:ident),*],
+        tachometers = [$($tach_interrupt:ident => $tach_task:ident),*],
+        slots = [$(
+            $install_task:ident,
+            $confirm_task:ident,
+            $feed_task:ident,
+            $watchdog_interrupt:ident => $warning_task:ident
+        )?] $(,)?
+    ) => {
+        #[rtic::app(
+            device = rtic_testing::board::pac,
+            peripherals = true,
+            dispatchers = [$($dispatcher),*],
+        )]
+        mod app {
+            use core::cell::RefCell;
+            #[cfg(feature = "slots")]
+            use bmc_proto::image::{Rejection, UpdateStatus};
+            use cortex_m::interrupt::Mutex;
+            use heapless::String;
+            use rtic_testing::backup::BackupState;
+            use rtic_testing::board::{Board, CurrentBoard, Led, PowerControl};
+            #[cfg(feature = "slots")]
+            use rtic_testing::boot::{self, Slot};
+            use rtic_testing::config::ConfigStore;
+            use rtic_testing::console::Console;
+            use rtic_testing::fans::{FanControl, FanPwm as _, Tachometers as _};
+            use rtic_testing::flash::{Flash as _, FlashWrapper, SharedFlash};
+            use rtic_testing::ghostfat::GhostFat;
+            use rtic_testing::identity::Identity;
+            #[cfg(feature = "slots")]
+            use rtic_testing::logging::error;
+            use rtic_testing::logging::{self, info, warn, LevelFilter};
+            use rtic_testing::rtc::{self, WallClock};
+            use rtic_testing::sensors::{self, SensorConfig, Sensors};
+            use rtic_testing::shell::Bmc;
+            use rtic_testing::status::{State, StatusIndicator, TICK_PERIOD};
+            use rtic_testing::usb_identity::UsbIdentity;
+            #[cfg(feature = "slots")]
+            use rtic_testing::watchdog::{self, Task, Watchdog as _};
+            use usb_device::{
+                bus::UsbBusAllocator,
+                device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
+            };
+            use usbd_scsi::Scsi;
+            use usbd_serial::SerialPort;
+
+            type UsbBus = <CurrentBoard as Board>::UsbBus;
+            type Flash = SharedFlash<<CurrentBoard as Board>::Flash>;
+            type StatusLed = <CurrentBoard as Board>::StatusLed;
+            type Power = <CurrentBoard as Board>::Power;
+            type I2c = <CurrentBoard as Board>::I2c;
+            type Analog = <CurrentBoard as Board>::Analog;
+            type FanPwm = <CurrentBoard as Board>::FanPwm;
+            type Tachometers = <CurrentBoard as Board>::Tachometers;
+            type Rtc = <CurrentBoard as Board>::Rtc;
+            #[cfg(feature = "slots")]
+            type Crypto = <CurrentBoard as Board>::Crypto;
+            // Boards without slots hash nothing, RTIC can't leave the resource out by `#[cfg]`
+            #[cfg(not(feature = "slots"))]
+            type Crypto = ();
+
+            const SENSOR_TABLES: &[&[SensorConfig]] = &[
+                sensors::SENSORS,
+                CurrentBoard::ON_CHIP_SENSORS,
+                CurrentBoard::FAN_SENSORS,
+            ];
+
+            /// The board information and the lines describing the running image
+            const MAX_UF2_INFO_LEN: usize = 256;
+
+            #[monotonic(binds = $mono_interrupt, default = true)]
+            type Mono = <CurrentBoard as Board>::Mono;
+
+            #[shared]
+            struct Shared {
+                #[lock_free]
+                identity: &'static Identity,
+                #[lock_free]
+                boot: &'static BackupState,
+                usb_dev: UsbDevice<'static, UsbBus>,
+                scsi: Scsi<'static, UsbBus, GhostFat<Flash>>,
+                serial: SerialPort<'static, UsbBus>,
+                console: Console,
+                power: Power,
+                status: StatusIndicator,
+                config: ConfigStore<Flash>,
+                sensors: Sensors,
+                tachometers: Tachometers,
+                clock: WallClock<Rtc>,
+                /// Only the USB tasks take it, which all run at the same priority
+                #[lock_free]
+                crypto: Crypto,
+            }
+
+            #[local]
+            struct Local {
+                status_led: StatusLed,
+                i2c: I2c,
+                analog: Analog,
+                fan_pwm: FanPwm,
+                fan_control: FanControl,
+                #[cfg(feature = "slots")]
+                update_flash: Flash,
+                #[cfg(feature = "slots")]
+                update_crypto: Crypto,
+            }
+
+            #[init(local = [
+                usb_allocator: Option<UsbBusAllocator<UsbBus>> = None,
+                flash: Option<Mutex<RefCell<<CurrentBoard as Board>::Flash>>> = None,
+                identity: Option<Identity> = None,
+                boot: Option<BackupState> = None,
+                usb_identity: Option<UsbIdentity> = None,
+                uf2_info: String<MAX_UF2_INFO_LEN> = String::new(),
+            ])]
+            fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
+                #[cfg(feature = "itm")]
+                logging::update_tpiu_baudrate(8_000_000, logging::ITM_BAUD_RATE)
+                    .expect("Failed to reset TPIU baudrate");
+                logging::init(LevelFilter::Info, &[]);
+                logging::set_timestamp_source(|| monotonics::now().ticks());
+
+                info!("Logger init ok.");
+
+                let parts = CurrentBoard::init(c.device, c.local.usb_allocator);
+                let boot = &*c.local.boot.insert(BackupState::boot(&mut CurrentBoard::backup()));
+
+                // The USB device borrows the serial number for the lifetime of the program
+                let identity = &*c.local.identity.insert(Identity::new(&CurrentBoard::unique_id()));
+                info!(
+                    "Serial number {}, node name {}",
+                    identity.serial_number(),
+                    identity.node_name()
+                );
+
+                #[cfg(feature = "itm")]
+                logging::update_tpiu_baudrate(parts.sysclk, logging::ITM_BAUD_RATE)
+                    .expect("Failed to reset TPIU baudrate");
+
+                let flash_end = parts.flash.end_address();
+                info!("Flash: {} KiB", flash_end / 1024);
+
+                // The settings take the last page, the application image may use the rest
+                let config_address = flash_end - parts.flash.page_size();
+                let flash = SharedFlash::new(c.local.flash.insert(Mutex::new(RefCell::new(parts.flash))));
+                let config = ConfigStore::new(flash.clone(), config_address);
+
+                // Parts with less flash than the board is linked for have no room for updates
+                let app_end = CurrentBoard::APP_END.min(config_address).max(CurrentBoard::APP_START);
+                let flash_wrapper = FlashWrapper::new(flash.clone(), CurrentBoard::APP_START, app_end);
+                if app_end == CurrentBoard::APP_START {
+                    warn!("No flash left for updates, the drive has no CURRENT.UF2");
+                } else {
+                    info!("Flash MAX: {:#x}", flash_wrapper.max_address());
+                }
+
+                // Like the serial number, the strings are borrowed for the lifetime of the program
+                let usb_identity = &*c.local.usb_identity.insert(UsbIdentity::load(&config));
+                info!("USB ID {:04x}:{:04x}", usb_identity.vid, usb_identity.pid);
+
+                // INFO_UF2.TXT also describes the running image
+                let uf2_info = c.local.uf2_info;
+                uf2_info.push_str(CurrentBoard::UF2_INFO).unwrap();
+                #[cfg(feature = "slots")]
+                if let Some(header) =
+                    boot::running_image(&CurrentBoard::SLOTS, |address, data| flash.read(address, data))
+                {
+                    info!(
+                        "Running image {} with security version {}",
+                        header.version, header.security_version
+                    );
+                    boot::write_uf2_info(uf2_info, &header).unwrap();
+                }
+                let uf2_info: &'static String<MAX_UF2_INFO_LEN> = uf2_info;
+
+                #[cfg_attr(not(any(feature = "slots", feature = "ram-log")), allow(unused_mut))]
+                let mut ghostfat = GhostFat::new(
+                    flash_wrapper,
+                    CurrentBoard::UF2_FAMILY_ID,
+                    uf2_info,
+                );
+                // The retained log is also on the drive, for decoding it without a probe
+                #[cfg(feature = "ram-log")]
+                ghostfat.set_log(logging::ram::read, logging::ram::BUFFER_SIZE as u32);
+                $(
+                    // Installs the update once the host is done writing it
+                    ghostfat.set_update_hook(|| {
+                        boot::LAST_UPDATE.set(UpdateStatus::Installing);
+                        $install_task::spawn_after(boot::INSTALL_DELAY).ok();
+                    });
+                )?
+
+                let scsi = Scsi::new(
+                    parts.usb_allocator,
+                    64,
+                    ghostfat,
+                    usb_identity.scsi_vendor.as_str(),
+                    usb_identity.scsi_product.as_str(),
+                    usb_identity.scsi_revision.as_str(),
+                );
+
+                let serial = SerialPort::new(parts.usb_allocator);
+
+                // The GhostFat drive and the console are separate functions of a composite device
+                let usb_ids = UsbVidPid(usb_identity.vid, usb_identity.pid);
+                let usb_dev = UsbDeviceBuilder::new(parts.usb_allocator, usb_ids)
+                    .manufacturer(usb_identity.manufacturer.as_str())
+                    .product(usb_identity.product.as_str())
+                    .serial_number(identity.serial_number())
+                    .self_powered(true)
+                    .composite_with_iads()
+                    .build();
+
+                let fan_control = FanControl::load(&config, sensors::POLL_PERIOD);
+                let clock = WallClock::load(parts.rtc, &config);
+
+                heartbeat::spawn().unwrap();
+                correct_clock::spawn().unwrap();
+                // The first poll reads the fan speeds from the pulses counted since now
+                poll_sensors::spawn_after(sensors::POLL_PERIOD).unwrap();
+                $(
+                    // Only spawned here, it can't be queued twice
+                    $confirm_task::spawn_after(boot::CONFIRM_DELAY).unwrap();
+                    $feed_task::spawn().unwrap();
+                )?
+
+                (
+                    Shared {
+                        identity,
+                        boot,
+                        usb_dev,
+                        scsi,
+                        serial,
+                        console: Console::new(),
+                        power: parts.power,
+                        status: StatusIndicator::new(),
+                        config,
+                        sensors: Sensors::new(SENSOR_TABLES),
+                        tachometers: parts.tachometers,
+                        clock,
+                        #[cfg(feature = "slots")]
+                        crypto: CurrentBoard::crypto(),
+                        #[cfg(not(feature = "slots"))]
+                        crypto: (),
+                    },
+                    Local {
+                        status_led: parts.status_led,
+                        i2c: parts.i2c,
+                        analog: parts.analog,
+                        fan_pwm: parts.fan_pwm,
+                        fan_control,
+                        #[cfg(feature = "slots")]
+                        update_flash: flash,
+                        #[cfg(feature = "slots")]
+                        update_crypto: CurrentBoard::crypto(),
+                    },
+                    init::Monotonics(parts.mono),
+                )
+            }
+
+            $(
+                #[task(
+                    binds = $usb_interrupt,
+                    priority = 2,
+                    shared = [
+                        identity, boot, usb_dev, scsi, serial, console, power, status, config, sensors,
+                        clock, crypto,
+                    ],
+                )]
+                fn $usb_task(c: $usb_task::Context) {
+                    let s = c.shared;
+                    let identity = *s.identity;
+                    let boot = *s.boot;
+                    #[cfg(feature = "slots")]
+                    let crypto = s.crypto;
+                    let mut resources = (
+                        s.usb_dev, s.scsi, s.serial, s.console, s.power, s.status, s.config, s.sensors,
+                        s.clock,
+                    );
+                    resources.lock(
+                        |usb_dev, scsi, serial, console, power, status, config, sensors, clock| {
+                            usb_dev.poll(&mut [scsi, serial]);
+                            console.poll(
+                                serial,
+                                &mut Bmc {
+                                    identity,
+                                    boot,
+                                    power,
+                                    status,
+                                    config,
+                                    sensors,
+                                    clock,
+                                    #[cfg(feature = "slots")]
+                                    slots: &CurrentBoard::SLOTS,
+                                    #[cfg(feature = "slots")]
+                                    crypto,
+                                },
+                            );
+                        },
+                    );
+                }
+            )*
+
+            /// Shows the BMC status on the LED, which blinks as a heartbeat while idle and red while
+            /// a sensor is critical
+            #[task(priority = 1, local = [status_led], shared = [power, status, sensors])]
+            fn heartbeat(c: heartbeat::Context) {
+                let s = c.shared;
+                let color = (s.power, s.status, s.sensors).lock(|power, status, sensors| {
+                    status.set_state(if sensors.any_critical() {
+                        State::Error
+                    } else if power.is_powered() {
+                        State::HostOn
+                    } else {
+                        State::Idle
+                    });
+                    status.tick()
+                });
+                c.local.status_led.set(color);
+                #[cfg(feature = "slots")]
+                watchdog::CHECK_INS.check_in(Task::Heartbeat);
+                heartbeat::spawn_after(TICK_PERIOD).unwrap();
+            }
+
+            /// Reads the sensors and sets the fan speed for the readings, the readings are only
+            /// locked for storing each one
+            #[task(
+                priority = 1,
+                local = [i2c, analog, fan_pwm, fan_control],
+                shared = [sensors, tachometers],
+            )]
+            fn poll_sensors(mut c: poll_sensors::Context) {
+                let pulses = c.shared.tachometers.lock(|tachometers| tachometers.take_pulses());
+                for (i, sensor) in sensors::all(SENSOR_TABLES).enumerate() {
+                    // Failed reads leave the sensor unavailable, which is logged
+                    let value = sensor
+                        .device
+                        .read(c.local.i2c, c.local.analog, &pulses, sensor.channel)
+                        .ok();
+                    c.shared.sensors.lock(|sensors| sensors.update(i, value));
+                }
+
+                let (headroom, fan_failed) = c.shared.sensors.lock(|sensors| {
+                    (sensors.temperature_headroom(), sensors.any_fan_failed())
+                });
+                let duty = c.local.fan_control.update(headroom, fan_failed);
+                c.local.fan_pwm.set_duty(duty);
+
+                #[cfg(feature = "slots")]
+                watchdog::CHECK_INS.check_in(Task::PollSensors);
+                poll_sensors::spawn_after(sensors::POLL_PERIOD).unwrap();
+            }
+
+            /// Steps the RTC by its drift
+            #[task(priority = 1, shared = [clock])]
+            fn correct_clock(mut c: correct_clock::Context) {
+                c.shared.clock.lock(|clock| clock.correct());
+                correct_clock::spawn_after(rtc::CORRECTION_PERIOD).unwrap();
+            }
+
+            $(
+            /// Checks the update received into the other slot and resets to start it on trial
+            #[task(priority = 1, local = [update_flash, update_crypto], shared = [config])]
+            fn $install_task(mut c: $install_task::Context) {
+                let flash = &*c.local.update_flash;
+                let read = |address, data: &mut [u8]| flash.read(address, data);
+                let slot = Slot::RUNNING.other();
+                if let Err(e) = boot::verify(&CurrentBoard::SLOTS, slot, c.local.update_crypto, read) {
+                    error!("Update rejected: {}", e);
+                    boot::LAST_UPDATE.set(UpdateStatus::Rejected(e.into()));
+                    return;
+                }
+
+                let scheduled = c
+                    .shared
+                    .config
+                    .lock(|config| boot::schedule_trial(config, &mut CurrentBoard::backup()));
+                match scheduled {
+                    Ok(()) => {
+                        boot::LAST_UPDATE.set(UpdateStatus::Scheduled);
+                        cortex_m::peripheral::SCB::sys_reset()
+                    }
+                    Err(_) => {
+                        warn!("Saving boot.trial failed");
+                        boot::LAST_UPDATE.set(UpdateStatus::Rejected(Rejection::Flash));
+                    }
+                }
+            }
+
+            /// Confirms the running firmware once it has run long enough without crashing
+            #[task(priority = 1, shared = [config])]
+            fn $confirm_task(mut c: $confirm_task::Context) {
+                if c.shared.config.lock(|config| boot::confirm(config)).is_err() {
+                    warn!("Confirming slot {} failed", Slot::RUNNING);
+                }
+            }
+
+            /// Feeds the watchdog once the tasks it waits for have all checked in
+            #[task(priority = 1)]
+            fn $feed_task(_: $feed_task::Context) {
+                if watchdog::CHECK_INS.take_all() {
+                    CurrentBoard::watchdog().feed();
+                }
+                $feed_task::spawn_after(watchdog::FEED_PERIOD).unwrap();
+            }
+
+            /// Records the task the watchdog waited for before it resets the MCU, above every
+            /// other priority so a hung task can't hold it off
+            #[task(binds = $watchdog_interrupt, priority = 4)]
+            fn $warning_task(_: $warning_task::Context) {
+                let culprit = watchdog::CHECK_INS.culprit();
+                BackupState::record_watchdog_culprit(&mut CurrentBoard::backup(), culprit as u8);
+                CurrentBoard::watchdog().acknowledge_warning();
+            }
+            )?
+
+            $(
+                /// Counts the pulses of a fan tachometer
+                #[task(binds = $tach_interrupt, priority = 3, shared = [tachometers])]
+                fn $tach_task(mut c: $tach_task::Context) {
+                    c.shared.tachometers.lock(|tachometers| tachometers.on_interrupt());
+                }
+            )*
         }
+    };
+}
 
-        assert!(clocks.usbclk_valid());
-
-        let flash_kib = get_flash_kibi();
-        info!("Flash: {} KiB", flash_kib);
-
-        // This may not be 100% accurate. Cube hal has some random IFDEFs that don't even appear
-        // to align with the core density.
-        let page_size = if flash_kib > 128 { 2048 } else { 1024 };
-
-        let flash_wrapper = FlashWrapper {
-            page_size,
-            page_buffer: [0; 2048],
-            current_page: None,
-            min_address: 0x08010000,
-            max_address: 0x08000000 + flash_kib as u32 * 1024,
-        };
-        info!("Flash MAX: 0x{:X?}", flash_wrapper.max_address);
-
-        let mut gpioa = cx.device.GPIOA.split(&mut rcc.apb2);
-
-        // BluePill board has a pull-up resistor on the D+ line.
-        // Pull the D+ pin down to send a RESET condition to the USB bus.
-        // This forced reset is needed only for development, without it host
-        // will not reset your device when you upload new firmware.
-        let mut usb_dp = gpioa.pa12.into_push_pull_output(&mut gpioa.crh);
-        usb_dp.set_low().unwrap();
-        delay(clocks.sysclk().0 / 100);
-
-        let usb_dm = gpioa.pa11;
-        let usb_dp = usb_dp.into_floating_input(&mut gpioa.crh);
-
-        let usb = Peripheral {
-            usb: cx.device.USB,
-            pin_dm: usb_dm,
-            pin_dp: usb_dp,
-        };
-
-        // *USB_BUS = Some(UsbBus::new(usb));
-
-        let mut tick_timer = Timer::tim2(tim2, &clocks, &mut rcc.apb1).start_count_down(TICK_HZ);
-        tick_timer.listen(Event::Update);
-
-        let ghost_fat = GhostFat::new(flash_wrapper, bkp);
-
-        let scsi = Scsi::new(
-            USB_BUS.as_ref().unwrap(),
-            64,
-            ghost_fat,
-            "Fake Co.",
-            "Fake product",
-            "FK01",
-        );
-
-        let serial_number = get_serial_number();
-        info!("Serial number: {}", serial_number);
-
-        let usb_dev = UsbDeviceBuilder::new(USB_BUS.as_ref().unwrap(), UsbVidPid(USB_VID, USB_PID))
-            .manufacturer("Fake company")
-            .product("Serial port")
-            .serial_number(serial_number)
-            .self_powered(true)
-            .device_class(USB_CLASS_MSC)
-            .build();
-
-        init::LateResources {
-            usb_dev,
-            scsi,
-            tick_timer,
-        }
-    }
-
-    #[task(binds = USB_HP_CAN_TX, resources = [usb_dev, scsi])]
-    fn usb_tx(mut cx: usb_tx::Context) {
-        usb_poll(&mut cx.resources.usb_dev, &mut cx.resources.scsi);
-    }
-
-    #[task(binds = USB_LP_CAN_RX0, resources = [usb_dev, scsi])]
-    fn usb_rx0(mut cx: usb_rx0::Context) {
-        usb_poll(&mut cx.resources.usb_dev, &mut cx.resources.scsi);
-    }
-
-    #[task(binds = TIM2, resources = [scsi, tick_timer])]
-    fn tick(cx: tick::Context) {
-        cx.resources.tick_timer.clear_update_interrupt_flag();
+#[cfg(feature = "board-itsybitsy-m4")]
+bmc_app! {
+    dispatchers = [EVSYS_0],
+    monotonic = TC0,
+    usb = [USB_OTHER => usb_other, USB_TRCPT0 => usb_trcpt0, USB_TRCPT1 => usb_trcpt1],
+    tachometers = [EIC_EXTINT_3 => fan1_tachometer, EIC_EXTINT_7 => fan2_tachometer],
+    slots = [install_update, confirm_boot, feed_watchdog, WDT => watchdog_warning],
+}
 
-        cx.resources.scsi.block_device_mut().tick(TICK_MS);
-    }
-};
-
-fn usb_poll<B: bus::UsbBus>(
-    usb_dev: &mut UsbDevice<'static, B>,
-    scsi: &mut Scsi<'static, B, GhostFat<FlashWrapper>>,
-) {
-    if !usb_dev.poll(&mut [scsi]) {
-        return;
-    }
+// CAN can't be used at the same time as USB on the STM32F103, which frees its interrupts
+#[cfg(feature = "board-bluepill")]
+bmc_app! {
+    dispatchers = [CAN_RX1],
+    monotonic = TIM2,
+    usb = [USB_HP_CAN_TX => usb_hp, USB_LP_CAN_RX0 => usb_lp],
+    tachometers = [],
+    slots = [],
 }
 
 #[panic_handler]
 fn panic(#[cfg_attr(not(feature = "itm"), allow(unused_variables))] info: &PanicInfo) -> ! {
     interrupt::disable();
+    BackupState::record_crash(&mut CurrentBoard::backup(), Crash::Panic);
 
     #[cfg(feature = "itm")]
     {
-        let itm = unsafe { &mut *ITM::ptr() };
+        let itm = unsafe { &mut *ITM::PTR };
         let stim = &mut itm.stim[0];
 
         iprintln!(stim, "{}", info);
     }
 
-    loop {
-        // add some side effect to prevent this from turning into a UDF instruction
-        // see rust-lang/rust#28728 for details
-        atomic::compiler_fence(Ordering::SeqCst)
-    }
+    // The bootloader rolls back firmware on trial that keeps crashing
+    SCB::sys_reset()
+}
+
+#[exception]
+fn HardFault(frame: &ExceptionFrame) -> ! {
+    BackupState::record_crash(&mut CurrentBoard::backup(), Crash::HardFault { pc: frame.pc });
+    SCB::sys_reset()
 }
@@ -0,0 +1,9 @@
+//! Timebase shared by the RTIC monotonics of all boards, so task periods can be given in one unit.
+
+pub use fugit::ExtU64;
+
+/// Tick rate of the timebase, one tick is a microsecond
+pub const TICK_HZ: u32 = 1_000_000;
+
+pub type Instant = fugit::TimerInstantU64<TICK_HZ>;
+pub type Duration = fugit::TimerDurationU64<TICK_HZ>;
@@ -0,0 +1,378 @@
+//! The binary protocol of [`bmc_proto`] on the console, which `racklet-bmc` switches to with the
+//! `rpc` shell command.
+
+#[cfg(feature = "slots")]
+use core::convert::TryFrom;
+
+use bmc_proto::attestation::REPORT_LEN;
+use bmc_proto::frame::{self, FrameDecoder, MAX_FRAME_LEN};
+#[cfg(feature = "slots")]
+use bmc_proto::image::ImageInfo;
+use bmc_proto::sdr::MAX_RECORD_LEN;
+use bmc_proto::{
+    Error, Request, RequestBody, Response, ResponseBody, SensorReading, SensorStatus, Unit,
+    MAX_LOG_CHUNK, VERSION,
+};
+use smart_leds::RGB8;
+
+#[cfg(feature = "slots")]
+use crate::attestation;
+#[cfg(feature = "slots")]
+use crate::boot;
+use crate::config;
+use crate::flash::Flash;
+#[cfg(feature = "slots")]
+use crate::keys::{self, KeyError, SecurityPage};
+use crate::logging::{debug, warn};
+#[cfg(feature = "rtc")]
+use crate::rtc;
+use crate::sensors::{self, sdr};
+use crate::shell::Bmc;
+
+/// The longest log output, record or report a response carries
+const MAX_RESPONSE_DATA: usize = if MAX_LOG_CHUNK > REPORT_LEN {
+    MAX_LOG_CHUNK
+} else {
+    REPORT_LEN
+};
+
+pub struct RpcServer {
+    decoder: FrameDecoder,
+}
+
+impl RpcServer {
+    pub const fn new() -> Self {
+        Self {
+            decoder: FrameDecoder::new(),
+        }
+    }
+
+    /// Handles bytes received from the host, passing the response frames to `out`. Returns
+    /// `Some(handled)` when [`RequestBody::Exit`] ended the session after the first `handled`
+    /// bytes, the rest of `data` is then for the shell.
+    pub fn input<F: Flash>(
+        &mut self,
+        data: &[u8],
+        bmc: &mut Bmc<F>,
+        out: &mut dyn FnMut(&[u8]),
+    ) -> Option<usize> {
+        for (i, &byte) in data.iter().enumerate() {
+            let message = match self.decoder.push(byte) {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    // Nothing can be answered without a sequence number, the host will retry
+                    debug!("Dropped RPC frame: {:?}", e);
+                    continue;
+                }
+                None => continue,
+            };
+
+            let mut buf = [0; MAX_RESPONSE_DATA];
+            match frame::decode::<Request>(message) {
+                Ok(request) => {
+                    let body = handle(&request.body, bmc, &mut buf);
+                    respond(request.seq, body, out);
+                    if request.body == RequestBody::Exit {
+                        return Some(i + 1);
+                    }
+                }
+                Err(_) => {
+                    if let Some(seq) = Request::seq_of(message) {
+                        respond(seq, ResponseBody::Error(Error::InvalidRequest), out);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for RpcServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn respond(seq: u16, body: ResponseBody, out: &mut dyn FnMut(&[u8])) {
+    let mut buf = [0; MAX_FRAME_LEN];
+    match frame::encode(&Response { seq, body }, &mut buf) {
+        Ok(frame) => out(frame),
+        Err(e) => warn!("RPC response {} not sent: {:?}", seq, e),
+    }
+}
+
+/// Runs a request, the log output, record or report a response carries is stored in `buf`
+fn handle<'a, F: Flash>(
+    request: &RequestBody,
+    bmc: &'a mut Bmc<F>,
+    buf: &'a mut [u8; MAX_RESPONSE_DATA],
+) -> ResponseBody<'a> {
+    match *request {
+        RequestBody::Hello { version } if version == VERSION => ResponseBody::Hello {
+            version,
+            firmware: env!("CARGO_PKG_VERSION"),
+        },
+        RequestBody::Hello { .. } => ResponseBody::Error(Error::UnsupportedVersion),
+        RequestBody::Exit => ResponseBody::Ok,
+        RequestBody::GetPower => ResponseBody::Power(bmc.power.is_powered()),
+        RequestBody::SetPower(on) => {
+            bmc.power.set_power(on);
+            ResponseBody::Ok
+        }
+        RequestBody::SetReset(asserted) => {
+            bmc.power.set_reset(asserted);
+            ResponseBody::Ok
+        }
+        RequestBody::SetLed(color) => {
+            let color = color.map(|[r, g, b]| RGB8 { r, g, b });
+            bmc.status.set_color_override(color);
+            ResponseBody::Ok
+        }
+        RequestBody::GetConfig(key) => match bmc.config.get(key) {
+            Some(value) => ResponseBody::Config(value),
+            None => ResponseBody::Error(Error::NotFound),
+        },
+        RequestBody::SetConfig { key, value } => match bmc.config.set(key, value) {
+            Ok(()) => ResponseBody::Ok,
+            Err(e) => ResponseBody::Error(config_error(e)),
+        },
+        RequestBody::UnsetConfig(key) => match bmc.config.remove(key) {
+            Ok(true) => ResponseBody::Ok,
+            Ok(false) => ResponseBody::Error(Error::NotFound),
+            Err(e) => ResponseBody::Error(config_error(e)),
+        },
+        RequestBody::ConfigEntry(index) => {
+            ResponseBody::ConfigEntry(bmc.config.iter().nth(index as usize))
+        }
+        RequestBody::GetIdentity => ResponseBody::Identity {
+            serial_number: bmc.identity.serial_number(),
+            node_name: bmc.identity.node_name(),
+            mac_address: bmc.identity.mac_address().0,
+        },
+        RequestBody::GetSensor(index) => ResponseBody::Sensor(
+            bmc.sensors
+                .iter()
+                .nth(index as usize)
+                .map(|(sensor, reading)| SensorReading {
+                    name: sensor.name,
+                    unit: unit(sensor.channel.unit()),
+                    value: reading.value,
+                    status: sensor_status(reading.status),
+                }),
+        ),
+        RequestBody::GetTime => ResponseBody::Time(bmc.clock.now()),
+        #[cfg(feature = "rtc")]
+        RequestBody::SetTime(seconds) => match bmc.clock.sync(seconds, bmc.config) {
+            Ok(()) => ResponseBody::Ok,
+            Err(rtc::Error::OutOfRange) => ResponseBody::Error(Error::OutOfRange),
+            Err(rtc::Error::Unsupported) => ResponseBody::Error(Error::Unsupported),
+        },
+        #[cfg(not(feature = "rtc"))]
+        RequestBody::SetTime(_) => ResponseBody::Error(Error::Unsupported),
+        #[cfg(feature = "slots")]
+        RequestBody::GetImage => {
+            let flash = bmc.config.flash_mut();
+            let header = boot::running_image(bmc.slots, |address, data| flash.read(address, data));
+            ResponseBody::Image(header.as_ref().map(ImageInfo::from))
+        }
+        #[cfg(not(feature = "slots"))]
+        RequestBody::GetImage => ResponseBody::Error(Error::Unsupported),
+        #[cfg(feature = "slots")]
+        RequestBody::GetKeyStore => match security_page(bmc) {
+            Some(security) => ResponseBody::KeyStore {
+                locked: security.locked(),
+                generation: security.generation(),
+            },
+            None => ResponseBody::Error(Error::Corrupt),
+        },
+        #[cfg(feature = "slots")]
+        RequestBody::GetKey(index) => match security_page(bmc) {
+            Some(security) => ResponseBody::Key(security.keys().nth(index as usize)),
+            None => ResponseBody::Error(Error::Corrupt),
+        },
+        #[cfg(feature = "slots")]
+        RequestBody::AddKey {
+            key,
+            role,
+            signature,
+        } => change_keys(bmc, signature, |security, signature| {
+            security.add(&key, role, signature)
+        }),
+        #[cfg(feature = "slots")]
+        RequestBody::RevokeKey { key, signature } => {
+            change_keys(bmc, signature, |security, signature| {
+                security.revoke(&key, signature)
+            })
+        }
+        #[cfg(feature = "slots")]
+        RequestBody::LockKeys => change_keys(bmc, None, |security, _| security.lock()),
+        #[cfg(feature = "slots")]
+        RequestBody::Attest { nonce } => {
+            let flash = bmc.config.flash_mut();
+            let unique_id = bmc.identity.unique_id();
+            let read = |address, data: &mut [u8]| flash.read(address, data);
+            match attestation::report(bmc.slots, unique_id, &nonce, bmc.crypto, read) {
+                Ok(report) => {
+                    let buf = buf.first_chunk_mut::<REPORT_LEN>().unwrap();
+                    *buf = report;
+                    ResponseBody::Attestation(buf)
+                }
+                Err(e) => ResponseBody::Error(attestation_error(e)),
+            }
+        }
+        #[cfg(feature = "slots")]
+        RequestBody::GetMeasurement(index) => {
+            let flash = bmc.config.flash_mut();
+            let measurement =
+                attestation::measurement(bmc.slots, index as usize, bmc.crypto, |address, data| {
+                    flash.read(address, data)
+                });
+            match measurement {
+                Ok(measurement) => ResponseBody::Measurement(measurement),
+                Err(_) => ResponseBody::Error(Error::Crypto),
+            }
+        }
+        #[cfg(feature = "slots")]
+        RequestBody::GetAttestationKey => {
+            let security = match security_page(bmc) {
+                Some(security) => security,
+                None => return ResponseBody::Error(Error::Corrupt),
+            };
+            match attestation::key(&security, bmc.identity.unique_id(), bmc.crypto) {
+                Ok(keypair) => ResponseBody::AttestationKey(keypair.public.to_bytes()),
+                Err(e) => ResponseBody::Error(attestation_error(e)),
+            }
+        }
+        #[cfg(feature = "slots")]
+        RequestBody::SetAttestationSecret(secret) => change_keys(bmc, None, |security, _| {
+            security.set_attestation_secret(&secret)
+        }),
+        #[cfg(feature = "slots")]
+        RequestBody::GetUpdate => {
+            let flash = bmc.config.flash_mut();
+            match boot::min_security_version(bmc.slots, |address, data| flash.read(address, data)) {
+                Some(min_security_version) => ResponseBody::Update {
+                    min_security_version,
+                    last: boot::LAST_UPDATE.get(),
+                },
+                None => ResponseBody::Error(Error::Corrupt),
+            }
+        }
+        #[cfg(not(feature = "slots"))]
+        RequestBody::GetKeyStore
+        | RequestBody::GetKey(_)
+        | RequestBody::AddKey { .. }
+        | RequestBody::RevokeKey { .. }
+        | RequestBody::LockKeys
+        | RequestBody::Attest { .. }
+        | RequestBody::GetMeasurement(_)
+        | RequestBody::GetAttestationKey
+        | RequestBody::SetAttestationSecret(_)
+        | RequestBody::GetUpdate => ResponseBody::Error(Error::Unsupported),
+        RequestBody::GetSdr(record_id) => {
+            let index = record_id as usize;
+            let record = buf.first_chunk_mut::<MAX_RECORD_LEN>().unwrap();
+            ResponseBody::Sdr(
+                bmc.sensors
+                    .iter()
+                    .nth(index)
+                    .map(move |(config, _)| sdr::record(index as u8, config).encode(record)),
+            )
+        }
+        #[cfg(feature = "ram-log")]
+        RequestBody::ReadLog { offset } => {
+            use crate::logging::ram;
+
+            // The log may move on between requests, which the host has to live with
+            let mut snapshot = [0; ram::BUFFER_SIZE];
+            let len = ram::snapshot(&mut snapshot);
+            let start = (offset as usize).min(len);
+            let end = (start + MAX_LOG_CHUNK).min(len);
+            buf[..end - start].copy_from_slice(&snapshot[start..end]);
+            ResponseBody::Log(&buf[..end - start])
+        }
+        #[cfg(feature = "ram-log")]
+        RequestBody::ClearLog => {
+            crate::logging::ram::clear();
+            ResponseBody::Ok
+        }
+        #[cfg(not(feature = "ram-log"))]
+        RequestBody::ReadLog { .. } | RequestBody::ClearLog => {
+            ResponseBody::Error(Error::Unsupported)
+        }
+    }
+}
+
+#[cfg(feature = "slots")]
+fn security_page<F: Flash>(bmc: &mut Bmc<F>) -> Option<SecurityPage> {
+    let flash = bmc.config.flash_mut();
+    SecurityPage::load(bmc.slots.security_pages, |address, data| {
+        flash.read(address, data)
+    })
+}
+
+#[cfg(feature = "slots")]
+fn attestation_error(e: attestation::Error) -> Error {
+    match e {
+        attestation::Error::NotProvisioned => Error::NotFound,
+        attestation::Error::SecurityPage => Error::Corrupt,
+        attestation::Error::Crypto => Error::Crypto,
+    }
+}
+
+/// Applies `change` to the key store, passing it the signature authorizing the change
+#[cfg(feature = "slots")]
+fn change_keys<'a, F: Flash>(
+    bmc: &mut Bmc<F>,
+    signature: Option<&[u8]>,
+    change: impl FnOnce(&mut SecurityPage, Option<&[u8; 64]>) -> Result<(), KeyError>,
+) -> ResponseBody<'a> {
+    let signature = match signature.map(<&[u8; 64]>::try_from) {
+        Some(Ok(signature)) => Some(signature),
+        Some(Err(_)) => return ResponseBody::Error(Error::Unauthorized),
+        None => None,
+    };
+    let result = keys::change(
+        bmc.config.flash_mut(),
+        bmc.slots.security_pages,
+        |security| change(security, signature),
+    );
+    match result {
+        Ok(()) => ResponseBody::Ok,
+        Err(KeyError::Unauthorized) => ResponseBody::Error(Error::Unauthorized),
+        Err(KeyError::Refused) => ResponseBody::Error(Error::KeyRefused),
+        Err(KeyError::Full) => ResponseBody::Error(Error::Full),
+        Err(KeyError::Corrupt) => ResponseBody::Error(Error::Corrupt),
+        Err(KeyError::Flash(_)) => ResponseBody::Error(Error::Flash),
+    }
+}
+
+fn config_error(error: config::Error) -> Error {
+    match error {
+        config::Error::InvalidKey => Error::InvalidKey,
+        config::Error::InvalidValue => Error::InvalidValue,
+        config::Error::Full => Error::Full,
+        config::Error::Flash(_) => Error::Flash,
+    }
+}
+
+fn unit(unit: sensors::Unit) -> Unit {
+    match unit {
+        sensors::Unit::Celsius => Unit::Celsius,
+        sensors::Unit::Volts => Unit::Volts,
+        sensors::Unit::Amps => Unit::Amps,
+        sensors::Unit::Rpm => Unit::Rpm,
+    }
+}
+
+fn sensor_status(status: sensors::Status) -> SensorStatus {
+    match status {
+        sensors::Status::Ok => SensorStatus::Ok,
+        sensors::Status::LowerNonCritical => SensorStatus::LowerNonCritical,
+        sensors::Status::UpperNonCritical => SensorStatus::UpperNonCritical,
+        sensors::Status::LowerCritical => SensorStatus::LowerCritical,
+        sensors::Status::UpperCritical => SensorStatus::UpperCritical,
+        sensors::Status::Unavailable => SensorStatus::Unavailable,
+    }
+}
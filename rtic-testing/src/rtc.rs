@@ -0,0 +1,380 @@
+//! Wall-clock time, kept by the real-time clock of the board and set by the host.
+//!
+//! The RTC runs from the 32 kHz oscillator of the MCU, which is far less accurate than a crystal.
+//! [`WallClock`] measures how fast the RTC runs between syncs that are at least
+//! [`MIN_DRIFT_INTERVAL`] apart and corrects it by stepping the RTC every [`CORRECTION_PERIOD`].
+//! The drift is kept in the setting below, so it is corrected from the next reset on too:
+//!
+//! | Key          | Value                                                      |
+//! |--------------|------------------------------------------------------------|
+//! | `time.drift` | How fast the RTC runs in parts per million, set by syncing |
+//!
+//! The time comes from the `time set` shell command or the host, `racklet-bmc` syncs it every time
+//! it connects. Without networking there is no SNTP client yet, it would sync the same way. Setting
+//! and showing the time needs the `rtc` feature, which the boards with an RTC enable.
+
+use core::fmt::Write;
+
+use bmc_proto::time::DateTime;
+use heapless::String;
+
+use crate::config::ConfigStore;
+use crate::flash::Flash;
+use crate::logging::{info, warn};
+use crate::monotonic::Duration;
+
+/// How often the drift is corrected
+pub const CORRECTION_PERIOD: Duration = Duration::millis(60_000);
+
+/// The shortest time between syncs the drift is measured over, as the RTC can only be read and
+/// set to the second
+pub const MIN_DRIFT_INTERVAL: i64 = 6 * 3600;
+
+/// The largest drift corrected, in parts per million. Measurements beyond it are taken as the
+/// clock having been changed and ignored.
+pub const MAX_DRIFT: i32 = 50_000;
+
+/// 2000-01-01T00:00:00Z, the earliest time the RTCs keep
+pub const EARLIEST: i64 = 946_684_800;
+/// 2063-12-31T23:59:59Z, the latest time the RTCs keep
+pub const LATEST: i64 = 2_966_371_199;
+
+/// The real-time clock of the board, counting seconds since the Unix epoch
+pub trait Rtc {
+    /// The time, `None` if the RTC hasn't been set since it lost power
+    fn unix_time(&mut self) -> Option<i64>;
+
+    /// Sets the time, which is between [`EARLIEST`] and [`LATEST`]
+    fn set_unix_time(&mut self, seconds: i64);
+}
+
+/// For boards without an RTC
+pub struct NoRtc;
+
+impl Rtc for NoRtc {
+    fn unix_time(&mut self) -> Option<i64> {
+        None
+    }
+
+    fn set_unix_time(&mut self, _seconds: i64) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The time is before [`EARLIEST`] or after [`LATEST`]
+    OutOfRange,
+    /// The board has no RTC
+    Unsupported,
+}
+
+/// The RTC with the correction of its drift
+pub struct WallClock<R: ?Sized = dyn Rtc> {
+    /// In parts per million, positive while the RTC runs fast
+    drift: i32,
+    /// The RTC time of the last correction
+    corrected_at: Option<i64>,
+    /// The microseconds the RTC has run fast since, not corrected yet
+    pending: i64,
+    /// The time of the sync the drift is measured from
+    measured_from: Option<i64>,
+    /// The seconds syncs have stepped the RTC back since, which are part of the measurement
+    stepped: i64,
+    rtc: R,
+}
+
+impl<R: Rtc> WallClock<R> {
+    /// The clock with the drift from the settings. An invalid setting is logged and ignored.
+    pub fn load<F: Flash>(rtc: R, config: &ConfigStore<F>) -> Self {
+        let mut drift = 0;
+        if let Some(value) = config.get("time.drift") {
+            match value.parse::<i32>() {
+                Ok(ppm) if ppm.abs() <= MAX_DRIFT => drift = ppm,
+                _ => warn!(
+                    "Ignoring time.drift, {} is not -{} to {} ppm",
+                    value, MAX_DRIFT, MAX_DRIFT
+                ),
+            }
+        }
+        Self {
+            drift,
+            corrected_at: None,
+            pending: 0,
+            measured_from: None,
+            stepped: 0,
+            rtc,
+        }
+    }
+}
+
+impl<R: Rtc + ?Sized> WallClock<R> {
+    /// The drift corrected in parts per million
+    pub fn drift(&self) -> i32 {
+        self.drift
+    }
+
+    /// The corrected time in seconds since the Unix epoch, `None` while the RTC isn't set
+    pub fn now(&mut self) -> Option<i64> {
+        self.correct();
+        self.rtc.unix_time()
+    }
+
+    /// Steps the RTC by the drift since the last correction, once it adds up to a second. Called
+    /// every [`CORRECTION_PERIOD`].
+    pub fn correct(&mut self) {
+        let Some(now) = self.rtc.unix_time() else {
+            return;
+        };
+        let Some(from) = self.corrected_at.replace(now) else {
+            return;
+        };
+
+        self.pending += (now - from) * self.drift as i64;
+        let seconds = self.pending / 1_000_000;
+        if seconds != 0 {
+            self.rtc.set_unix_time(now - seconds);
+            self.corrected_at = Some(now - seconds);
+            self.pending -= seconds * 1_000_000;
+        }
+    }
+
+    /// Sets the clock to the time of the host, measuring the drift if the last sync was long
+    /// enough ago. A new drift is saved to the settings.
+    pub fn sync<F: Flash>(
+        &mut self,
+        seconds: i64,
+        config: &mut ConfigStore<F>,
+    ) -> Result<(), Error> {
+        if !(EARLIEST..=LATEST).contains(&seconds) {
+            return Err(Error::OutOfRange);
+        }
+        self.correct();
+
+        let current = self.rtc.unix_time();
+        match (current, self.measured_from) {
+            (Some(current), Some(from)) if seconds - from >= MIN_DRIFT_INTERVAL => {
+                // What the corrected RTC gained since the measurement began
+                let error = current - seconds + self.stepped;
+                let drift = self.drift as i64 + error * 1_000_000 / (seconds - from);
+                if drift.abs() <= MAX_DRIFT as i64 {
+                    self.save_drift(drift as i32, config);
+                } else {
+                    warn!("Ignoring a clock drift of {} ppm", drift);
+                }
+                self.measured_from = Some(seconds);
+                self.stepped = 0;
+            }
+            (Some(current), Some(_)) => self.stepped += current - seconds,
+            _ => {
+                self.measured_from = Some(seconds);
+                self.stepped = 0;
+            }
+        }
+
+        self.rtc.set_unix_time(seconds);
+        if self.rtc.unix_time().is_none() {
+            return Err(Error::Unsupported);
+        }
+        self.corrected_at = Some(seconds);
+        self.pending = 0;
+
+        // Syncs are frequent, only setting the clock is worth logging
+        if current.is_none_or(|current| (current - seconds).abs() > 1) {
+            info!("Clock set to {}", DateTime::from_unix(seconds));
+        }
+        Ok(())
+    }
+
+    fn save_drift<F: Flash>(&mut self, drift: i32, config: &mut ConfigStore<F>) {
+        if drift == self.drift {
+            return;
+        }
+        info!("Clock drift is {} ppm", drift);
+        self.drift = drift;
+
+        let mut value = String::<12>::new();
+        write!(value, "{}", drift).ok();
+        if config.set("time.drift", &value).is_err() {
+            warn!("Saving time.drift failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{RamFlash, PAGE_SIZE};
+
+    /// 2026-10-19T00:00:00Z
+    const START: i64 = 1_792_368_000;
+
+    /// An RTC running `ppm` parts per million fast, kept to the microsecond
+    struct FakeRtc {
+        micros: Option<i64>,
+        ppm: i64,
+    }
+
+    impl FakeRtc {
+        fn new(ppm: i64) -> Self {
+            Self { micros: None, ppm }
+        }
+
+        /// Lets `seconds` of real time pass
+        fn run(&mut self, seconds: i64) {
+            if let Some(micros) = &mut self.micros {
+                *micros += seconds * (1_000_000 + self.ppm);
+            }
+        }
+    }
+
+    impl Rtc for FakeRtc {
+        fn unix_time(&mut self) -> Option<i64> {
+            Some(self.micros?.div_euclid(1_000_000))
+        }
+
+        /// Keeps the fraction of the second, as the prescalers of the RTCs run on
+        fn set_unix_time(&mut self, seconds: i64) {
+            let fraction = self.micros.unwrap_or(0).rem_euclid(1_000_000);
+            self.micros = Some(seconds * 1_000_000 + fraction);
+        }
+    }
+
+    fn config(drift: Option<&str>) -> ConfigStore<RamFlash> {
+        let mut config = ConfigStore::new(RamFlash::new(0, PAGE_SIZE, PAGE_SIZE), 0);
+        if let Some(drift) = drift {
+            config.set("time.drift", drift).unwrap();
+        }
+        config
+    }
+
+    /// Runs the clock for `seconds`, correcting it every [`CORRECTION_PERIOD`] as the application
+    fn run(clock: &mut WallClock<FakeRtc>, seconds: i64) {
+        let period = CORRECTION_PERIOD.to_secs() as i64;
+        for _ in 0..seconds / period {
+            clock.rtc.run(period);
+            clock.correct();
+        }
+        clock.rtc.run(seconds % period);
+    }
+
+    #[test]
+    fn loads_drift() {
+        let clock = WallClock::load(FakeRtc::new(0), &config(Some("-120")));
+        assert_eq!(clock.drift(), -120);
+        for invalid in ["50001", "-50001", "12ppm", ""] {
+            let clock = WallClock::load(FakeRtc::new(0), &config(Some(invalid)));
+            assert_eq!(clock.drift(), 0);
+        }
+    }
+
+    #[test]
+    fn unset_until_synced() {
+        let mut config = config(None);
+        let mut clock = WallClock::load(FakeRtc::new(0), &config);
+        clock.correct();
+        assert_eq!(clock.now(), None);
+
+        clock.sync(START, &mut config).unwrap();
+        assert_eq!(clock.now(), Some(START));
+    }
+
+    #[test]
+    fn rejects_times_out_of_range() {
+        let mut config = config(None);
+        let mut clock = WallClock::load(FakeRtc::new(0), &config);
+        assert_eq!(
+            clock.sync(EARLIEST - 1, &mut config),
+            Err(Error::OutOfRange)
+        );
+        assert_eq!(clock.sync(LATEST + 1, &mut config), Err(Error::OutOfRange));
+        assert_eq!(clock.now(), None);
+        assert_eq!(clock.sync(EARLIEST, &mut config), Ok(()));
+        assert_eq!(clock.sync(LATEST, &mut config), Ok(()));
+
+        let mut clock = WallClock::load(NoRtc, &config);
+        assert_eq!(clock.sync(START, &mut config), Err(Error::Unsupported));
+    }
+
+    #[test]
+    fn corrects_once_the_drift_adds_up_to_a_second() {
+        let mut config = config(Some("100"));
+        let mut clock = WallClock::load(FakeRtc::new(0), &config);
+        clock.sync(START, &mut config).unwrap();
+
+        // 100 ppm are a second every 10 000 seconds
+        clock.rtc.run(9_999);
+        clock.correct();
+        assert_eq!(clock.now(), Some(START + 9_999));
+        clock.rtc.run(1);
+        assert_eq!(clock.now(), Some(START + 9_999));
+        clock.rtc.run(10_000);
+        assert_eq!(clock.now(), Some(START + 19_998));
+
+        // A slow RTC is stepped forward
+        let mut config = self::config(Some("-100"));
+        let mut clock = WallClock::load(FakeRtc::new(0), &config);
+        clock.sync(START, &mut config).unwrap();
+        clock.rtc.run(10_000);
+        assert_eq!(clock.now(), Some(START + 10_001));
+    }
+
+    #[test]
+    fn corrected_clock_keeps_time() {
+        let mut config = config(Some("100"));
+        let mut clock = WallClock::load(FakeRtc::new(100), &config);
+        clock.sync(START, &mut config).unwrap();
+        run(&mut clock, 7 * 86_400);
+        // Uncorrected it would be a minute ahead
+        let error = clock.now().unwrap() - (START + 7 * 86_400);
+        assert!(error.abs() <= 1, "{}", error);
+    }
+
+    #[test]
+    fn measures_drift_between_syncs() {
+        let mut config = config(None);
+        let mut clock = WallClock::load(FakeRtc::new(100), &config);
+        clock.sync(START, &mut config).unwrap();
+        run(&mut clock, 100_000);
+        assert_eq!(clock.now(), Some(START + 100_010));
+
+        clock.sync(START + 100_000, &mut config).unwrap();
+        assert_eq!(clock.drift(), 100);
+        assert_eq!(config.get("time.drift"), Some("100"));
+        assert_eq!(clock.now(), Some(START + 100_000));
+
+        // Corrected from now on
+        run(&mut clock, 100_000);
+        assert_eq!(clock.now(), Some(START + 200_000));
+    }
+
+    #[test]
+    fn refines_drift_across_short_syncs() {
+        let mut config = config(Some("100"));
+        let mut clock = WallClock::load(FakeRtc::new(150), &config);
+        clock.sync(START, &mut config).unwrap();
+
+        // Syncs too close to measure over still count the seconds they step back
+        for elapsed in [10_000, 20_000] {
+            run(&mut clock, 10_000);
+            clock.sync(START + elapsed, &mut config).unwrap();
+            assert_eq!(clock.drift(), 100);
+        }
+        run(&mut clock, 80_000);
+        clock.sync(START + 100_000, &mut config).unwrap();
+        let drift = clock.drift();
+        assert!((140..=160).contains(&drift), "{}", drift);
+        assert_eq!(config.get("time.drift"), Some(drift.to_string().as_str()));
+    }
+
+    #[test]
+    fn ignores_implausible_drift() {
+        let mut config = config(None);
+        let mut clock = WallClock::load(FakeRtc::new(0), &config);
+        clock.sync(START, &mut config).unwrap();
+        // The clock was changed an hour ahead in between
+        run(&mut clock, MIN_DRIFT_INTERVAL);
+        clock.rtc.run(3_600);
+        clock.sync(START + MIN_DRIFT_INTERVAL, &mut config).unwrap();
+        assert_eq!(clock.drift(), 0);
+        assert_eq!(config.get("time.drift"), None);
+    }
+}
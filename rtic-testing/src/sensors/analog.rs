@@ -0,0 +1,192 @@
+//! Sensors built into the MCU: its temperature sensor and the ADC inputs measuring board voltages.
+//!
+//! The board provides the ADC through [`Analog`]. The conversions of the raw ADC results are kept
+//! apart as plain functions, like the register conversions of the I2C parts.
+
+/// An ADC input measuring a voltage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnalogInput {
+    /// The I/O supply of the MCU, through its internal divider
+    IoSupply,
+    /// An analog pin of the board by number, e.g. 2 for A2
+    Pin(u8),
+}
+
+/// The ADC of the MCU
+pub trait Analog {
+    /// Reads the voltage at `input` in millivolts, `None` if the board doesn't have the input
+    fn millivolts(&mut self, input: AnalogInput) -> Option<i32>;
+
+    /// Reads the temperature sensor of the MCU in millidegrees Celsius, `None` if it has none
+    fn millicelsius(&mut self) -> Option<i32>;
+}
+
+/// For boards without on-chip sensors
+pub struct NoAnalog;
+
+impl Analog for NoAnalog {
+    fn millivolts(&mut self, _input: AnalogInput) -> Option<i32> {
+        None
+    }
+
+    fn millicelsius(&mut self) -> Option<i32> {
+        None
+    }
+}
+
+/// The voltage of a conversion result relative to the reference voltage
+pub fn adc_millivolts(result: u16, bits: u32, reference_millivolts: i32) -> i32 {
+    (result as i64 * reference_millivolts as i64 / ((1 << bits) - 1)) as i32
+}
+
+/// The voltage in front of a divider bringing it down to `1 / ratio`
+pub fn undivided_millivolts(millivolts: i32, ratio: u32) -> i32 {
+    millivolts.saturating_mul(ratio as i32)
+}
+
+/// The calibration of the SAMD51 temperature sensor, measured at the factory at a room and a hot
+/// temperature and stored in the temperature log row of the NVM software calibration area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemperatureCalibration {
+    /// The temperatures in millidegrees Celsius
+    pub room_millicelsius: i32,
+    pub hot_millicelsius: i32,
+    /// The 12-bit ADC results of the PTAT (proportional to absolute temperature) and CTAT
+    /// (complementary to absolute temperature) sensors at the temperatures, with a 1.0 V reference
+    pub room_ptat: u16,
+    pub hot_ptat: u16,
+    pub room_ctat: u16,
+    pub hot_ctat: u16,
+}
+
+impl TemperatureCalibration {
+    /// Parses the first 128 bits of the temperature log row, laid out as in the "Temperature Log
+    /// Row" table of the SAMD5x/E5x datasheet
+    pub fn from_words(words: [u32; 4]) -> Self {
+        let field = |bit: u32, len: u32| {
+            let word = words[bit as usize / 32] >> (bit % 32);
+            word & ((1 << len) - 1)
+        };
+        Self {
+            room_millicelsius: temperature(field(0, 8), field(8, 4)),
+            hot_millicelsius: temperature(field(12, 8), field(20, 4)),
+            room_ptat: field(40, 12) as u16,
+            hot_ptat: field(52, 12) as u16,
+            room_ctat: field(64, 12) as u16,
+            hot_ctat: field(76, 12) as u16,
+        }
+    }
+
+    /// The temperature in millidegrees Celsius from the ADC results of the PTAT and CTAT sensors,
+    /// by the formula of the "Device Temperature Measurement" section of the datasheet. `None` if
+    /// the calibration is blank.
+    pub fn millicelsius(&self, ptat: u16, ctat: u16) -> Option<i32> {
+        let (tl, th) = (self.room_millicelsius as i64, self.hot_millicelsius as i64);
+        let (vpl, vph) = (self.room_ptat as i64, self.hot_ptat as i64);
+        let (vcl, vch) = (self.room_ctat as i64, self.hot_ctat as i64);
+        let (tp, tc) = (ptat as i64, ctat as i64);
+
+        let numerator = tl * (vph * tc - vch * tp) - th * (vpl * tc - vcl * tp);
+        let denominator = tc * (vph - vpl) - tp * (vch - vcl);
+        if denominator == 0 {
+            return None;
+        }
+        Some((numerator / denominator) as i32)
+    }
+}
+
+/// A calibration temperature from its integer part and its decimal part, which is stored in tenths
+/// of a degree, e.g. 25.3 °C as 25 and 3
+fn temperature(integer: u32, decimal: u32) -> i32 {
+    (integer * 1000 + decimal * 100) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs the fields of the temperature log row as the NVM holds them
+    fn words(tli: u32, tld: u32, thi: u32, thd: u32, ptat: [u32; 2], ctat: [u32; 2]) -> [u32; 4] {
+        let mut row = 0u128;
+        for (bit, value) in [
+            (0, tli),
+            (8, tld),
+            (12, thi),
+            (20, thd),
+            (40, ptat[0]),
+            (52, ptat[1]),
+            (64, ctat[0]),
+            (76, ctat[1]),
+        ] {
+            row |= (value as u128) << bit;
+        }
+        [0, 32, 64, 96].map(|bit| (row >> bit) as u32)
+    }
+
+    fn calibration() -> TemperatureCalibration {
+        TemperatureCalibration::from_words(words(25, 3, 85, 7, [0x6A0, 0x8C0], [0xA10, 0x8F0]))
+    }
+
+    #[test]
+    fn calibration_row_is_parsed() {
+        assert_eq!(
+            calibration(),
+            TemperatureCalibration {
+                room_millicelsius: 25_300,
+                hot_millicelsius: 85_700,
+                room_ptat: 0x6A0,
+                hot_ptat: 0x8C0,
+                room_ctat: 0xA10,
+                hot_ctat: 0x8F0,
+            }
+        );
+    }
+
+    #[test]
+    fn calibration_decimals_are_tenths() {
+        // INT + DEC / 10 of the datasheet, for every decimal the 4 bits can hold
+        for decimal in 0..16 {
+            let calibration =
+                TemperatureCalibration::from_words(words(30, decimal, 90, 0, [0; 2], [0; 2]));
+            assert_eq!(calibration.room_millicelsius, 30_000 + decimal as i32 * 100);
+        }
+    }
+
+    #[test]
+    fn calibration_points_give_their_temperatures() {
+        let calibration = calibration();
+        assert_eq!(calibration.millicelsius(0x6A0, 0xA10), Some(25_300));
+        assert_eq!(calibration.millicelsius(0x8C0, 0x8F0), Some(85_700));
+    }
+
+    #[test]
+    fn temperatures_between_the_calibration_points() {
+        // Halfway between the points on both sensors is halfway between the temperatures
+        let calibration = calibration();
+        assert_eq!(calibration.millicelsius(0x7B0, 0x980), Some(55_500));
+        assert!(calibration.millicelsius(0x7B0, 0xA10).unwrap() > 25_300);
+    }
+
+    #[test]
+    fn blank_calibration_is_rejected() {
+        let blank = TemperatureCalibration::from_words([u32::MAX; 4]);
+        assert_eq!(blank.millicelsius(0x7B0, 0x980), None);
+    }
+
+    #[test]
+    fn adc_results_are_scaled_to_the_reference() {
+        assert_eq!(adc_millivolts(0, 12, 3_300), 0);
+        assert_eq!(adc_millivolts(4_095, 12, 3_300), 3_300);
+        assert_eq!(adc_millivolts(2_048, 12, 3_300), 1_650);
+        assert_eq!(adc_millivolts(1_023, 10, 1_000), 1_000);
+        assert_eq!(adc_millivolts(u16::MAX, 16, 3_300), 3_300);
+    }
+
+    #[test]
+    fn dividers_are_undone() {
+        assert_eq!(undivided_millivolts(1_650, 2), 3_300);
+        assert_eq!(undivided_millivolts(2_000, 6), 12_000);
+        assert_eq!(undivided_millivolts(1_234, 1), 1_234);
+        assert_eq!(undivided_millivolts(i32::MAX / 2, 4), i32::MAX);
+    }
+}
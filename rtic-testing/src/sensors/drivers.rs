@@ -0,0 +1,325 @@
+//! Drivers for the I2C sensor parts, reading their registers through `embedded-hal`, for the
+//! on-chip sensors, reading them through the [`Analog`] of the board, and for the fan
+//! tachometers, whose pulses are counted by the board.
+//!
+//! The parts measure continuously with their power-on defaults, so reading a sensor is a single
+//! register read. The conversions from the register values are kept apart as plain functions.
+
+use embedded_hal::blocking::i2c::WriteRead;
+
+use super::analog::{self, Analog, AnalogInput};
+use super::{Channel, POLL_PERIOD};
+use crate::fans::{self, Pulses};
+
+/// A sensor part and its I2C address, or a sensor of the MCU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// TI TMP102 temperature sensor
+    Tmp102 { address: u8 },
+    /// LM75 temperature sensor, including the 11-bit LM75B
+    Lm75 { address: u8 },
+    /// TI INA219 power monitor with the shunt resistor it measures across
+    Ina219 { address: u8, shunt_milliohms: u32 },
+    /// TI INA226 power monitor with the shunt resistor it measures across
+    Ina226 { address: u8, shunt_milliohms: u32 },
+    /// The temperature sensor of the MCU
+    McuTemperature,
+    /// An ADC input of the MCU behind a divider bringing the voltage down to `1 / divider_ratio`
+    Adc {
+        input: AnalogInput,
+        divider_ratio: u32,
+    },
+    /// The tachometer of a fan
+    Fan { index: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// The part doesn't measure the channel
+    UnsupportedChannel,
+    /// The board doesn't have the on-chip sensor or fan
+    Unavailable,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::I2c(e)
+    }
+}
+
+/// The temperature register of the TMP102 and LM75
+const TEMPERATURE: u8 = 0x00;
+/// The shunt voltage register of the INA219 and INA226
+const SHUNT_VOLTAGE: u8 = 0x01;
+/// The bus voltage register of the INA219 and INA226
+const BUS_VOLTAGE: u8 = 0x02;
+
+impl Device {
+    /// Reads `channel` in thousandths of its unit. Fan speeds are read from the `pulses` counted
+    /// over the last [`POLL_PERIOD`].
+    pub fn read<I2C: WriteRead, A: Analog>(
+        &self,
+        i2c: &mut I2C,
+        analog: &mut A,
+        pulses: &Pulses,
+        channel: Channel,
+    ) -> Result<i32, Error<I2C::Error>> {
+        match (*self, channel) {
+            (Device::Tmp102 { address }, Channel::Temperature) => Ok(tmp102_millicelsius(
+                read_register(i2c, address, TEMPERATURE)?,
+            )),
+            (Device::Lm75 { address }, Channel::Temperature) => {
+                Ok(lm75_millicelsius(read_register(i2c, address, TEMPERATURE)?))
+            }
+            (Device::Ina219 { address, .. }, Channel::BusVoltage) => Ok(ina219_bus_millivolts(
+                read_register(i2c, address, BUS_VOLTAGE)?,
+            )),
+            (
+                Device::Ina219 {
+                    address,
+                    shunt_milliohms,
+                },
+                Channel::Current,
+            ) => {
+                let shunt = ina219_shunt_microvolts(read_register(i2c, address, SHUNT_VOLTAGE)?);
+                Ok(shunt_milliamps(shunt, shunt_milliohms))
+            }
+            (Device::Ina226 { address, .. }, Channel::BusVoltage) => Ok(ina226_bus_millivolts(
+                read_register(i2c, address, BUS_VOLTAGE)?,
+            )),
+            (
+                Device::Ina226 {
+                    address,
+                    shunt_milliohms,
+                },
+                Channel::Current,
+            ) => {
+                let shunt = ina226_shunt_microvolts(read_register(i2c, address, SHUNT_VOLTAGE)?);
+                Ok(shunt_milliamps(shunt, shunt_milliohms))
+            }
+            (Device::McuTemperature, Channel::Temperature) => {
+                analog.millicelsius().ok_or(Error::Unavailable)
+            }
+            (
+                Device::Adc {
+                    input,
+                    divider_ratio,
+                },
+                Channel::Voltage,
+            ) => {
+                let millivolts = analog.millivolts(input).ok_or(Error::Unavailable)?;
+                Ok(analog::undivided_millivolts(millivolts, divider_ratio))
+            }
+            (Device::Fan { index }, Channel::Speed) => {
+                let pulses = pulses.get(index as usize).copied().flatten();
+                let pulses = pulses.ok_or(Error::Unavailable)?;
+                Ok(fans::millirpm(pulses, POLL_PERIOD))
+            }
+            _ => Err(Error::UnsupportedChannel),
+        }
+    }
+}
+
+/// Reads a 16-bit register, which all of the parts send most significant byte first
+fn read_register<I2C: WriteRead>(
+    i2c: &mut I2C,
+    address: u8,
+    register: u8,
+) -> Result<u16, I2C::Error> {
+    let mut value = [0; 2];
+    i2c.write_read(address, &[register], &mut value)?;
+    Ok(u16::from_be_bytes(value))
+}
+
+/// The 12-bit two's complement temperature is left aligned, 0.0625 °C per bit
+pub fn tmp102_millicelsius(register: u16) -> i32 {
+    (register as i16 >> 4) as i32 * 625 / 10
+}
+
+/// The 11-bit two's complement temperature is left aligned, 0.125 °C per bit. The original LM75
+/// only has the top 9 bits, the rest read as zero.
+pub fn lm75_millicelsius(register: u16) -> i32 {
+    (register as i16 >> 5) as i32 * 125
+}
+
+/// The voltage is in the top 13 bits, 4 mV per bit
+pub fn ina219_bus_millivolts(register: u16) -> i32 {
+    (register >> 3) as i32 * 4
+}
+
+/// Two's complement, 10 µV per bit
+pub fn ina219_shunt_microvolts(register: u16) -> i32 {
+    register as i16 as i32 * 10
+}
+
+/// 1.25 mV per bit
+pub fn ina226_bus_millivolts(register: u16) -> i32 {
+    register as i32 * 125 / 100
+}
+
+/// Two's complement, 2.5 µV per bit
+pub fn ina226_shunt_microvolts(register: u16) -> i32 {
+    register as i16 as i32 * 25 / 10
+}
+
+/// The current through a shunt by Ohm's law, µV / mΩ = mA
+pub fn shunt_milliamps(shunt_microvolts: i32, shunt_milliohms: u32) -> i32 {
+    shunt_microvolts / shunt_milliohms.max(1) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use embedded_hal_mock::MockError;
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::fans::MAX_FANS;
+    use crate::sensors::analog::NoAnalog;
+
+    /// Reads `channel` of `device` from an I2C bus expecting `transactions`
+    fn read(
+        device: Device,
+        channel: Channel,
+        transactions: &[Transaction],
+    ) -> Result<i32, Error<MockError>> {
+        let mut i2c = Mock::new(transactions);
+        let result = device.read(&mut i2c, &mut NoAnalog, &[None; MAX_FANS], channel);
+        i2c.done();
+        result
+    }
+
+    fn register(address: u8, register: u8, value: u16) -> Transaction {
+        Transaction::write_read(address, vec![register], value.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn tmp102_temperatures() {
+        // The examples of the TMP102 datasheet, table 8-2
+        assert_eq!(tmp102_millicelsius(0x7FF0), 127_937);
+        assert_eq!(tmp102_millicelsius(0x6400), 100_000);
+        assert_eq!(tmp102_millicelsius(0x1900), 25_000);
+        assert_eq!(tmp102_millicelsius(0x0040), 250);
+        assert_eq!(tmp102_millicelsius(0x0000), 0);
+        assert_eq!(tmp102_millicelsius(0xFFC0), -250);
+        assert_eq!(tmp102_millicelsius(0xE700), -25_000);
+        assert_eq!(tmp102_millicelsius(0xC900), -55_000);
+    }
+
+    #[test]
+    fn lm75_temperatures() {
+        // The examples of the LM75B datasheet, table 10
+        assert_eq!(lm75_millicelsius(0x7F00), 127_000);
+        assert_eq!(lm75_millicelsius(0x7EE0), 126_875);
+        assert_eq!(lm75_millicelsius(0x1900), 25_000);
+        assert_eq!(lm75_millicelsius(0x0020), 125);
+        assert_eq!(lm75_millicelsius(0x0000), 0);
+        assert_eq!(lm75_millicelsius(0xFFE0), -125);
+        assert_eq!(lm75_millicelsius(0xE700), -25_000);
+        assert_eq!(lm75_millicelsius(0xC920), -54_875);
+        assert_eq!(lm75_millicelsius(0xC900), -55_000);
+        // The original LM75 has half degrees and zeros below
+        assert_eq!(lm75_millicelsius(0xFF80), -500);
+    }
+
+    #[test]
+    fn ina219_voltages() {
+        // The lowest 3 bits are flags
+        assert_eq!(ina219_bus_millivolts(0x5DC0), 12_000);
+        assert_eq!(ina219_bus_millivolts(0x5DC3), 12_000);
+        assert_eq!(ina219_bus_millivolts(0xFFF8), 32_764);
+        // The full scale of the 320 mV range and a step of each sign
+        assert_eq!(ina219_shunt_microvolts(0x7D00), 320_000);
+        assert_eq!(ina219_shunt_microvolts(0x0001), 10);
+        assert_eq!(ina219_shunt_microvolts(0xFFFF), -10);
+        assert_eq!(ina219_shunt_microvolts(0x8300), -320_000);
+    }
+
+    #[test]
+    fn ina226_voltages() {
+        assert_eq!(ina226_bus_millivolts(0x2580), 12_000);
+        assert_eq!(ina226_bus_millivolts(0x7FFF), 40_958);
+        assert_eq!(ina226_shunt_microvolts(0x7FFF), 81_917);
+        assert_eq!(ina226_shunt_microvolts(0x0004), 10);
+        assert_eq!(ina226_shunt_microvolts(0xFFFC), -10);
+        assert_eq!(ina226_shunt_microvolts(0x8000), -81_920);
+    }
+
+    #[test]
+    fn shunt_currents() {
+        assert_eq!(shunt_milliamps(320_000, 100), 3_200);
+        assert_eq!(shunt_milliamps(-25_000, 10), -2_500);
+        assert_eq!(shunt_milliamps(0, 100), 0);
+        // A shunt of less than 1 mΩ in the table doesn't divide by zero
+        assert_eq!(shunt_milliamps(1_000, 0), 1_000);
+    }
+
+    #[test]
+    fn tmp102_is_read_from_its_temperature_register() {
+        let device = Device::Tmp102 { address: 0x48 };
+        let transactions = [register(0x48, 0x00, 0x1900)];
+        assert_eq!(
+            read(device, Channel::Temperature, &transactions),
+            Ok(25_000)
+        );
+    }
+
+    #[test]
+    fn lm75_is_read_from_its_temperature_register() {
+        let device = Device::Lm75 { address: 0x4F };
+        let transactions = [register(0x4F, 0x00, 0xE700)];
+        assert_eq!(
+            read(device, Channel::Temperature, &transactions),
+            Ok(-25_000)
+        );
+    }
+
+    #[test]
+    fn ina219_bus_voltage_and_current() {
+        let device = Device::Ina219 {
+            address: 0x40,
+            shunt_milliohms: 100,
+        };
+        let bus = [register(0x40, 0x02, 0x5DC0)];
+        assert_eq!(read(device, Channel::BusVoltage, &bus), Ok(12_000));
+        let shunt = [register(0x40, 0x01, 0x0FA0)];
+        assert_eq!(read(device, Channel::Current, &shunt), Ok(400));
+    }
+
+    #[test]
+    fn ina226_bus_voltage_and_current() {
+        let device = Device::Ina226 {
+            address: 0x41,
+            shunt_milliohms: 2,
+        };
+        let bus = [register(0x41, 0x02, 0x0F00)];
+        assert_eq!(read(device, Channel::BusVoltage, &bus), Ok(4_800));
+        let shunt = [register(0x41, 0x01, 0xFC18)];
+        assert_eq!(read(device, Channel::Current, &shunt), Ok(-1_250));
+    }
+
+    #[test]
+    fn unsupported_channels_are_not_read() {
+        let device = Device::Tmp102 { address: 0x48 };
+        assert_eq!(
+            read(device, Channel::Current, &[]),
+            Err(Error::UnsupportedChannel)
+        );
+        assert_eq!(
+            read(Device::McuTemperature, Channel::Temperature, &[]),
+            Err(Error::Unavailable)
+        );
+    }
+
+    #[test]
+    fn i2c_errors_are_passed_on() {
+        let device = Device::Tmp102 { address: 0x48 };
+        let error = MockError::Io(ErrorKind::Other);
+        let transactions = [register(0x48, 0x00, 0).with_error(error.clone())];
+        assert_eq!(
+            read(device, Channel::Temperature, &transactions),
+            Err(Error::I2c(error))
+        );
+    }
+}
@@ -0,0 +1,415 @@
+//! Sensors on the I2C bus of the BMC, built into its MCU and measuring its fans.
+//!
+//! The sensors fitted are described by the table in [`SENSORS`], the on-chip sensors and fans by
+//! the tables of the board. The application reads every sensor each [`POLL_PERIOD`] and stores
+//! the readings in [`Sensors`], which checks them against the thresholds of the sensor. Readings
+//! are in thousandths of the unit of the sensor, e.g. millidegrees Celsius, so no floating point
+//! is needed.
+
+pub mod analog;
+pub mod drivers;
+pub mod sdr;
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use crate::logging::{error, info, warn};
+use crate::monotonic::Duration;
+
+pub use self::analog::{Analog, AnalogInput};
+pub use self::drivers::Device;
+
+pub const POLL_PERIOD: Duration = Duration::millis(1000);
+
+/// Most sensors in all tables together
+pub const MAX_SENSORS: usize = 16;
+
+/// The sensors of the Racklet BMC board
+pub const SENSORS: &[SensorConfig] = &[
+    SensorConfig {
+        name: "Inlet Temp",
+        entity: Entity::AirInlet,
+        device: Device::Tmp102 { address: 0x48 },
+        channel: Channel::Temperature,
+        thresholds: Thresholds {
+            lower_critical: Some(0),
+            lower_non_critical: Some(5_000),
+            upper_non_critical: Some(45_000),
+            upper_critical: Some(55_000),
+        },
+    },
+    SensorConfig {
+        name: "Host 5V",
+        entity: Entity::PowerSupply,
+        device: HOST_POWER_MONITOR,
+        channel: Channel::BusVoltage,
+        thresholds: Thresholds {
+            lower_critical: Some(4_500),
+            lower_non_critical: Some(4_750),
+            upper_non_critical: Some(5_250),
+            upper_critical: Some(5_500),
+        },
+    },
+    SensorConfig {
+        name: "Host 5V Current",
+        entity: Entity::PowerSupply,
+        device: HOST_POWER_MONITOR,
+        channel: Channel::Current,
+        thresholds: Thresholds {
+            upper_non_critical: Some(2_000),
+            upper_critical: Some(3_000),
+            ..Thresholds::NONE
+        },
+    },
+];
+
+/// Measures the supply of the host through a 100 mΩ shunt
+const HOST_POWER_MONITOR: Device = Device::Ina219 {
+    address: 0x40,
+    shunt_milliohms: 100,
+};
+
+const _: () = assert!(SENSORS.len() <= MAX_SENSORS);
+
+/// What a sensor sits on or monitors, named after the IPMI entity IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entity {
+    SystemBoard,
+    AirInlet,
+    PowerSupply,
+    Fan,
+}
+
+/// What a sensor measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Temperature,
+    /// The voltage of the supply a power monitor sits on
+    BusVoltage,
+    /// The current through the shunt of a power monitor
+    Current,
+    /// A voltage measured by the ADC of the MCU
+    Voltage,
+    /// The speed of a fan
+    Speed,
+}
+
+impl Channel {
+    pub fn unit(self) -> Unit {
+        match self {
+            Channel::Temperature => Unit::Celsius,
+            Channel::BusVoltage | Channel::Voltage => Unit::Volts,
+            Channel::Current => Unit::Amps,
+            Channel::Speed => Unit::Rpm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Celsius,
+    Volts,
+    Amps,
+    Rpm,
+}
+
+impl Unit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Celsius => "C",
+            Unit::Volts => "V",
+            Unit::Amps => "A",
+            Unit::Rpm => "RPM",
+        }
+    }
+}
+
+/// The thresholds of a sensor in thousandths of its unit, `None` where it has none. A reading
+/// beyond a threshold is outside of it, so an upper critical threshold of 55 °C is crossed at
+/// 55.001 °C.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thresholds {
+    pub lower_critical: Option<i32>,
+    pub lower_non_critical: Option<i32>,
+    pub upper_non_critical: Option<i32>,
+    pub upper_critical: Option<i32>,
+}
+
+impl Thresholds {
+    pub const NONE: Self = Self {
+        lower_critical: None,
+        lower_non_critical: None,
+        upper_non_critical: None,
+        upper_critical: None,
+    };
+
+    /// The most severe threshold `value` crosses
+    pub fn status(&self, value: i32) -> Status {
+        let below = |threshold: Option<i32>| threshold.is_some_and(|t| value < t);
+        let above = |threshold: Option<i32>| threshold.is_some_and(|t| value > t);
+
+        if below(self.lower_critical) {
+            Status::LowerCritical
+        } else if above(self.upper_critical) {
+            Status::UpperCritical
+        } else if below(self.lower_non_critical) {
+            Status::LowerNonCritical
+        } else if above(self.upper_non_critical) {
+            Status::UpperNonCritical
+        } else {
+            Status::Ok
+        }
+    }
+}
+
+/// The state of a sensor, named after the IPMI threshold events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    LowerNonCritical,
+    UpperNonCritical,
+    LowerCritical,
+    UpperCritical,
+    /// The sensor has not been read yet or didn't answer
+    Unavailable,
+}
+
+impl Status {
+    pub fn is_critical(self) -> bool {
+        matches!(self, Status::LowerCritical | Status::UpperCritical)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::LowerNonCritical => "low",
+            Status::UpperNonCritical => "high",
+            Status::LowerCritical => "critical low",
+            Status::UpperCritical => "critical high",
+            Status::Unavailable => "unavailable",
+        }
+    }
+}
+
+/// A sensor in the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorConfig {
+    /// Up to 16 characters, the longest name IPMI can carry
+    pub name: &'static str,
+    pub entity: Entity,
+    pub device: Device,
+    pub channel: Channel,
+    pub thresholds: Thresholds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    /// In thousandths of the unit, `None` while unavailable
+    pub value: Option<i32>,
+    pub status: Status,
+}
+
+/// The sensors of `tables` one after the other, in the order [`Sensors`] indexes them
+pub fn all(
+    tables: &'static [&'static [SensorConfig]],
+) -> impl Iterator<Item = &'static SensorConfig> {
+    tables.iter().flat_map(|table| table.iter())
+}
+
+/// The latest readings of the sensors in a set of tables
+pub struct Sensors {
+    configs: Vec<&'static SensorConfig, MAX_SENSORS>,
+    readings: Vec<Reading, MAX_SENSORS>,
+}
+
+impl Sensors {
+    /// Panics if the tables hold more than [`MAX_SENSORS`] sensors
+    pub fn new(tables: &'static [&'static [SensorConfig]]) -> Self {
+        let unavailable = Reading {
+            value: None,
+            status: Status::Unavailable,
+        };
+        let configs: Vec<_, MAX_SENSORS> = all(tables).collect();
+        Self {
+            readings: configs.iter().map(|_| unavailable).collect(),
+            configs,
+        }
+    }
+
+    /// Stores a reading of the sensor at `index`, `None` if it couldn't be read. Changes of the
+    /// status are logged.
+    pub fn update(&mut self, index: usize, value: Option<i32>) {
+        let config = self.configs[index];
+        let status = match value {
+            Some(value) => config.thresholds.status(value),
+            None => Status::Unavailable,
+        };
+
+        let reading = &mut self.readings[index];
+        if status != reading.status {
+            let unit = config.channel.unit().symbol();
+            match (status, value) {
+                (Status::Ok, Some(value)) => {
+                    info!("{} is ok at {} {}", config.name, Milli(value), unit)
+                }
+                (status, Some(value)) if status.is_critical() => error!(
+                    "{} is {} at {} {}",
+                    config.name,
+                    status.as_str(),
+                    Milli(value),
+                    unit
+                ),
+                (status, Some(value)) => warn!(
+                    "{} is {} at {} {}",
+                    config.name,
+                    status.as_str(),
+                    Milli(value),
+                    unit
+                ),
+                (_, None) => warn!("{} is unavailable", config.name),
+            }
+        }
+        *reading = Reading { value, status };
+    }
+
+    /// The sensors with their latest readings, in table order
+    pub fn iter(&self) -> impl Iterator<Item = (&SensorConfig, Reading)> {
+        self.configs
+            .iter()
+            .copied()
+            .zip(self.readings.iter().copied())
+    }
+
+    /// Whether any sensor has crossed a critical threshold
+    pub fn any_critical(&self) -> bool {
+        self.readings
+            .iter()
+            .any(|reading| reading.status.is_critical())
+    }
+
+    /// How far the hottest temperature sensor is below its upper non-critical threshold, in
+    /// millidegrees Celsius. `None` if no such sensor could be read.
+    pub fn temperature_headroom(&self) -> Option<i32> {
+        self.iter()
+            .filter(|(config, _)| config.channel == Channel::Temperature)
+            .filter_map(|(config, reading)| {
+                Some(config.thresholds.upper_non_critical? - reading.value?)
+            })
+            .min()
+    }
+
+    /// Whether a fan has dropped below its critical speed
+    pub fn any_fan_failed(&self) -> bool {
+        self.iter().any(|(config, reading)| {
+            config.channel == Channel::Speed && reading.status.is_critical()
+        })
+    }
+}
+
+/// Formats a value in thousandths as a decimal, e.g. `-1.250`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Milli(pub i32);
+
+impl fmt::Display for Milli {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let value = self.0.unsigned_abs();
+        // Formatted first so the width and alignment apply to the whole number
+        let mut number = String::<16>::new();
+        write!(number, "{}{}.{:03}", sign, value / 1000, value % 1000)?;
+        f.pad(&number)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Milli {
+    fn format(&self, f: defmt::Formatter) {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let value = self.0.unsigned_abs();
+        defmt::write!(f, "{}{}.{:03}", sign, value / 1000, value % 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        lower_critical: Some(5_000),
+        lower_non_critical: Some(10_000),
+        upper_non_critical: Some(45_000),
+        upper_critical: Some(55_000),
+    };
+
+    const TABLE: &[SensorConfig] = &[SensorConfig {
+        name: "Test Temp",
+        entity: Entity::SystemBoard,
+        device: Device::Tmp102 { address: 0x48 },
+        channel: Channel::Temperature,
+        thresholds: THRESHOLDS,
+    }];
+
+    #[test]
+    fn thresholds_are_crossed_beyond_them() {
+        assert_eq!(THRESHOLDS.status(25_000), Status::Ok);
+        assert_eq!(THRESHOLDS.status(45_000), Status::Ok);
+        assert_eq!(THRESHOLDS.status(45_001), Status::UpperNonCritical);
+        assert_eq!(THRESHOLDS.status(55_000), Status::UpperNonCritical);
+        assert_eq!(THRESHOLDS.status(55_001), Status::UpperCritical);
+        assert_eq!(THRESHOLDS.status(10_000), Status::Ok);
+        assert_eq!(THRESHOLDS.status(9_999), Status::LowerNonCritical);
+        assert_eq!(THRESHOLDS.status(5_000), Status::LowerNonCritical);
+        assert_eq!(THRESHOLDS.status(4_999), Status::LowerCritical);
+    }
+
+    #[test]
+    fn missing_thresholds_are_never_crossed() {
+        assert_eq!(Thresholds::NONE.status(i32::MIN), Status::Ok);
+        assert_eq!(Thresholds::NONE.status(i32::MAX), Status::Ok);
+
+        let only_critical = Thresholds {
+            upper_critical: Some(85_000),
+            ..Thresholds::NONE
+        };
+        assert_eq!(only_critical.status(84_000), Status::Ok);
+        assert_eq!(only_critical.status(86_000), Status::UpperCritical);
+    }
+
+    #[test]
+    fn critical_thresholds_win_over_non_critical_ones() {
+        // Overlapping thresholds from a careless table still report the worse state
+        let overlapping = Thresholds {
+            lower_critical: Some(20_000),
+            lower_non_critical: Some(30_000),
+            upper_non_critical: Some(10_000),
+            upper_critical: Some(15_000),
+        };
+        assert_eq!(overlapping.status(17_000), Status::LowerCritical);
+        assert!(Status::LowerCritical.is_critical());
+        assert!(Status::UpperCritical.is_critical());
+        assert!(!Status::UpperNonCritical.is_critical());
+        assert!(!Status::Unavailable.is_critical());
+    }
+
+    #[test]
+    fn readings_keep_their_status() {
+        static TABLES: &[&[SensorConfig]] = &[TABLE];
+        let mut sensors = Sensors::new(TABLES);
+        assert_eq!(sensors.iter().next().unwrap().1.status, Status::Unavailable);
+
+        sensors.update(0, Some(60_000));
+        let reading = sensors.iter().next().unwrap().1;
+        assert_eq!(reading.value, Some(60_000));
+        assert_eq!(reading.status, Status::UpperCritical);
+        assert!(sensors.any_critical());
+        assert_eq!(sensors.temperature_headroom(), Some(-15_000));
+
+        sensors.update(0, None);
+        let reading = sensors.iter().next().unwrap().1;
+        assert_eq!(reading.value, None);
+        assert_eq!(reading.status, Status::Unavailable);
+        assert!(!sensors.any_critical());
+        assert_eq!(sensors.temperature_headroom(), None);
+    }
+}
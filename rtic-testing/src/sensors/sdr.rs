@@ -0,0 +1,111 @@
+//! The Sensor Data Record repository: a Full Sensor Record for each sensor in the tables, built
+//! when requested, so it always matches the sensors the firmware reads.
+//!
+//! The record ID and sensor number of a sensor are its index in [`Sensors`](super::Sensors). The
+//! linearization is chosen per sensor, with the finest step whose raw range still covers the
+//! sensor: a quarter beyond its highest upper threshold, or a range typical of its unit for
+//! sensors without one.
+
+use bmc_proto::sdr::{entity, sensor_type, unit, FullSensorRecord, Linearization, RawThresholds};
+
+use super::{Channel, Device, Entity, SensorConfig, Unit};
+
+/// The record of the sensor at `index`
+pub fn record(index: u8, config: &SensorConfig) -> FullSensorRecord<'static> {
+    // Temperatures go below 0 °C and currents flow backwards
+    let signed = matches!(config.channel.unit(), Unit::Celsius | Unit::Amps);
+    let linearization = linearization(full_scale(config), signed);
+
+    let mut record = FullSensorRecord {
+        record_id: index as u16,
+        sensor_number: index,
+        entity_id: entity_id(config.entity),
+        entity_instance: entity_instance(config),
+        sensor_type: match config.channel {
+            Channel::Temperature => sensor_type::TEMPERATURE,
+            Channel::BusVoltage | Channel::Voltage => sensor_type::VOLTAGE,
+            Channel::Current => sensor_type::CURRENT,
+            Channel::Speed => sensor_type::FAN,
+        },
+        base_unit: match config.channel.unit() {
+            Unit::Celsius => unit::DEGREES_C,
+            Unit::Volts => unit::VOLTS,
+            Unit::Amps => unit::AMPS,
+            Unit::Rpm => unit::RPM,
+        },
+        signed,
+        linearization,
+        thresholds: RawThresholds::default(),
+        name: config.name,
+    };
+
+    let t = &config.thresholds;
+    let raw = |threshold: Option<i32>| threshold.map(|milli| record.to_raw(milli as i64));
+    record.thresholds = RawThresholds {
+        lower_critical: raw(t.lower_critical),
+        lower_non_critical: raw(t.lower_non_critical),
+        upper_non_critical: raw(t.upper_non_critical),
+        upper_critical: raw(t.upper_critical),
+        ..RawThresholds::default()
+    };
+    record
+}
+
+fn entity_id(entity: Entity) -> u8 {
+    match entity {
+        Entity::SystemBoard => entity::SYSTEM_BOARD,
+        Entity::AirInlet => entity::AIR_INLET,
+        Entity::PowerSupply => entity::POWER_SUPPLY,
+        Entity::Fan => entity::FAN,
+    }
+}
+
+/// Fans are told apart by instance, counting from 1 as IPMI tools show them. The BMC has one of
+/// everything else.
+fn entity_instance(config: &SensorConfig) -> u8 {
+    match config.device {
+        Device::Fan { index } => index + 1,
+        _ => 1,
+    }
+}
+
+/// The largest value the sensor has to read, in thousandths of its unit
+fn full_scale(config: &SensorConfig) -> i64 {
+    let t = &config.thresholds;
+    match t.upper_non_critical.max(t.upper_critical) {
+        Some(highest) => highest.max(1) as i64 * 5 / 4,
+        None => match config.channel.unit() {
+            Unit::Celsius => 127_000,
+            Unit::Volts => 15_000,
+            Unit::Amps => 5_000,
+            Unit::Rpm => 10_000_000,
+        },
+    }
+}
+
+/// The finest step of 1, 2 or 5 times a power of ten covering `full_scale` with 8-bit raw
+/// readings
+fn linearization(full_scale: i64, signed: bool) -> Linearization {
+    let steps = if signed {
+        i8::MAX as i64
+    } else {
+        u8::MAX as i64
+    };
+    let mut power = 1;
+    // R counts from thousandths, where the search starts
+    let mut exponent = -3;
+    loop {
+        for m in [1, 2, 5] {
+            if m * power * steps >= full_scale || exponent == 7 {
+                return Linearization {
+                    m: m as i16,
+                    b: 0,
+                    b_exponent: 0,
+                    r_exponent: exponent,
+                };
+            }
+        }
+        power *= 10;
+        exponent += 1;
+    }
+}
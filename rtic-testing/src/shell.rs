@@ -0,0 +1,425 @@
+//! The command shell on the BMC console.
+//!
+//! [`Shell`] does its own echo and line editing, so the terminal on the other end should be in raw
+//! mode (e.g. `picocom`, `screen`). Commands act on the parts of the BMC passed in as [`Bmc`].
+
+use core::fmt::{self, Write};
+use core::str;
+
+#[cfg(feature = "slots")]
+use bmc_proto::image::UpdateStatus;
+#[cfg(any(feature = "rtc", feature = "slots"))]
+use bmc_proto::time::DateTime;
+use smart_leds::RGB8;
+
+use crate::backup::BackupState;
+use crate::board::PowerControl;
+#[cfg(feature = "slots")]
+use crate::boot::{self, Layout, Slot};
+use crate::config::{self, ConfigStore};
+#[cfg(feature = "slots")]
+use crate::crypto::Crypto;
+use crate::flash::Flash;
+use crate::identity::Identity;
+#[cfg(feature = "slots")]
+use crate::keys::SecurityPage;
+#[cfg(feature = "ram-log")]
+use crate::logging;
+#[cfg(feature = "rtc")]
+use crate::rtc;
+use crate::rtc::WallClock;
+use crate::sensors::{Milli, Sensors};
+use crate::status::StatusIndicator;
+use crate::watchdog::Culprit;
+
+/// Longest command line accepted, further input is dropped until the line ends
+pub const MAX_LINE_LEN: usize = 80;
+
+pub const PROMPT: &str = "bmc> ";
+
+const HELP: &str = "\
+help                      show this help\r\n\
+version                   show the firmware version and image\r\n\
+id                        show the serial number, node name and MAC address\r\n\
+boot                      show the boots since power-on, the last crash and the slots\r\n\
+boot minimum <version>    raise the minimum security version of firmware images\r\n\
+keys                      list the keys trusted to sign images\r\n\
+power [on|off]            show or switch the host power\r\n\
+reset on|off              assert or release the host reset\r\n\
+led <r> <g> <b>|auto      show a color on the status LED, auto shows the BMC status\r\n\
+sensors                   show the sensor readings\r\n\
+time                      show the time\r\n\
+time set <date>T<time>    set the time in UTC, e.g. 2026-10-19T12:00:00\r\n\
+config                    list the settings\r\n\
+config get <key>          show a setting\r\n\
+config set <key> <value>  change a setting\r\n\
+config unset <key>        remove a setting\r\n\
+log [clear]               show or discard the retained log output\r\n\
+rpc                       switch to the binary protocol used by racklet-bmc\r\n";
+
+/// The parts of the BMC the shell commands act on
+pub struct Bmc<'a, F> {
+    pub identity: &'a Identity,
+    /// The state kept across resets as of this boot
+    pub boot: &'a BackupState,
+    pub power: &'a mut dyn PowerControl,
+    pub status: &'a mut StatusIndicator,
+    pub config: &'a mut ConfigStore<F>,
+    pub sensors: &'a Sensors,
+    pub clock: &'a mut WallClock,
+    /// Where the firmware slots and the minimum security version are in the flash of `config`
+    #[cfg(feature = "slots")]
+    pub slots: &'a Layout,
+    /// The crypto of the board, which attestation hashes with
+    #[cfg(feature = "slots")]
+    pub crypto: &'a mut dyn Crypto,
+}
+
+pub struct Shell {
+    line: [u8; MAX_LINE_LEN],
+    len: usize,
+    /// Whether the previous byte ended a line with `\r`, so a following `\n` is skipped
+    after_cr: bool,
+}
+
+impl Shell {
+    pub const fn new() -> Self {
+        Self {
+            line: [0; MAX_LINE_LEN],
+            len: 0,
+            after_cr: false,
+        }
+    }
+
+    /// Handles bytes received from the terminal, writing the echo and command output to `out`.
+    /// Returns `Some(handled)` when the `rpc` command ended the shell after the first `handled`
+    /// bytes, the rest of `data` is then for [`crate::rpc`].
+    pub fn input<F: Flash, W: Write>(
+        &mut self,
+        data: &[u8],
+        bmc: &mut Bmc<F>,
+        out: &mut W,
+    ) -> Result<Option<usize>, fmt::Error> {
+        for (i, &byte) in data.iter().enumerate() {
+            let after_cr = self.after_cr;
+            self.after_cr = byte == b'\r';
+
+            match byte {
+                b'\n' if after_cr => {}
+                b'\r' | b'\n' => {
+                    out.write_str("\r\n")?;
+                    // Only printable ASCII is ever stored
+                    let line = str::from_utf8(&self.line[..self.len]).unwrap_or_default();
+                    if line.trim() == "rpc" {
+                        self.len = 0;
+                        return Ok(Some(i + 1));
+                    }
+                    execute(line, bmc, out)?;
+                    self.len = 0;
+                    out.write_str(PROMPT)?;
+                }
+                // Backspace and delete
+                0x08 | 0x7F if self.len > 0 => {
+                    self.len -= 1;
+                    out.write_str("\x08 \x08")?;
+                }
+                // Ctrl-C
+                0x03 => {
+                    self.len = 0;
+                    write!(out, "^C\r\n{}", PROMPT)?;
+                }
+                b' '..=b'~' if self.len < MAX_LINE_LEN => {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                    out.write_char(byte as char)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs a single command line
+pub fn execute<F: Flash, W: Write>(line: &str, bmc: &mut Bmc<F>, out: &mut W) -> fmt::Result {
+    let mut args = line.split_whitespace();
+    let Some(command) = args.next() else {
+        return Ok(());
+    };
+
+    match (command, args.next(), args.next(), args.next()) {
+        ("help", None, ..) => out.write_str(HELP),
+        ("version", None, ..) => {
+            write!(out, "{}\r\n", env!("CARGO_PKG_VERSION"))?;
+            #[cfg(feature = "slots")]
+            {
+                let flash = bmc.config.flash_mut();
+                if let Some(header) =
+                    boot::running_image(bmc.slots, |address, data| flash.read(address, data))
+                {
+                    write!(out, "image {}", header.version)?;
+                    if header.git_commit.is_known() {
+                        write!(out, ", git {:.12}", header.git_commit)?;
+                    }
+                    write!(
+                        out,
+                        ", built {}, security version {}\r\n",
+                        DateTime::from_unix(header.build_time),
+                        header.security_version
+                    )?;
+                }
+            }
+            Ok(())
+        }
+        ("id", None, ..) => write!(
+            out,
+            "serial number  {}\r\nnode name      {}\r\nMAC address    {}\r\n",
+            bmc.identity.serial_number(),
+            bmc.identity.node_name(),
+            bmc.identity.mac_address()
+        ),
+        ("boot", None, ..) => {
+            write!(out, "boot {} since power-on\r\n", bmc.boot.boot_count)?;
+            match bmc.boot.last_crash {
+                Some(crash) => write!(out, "last crash: {}\r\n", crash)?,
+                None => out.write_str("no crash since power-on\r\n")?,
+            }
+            if let Some(task) = bmc.boot.watchdog_culprit {
+                write!(out, "watchdog reset waiting for task {}\r\n", Culprit(task))?;
+            }
+            #[cfg(feature = "slots")]
+            {
+                let confirmed = boot::confirmed(bmc.config);
+                write!(
+                    out,
+                    "running slot {}, confirmed slot {}\r\n",
+                    Slot::RUNNING,
+                    confirmed
+                )?;
+                match boot::trial(bmc.config) {
+                    Some(slot) if slot == Slot::RUNNING => write!(
+                        out,
+                        "on trial, boot {} of {}\r\n",
+                        bmc.boot.trial_boots,
+                        boot::MAX_TRIAL_BOOTS
+                    )?,
+                    Some(slot) => write!(out, "slot {} goes on trial at the next reset\r\n", slot)?,
+                    None => {}
+                }
+
+                let flash = bmc.config.flash_mut();
+                let minimum = boot::min_security_version(bmc.slots, |address, data| {
+                    flash.read(address, data)
+                });
+                let running =
+                    boot::running_image(bmc.slots, |address, data| flash.read(address, data));
+                if let Some(header) = running {
+                    write!(out, "security version {}, ", header.security_version)?;
+                }
+                match minimum {
+                    Some(minimum) => write!(out, "minimum security version {}\r\n", minimum)?,
+                    None => out.write_str("security page corrupt, no image is trusted\r\n")?,
+                }
+                match boot::LAST_UPDATE.get() {
+                    Some(UpdateStatus::Installing) => out.write_str("installing an update\r\n")?,
+                    Some(UpdateStatus::Scheduled) => out.write_str("update installed\r\n")?,
+                    Some(UpdateStatus::Rejected(rejection)) => {
+                        write!(out, "update rejected: {}\r\n", rejection)?
+                    }
+                    None => {}
+                }
+            }
+            Ok(())
+        }
+        #[cfg(feature = "slots")]
+        ("boot", Some("minimum"), Some(version), None) => match version.parse() {
+            Ok(version) => {
+                let flash = bmc.config.flash_mut();
+                match boot::raise_min_security_version(flash, bmc.slots, version) {
+                    Ok(()) => Ok(()),
+                    Err(e) => write!(out, "{}\r\n", e),
+                }
+            }
+            Err(_) => out.write_str("versions are 0-4294967295\r\n"),
+        },
+        #[cfg(not(feature = "slots"))]
+        ("boot", Some("minimum"), Some(_), None) => {
+            out.write_str("the board has no bootloader checking images\r\n")
+        }
+        #[cfg(feature = "slots")]
+        ("keys", None, ..) => {
+            let flash = bmc.config.flash_mut();
+            let security = match SecurityPage::load(bmc.slots.security_pages, |address, data| {
+                flash.read(address, data)
+            }) {
+                Some(security) => security,
+                None => return out.write_str("security page corrupt\r\n"),
+            };
+            for entry in security.keys() {
+                write!(out, "{:<10}  ", entry.role.as_str())?;
+                // Enough of the key to tell them apart, racklet-bmc shows all of it
+                for byte in &entry.key[..8] {
+                    write!(out, "{:02x}", byte)?;
+                }
+                if entry.built_in {
+                    out.write_str(" built-in")?;
+                }
+                if entry.revoked {
+                    out.write_str(" revoked")?;
+                }
+                out.write_str("\r\n")?;
+            }
+            let state = if security.locked() {
+                "locked"
+            } else {
+                "unlocked"
+            };
+            write!(out, "{}, {} changes\r\n", state, security.generation())
+        }
+        #[cfg(not(feature = "slots"))]
+        ("keys", None, ..) => out.write_str("the board has no bootloader checking images\r\n"),
+        ("power", None, ..) => {
+            let state = if bmc.power.is_powered() { "on" } else { "off" };
+            write!(out, "{}\r\n", state)
+        }
+        ("power", Some(state @ ("on" | "off")), None, _) => {
+            bmc.power.set_power(state == "on");
+            Ok(())
+        }
+        ("reset", Some(state @ ("on" | "off")), None, _) => {
+            bmc.power.set_reset(state == "on");
+            Ok(())
+        }
+        ("led", Some("auto"), None, _) => {
+            bmc.status.set_color_override(None);
+            Ok(())
+        }
+        ("led", Some(r), Some(g), Some(b)) if args.next().is_none() => {
+            match (r.parse(), g.parse(), b.parse()) {
+                (Ok(r), Ok(g), Ok(b)) => {
+                    bmc.status.set_color_override(Some(RGB8 { r, g, b }));
+                    Ok(())
+                }
+                _ => out.write_str("color components must be 0-255\r\n"),
+            }
+        }
+        ("sensors", None, ..) => {
+            for (sensor, reading) in bmc.sensors.iter() {
+                write!(out, "{:16}  ", sensor.name)?;
+                match reading.value {
+                    Some(value) => write!(
+                        out,
+                        "{:>9} {}",
+                        Milli(value),
+                        sensor.channel.unit().symbol()
+                    )?,
+                    None => out.write_str("        - -")?,
+                }
+                write!(out, "  {}\r\n", reading.status.as_str())?;
+            }
+            Ok(())
+        }
+        #[cfg(feature = "rtc")]
+        ("time", None, ..) => match bmc.clock.now() {
+            Some(now) => write!(
+                out,
+                "{} (drift {} ppm)\r\n",
+                DateTime::from_unix(now),
+                bmc.clock.drift()
+            ),
+            None => out.write_str("the time is not set\r\n"),
+        },
+        #[cfg(feature = "rtc")]
+        ("time", Some("set"), Some(time), None) => match DateTime::parse(time) {
+            Some(time) => match bmc.clock.sync(time.to_unix(), bmc.config) {
+                Ok(()) => Ok(()),
+                Err(rtc::Error::OutOfRange) => out.write_str("the clock keeps 2000 to 2063\r\n"),
+                Err(rtc::Error::Unsupported) => out.write_str("the board has no RTC\r\n"),
+            },
+            None => out.write_str("times are like 2026-10-19T12:00:00\r\n"),
+        },
+        #[cfg(not(feature = "rtc"))]
+        ("time", None | Some("set"), ..) => out.write_str("the board has no RTC\r\n"),
+        ("config", None, ..) => {
+            for (key, value) in bmc.config.iter() {
+                write!(out, "{}={}\r\n", key, value)?;
+            }
+            Ok(())
+        }
+        ("config", Some("get"), Some(key), None) => match bmc.config.get(key) {
+            Some(value) => write!(out, "{}\r\n", value),
+            None => write!(out, "{} is not set\r\n", key),
+        },
+        ("config", Some("set"), Some(key), Some(_)) => {
+            // The value is the rest of the line, so it can contain spaces
+            let value = skip_words(line, 3).trim_end();
+            match bmc.config.set(key, value) {
+                Ok(()) => Ok(()),
+                Err(e) => config_error(e, out),
+            }
+        }
+        ("config", Some("unset"), Some(key), None) => match bmc.config.remove(key) {
+            Ok(true) => Ok(()),
+            Ok(false) => write!(out, "{} is not set\r\n", key),
+            Err(e) => config_error(e, out),
+        },
+        #[cfg(feature = "ram-log")]
+        ("log", None, ..) => {
+            let mut log = [0; logging::ram::BUFFER_SIZE];
+            let len = logging::ram::snapshot(&mut log);
+            // The oldest record may have been cut off, possibly within a character
+            let log = &log[..len];
+            let start = if len == logging::ram::BUFFER_SIZE {
+                log.iter().position(|&b| b == b'\n').map_or(len, |i| i + 1)
+            } else {
+                0
+            };
+            out.write_str(str::from_utf8(&log[start..]).unwrap_or("(log is not valid UTF-8)\r\n"))
+        }
+        #[cfg(feature = "ram-log")]
+        ("log", Some("clear"), None, _) => {
+            logging::ram::clear();
+            Ok(())
+        }
+        #[cfg(not(feature = "ram-log"))]
+        ("log", None | Some("clear"), None, _) => {
+            out.write_str("the log is not retained, build with the ram-log feature\r\n")
+        }
+        _ => write!(out, "invalid command, try help\r\n"),
+    }
+}
+
+/// The rest of `line` after the first `words` words
+fn skip_words(line: &str, words: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..words {
+        rest = rest
+            .find(char::is_whitespace)
+            .map_or("", |i| rest[i..].trim_start());
+    }
+    rest
+}
+
+fn config_error<W: Write>(error: config::Error, out: &mut W) -> fmt::Result {
+    match error {
+        config::Error::InvalidKey => write!(
+            out,
+            "keys are 1-{} printable characters without =\r\n",
+            config::MAX_KEY_LEN
+        ),
+        config::Error::InvalidValue => write!(
+            out,
+            "values are up to {} printable characters\r\n",
+            config::MAX_VALUE_LEN
+        ),
+        config::Error::Full => write!(out, "all {} settings are in use\r\n", config::MAX_ENTRIES),
+        config::Error::Flash(e) => write!(out, "saving failed: {:?}\r\n", e),
+    }
+}
@@ -0,0 +1,708 @@
+//! Simulated hardware for running the BMC logic on a Linux host, enabled with the `sim` feature.
+//! See `src/bin/sim.rs` for the application using it.
+
+pub mod nbd;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use embedded_hal::blocking::i2c::WriteRead;
+use embedded_hal::digital::v2::OutputPin;
+use log::{LevelFilter, Log, Metadata, Record};
+use smart_leds::RGB8;
+use usbd_scsi::{BlockDevice, BlockDeviceError};
+
+use crate::backup::{BackupMemory, STATE_LEN};
+use crate::board::Led;
+use crate::boot::{Layout, Slot};
+use crate::fans::{self, FanPwm, Pulses, MAX_FANS};
+use crate::flash::{Error, Flash};
+use crate::logging::{debug, info};
+use crate::monotonic::Duration;
+use crate::rtc::Rtc;
+use crate::sensors::{Analog, AnalogInput, Channel, Device, Entity, SensorConfig, Thresholds};
+
+/// The simulated flash has the layout of the ATSAMD51G19A on the ItsyBitsy M4, so the same UF2
+/// files can be used
+pub const FLASH_SIZE: u32 = 512 * 1024;
+pub const PAGE_SIZE: u32 = 8192;
+/// The firmware slots of the ItsyBitsy M4, the simulated BMC runs from slot A
+pub const SLOTS: Layout = Layout {
+    slot_a: 0x0001_0000,
+    slot_b: 0x0004_4000,
+    slot_size: 0x0003_4000,
+    security_pages: [0x0007_A000, 0x0007_C000],
+};
+/// Updates go into the other slot
+pub const APP_START: u32 = SLOTS.start(Slot::RUNNING.other());
+pub const APP_END: u32 = SLOTS.end(Slot::RUNNING.other());
+/// The last page holds the settings
+pub const CONFIG_ADDRESS: u32 = FLASH_SIZE - PAGE_SIZE;
+pub const UF2_FAMILY_ID: u32 = 0x5511_4460;
+pub const UF2_INFO: &str = concat!(
+    "UF2 Bootloader ",
+    env!("CARGO_PKG_VERSION"),
+    "\r\nModel: Racklet BMC\r\nBoard-ID: Racklet-BMC-Sim\r\n"
+);
+/// Stands in for the serial number of the MCU, see [`crate::identity`]
+pub const UNIQUE_ID: [u8; 16] = *b"Racklet BMC sim\0";
+/// The on-chip sensors of the ItsyBitsy M4 that don't need external dividers, read through
+/// [`SimAnalog`]. The temperature follows a [`ThermalPlant`].
+pub const ON_CHIP_SENSORS: &[SensorConfig] = &[
+    SensorConfig {
+        name: "MCU Temp",
+        entity: Entity::SystemBoard,
+        device: Device::McuTemperature,
+        channel: Channel::Temperature,
+        thresholds: Thresholds {
+            upper_non_critical: Some(70_000),
+            upper_critical: Some(85_000),
+            ..Thresholds::NONE
+        },
+    },
+    SensorConfig {
+        name: "BMC 3.3V",
+        entity: Entity::SystemBoard,
+        device: Device::Adc {
+            input: AnalogInput::IoSupply,
+            divider_ratio: 1,
+        },
+        channel: Channel::Voltage,
+        thresholds: Thresholds {
+            lower_critical: Some(3_000),
+            lower_non_critical: Some(3_135),
+            upper_non_critical: Some(3_465),
+            upper_critical: Some(3_600),
+        },
+    },
+];
+
+/// Installs a logger printing to stderr, which the [`crate::logging`] macros write to
+pub fn init_logging(level: LevelFilter) {
+    static LOGGER: StderrLogger = StderrLogger;
+    log::set_logger(&LOGGER).ok();
+    log::set_max_level(level);
+}
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{:<5} [{}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// An output GPIO, level changes are logged
+pub struct SimPin {
+    name: &'static str,
+    high: bool,
+}
+
+impl SimPin {
+    /// The pin starts low
+    pub fn new(name: &'static str) -> Self {
+        Self { name, high: false }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+impl OutputPin for SimPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        if self.high {
+            info!("{}: low", self.name);
+        }
+        self.high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        if !self.high {
+            info!("{}: high", self.name);
+        }
+        self.high = true;
+        Ok(())
+    }
+}
+
+/// The status LED, color changes are logged
+#[derive(Default)]
+pub struct SimLed {
+    color: RGB8,
+}
+
+impl Led for SimLed {
+    fn set(&mut self, color: RGB8) {
+        if color != self.color {
+            debug!("LED: {:?}", color);
+        }
+        self.color = color;
+    }
+
+    fn color(&self) -> RGB8 {
+        self.color
+    }
+}
+
+/// An I2C bus with devices made of 16-bit registers, like the sensor parts. Reads from addresses
+/// without devices fail like a missing acknowledge.
+#[derive(Default)]
+pub struct SimI2c {
+    registers: HashMap<(u8, u8), u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nack;
+
+impl SimI2c {
+    pub fn set_register(&mut self, address: u8, register: u8, value: u16) {
+        self.registers.insert((address, register), value);
+    }
+}
+
+impl WriteRead for SimI2c {
+    type Error = Nack;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Nack> {
+        let register = *bytes.first().ok_or(Nack)?;
+        let value = self.registers.get(&(address, register)).ok_or(Nack)?;
+        let len = buffer.len().min(2);
+        buffer[..len].copy_from_slice(&value.to_be_bytes()[..len]);
+        Ok(())
+    }
+}
+
+/// An ADC reading set values, inputs without a value read as missing
+#[derive(Default)]
+pub struct SimAnalog {
+    millicelsius: Option<i32>,
+    millivolts: HashMap<AnalogInput, i32>,
+}
+
+impl SimAnalog {
+    pub fn set_millicelsius(&mut self, millicelsius: i32) {
+        self.millicelsius = Some(millicelsius);
+    }
+
+    pub fn set_millivolts(&mut self, input: AnalogInput, millivolts: i32) {
+        self.millivolts.insert(input, millivolts);
+    }
+}
+
+impl Analog for SimAnalog {
+    fn millivolts(&mut self, input: AnalogInput) -> Option<i32> {
+        self.millivolts.get(&input).copied()
+    }
+
+    fn millicelsius(&mut self) -> Option<i32> {
+        self.millicelsius
+    }
+}
+
+/// An RTC counting from when it was set, which it isn't at startup as after power-on
+#[derive(Default)]
+pub struct SimRtc {
+    set: Option<(Instant, i64)>,
+}
+
+impl Rtc for SimRtc {
+    fn unix_time(&mut self) -> Option<i64> {
+        let (at, seconds) = self.set?;
+        Some(seconds + at.elapsed().as_secs() as i64)
+    }
+
+    fn set_unix_time(&mut self, seconds: i64) {
+        self.set = Some((Instant::now(), seconds));
+    }
+}
+
+/// Backup memory in RAM, which starts out lost as after power-on
+#[derive(Default)]
+pub struct SimBackup {
+    data: [u8; STATE_LEN],
+}
+
+impl BackupMemory for SimBackup {
+    fn read(&self) -> [u8; STATE_LEN] {
+        self.data
+    }
+
+    fn write(&mut self, data: &[u8; STATE_LEN]) {
+        self.data = *data;
+    }
+}
+
+/// The two fans of the ItsyBitsy M4
+pub const FAN_SENSORS: &[SensorConfig] =
+    &[fans::fan_sensor("Fan 1", 0), fans::fan_sensor("Fan 2", 1)];
+
+/// Fans whose speed follows the duty cycle, unless stalled
+pub struct SimFans {
+    duty: u8,
+    stalled: [bool; Self::COUNT],
+}
+
+impl SimFans {
+    pub const COUNT: usize = FAN_SENSORS.len();
+    /// The speed at full duty cycle
+    pub const MAX_RPM: u32 = 3000;
+
+    /// The fans start at full speed
+    pub fn new() -> Self {
+        Self {
+            duty: fans::MAX_DUTY,
+            stalled: [false; Self::COUNT],
+        }
+    }
+
+    pub fn stall(&mut self, fan: usize) {
+        self.stalled[fan] = true;
+    }
+
+    fn rpm(&self, fan: usize) -> u32 {
+        if self.stalled[fan] {
+            0
+        } else {
+            Self::MAX_RPM * self.duty as u32 / 100
+        }
+    }
+
+    /// The tachometer pulses the fans give over `period`
+    pub fn pulses(&self, period: Duration) -> Pulses {
+        let mut pulses = [None; MAX_FANS];
+        for (fan, pulses) in pulses.iter_mut().enumerate().take(Self::COUNT) {
+            let revolutions = self.rpm(fan) as u64 * period.to_millis() / 60_000;
+            *pulses = Some((revolutions * fans::PULSES_PER_REVOLUTION as u64) as u32);
+        }
+        pulses
+    }
+
+    /// The combined airflow of the fans in percent of all of them at full speed
+    pub fn airflow(&self) -> u32 {
+        let rpm: u32 = (0..Self::COUNT).map(|fan| self.rpm(fan)).sum();
+        rpm * 100 / (Self::MAX_RPM * Self::COUNT as u32)
+    }
+}
+
+impl Default for SimFans {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FanPwm for SimFans {
+    fn set_duty(&mut self, percent: u8) {
+        self.duty = percent.min(fans::MAX_DUTY);
+    }
+}
+
+/// A first order thermal model of the MCU: a constant heat load cooled by the airflow of the fans.
+/// Still air lets it settle at [`ThermalPlant::STILL_AIR_RISE`] above the ambient temperature,
+/// more airflow brings that down.
+pub struct ThermalPlant {
+    millicelsius: i32,
+}
+
+impl ThermalPlant {
+    pub const AMBIENT: i32 = 25_000;
+    pub const STILL_AIR_RISE: i32 = 80_000;
+    /// The airflow in percent that halves the rise
+    const HALF_RISE_AIRFLOW: i32 = 25;
+    /// The time constant of approaching the settling temperature
+    const TIME_CONSTANT_MILLIS: i64 = 20_000;
+
+    /// Starts at the ambient temperature
+    pub fn new() -> Self {
+        Self {
+            millicelsius: Self::AMBIENT,
+        }
+    }
+
+    pub fn millicelsius(&self) -> i32 {
+        self.millicelsius
+    }
+
+    /// Advances the model by `period` with `airflow` in percent
+    pub fn step(&mut self, period: Duration, airflow: u32) {
+        let rise = Self::STILL_AIR_RISE as i64 * Self::HALF_RISE_AIRFLOW as i64
+            / (Self::HALF_RISE_AIRFLOW as i64 + airflow as i64);
+        let settled = Self::AMBIENT as i64 + rise;
+        let change = (settled - self.millicelsius as i64) * period.to_millis() as i64
+            / Self::TIME_CONSTANT_MILLIS;
+        self.millicelsius += change as i32;
+    }
+}
+
+impl Default for ThermalPlant {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Flash kept in RAM, starting out erased
+pub struct RamFlash {
+    start: u32,
+    page_size: u32,
+    data: Vec<u8>,
+}
+
+impl RamFlash {
+    pub fn new(start: u32, size: u32, page_size: u32) -> Self {
+        assert!(start.is_multiple_of(page_size) && size.is_multiple_of(page_size));
+        Self {
+            start,
+            page_size,
+            data: vec![0xFF; size as usize],
+        }
+    }
+
+    /// The flash contents, the first byte is at the start address
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Flash for RamFlash {
+    fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    fn end_address(&self) -> u32 {
+        self.start + self.data.len() as u32
+    }
+
+    fn read(&self, address: u32, data: &mut [u8]) {
+        // Like on the hardware, reads outside the flash don't fail
+        for (i, byte) in data.iter_mut().enumerate() {
+            let offset = (address as usize + i).wrapping_sub(self.start as usize);
+            *byte = self.data.get(offset).copied().unwrap_or(0xFF);
+        }
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        if address < self.start
+            || address + self.page_size > self.end_address()
+            || !address.is_multiple_of(self.page_size)
+            || data.len() != self.page_size as usize
+        {
+            return Err(Error::InvalidAddress);
+        }
+
+        let offset = (address - self.start) as usize;
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// A pseudoterminal standing in for the USB CDC-ACM console. Connect to [`Pty::path`] with a
+/// terminal program, e.g. `picocom`.
+pub struct Pty {
+    master: File,
+    /// Kept open so reads don't fail while no terminal is connected
+    _slave: File,
+    path: PathBuf,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+
+            let mut name = [0; 64];
+            if libc::grantpt(fd) != 0
+                || libc::unlockpt(fd) != 0
+                || libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_str().unwrap());
+
+            let slave = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NOCTTY)
+                .open(&path)?;
+
+            // Like a CDC-ACM port, the line discipline passes everything through unchanged
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self {
+                master,
+                _slave: slave,
+                path,
+            })
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads what the terminal has sent without blocking
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.master.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            result => result,
+        }
+    }
+
+    /// Writes to the terminal, output is dropped while the terminal doesn't keep up, like with USB
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.master.write_all(data) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// A disk image file standing in for the USB host side of a [`BlockDevice`]. The image is written
+/// with the contents of the device, and blocks changed in the file, e.g. by `mcopy`, are written
+/// back to the device on [`FileDisk::sync`].
+pub struct FileDisk {
+    path: PathBuf,
+    image: Vec<u8>,
+    modified: Option<SystemTime>,
+}
+
+impl FileDisk {
+    pub fn create<D: BlockDevice>(path: &Path, device: &D) -> io::Result<Self> {
+        let mut disk = Self {
+            path: path.to_owned(),
+            image: Vec::new(),
+            modified: None,
+        };
+        disk.write_image(device)?;
+        Ok(disk)
+    }
+
+    fn write_image<D: BlockDevice>(&mut self, device: &D) -> io::Result<()> {
+        let blocks = device.max_lba() as usize + 1;
+        self.image = vec![0; blocks * D::BLOCK_BYTES];
+        for (lba, block) in self.image.chunks_mut(D::BLOCK_BYTES).enumerate() {
+            device
+                .read_block(lba as u32, block)
+                .map_err(block_device_error)?;
+        }
+
+        fs::write(&self.path, &self.image)?;
+        self.modified = fs::metadata(&self.path)?.modified().ok();
+        Ok(())
+    }
+
+    /// Writes the blocks changed in the file since the last call to `device`, then updates the
+    /// file with what the device reads back
+    pub fn sync<D: BlockDevice>(&mut self, device: &mut D) -> io::Result<()> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified == self.modified {
+            return Ok(());
+        }
+
+        let file = fs::read(&self.path)?;
+        let changed = file
+            .chunks(D::BLOCK_BYTES)
+            .zip(self.image.chunks(D::BLOCK_BYTES))
+            .enumerate()
+            .filter(|(_, (new, old))| new.len() == D::BLOCK_BYTES && new != old);
+
+        let mut count = 0;
+        for (lba, (block, _)) in changed {
+            device
+                .write_block(lba as u32, block)
+                .map_err(block_device_error)?;
+            count += 1;
+        }
+        debug!(
+            "{} changed blocks written from {}",
+            count,
+            self.path.display()
+        );
+
+        self.write_image(device)
+    }
+}
+
+fn block_device_error(e: BlockDeviceError) -> io::Error {
+    io::Error::other(format!("block device: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fans::{FanControl, Pid, Policy, CURVE, DEFAULT_TARGET, MAX_DUTY, PID_GAINS};
+    use crate::sensors::{self, Sensors, POLL_PERIOD};
+
+    const SENSOR_TABLES: &[&[SensorConfig]] = &[ON_CHIP_SENSORS, FAN_SENSORS];
+
+    /// The fans cooling the MCU under the thermal policy, polled as the sim application does
+    struct ClosedLoop {
+        plant: ThermalPlant,
+        fans: SimFans,
+        analog: SimAnalog,
+        sensors: Sensors,
+        control: FanControl,
+    }
+
+    impl ClosedLoop {
+        fn new(policy: Policy) -> Self {
+            Self {
+                plant: ThermalPlant::new(),
+                fans: SimFans::new(),
+                analog: SimAnalog::default(),
+                sensors: Sensors::new(SENSOR_TABLES),
+                control: FanControl::new(policy),
+            }
+        }
+
+        fn run(&mut self, polls: usize) {
+            for _ in 0..polls {
+                self.plant.step(POLL_PERIOD, self.fans.airflow());
+                self.analog.set_millicelsius(self.plant.millicelsius());
+
+                let pulses = self.fans.pulses(POLL_PERIOD);
+                for (i, sensor) in sensors::all(SENSOR_TABLES).enumerate() {
+                    let value = sensor
+                        .device
+                        .read(
+                            &mut SimI2c::default(),
+                            &mut self.analog,
+                            &pulses,
+                            sensor.channel,
+                        )
+                        .ok();
+                    self.sensors.update(i, value);
+                }
+
+                let duty = self.control.update(
+                    self.sensors.temperature_headroom(),
+                    self.sensors.any_fan_failed(),
+                );
+                self.fans.set_duty(duty);
+            }
+        }
+
+        fn headroom(&self) -> i32 {
+            self.sensors.temperature_headroom().unwrap()
+        }
+
+        fn fan_speed(&self, fan: usize) -> i32 {
+            let (config, reading) = self
+                .sensors
+                .iter()
+                .filter(|(config, _)| config.channel == Channel::Speed)
+                .nth(fan)
+                .unwrap();
+            assert_eq!(config.device, Device::Fan { index: fan as u8 });
+            reading.value.unwrap()
+        }
+    }
+
+    #[test]
+    fn plant_settles_by_airflow() {
+        let mut plant = ThermalPlant::new();
+        for _ in 0..600 {
+            plant.step(POLL_PERIOD, 0);
+        }
+        let still = ThermalPlant::AMBIENT + ThermalPlant::STILL_AIR_RISE;
+        assert!((still - 500..=still).contains(&plant.millicelsius()));
+
+        for _ in 0..600 {
+            plant.step(POLL_PERIOD, 100);
+        }
+        let cooled = ThermalPlant::AMBIENT + ThermalPlant::STILL_AIR_RISE / 5;
+        assert!((cooled..=cooled + 500).contains(&plant.millicelsius()));
+    }
+
+    #[test]
+    fn fan_speed_follows_duty() {
+        let mut fans = SimFans::new();
+        fans.set_duty(50);
+        let pulses = fans.pulses(POLL_PERIOD);
+        assert_eq!(fans::millirpm(pulses[0].unwrap(), POLL_PERIOD), 1_500_000);
+        assert_eq!(fans.airflow(), 50);
+
+        fans.stall(1);
+        let pulses = fans.pulses(POLL_PERIOD);
+        assert_eq!(pulses[1], Some(0));
+        assert_eq!(pulses[SimFans::COUNT], None);
+        assert_eq!(fans.airflow(), 25);
+    }
+
+    #[test]
+    fn curve_keeps_headroom() {
+        let mut system = ClosedLoop::new(Policy::Curve(CURVE));
+        system.run(600);
+        let headroom = system.headroom();
+        let duty = system.control.duty();
+        // Settles where the curve and the plant meet, well short of full speed
+        system.run(60);
+        assert_eq!(system.control.duty(), duty);
+        assert!((10_000..=20_000).contains(&headroom), "{}", headroom);
+        assert!(duty < MAX_DUTY);
+        // Counting whole revolutions per poll reads up to 60 RPM slow
+        let speed = SimFans::MAX_RPM as i32 * duty as i32 * 10;
+        assert!((speed - 60_000..=speed).contains(&system.fan_speed(0)));
+    }
+
+    #[test]
+    fn pid_holds_target() {
+        let pid = Pid::new(PID_GAINS, DEFAULT_TARGET, POLL_PERIOD);
+        let mut system = ClosedLoop::new(Policy::Pid(pid));
+        system.run(600);
+        for _ in 0..60 {
+            system.run(1);
+            let error = system.headroom() - DEFAULT_TARGET;
+            assert!(error.abs() <= 500, "{}", error);
+        }
+        assert!(system.control.duty() < MAX_DUTY);
+    }
+
+    #[test]
+    fn failed_fan_runs_the_others_at_full_speed() {
+        let mut system = ClosedLoop::new(Policy::Curve(CURVE));
+        system.run(600);
+        assert!(!system.sensors.any_fan_failed());
+
+        system.fans.stall(0);
+        system.run(1);
+        assert!(system.sensors.any_fan_failed());
+        assert_eq!(system.control.duty(), MAX_DUTY);
+        system.run(1);
+        assert_eq!(system.fan_speed(0), 0);
+        assert_eq!(system.fan_speed(1), SimFans::MAX_RPM as i32 * 1000);
+    }
+}
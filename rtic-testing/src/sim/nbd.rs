@@ -0,0 +1,442 @@
+//! A [Network Block Device] server exporting a [`BlockDevice`], so the Linux kernel can mount the
+//! GhostFat drive like it would over USB mass storage:
+//!
+//! ```shell
+//! nbd-client localhost 10809 /dev/nbd0 -N bmc
+//! mount /dev/nbd0 /mnt
+//! ```
+//!
+//! Only the fixed newstyle handshake and the simple replies are implemented, which is what
+//! `nbd-client` and `qemu-nbd` use. One client is served at a time.
+//!
+//! [Network Block Device]: https://github.com/NetworkBlockDevice/nbd/blob/master/doc/proto.md
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use usbd_scsi::BlockDevice;
+
+use crate::logging::{debug, info, warn};
+
+/// The name of the export, any name is accepted from clients
+pub const EXPORT_NAME: &str = "bmc";
+
+const NBDMAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const OPTION_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+const FLAG_C_NO_ZEROES: u32 = 1 << 1;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = 1 << 31 | 1;
+
+const INFO_EXPORT: u16 = 0;
+const INFO_BLOCK_SIZE: u16 = 3;
+
+const FLAG_HAS_FLAGS: u16 = 1 << 0;
+const FLAG_SEND_FLUSH: u16 = 1 << 2;
+const TRANSMISSION_FLAGS: u16 = FLAG_HAS_FLAGS | FLAG_SEND_FLUSH;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+
+const REQUEST_HEADER_LEN: usize = 28;
+/// Largest read or write accepted, the kernel sends at most a few hundred KiB at once
+const MAX_REQUEST_LEN: u32 = 32 * 1024 * 1024;
+/// Time the client has for the handshake, which blocks the application
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct NbdServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    /// Received bytes not yet forming a complete request
+    buffer: Vec<u8>,
+}
+
+impl NbdServer {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn listener(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// Accepts a new client and serves the requests received from it without blocking, except
+    /// for the handshake. Errors of a client only disconnect it.
+    pub fn poll<D: BlockDevice>(&mut self, device: &mut D) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, peer)) => match handshake(stream, device) {
+                    Ok(Some(client)) => {
+                        info!("NBD client {} connected", peer);
+                        self.client = Some(client);
+                    }
+                    Ok(None) => debug!("NBD client {} left during the handshake", peer),
+                    Err(e) => warn!("NBD handshake with {} failed: {}", peer, e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => warn!("Accepting an NBD client failed: {}", e),
+            }
+        }
+
+        if let Some(client) = &mut self.client {
+            match client.serve(device) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("NBD client disconnected");
+                    self.client = None;
+                }
+                Err(e) => {
+                    warn!("NBD client dropped: {}", e);
+                    self.client = None;
+                }
+            }
+        }
+    }
+}
+
+fn export_size<D: BlockDevice>(device: &D) -> u64 {
+    (device.max_lba() as u64 + 1) * D::BLOCK_BYTES as u64
+}
+
+/// Negotiates the export, returns `None` if the client aborted
+fn handshake<D: BlockDevice>(mut stream: TcpStream, device: &D) -> io::Result<Option<Client>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+
+    stream.write_all(&NBDMAGIC.to_be_bytes())?;
+    stream.write_all(&IHAVEOPT.to_be_bytes())?;
+    stream.write_all(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes())?;
+    let client_flags = read_u32(&mut stream)?;
+
+    loop {
+        if read_u64(&mut stream)? != IHAVEOPT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad option magic",
+            ));
+        }
+        let option = read_u32(&mut stream)?;
+        let len = read_u32(&mut stream)?;
+        if len > 4096 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "option too long",
+            ));
+        }
+        let mut data = vec![0; len as usize];
+        stream.read_exact(&mut data)?;
+
+        match option {
+            OPT_EXPORT_NAME => {
+                stream.write_all(&export_size(device).to_be_bytes())?;
+                stream.write_all(&TRANSMISSION_FLAGS.to_be_bytes())?;
+                if client_flags & FLAG_C_NO_ZEROES == 0 {
+                    stream.write_all(&[0; 124])?;
+                }
+                break;
+            }
+            OPT_ABORT => {
+                option_reply(&mut stream, option, REP_ACK, &[])?;
+                return Ok(None);
+            }
+            OPT_LIST => {
+                let mut reply = (EXPORT_NAME.len() as u32).to_be_bytes().to_vec();
+                reply.extend_from_slice(EXPORT_NAME.as_bytes());
+                option_reply(&mut stream, option, REP_SERVER, &reply)?;
+                option_reply(&mut stream, option, REP_ACK, &[])?;
+            }
+            OPT_INFO | OPT_GO => {
+                let mut export = INFO_EXPORT.to_be_bytes().to_vec();
+                export.extend_from_slice(&export_size(device).to_be_bytes());
+                export.extend_from_slice(&TRANSMISSION_FLAGS.to_be_bytes());
+                option_reply(&mut stream, option, REP_INFO, &export)?;
+
+                let block = D::BLOCK_BYTES as u32;
+                let mut block_size = INFO_BLOCK_SIZE.to_be_bytes().to_vec();
+                for size in [block, block, MAX_REQUEST_LEN] {
+                    block_size.extend_from_slice(&size.to_be_bytes());
+                }
+                option_reply(&mut stream, option, REP_INFO, &block_size)?;
+                option_reply(&mut stream, option, REP_ACK, &[])?;
+
+                if option == OPT_GO {
+                    break;
+                }
+            }
+            _ => option_reply(&mut stream, option, REP_ERR_UNSUP, &[])?,
+        }
+    }
+
+    stream.set_nonblocking(true)?;
+    Ok(Some(Client {
+        stream,
+        buffer: Vec::new(),
+    }))
+}
+
+fn option_reply(stream: &mut TcpStream, option: u32, reply: u32, data: &[u8]) -> io::Result<()> {
+    let mut message = OPTION_REPLY_MAGIC.to_be_bytes().to_vec();
+    message.extend_from_slice(&option.to_be_bytes());
+    message.extend_from_slice(&reply.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    message.extend_from_slice(data);
+    stream.write_all(&message)
+}
+
+fn read_u32(stream: &mut TcpStream) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    stream.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(stream: &mut TcpStream) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    stream.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl Client {
+    /// Serves the complete requests received so far, returns false once the client is gone
+    fn serve<D: BlockDevice>(&mut self, device: &mut D) -> io::Result<bool> {
+        let mut chunk = [0; 64 * 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while self.buffer.len() >= REQUEST_HEADER_LEN {
+            let header = &self.buffer[..REQUEST_HEADER_LEN];
+            let magic = u32::from_be_bytes(header[0..4].try_into().unwrap());
+            let command = u16::from_be_bytes(header[6..8].try_into().unwrap());
+            let handle = u64::from_be_bytes(header[8..16].try_into().unwrap());
+            let offset = u64::from_be_bytes(header[16..24].try_into().unwrap());
+            let len = u32::from_be_bytes(header[24..28].try_into().unwrap());
+
+            if magic != REQUEST_MAGIC || len > MAX_REQUEST_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request"));
+            }
+
+            let data_len = if command == CMD_WRITE {
+                len as usize
+            } else {
+                0
+            };
+            if self.buffer.len() < REQUEST_HEADER_LEN + data_len {
+                break;
+            }
+            let request: Vec<u8> = self.buffer.drain(..REQUEST_HEADER_LEN + data_len).collect();
+            let data = &request[REQUEST_HEADER_LEN..];
+
+            match command {
+                CMD_READ => {
+                    let mut blocks = vec![0; len as usize];
+                    let error = transfer(device, offset, &mut blocks, Direction::Read);
+                    self.reply(handle, error, if error == 0 { &blocks } else { &[] })?;
+                }
+                CMD_WRITE => {
+                    let mut blocks = data.to_vec();
+                    let error = transfer(device, offset, &mut blocks, Direction::Write);
+                    self.reply(handle, error, &[])?;
+                }
+                // Writes go straight to the device, there is nothing to flush
+                CMD_FLUSH => self.reply(handle, 0, &[])?,
+                CMD_DISC => return Ok(false),
+                _ => self.reply(handle, EINVAL, &[])?,
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn reply(&mut self, handle: u64, error: u32, data: &[u8]) -> io::Result<()> {
+        let mut reply = SIMPLE_REPLY_MAGIC.to_be_bytes().to_vec();
+        reply.extend_from_slice(&error.to_be_bytes());
+        reply.extend_from_slice(&handle.to_be_bytes());
+        reply.extend_from_slice(data);
+
+        // The reply is written in full even if the client is slow to read it
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(&reply);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Read,
+    Write,
+}
+
+/// Reads or writes whole blocks, returning an NBD error code
+fn transfer<D: BlockDevice>(device: &mut D, offset: u64, data: &mut [u8], dir: Direction) -> u32 {
+    let block = D::BLOCK_BYTES;
+    // The offset comes from the client, so it may be anything
+    let end = offset.checked_add(data.len() as u64);
+    if !offset.is_multiple_of(block as u64)
+        || !data.len().is_multiple_of(block)
+        || end.is_none_or(|end| end > export_size(device))
+    {
+        return EINVAL;
+    }
+
+    let first = (offset / block as u64) as u32;
+    for (i, chunk) in data.chunks_mut(block).enumerate() {
+        let lba = first + i as u32;
+        let result = match dir {
+            Direction::Read => device.read_block(lba, chunk),
+            Direction::Write => device.write_block(lba, chunk),
+        };
+        if let Err(e) = result {
+            warn!("NBD {:?} of block {} failed: {:?}", dir, lba, e);
+            return EIO;
+        }
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::FlashWrapper;
+    use crate::ghostfat::GhostFat;
+    use crate::sim::{RamFlash, APP_END, APP_START, PAGE_SIZE, UF2_FAMILY_ID, UF2_INFO};
+
+    const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+    const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+    const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+    const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+    const PAYLOAD_SIZE: usize = 256;
+
+    /// The sector of the data area the UF2 file is written to, as a host copying it would
+    const FILE_SECTOR: u64 = 1000;
+
+    fn ghostfat() -> GhostFat<RamFlash> {
+        let flash = RamFlash::new(APP_START, APP_END - APP_START, PAGE_SIZE);
+        let flash = FlashWrapper::new(flash, APP_START, APP_END);
+        GhostFat::new(flash, UF2_FAMILY_ID, UF2_INFO)
+    }
+
+    /// Encodes `payload` as UF2 blocks programming it at `address`
+    fn uf2(address: u32, payload: &[u8], family_id: u32) -> Vec<u8> {
+        let blocks = payload.chunks(PAYLOAD_SIZE).count() as u32;
+        let mut file = Vec::new();
+        for (i, chunk) in payload.chunks(PAYLOAD_SIZE).enumerate() {
+            let words = [
+                UF2_MAGIC_START0,
+                UF2_MAGIC_START1,
+                UF2_FLAG_FAMILY_ID_PRESENT,
+                address + (i * PAYLOAD_SIZE) as u32,
+                chunk.len() as u32,
+                i as u32,
+                blocks,
+                family_id,
+            ];
+            let mut block = [0; 512];
+            for (bytes, word) in block.chunks_mut(4).zip(words) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+            block[32..32 + chunk.len()].copy_from_slice(chunk);
+            block[508..].copy_from_slice(&UF2_MAGIC_END.to_le_bytes());
+            file.extend_from_slice(&block);
+        }
+        file
+    }
+
+    fn write(ghostfat: &mut GhostFat<RamFlash>, file: &mut [u8]) -> u32 {
+        transfer(ghostfat, FILE_SECTOR * 512, file, Direction::Write)
+    }
+
+    #[test]
+    fn uf2_written_over_nbd_is_programmed_into_flash() {
+        let mut ghostfat = ghostfat();
+        let payload: Vec<u8> = (0..3 * PAYLOAD_SIZE + 100).map(|i| (i * 7) as u8).collect();
+        let mut file = uf2(APP_START + PAGE_SIZE, &payload, UF2_FAMILY_ID);
+
+        assert_eq!(write(&mut ghostfat, &mut file), 0);
+        assert!(ghostfat.update_complete());
+
+        let mut flash = vec![0; payload.len()];
+        ghostfat.flash().read(APP_START + PAGE_SIZE, &mut flash);
+        assert_eq!(flash, payload);
+        // The rest of the page stays erased
+        let mut rest = [0; 16];
+        ghostfat
+            .flash()
+            .read(APP_START + PAGE_SIZE + payload.len() as u32, &mut rest);
+        assert_eq!(rest, [0xFF; 16]);
+    }
+
+    #[test]
+    fn uf2_for_another_family_is_ignored() {
+        let mut ghostfat = ghostfat();
+        let mut file = uf2(APP_START, &[0x55; PAYLOAD_SIZE], UF2_FAMILY_ID + 1);
+
+        assert_eq!(write(&mut ghostfat, &mut file), 0);
+        assert!(!ghostfat.update_complete());
+        let mut flash = [0; PAYLOAD_SIZE];
+        ghostfat.flash().read(APP_START, &mut flash);
+        assert_eq!(flash, [0xFF; PAYLOAD_SIZE]);
+    }
+
+    #[test]
+    fn transfer_rejects_requests_outside_the_export() {
+        let mut ghostfat = ghostfat();
+        let mut data = [0; 512];
+        let size = export_size(&ghostfat);
+
+        assert_eq!(
+            transfer(&mut ghostfat, size, &mut data, Direction::Read),
+            EINVAL
+        );
+        assert_eq!(
+            transfer(&mut ghostfat, 1, &mut data, Direction::Read),
+            EINVAL
+        );
+        let last = u64::MAX - 511;
+        assert_eq!(
+            transfer(&mut ghostfat, last, &mut data, Direction::Write),
+            EINVAL
+        );
+        assert_eq!(
+            transfer(&mut ghostfat, size - 512, &mut data, Direction::Read),
+            0
+        );
+    }
+}
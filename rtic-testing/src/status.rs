@@ -0,0 +1,87 @@
+//! What the status LED shows. The application ticks [`StatusIndicator`] at [`TICK_PERIOD`] and
+//! sets the LED to the returned color, so the blink patterns are the same on every board.
+
+use smart_leds::RGB8;
+
+use crate::monotonic::Duration;
+
+pub const TICK_PERIOD: Duration = Duration::millis(1000);
+
+const OFF: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+const GREEN: RGB8 = RGB8 { r: 0, g: 20, b: 0 };
+const BLUE: RGB8 = RGB8 { r: 0, g: 0, b: 40 };
+const RED: RGB8 = RGB8 { r: 40, g: 0, b: 0 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The host is powered off, the LED blinks green as a heartbeat
+    Idle,
+    /// The host is powered on, the LED is steady green
+    HostOn,
+    /// A firmware update is being written, the LED blinks blue
+    Updating,
+    /// Something needs attention, the LED blinks red
+    Error,
+}
+
+pub struct StatusIndicator {
+    state: State,
+    /// A color set by the user, shown steadily instead of the state
+    color_override: Option<RGB8>,
+    lit: bool,
+}
+
+impl StatusIndicator {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle,
+            color_override: None,
+            lit: false,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    pub fn color_override(&self) -> Option<RGB8> {
+        self.color_override
+    }
+
+    /// Shows `color` until cleared with `None`
+    pub fn set_color_override(&mut self, color: Option<RGB8>) {
+        self.color_override = color;
+    }
+
+    /// Advances the blink pattern and returns the color to show until the next tick
+    pub fn tick(&mut self) -> RGB8 {
+        self.lit = !self.lit;
+
+        if let Some(color) = self.color_override {
+            return color;
+        }
+
+        let (color, blink) = match self.state {
+            State::Idle => (GREEN, true),
+            State::HostOn => (GREEN, false),
+            State::Updating => (BLUE, true),
+            State::Error => (RED, true),
+        };
+
+        if blink && !self.lit {
+            OFF
+        } else {
+            color
+        }
+    }
+}
+
+impl Default for StatusIndicator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
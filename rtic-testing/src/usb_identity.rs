@@ -0,0 +1,105 @@
+//! The identity the BMC presents on USB: VID/PID, the device strings and the SCSI inquiry data of
+//! the GhostFat drive.
+//!
+//! The defaults are compiled in from `usb.toml`, or the file named by `BMC_USB_CONFIG` at build
+//! time, so a deployment can build firmware with its own IDs. The settings below override them
+//! from the config store and take effect at the next reset, as the USB device is only built at
+//! startup:
+//!
+//! | Key                | Value                       |
+//! |--------------------|-----------------------------|
+//! | `usb.vid`          | Vendor ID in hex            |
+//! | `usb.pid`          | Product ID in hex           |
+//! | `usb.manufacturer` | Manufacturer string         |
+//! | `usb.product`      | Product string              |
+//! | `scsi.vendor`      | Up to 8 characters          |
+//! | `scsi.product`     | Up to 16 characters         |
+//! | `scsi.revision`    | Up to 4 characters          |
+
+use heapless::String;
+
+use crate::config::{ConfigStore, MAX_VALUE_LEN};
+use crate::flash::Flash;
+use crate::logging::warn;
+
+/// The identity compiled in from the build-time configuration, for applications without the
+/// config store
+pub mod defaults {
+    include!(concat!(env!("OUT_DIR"), "/usb_identity.rs"));
+}
+
+/// The lengths of the SCSI inquiry data fields
+pub const SCSI_VENDOR_LEN: usize = 8;
+pub const SCSI_PRODUCT_LEN: usize = 16;
+pub const SCSI_REVISION_LEN: usize = 4;
+
+pub struct UsbIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: String<MAX_VALUE_LEN>,
+    pub product: String<MAX_VALUE_LEN>,
+    pub scsi_vendor: String<SCSI_VENDOR_LEN>,
+    pub scsi_product: String<SCSI_PRODUCT_LEN>,
+    pub scsi_revision: String<SCSI_REVISION_LEN>,
+}
+
+impl UsbIdentity {
+    /// The identity compiled into the firmware
+    pub fn new() -> Self {
+        Self {
+            vid: defaults::VID,
+            pid: defaults::PID,
+            // build.rs checks the lengths
+            manufacturer: defaults::MANUFACTURER.into(),
+            product: defaults::PRODUCT.into(),
+            scsi_vendor: defaults::SCSI_VENDOR.into(),
+            scsi_product: defaults::SCSI_PRODUCT.into(),
+            scsi_revision: defaults::SCSI_REVISION.into(),
+        }
+    }
+
+    /// The identity compiled into the firmware with the overrides from `config` applied. Invalid
+    /// overrides are logged and ignored.
+    pub fn load<F: Flash>(config: &ConfigStore<F>) -> Self {
+        let mut identity = Self::new();
+        override_id(&mut identity.vid, config, "usb.vid");
+        override_id(&mut identity.pid, config, "usb.pid");
+        override_string(&mut identity.manufacturer, config, "usb.manufacturer");
+        override_string(&mut identity.product, config, "usb.product");
+        override_string(&mut identity.scsi_vendor, config, "scsi.vendor");
+        override_string(&mut identity.scsi_product, config, "scsi.product");
+        override_string(&mut identity.scsi_revision, config, "scsi.revision");
+        identity
+    }
+}
+
+impl Default for UsbIdentity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn override_id<F: Flash>(id: &mut u16, config: &ConfigStore<F>, key: &str) {
+    if let Some(value) = config.get(key) {
+        let hex = value.trim_start_matches("0x");
+        match u16::from_str_radix(hex, 16) {
+            Ok(value) => *id = value,
+            Err(_) => warn!("Ignoring {}, {} is not a 16-bit hex number", key, value),
+        }
+    }
+}
+
+fn override_string<F: Flash, const N: usize>(
+    string: &mut String<N>,
+    config: &ConfigStore<F>,
+    key: &str,
+) {
+    if let Some(value) = config.get(key) {
+        // The config store only holds printable ASCII
+        if value.len() <= N {
+            *string = value.into();
+        } else {
+            warn!("Ignoring {}, it is longer than {} characters", key, N);
+        }
+    }
+}
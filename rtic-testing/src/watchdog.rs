@@ -0,0 +1,175 @@
+//! The hardware watchdog guarding the firmware on trial, see [`crate::boot`].
+//!
+//! The bootloader starts the watchdog before it jumps to a slot on trial, after which it runs until
+//! the next reset. The firmware feeds it every [`FEED_PERIOD`], but only once every task in
+//! [`Task::CHECKED`] has checked in since it last did, so a task that hangs or stops being
+//! scheduled lets the watchdog reset the MCU. Shortly before, the early warning interrupt records
+//! the task the watchdog waited for as the
+//! [`watchdog_culprit`](crate::backup::BackupState::watchdog_culprit). The reset counts as a boot
+//! of the slot on trial like any other, so firmware that keeps hanging is rolled back as firmware
+//! that keeps crashing is.
+//!
+//! Confirmed firmware runs without the watchdog, feeding it does nothing then.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::monotonic::Duration;
+
+/// How often the firmware feeds the watchdog, well within the timeout the bootloader sets
+pub const FEED_PERIOD: Duration = Duration::millis(1000);
+
+/// The tasks the watchdog waits for. The number of each is recorded as the culprit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Task {
+    Heartbeat = 0,
+    PollSensors = 1,
+    /// The task feeding the watchdog, the culprit when every other task has checked in
+    FeedWatchdog = 2,
+}
+
+impl Task {
+    /// The tasks that check in
+    pub const CHECKED: [Task; 2] = [Task::Heartbeat, Task::PollSensors];
+
+    /// The task recorded as `number`
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            0 => Some(Task::Heartbeat),
+            1 => Some(Task::PollSensors),
+            2 => Some(Task::FeedWatchdog),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Task::Heartbeat => "heartbeat",
+            Task::PollSensors => "poll_sensors",
+            Task::FeedWatchdog => "feed_watchdog",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Shows a recorded culprit by the name of its task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Culprit(pub u8);
+
+impl fmt::Display for Culprit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match Task::from_number(self.0) {
+            Some(task) => f.write_str(task.as_str()),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Culprit {
+    fn format(&self, f: defmt::Formatter) {
+        match Task::from_number(self.0) {
+            Some(task) => defmt::write!(f, "{=str}", task.as_str()),
+            None => defmt::write!(f, "{=u8}", self.0),
+        }
+    }
+}
+
+/// The watchdog of the board, see [`crate::board::Board::watchdog`]
+pub trait Watchdog {
+    /// Starts the watchdog, for the bootloader. It can't be stopped until the next reset.
+    fn start(&mut self);
+
+    /// Restarts the timeout of the watchdog
+    fn feed(&mut self);
+
+    /// Clears the early warning, for its interrupt handler
+    fn acknowledge_warning(&mut self);
+}
+
+/// The tasks that checked in since the watchdog was last fed
+pub struct CheckIns(AtomicU8);
+
+/// The check-ins of the application
+pub static CHECK_INS: CheckIns = CheckIns::new();
+
+impl CheckIns {
+    const ALL: u8 = (1 << Task::CHECKED.len()) - 1;
+
+    pub const fn new() -> Self {
+        Self(AtomicU8::new(0))
+    }
+
+    pub fn check_in(&self, task: Task) {
+        self.0.fetch_or(task.bit(), Ordering::Relaxed);
+    }
+
+    /// Whether every task has checked in, starting over if so
+    pub fn take_all(&self) -> bool {
+        self.0
+            .compare_exchange(Self::ALL, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// The first task that hasn't checked in, or the task feeding the watchdog if all have
+    pub fn culprit(&self) -> Task {
+        let checked_in = self.0.load(Ordering::Relaxed);
+        Task::CHECKED
+            .iter()
+            .copied()
+            .find(|task| checked_in & task.bit() == 0)
+            .unwrap_or(Task::FeedWatchdog)
+    }
+}
+
+impl Default for CheckIns {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fed_once_every_task_checked_in() {
+        let check_ins = CheckIns::new();
+        assert!(!check_ins.take_all());
+        check_ins.check_in(Task::Heartbeat);
+        check_ins.check_in(Task::Heartbeat);
+        assert!(!check_ins.take_all());
+        check_ins.check_in(Task::PollSensors);
+        assert!(check_ins.take_all());
+        // Starts over
+        assert!(!check_ins.take_all());
+    }
+
+    #[test]
+    fn blames_the_first_task_missing() {
+        let check_ins = CheckIns::new();
+        assert_eq!(check_ins.culprit(), Task::Heartbeat);
+        check_ins.check_in(Task::Heartbeat);
+        assert_eq!(check_ins.culprit(), Task::PollSensors);
+        check_ins.check_in(Task::PollSensors);
+        assert_eq!(check_ins.culprit(), Task::FeedWatchdog);
+    }
+
+    #[test]
+    fn task_numbers() {
+        for task in [Task::Heartbeat, Task::PollSensors, Task::FeedWatchdog] {
+            assert_eq!(Task::from_number(task as u8), Some(task));
+        }
+        assert_eq!(Task::from_number(3), None);
+    }
+
+    #[test]
+    fn culprit_names() {
+        assert_eq!(format!("{}", Culprit(1)), "poll_sensors");
+        assert_eq!(format!("{}", Culprit(7)), "7");
+    }
+}